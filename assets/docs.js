@@ -0,0 +1,84 @@
+function toggleMenu() {
+    const sidebar = document.querySelector('.sidebar');
+    sidebar.classList.toggle('open');
+}
+
+// Close sidebar when clicking outside on mobile
+document.addEventListener('click', function(event) {
+    const sidebar = document.querySelector('.sidebar');
+    const menuToggle = document.querySelector('.menu-toggle');
+
+    if (window.innerWidth <= 768 &&
+        !sidebar.contains(event.target) &&
+        !menuToggle.contains(event.target) &&
+        sidebar.classList.contains('open')) {
+        sidebar.classList.remove('open');
+    }
+});
+
+// Try-it consoles: each service section's endpoints, with their example texts
+const consoles = JSON.parse(document.getElementById('consoles').textContent) || {};
+
+document.querySelectorAll('.try-it').forEach(function(form) {
+    const endpoints = consoles[form.dataset.service] || [];
+    const select = form.querySelector('select');
+    const text = form.querySelector('textarea');
+    const button = form.querySelector('button');
+    const output = form.querySelector('pre');
+    const audio = form.querySelector('audio');
+
+    endpoints.forEach(function(endpoint, index) {
+        const option = document.createElement('option');
+        option.value = index;
+        option.textContent = endpoint.label;
+        select.append(option);
+    });
+
+    let example = '';
+    function showExample() {
+        const endpoint = endpoints[select.value];
+        // Keep what the user typed
+        if (endpoint && (text.value === '' || text.value === example)) {
+            example = endpoint.example;
+            text.value = example;
+        }
+    }
+    select.addEventListener('change', showExample);
+    showExample();
+
+    button.addEventListener('click', async function() {
+        const endpoint = endpoints[select.value];
+        if (!endpoint) return;
+        button.disabled = true;
+        output.hidden = true;
+        audio.hidden = true;
+        try {
+            const response = await fetch(endpoint.path, {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ text: text.value }),
+            });
+            const type = response.headers.get('Content-Type') || '';
+            if (response.ok && type.startsWith('audio/')) {
+                audio.src = URL.createObjectURL(await response.blob());
+                audio.hidden = false;
+                audio.play();
+            } else {
+                const body = await response.text();
+                let shown = body;
+                try {
+                    shown = JSON.stringify(JSON.parse(body), null, 2);
+                } catch (err) {
+                    // Not JSON; show it as it is
+                }
+                output.textContent = response.ok ? shown : response.status + ' ' + response.statusText + '\n' + shown;
+                output.hidden = false;
+            }
+        } catch (err) {
+            output.textContent = String(err);
+            output.hidden = false;
+        } finally {
+            button.disabled = false;
+        }
+    });
+});
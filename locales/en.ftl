@@ -0,0 +1,56 @@
+lang = en
+page_title = Documentation
+menu = Menu
+nav_title = API Endpoints
+header_subtitle = Documentation for the Divvun API endpoints
+introduction_title = Introduction
+introduction_body = Welcome to the Divvun API documentation. This API provides endpoints for interacting with the Divvun service.
+introduction_offsets = Offsets in responses count Unicode characters in logical order, whatever the text's display direction. Line endings may be CRLF, CR or LF, and directional formatting characters (such as U+200F RIGHT-TO-LEFT MARK) are removed before checking; neither shifts the offsets.
+introduction_bodies = Requests are JSON, but the text can also be sent as is with <code>Content-Type: text/plain</code>, or as a <code>text</code> field with <code>application/x-www-form-urlencoded</code>, e.g. <code>curl -d text=sami</code>.
+base_url_title = Base URL
+base_url_body = All API endpoints are relative to the base URL:
+endpoints_title = Endpoints
+request = Request
+response = Response
+health_title = Health Check
+health_description = Check the health status of the API.
+grammar_title = Grammar Check
+grammar_description = Check grammar for text. Available languages:
+grammar_mixed = Texts mixing several languages can be split by paragraph and checked with
+grammar_tags_hint = <strong>Categories:</strong> leave out findings by <code>error_code</code> prefix with <code>{"{"}&quot;ignore_tags&quot;: [&quot;punct&quot;]{"}"}</code>, or keep only some with <code>include_tags</code>.
+speller_title = Spell Check
+speller_description = Check spelling for text. Available languages:
+speller_suggestions_hint = <strong>Suggestions:</strong> get at most a few suggestions per word with <code>{"{"}&quot;max_suggestions&quot;: 5{"}"}</code>, and leave out unlikely ones with <code>max_weight</code>.
+markup_hint = <strong>Rich text:</strong> send HTML or Markdown with <code>{"{"}&quot;format&quot;: &quot;html&quot;{"}"}</code> or <code>&quot;markdown&quot;</code>; the markup is left out of the check and offsets point into the original document.
+hyphenation_title = Hyphenation
+hyphenation_description = Find where words may be hyphenated. Available languages:
+hyphenation_positions = <code>patterns</code> mark hyphenation points with <code>^</code>; <code>positions</code> are the character offsets in the word where the first pattern allows a hyphen.
+tts_title = Text-to-Speech
+tts_description = Convert text to speech. Available languages and voices:
+tts_mp3_hint = <strong>MP3:</strong> add <code>Accept: audio/mpeg</code> header to get MP3 audio instead of WAV.
+tts_format_hint = <strong>Other formats:</strong> add <code>?format=mp3</code>, <code>ogg</code> or <code>flac</code> (or a <code>format</code> field in the body) to get the audio converted.
+tts_ssml_hint = <strong>SSML:</strong> send <code>{"{"}&quot;ssml&quot;: &quot;&lt;speak&gt;…&lt;/speak&gt;&quot;{"}"}</code> instead of <code>text</code>; <code>break</code>, <code>prosody</code> and <code>say-as</code> are kept.
+tts_voices = voices
+tts_response_wav = WAV audio file containing the synthesized speech.
+tts_response_mp3 = MP3 audio file containing the synthesized speech (if <code>Accept: audio/mpeg</code> header provided)
+status_title = Divvun API Status
+status_subtitle = Current health of the language backends behind this API
+status_service = Service
+status_tag = Language
+status_state = Status
+status_latency = Latency
+status_last_checked = Last checked
+status_last_error = Last error
+status_up = up
+status_down = down
+status_pending = pending
+status_seconds_ago = { $seconds } s ago
+demo_title = Divvun Demo
+demo_subtitle = Try the Divvun language tools for this language
+demo_text_label = Text
+demo_speak = Speak
+demo_no_errors = No errors found.
+demo_failed = Request failed:
+try_title = Try it
+try_language = Language
+try_send = Send
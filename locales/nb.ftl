@@ -0,0 +1,56 @@
+lang = nb
+page_title = Dokumentasjon
+menu = Meny
+nav_title = API-endepunkter
+header_subtitle = Dokumentasjon for endepunktene i Divvun-API-et
+introduction_title = Innledning
+introduction_body = Velkommen til dokumentasjonen for Divvun-API-et. API-et tilbyr endepunkter for å bruke Divvun-tjenestene.
+introduction_offsets = Posisjoner i svarene teller Unicode-tegn i logisk rekkefølge, uansett tekstens skriveretning. Linjeskift kan være CRLF, CR eller LF, og retningstegn (som U+200F RIGHT-TO-LEFT MARK) fjernes før kontrollen; ingen av delene forskyver posisjonene.
+introduction_bodies = Forespørsler er JSON, men teksten kan også sendes som den er med <code>Content-Type: text/plain</code>, eller som et <code>text</code>-felt med <code>application/x-www-form-urlencoded</code>, f.eks. <code>curl -d text=sami</code>.
+base_url_title = Basis-URL
+base_url_body = Alle API-endepunkter er relative til basis-URL-en:
+endpoints_title = Endepunkter
+request = Forespørsel
+response = Svar
+health_title = Helsesjekk
+health_description = Sjekk helsestatusen til API-et.
+grammar_title = Grammatikkontroll
+grammar_description = Kontroller grammatikken i en tekst. Tilgjengelige språk:
+grammar_mixed = Tekster som blander flere språk kan deles opp i avsnitt og kontrolleres med
+grammar_tags_hint = <strong>Kategorier:</strong> utelat funn etter prefiks på <code>error_code</code> med <code>{"{"}&quot;ignore_tags&quot;: [&quot;punct&quot;]{"}"}</code>, eller behold bare noen med <code>include_tags</code>.
+speller_title = Stavekontroll
+speller_description = Kontroller stavingen i en tekst. Tilgjengelige språk:
+speller_suggestions_hint = <strong>Forslag:</strong> få høyst noen få forslag per ord med <code>{"{"}&quot;max_suggestions&quot;: 5{"}"}</code>, og utelat usannsynlige forslag med <code>max_weight</code>.
+markup_hint = <strong>Formatert tekst:</strong> send HTML eller Markdown med <code>{"{"}&quot;format&quot;: &quot;html&quot;{"}"}</code> eller <code>&quot;markdown&quot;</code>; markeringen holdes utenfor kontrollen, og posisjonene viser til det opprinnelige dokumentet.
+hyphenation_title = Orddeling
+hyphenation_description = Finn hvor ord kan deles. Tilgjengelige språk:
+hyphenation_positions = <code>patterns</code> markerer delingspunkter med <code>^</code>; <code>positions</code> er tegnposisjonene i ordet der det første mønsteret tillater bindestrek.
+tts_title = Tekst til tale
+tts_description = Gjør om tekst til tale. Tilgjengelige språk og stemmer:
+tts_mp3_hint = <strong>MP3:</strong> legg til headeren <code>Accept: audio/mpeg</code> for å få MP3-lyd i stedet for WAV.
+tts_format_hint = <strong>Andre formater:</strong> legg til <code>?format=mp3</code>, <code>ogg</code> eller <code>flac</code> (eller et <code>format</code>-felt i kroppen) for å få lyden konvertert.
+tts_ssml_hint = <strong>SSML:</strong> send <code>{"{"}&quot;ssml&quot;: &quot;&lt;speak&gt;…&lt;/speak&gt;&quot;{"}"}</code> i stedet for <code>text</code>; <code>break</code>, <code>prosody</code> og <code>say-as</code> beholdes.
+tts_voices = stemmer
+tts_response_wav = WAV-lydfil med den syntetiserte talen.
+tts_response_mp3 = MP3-lydfil med den syntetiserte talen (hvis headeren <code>Accept: audio/mpeg</code> er satt)
+status_title = Divvun API-status
+status_subtitle = Nåværende helsetilstand for språktjenestene bak API-et
+status_service = Tjeneste
+status_tag = Språk
+status_state = Status
+status_latency = Svartid
+status_last_checked = Sist sjekket
+status_last_error = Siste feil
+status_up = oppe
+status_down = nede
+status_pending = venter
+status_seconds_ago = { $seconds } s siden
+demo_title = Divvun-demo
+demo_subtitle = Prøv Divvuns språkverktøy for dette språket
+demo_text_label = Tekst
+demo_speak = Les opp
+demo_no_errors = Fant ingen feil.
+demo_failed = Forespørselen feilet:
+try_title = Prøv det
+try_language = Språk
+try_send = Send
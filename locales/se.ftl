@@ -0,0 +1,56 @@
+lang = se
+page_title = Dokumentašuvdna
+menu = Fállu
+nav_title = API-geažit
+header_subtitle = Dokumentašuvdna Divvun API-geažiide
+introduction_title = Álggahus
+introduction_body = Bures boahtin Divvun API-dokumentašuvdnii. Dát API fállá geažiid maiguin sáhttá geavahit Divvun-bálvalusa.
+introduction_offsets = Vástádusaid sajit lohket Unicode-mearkkaid logalaš ortnegis, beroškeahttá das guđe guvlui teaksta čállo. Linnjámolsumat sáhttet leat CRLF, CR dahje LF, ja guovlomearkkat (nugo U+200F RIGHT-TO-LEFT MARK) sihkkojuvvojit ovdal dárkkisteami; dat eai sirdde sajiid.
+introduction_bodies = Jearaldagat leat JSON, muhto teaksta sáhttá maiddái sáddejuvvot nu go lea <code>Content-Type: text/plain</code> mielde, dahje <code>text</code>-gieddin <code>application/x-www-form-urlencoded</code> mielde, omd. <code>curl -d text=sami</code>.
+base_url_title = Vuođđo-URL
+base_url_body = Buot API-geažit leat relatiivvat dán vuođđo-URL:ii:
+endpoints_title = Geažit
+request = Jearaldat
+response = Vástádus
+health_title = Dearvvašvuođadárkkisteapmi
+health_description = Dárkkis API dearvvašvuođastáhtusa.
+grammar_title = Grammatihkkadárkkisteapmi
+grammar_description = Dárkkis teavstta grammatihka. Olámuttos gielat:
+grammar_mixed = Teavsttaid main leat máŋga giela sáhttá juohkit bihttáide ja dárkkistit dáinna:
+grammar_tags_hint = <strong>Kategoriijat:</strong> guođe olggobeallái gávdnosiid <code>error_code</code> álgguid mielde <code>{"{"}&quot;ignore_tags&quot;: [&quot;punct&quot;]{"}"}</code> bokte, dahje bisut dušše muhtimiid <code>include_tags</code> bokte.
+speller_title = Čállindárkkisteapmi
+speller_description = Dárkkis teavstta čállima. Olámuttos gielat:
+speller_suggestions_hint = <strong>Evttohusat:</strong> oaččo eanemusat moadde evttohusa juohke sátnái <code>{"{"}&quot;max_suggestions&quot;: 5{"}"}</code> bokte, ja guođe olggobeallái heajut evttohusaid <code>max_weight</code> bokte.
+markup_hint = <strong>Hábmejuvvon teaksta:</strong> sádde HTML dahje Markdown <code>{"{"}&quot;format&quot;: &quot;html&quot;{"}"}</code> dahje <code>&quot;markdown&quot;</code> bokte; merkemat eai dárkkistuvvo, ja sajit čujuhit álgovuolggalaš dokumentii.
+hyphenation_title = Sátnejuohkin
+hyphenation_description = Gávnna gos sániid sáhttá juohkit. Olámuttos gielat:
+hyphenation_positions = <code>patterns</code> merkejit juohkinsajiid <code>^</code>:in; <code>positions</code> leat mearkasajit sánis gos vuosttaš minsttar suovvá juohkinsárggá.
+tts_title = Teakstas hállamii
+tts_description = Jorgal teavstta hállamin. Olámuttos gielat ja jienat:
+tts_mp3_hint = <strong>MP3:</strong> lasit <code>Accept: audio/mpeg</code>-headera vai oaččut MP3-jiena WAV sajis.
+tts_format_hint = <strong>Eará formáhtat:</strong> lasit <code>?format=mp3</code>, <code>ogg</code> dahje <code>flac</code> (dahje <code>format</code>-gietti sisdollui) vai jietna konverterejuvvo.
+tts_ssml_hint = <strong>SSML:</strong> sádde <code>{"{"}&quot;ssml&quot;: &quot;&lt;speak&gt;…&lt;/speak&gt;&quot;{"}"}</code> <code>text</code> sajis; <code>break</code>, <code>prosody</code> ja <code>say-as</code> bisuhuvvojit.
+tts_voices = jienat
+tts_response_wav = WAV-jietnafiila mas lea syntetiserejuvvon hállan.
+tts_response_mp3 = MP3-jietnafiila mas lea syntetiserejuvvon hállan (jus <code>Accept: audio/mpeg</code>-headera lea mielde)
+status_title = Divvun API-stáhtus
+status_subtitle = Dán API giellabálvalusaid dálá dearvvašvuohta
+status_service = Bálvalus
+status_tag = Giella
+status_state = Stáhtus
+status_latency = Vástidanáigi
+status_last_checked = Maŋimuš dárkkistus
+status_last_error = Maŋimuš meattáhus
+status_up = doaibmá
+status_down = ii doaimma
+status_pending = vuordá
+status_seconds_ago = { $seconds } s áigi
+demo_title = Divvun-demo
+demo_subtitle = Geahččal Divvuna giellareaidduid dán gillii
+demo_text_label = Teaksta
+demo_speak = Logat
+demo_no_errors = Ii gávdnon meattáhus.
+demo_failed = Jearaldat ii lihkostuvvan:
+try_title = Geahččal
+try_language = Giella
+try_send = Sádde
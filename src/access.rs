@@ -0,0 +1,136 @@
+//! Access logging with request IDs.
+//!
+//! Every request gets an `X-Request-Id`, the client's own if it sent a usable
+//! one, which the proxy forwards to the backend and the response carries
+//! back. Each request is logged as a JSON line under the `access` target, so
+//! a client's report can be followed through the gateway and backend logs.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use poem::{
+    http::{header, HeaderValue},
+    Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use serde_json::json;
+
+pub const REQUEST_ID: &str = "X-Request-Id";
+
+/// Longest request ID taken from a client.
+const MAX_LENGTH: usize = 128;
+
+/// A process-unique ID: a prefix fixed at startup and a counter.
+fn generate() -> String {
+    static PREFIX: OnceLock<u32> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let prefix = PREFIX.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos() ^ elapsed.as_secs() as u32)
+            .unwrap_or_default();
+        nanos ^ std::process::id().rotate_left(16)
+    });
+    format!(
+        "{:08x}{:016x}",
+        prefix,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The client's request ID, if it is short and printable enough to log.
+fn given(req: &Request) -> Option<&str> {
+    req.header(REQUEST_ID).filter(|id| {
+        !id.is_empty() && id.len() <= MAX_LENGTH && id.bytes().all(|b| b.is_ascii_graphic())
+    })
+}
+
+/// Middleware assigning request IDs and logging every request.
+pub struct AccessLog;
+
+impl<E: Endpoint> Middleware<E> for AccessLog {
+    type Output = AccessLogEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        AccessLogEndpoint { inner: ep }
+    }
+}
+
+pub struct AccessLogEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for AccessLogEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let id = given(&req).map(str::to_string).unwrap_or_else(generate);
+        let id = HeaderValue::from_str(&id).expect("request IDs are printable ASCII");
+        req.headers_mut().insert(REQUEST_ID, id.clone());
+
+        let method = req.method().to_string();
+        let path = req.uri().path().to_string();
+        let size = req
+            .header(header::CONTENT_LENGTH)
+            .and_then(|length| length.parse::<u64>().ok());
+        let start = Instant::now();
+
+        let mut response = match self.inner.call(req).await {
+            Ok(response) => response.into_response(),
+            Err(err) => err.into_response(),
+        };
+        response.headers_mut().insert(REQUEST_ID, id.clone());
+
+        // Service paths are /<service>/<tag>[/...]
+        let tag = path.split('/').nth(2).filter(|tag| !tag.is_empty());
+        tracing::info!(
+            target: "access",
+            "{}",
+            json!({
+                "request_id": id.to_str().unwrap_or_default(),
+                "method": method,
+                "path": path,
+                "tag": tag,
+                "status": response.status().as_u16(),
+                "duration_ms": (start.elapsed().as_secs_f64() * 1000.0 * 10.0).round() / 10.0,
+                "size": size,
+            })
+        );
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{endpoint::make, test::TestClient, EndpointExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_keep_or_get_an_id() {
+        let endpoint = make(|req: Request| async move {
+            req.header(REQUEST_ID).unwrap_or_default().to_string()
+        })
+        .with(AccessLog);
+        let client = TestClient::new(endpoint);
+
+        let response = client
+            .get("/grammar/se")
+            .header(REQUEST_ID, "abc-123")
+            .send()
+            .await;
+        response.assert_header(REQUEST_ID, "abc-123");
+        response.assert_text("abc-123").await;
+
+        let first = client.get("/").header(REQUEST_ID, "bad id").send().await;
+        let second = client.get("/").send().await;
+        let id = |response: &poem::test::TestResponse| {
+            response.0.headers()[REQUEST_ID]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(id(&first).len(), 24);
+        assert_ne!(id(&first), id(&second));
+    }
+}
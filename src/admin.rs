@@ -0,0 +1,354 @@
+//! Token-protected administration endpoints, enabled by `[config.admin]`:
+//! reloading the configuration, listing the services, disabling and enabling
+//! languages, and the state of the backends.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use poem::{
+    get, handler,
+    http::{header, HeaderValue, StatusCode},
+    post,
+    web::{self, Data, Json, Query},
+    Endpoint, IntoResponse, Middleware, Request, Response, Route,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::auth::constant_time_eq;
+use crate::config::LanguagesConfig;
+use crate::health::{Disabled, HealthMonitor};
+use crate::problem::Problem;
+use crate::server::RoutingTable;
+use crate::services::ServiceRegistry;
+
+/// The bearer token the admin endpoints require.
+#[derive(Clone)]
+pub struct AdminToken(pub String);
+
+/// What a reload changed, by public path.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ReloadSummary {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Paths whose backend moved.
+    pub changed: Vec<String>,
+}
+
+/// Compare the locations of two configurations.
+pub fn diff(
+    old: &LanguagesConfig,
+    new: &LanguagesConfig,
+    services: &ServiceRegistry,
+) -> ReloadSummary {
+    let backends = |languages: &LanguagesConfig| -> BTreeMap<String, String> {
+        services
+            .iter()
+            .flat_map(|kind| kind.locations(languages))
+            .map(|location| (location.path.clone(), location.backend_url()))
+            .collect()
+    };
+    let (old, new) = (backends(old), backends(new));
+
+    let mut summary = ReloadSummary::default();
+    for (path, backend) in &new {
+        match old.get(path) {
+            None => summary.added.push(path.clone()),
+            Some(previous) if previous != backend => summary.changed.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    summary.removed = old
+        .keys()
+        .filter(|path| !new.contains_key(*path))
+        .cloned()
+        .collect();
+    summary
+}
+
+/// Re-reads the config file into the server's routing table, on
+/// `POST /admin/reload` or whenever it is triggered, e.g. by SIGHUP.
+pub struct Reloader {
+    path: Option<PathBuf>,
+    routing: RoutingTable,
+    services: ServiceRegistry,
+}
+
+impl Reloader {
+    pub fn new(path: Option<PathBuf>, routing: RoutingTable, services: ServiceRegistry) -> Self {
+        Self {
+            path,
+            routing,
+            services,
+        }
+    }
+
+    /// Load and apply the config file. On error the current configuration
+    /// stays in place.
+    pub fn reload(&self) -> Result<ReloadSummary, Problem> {
+        let Some(path) = &self.path else {
+            return Err(Problem::new(StatusCode::CONFLICT, "Nothing to reload")
+                .detail("The server was started with the embedded configuration"));
+        };
+        self.apply(path).map_err(|err| {
+            Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "Invalid configuration")
+                .detail(format!("{:#}", err))
+        })
+    }
+
+    fn apply(&self, path: &Path) -> anyhow::Result<ReloadSummary> {
+        let languages = LanguagesConfig::load(Some(path))?;
+        let previous = self.routing.apply(languages)?;
+        let current = self.routing.snapshot();
+        Ok(diff(
+            &previous.languages,
+            &current.languages,
+            &self.services,
+        ))
+    }
+
+    /// Reload every time `triggers` receives, until it is closed.
+    pub async fn run(self: Arc<Self>, mut triggers: UnboundedReceiver<()>) {
+        while triggers.recv().await.is_some() {
+            let Some(path) = &self.path else {
+                tracing::warn!("Not reloading: the server uses the embedded configuration");
+                continue;
+            };
+            match self.apply(path) {
+                Ok(summary) => log_summary(&summary),
+                Err(err) => tracing::error!(
+                    "Can't reload {}, keeping the current configuration: {:#}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+    }
+}
+
+fn log_summary(summary: &ReloadSummary) {
+    tracing::info!(
+        "Reloaded configuration: {} added, {} removed, {} changed",
+        summary.added.len(),
+        summary.removed.len(),
+        summary.changed.len()
+    );
+}
+
+/// Check the request's `Authorization: Bearer` token.
+fn authorize(req: &Request, token: &AdminToken) -> Result<(), Problem> {
+    let given = req
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if given.is_some_and(|given| constant_time_eq(given, &token.0)) {
+        Ok(())
+    } else {
+        Err(Problem::new(StatusCode::UNAUTHORIZED, "Unauthorized")
+            .detail("A valid admin token is required"))
+    }
+}
+
+#[handler]
+async fn reload_post(
+    req: &Request,
+    Data(token): Data<&AdminToken>,
+    Data(reloader): Data<&Arc<Reloader>>,
+) -> poem::Result<Json<ReloadSummary>> {
+    authorize(req, token)?;
+    let summary = reloader.reload()?;
+    log_summary(&summary);
+    Ok(Json(summary))
+}
+
+/// The endpoints under `/admin`, given the [`AdminToken`], the
+/// [`Reloader`], and the server's [`RoutingTable`], [`ServiceRegistry`] and
+/// [`HealthMonitor`] as data.
+pub fn routes() -> Route {
+    Route::new()
+        .at("/reload", post(reload_post))
+        .at("/services", get(services_get))
+        .at("/services/:service/:tag/disable", post(disable_post))
+        .at("/services/:service/:tag/enable", post(enable_post))
+        .at("/backends", get(backends_get))
+}
+
+/// Every location of the current configuration, with its backend, its last
+/// health probe and whether it is disabled.
+#[handler]
+async fn services_get(
+    req: &Request,
+    Data(token): Data<&AdminToken>,
+    Data(routing): Data<&RoutingTable>,
+    Data(services): Data<&ServiceRegistry>,
+    Data(health): Data<&HealthMonitor>,
+) -> poem::Result<Json<Value>> {
+    authorize(req, token)?;
+    let snapshot = routing.snapshot();
+    let statuses = health.snapshot();
+    let locations: Vec<Value> = services
+        .iter()
+        .flat_map(|kind| {
+            kind.locations(&snapshot.languages)
+                .into_iter()
+                .map(move |location| (kind.clone(), location))
+        })
+        .map(|(kind, location)| {
+            let up = statuses
+                .iter()
+                .find(|status| status.service == kind.name() && status.tag == location.tag)
+                .and_then(|status| status.up);
+            let disabled = health.disabled(kind.name(), &location.tag);
+            json!({
+                "service": kind.name(),
+                "tag": location.tag,
+                "path": location.path,
+                "backend": location.backend_url(),
+                "proxied": kind.proxied(),
+                "up": up,
+                "enabled": disabled.is_none(),
+                "disabled_for": disabled.and_then(|disabled| disabled.remaining),
+            })
+        })
+        .collect();
+    Ok(Json(json!({ "services": locations })))
+}
+
+#[derive(Debug, Deserialize)]
+struct DisableQuery {
+    /// Seconds until the language is enabled again by itself.
+    seconds: Option<u64>,
+}
+
+/// Turn requests for a language away with a 503, e.g. while its backend
+/// misbehaves, until it is enabled again or `seconds` have passed.
+#[handler]
+async fn disable_post(
+    req: &Request,
+    web::Path((service, tag)): web::Path<(String, String)>,
+    Query(query): Query<DisableQuery>,
+    Data(token): Data<&AdminToken>,
+    Data(routing): Data<&RoutingTable>,
+    Data(health): Data<&HealthMonitor>,
+) -> poem::Result<Json<Disabled>> {
+    authorize(req, token)?;
+    let tag = configured_tag(routing, &service, &tag)?;
+    health.disable(&service, &tag, query.seconds.map(Duration::from_secs));
+    tracing::warn!(
+        "Disabled {} for {}{}",
+        service,
+        tag,
+        query
+            .seconds
+            .map(|seconds| format!(" for {}s", seconds))
+            .unwrap_or_default()
+    );
+    Ok(Json(Disabled {
+        service,
+        tag,
+        remaining: query.seconds,
+    }))
+}
+
+#[handler]
+async fn enable_post(
+    req: &Request,
+    web::Path((service, tag)): web::Path<(String, String)>,
+    Data(token): Data<&AdminToken>,
+    Data(routing): Data<&RoutingTable>,
+    Data(health): Data<&HealthMonitor>,
+) -> poem::Result<Json<Value>> {
+    authorize(req, token)?;
+    let tag = configured_tag(routing, &service, &tag)?;
+    if health.enable(&service, &tag) {
+        tracing::info!("Enabled {} for {} again", service, tag);
+    }
+    Ok(Json(
+        json!({ "service": service, "tag": tag, "enabled": true }),
+    ))
+}
+
+/// The backends' health probes and circuits, and the disabled languages.
+#[handler]
+async fn backends_get(
+    req: &Request,
+    Data(token): Data<&AdminToken>,
+    Data(routing): Data<&RoutingTable>,
+    Data(health): Data<&HealthMonitor>,
+) -> poem::Result<Json<Value>> {
+    authorize(req, token)?;
+    let circuits = routing
+        .snapshot()
+        .breakers
+        .as_ref()
+        .map(|breakers| breakers.snapshot())
+        .unwrap_or_default();
+    Ok(Json(json!({
+        "backends": health.snapshot(),
+        "circuits": circuits,
+        "disabled": health.disabled_languages(),
+    })))
+}
+
+/// `tag` of `service` in the current configuration, resolving aliases.
+fn configured_tag(routing: &RoutingTable, service: &str, tag: &str) -> Result<String, Problem> {
+    let languages = &routing.snapshot().languages;
+    let tag = languages.canonical_tag(service, tag);
+    match languages.language_name(service, &tag) {
+        Some(_) => Ok(tag),
+        None => Err(Problem::new(StatusCode::NOT_FOUND, "Unknown language")
+            .detail(format!("No {} is configured for {}", service, tag))),
+    }
+}
+
+/// Middleware answering requests for languages disabled through the admin
+/// API with a 503. Runs after aliases are resolved.
+#[derive(Clone)]
+pub struct DisabledLanguages(pub HealthMonitor);
+
+impl<E: Endpoint> Middleware<E> for DisabledLanguages {
+    type Output = DisabledLanguagesEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        DisabledLanguagesEndpoint {
+            inner: ep,
+            health: self.0.clone(),
+        }
+    }
+}
+
+pub struct DisabledLanguagesEndpoint<E> {
+    inner: E,
+    health: HealthMonitor,
+}
+
+impl<E: Endpoint> Endpoint for DisabledLanguagesEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> poem::Result<Response> {
+        let mut segments = req.uri().path().trim_start_matches('/').split('/');
+        let disabled = match (segments.next(), segments.next()) {
+            (Some(service), Some(tag)) => self.health.disabled(service, tag),
+            _ => None,
+        };
+        let Some(disabled) = disabled else {
+            return Ok(self.inner.call(req).await?.into_response());
+        };
+
+        let mut response = Problem::new(StatusCode::SERVICE_UNAVAILABLE, "Language disabled")
+            .detail(format!(
+                "The {} service for {} is temporarily disabled",
+                disabled.service, disabled.tag
+            ))
+            .extension("reason", "disabled")
+            .into_response();
+        if let Some(remaining) = disabled.remaining {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(remaining));
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,138 @@
+//! Tag aliases in request paths. `/grammar/sme` is served as `/grammar/se`
+//! when `sme` is one of `grammar.se`'s `aliases`, so both ISO 639-1 and
+//! 639-3 tags reach the same backend. `/check/:tag` takes the speller's and
+//! grammar checker's aliases, `/demo/:tag` those of every category.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use poem::{http::Uri, Endpoint, Middleware, Request, Result};
+
+use crate::config::LanguagesConfig;
+use crate::services::ServiceRegistry;
+
+/// Alias to tag by first path segment.
+#[derive(Debug, Clone, Default)]
+pub struct TagAliases(HashMap<String, BTreeMap<String, String>>);
+
+impl TagAliases {
+    pub fn new(languages: &LanguagesConfig, services: &ServiceRegistry) -> Self {
+        let names: Vec<&str> = services.iter().map(|kind| kind.name()).collect();
+        let mut prefixes: HashMap<String, BTreeMap<String, String>> = names
+            .iter()
+            .map(|name| (name.to_string(), languages.aliases(name)))
+            .collect();
+        prefixes.insert(
+            "check".to_string(),
+            merged(languages, &["speller", "grammar"]),
+        );
+        prefixes.insert("demo".to_string(), merged(languages, &names));
+        prefixes.retain(|_, aliases| !aliases.is_empty());
+        Self(prefixes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `path` with an aliased tag replaced by its configured tag.
+    fn resolve(&self, path: &str) -> Option<String> {
+        let mut segments = path.trim_start_matches('/').splitn(3, '/');
+        let prefix = segments.next()?;
+        let tag = self.0.get(prefix)?.get(segments.next()?)?;
+        Some(match segments.next() {
+            Some(rest) => format!("/{}/{}/{}", prefix, tag, rest),
+            None => format!("/{}/{}", prefix, tag),
+        })
+    }
+}
+
+/// The aliases of `services`, leaving out any that another of them
+/// configures as a tag of its own, as they'd be ambiguous.
+fn merged(languages: &LanguagesConfig, services: &[&str]) -> BTreeMap<String, String> {
+    let tags: HashSet<String> = services
+        .iter()
+        .filter_map(|service| configured_tags(languages, service))
+        .flatten()
+        .collect();
+    let mut aliases = BTreeMap::new();
+    for service in services {
+        for (alias, tag) in languages.aliases(service) {
+            if !tags.contains(&alias) {
+                aliases.entry(alias).or_insert(tag);
+            }
+        }
+    }
+    aliases
+}
+
+fn configured_tags(languages: &LanguagesConfig, service: &str) -> Option<Vec<String>> {
+    let tags = match service {
+        "grammar" => languages.grammar.keys(),
+        "speller" => languages.speller.keys(),
+        "hyphenation" => languages.hyphenation.keys(),
+        "tts" => return Some(languages.tts.keys().cloned().collect()),
+        other => languages.custom.get(other)?.keys(),
+    };
+    Some(tags.cloned().collect())
+}
+
+impl<E: Endpoint> Middleware<E> for TagAliases {
+    type Output = TagAliasesEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        TagAliasesEndpoint {
+            inner: ep,
+            aliases: self.clone(),
+        }
+    }
+}
+
+pub struct TagAliasesEndpoint<E> {
+    inner: E,
+    aliases: TagAliases,
+}
+
+impl<E: Endpoint> Endpoint for TagAliasesEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        if let Some(path) = self.aliases.resolve(req.uri().path()) {
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path,
+            };
+            let mut parts = req.uri().clone().into_parts();
+            if let Ok(path_and_query) = path_and_query.parse() {
+                parts.path_and_query = Some(path_and_query);
+                if let Ok(uri) = Uri::from_parts(parts) {
+                    *req.uri_mut() = uri;
+                }
+            }
+        }
+        self.inner.call(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliased_tags_are_replaced() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().aliases = vec!["sme".to_string()];
+        languages.speller.get_mut("se").unwrap().aliases = vec!["sme".to_string()];
+        let aliases = TagAliases::new(&languages, &ServiceRegistry::builtin());
+
+        let resolve = |path| aliases.resolve(path);
+        assert_eq!(resolve("/grammar/sme").as_deref(), Some("/grammar/se"));
+        assert_eq!(
+            resolve("/grammar/sme/stream").as_deref(),
+            Some("/grammar/se/stream")
+        );
+        assert_eq!(resolve("/check/sme").as_deref(), Some("/check/se"));
+        assert_eq!(resolve("/demo/sme").as_deref(), Some("/demo/se"));
+        assert_eq!(resolve("/grammar/se"), None);
+        assert_eq!(resolve("/hyphenation/sme"), None);
+    }
+}
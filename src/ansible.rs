@@ -0,0 +1,228 @@
+//! An Ansible role and playbook for standing up a gateway host.
+//!
+//! The role installs the binary, the nginx files from [`crate::nginx`] and a
+//! systemd unit, and creates the directories the config refers to. Hosts and
+//! paths are variables in `defaults/main.yml`, so the output works with any
+//! inventory.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::LanguagesConfig;
+use crate::nginx;
+use crate::services::ServiceRegistry;
+
+pub const ROLE: &str = "divvun_worker_static";
+
+const PLAYBOOK: &str = r#"# Generated by divvun-worker-static. Do not edit.
+#
+# nginx must include the generated locations in the server block fronting
+# the gateway:
+#
+#   include divvun-worker-static/locations.conf;
+#   location / { proxy_pass http://127.0.0.1:4000; include proxy-headers.conf; }
+- hosts: divvun_worker_static
+  become: true
+  roles:
+    - divvun_worker_static
+"#;
+
+const HANDLERS: &str = r#"- name: Restart divvun-worker-static
+  ansible.builtin.systemd:
+    name: divvun-worker-static
+    state: restarted
+    daemon_reload: true
+
+- name: Reload nginx
+  ansible.builtin.service:
+    name: nginx
+    state: reloaded
+"#;
+
+const UNIT: &str = r#"[Unit]
+Description=Divvun language services gateway
+After=network.target
+
+[Service]
+User={{ divvun_worker_static_user }}
+WorkingDirectory={{ divvun_worker_static_dir }}
+ExecStart={{ divvun_worker_static_dir }}/divvun-worker-static serve --host 127.0.0.1 --port {{ divvun_worker_static_port }}
+Restart=on-failure
+
+[Install]
+WantedBy=multi-user.target
+"#;
+
+/// The role's files, relative to the output directory.
+pub fn generate_role(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+) -> Vec<(PathBuf, String)> {
+    let role = Path::new("roles").join(ROLE);
+    vec![
+        (PathBuf::from("playbook.yml"), PLAYBOOK.to_string()),
+        (role.join("defaults/main.yml"), defaults()),
+        (role.join("tasks/main.yml"), tasks(languages)),
+        (role.join("handlers/main.yml"), HANDLERS.to_string()),
+        (
+            role.join("files/locations.conf"),
+            nginx::generate_nginx_config(languages, services),
+        ),
+        (
+            role.join("files/proxy-headers.conf"),
+            nginx::generate_proxy_headers_config(),
+        ),
+        (
+            role.join("templates/divvun-worker-static.service.j2"),
+            UNIT.to_string(),
+        ),
+    ]
+}
+
+fn defaults() -> String {
+    r#"# Binary on the control node to install
+divvun_worker_static_binary: target/release/divvun-worker-static
+divvun_worker_static_user: divvun
+divvun_worker_static_dir: /opt/divvun-worker-static
+divvun_worker_static_port: 4000
+nginx_conf_dir: /etc/nginx
+"#
+    .to_string()
+}
+
+/// Directories the config points at, which must exist and be writable.
+fn directories(languages: &LanguagesConfig) -> Vec<String> {
+    let mut dirs = Vec::new();
+    if let Some(static_dir) = &languages.config.static_dir {
+        dirs.push(static_dir.clone());
+    }
+    if let Some(log) = &languages.config.log {
+        if let Some(dir) = log.file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            dirs.push(dir.display().to_string());
+        }
+    }
+    dirs
+}
+
+fn tasks(languages: &LanguagesConfig) -> String {
+    let mut tasks = String::from(
+        r#"- name: Create the service user
+  ansible.builtin.user:
+    name: "{{ divvun_worker_static_user }}"
+    system: true
+    create_home: false
+
+- name: Create the install directory
+  ansible.builtin.file:
+    path: "{{ divvun_worker_static_dir }}"
+    state: directory
+    mode: "0755"
+"#,
+    );
+
+    let dirs = directories(languages);
+    if !dirs.is_empty() {
+        tasks.push_str(
+            r#"
+- name: Create the directories the config uses
+  ansible.builtin.file:
+    path: "{{ item }}"
+    state: directory
+    owner: "{{ divvun_worker_static_user }}"
+    mode: "0750"
+  loop:
+"#,
+        );
+        for dir in dirs {
+            // Relative paths resolve against the service's working directory
+            let dir = if Path::new(&dir).is_absolute() {
+                dir
+            } else {
+                format!(
+                    "{{{{ divvun_worker_static_dir }}}}/{}",
+                    dir.trim_start_matches("./")
+                )
+            };
+            tasks.push_str(&format!("    - \"{}\"\n", dir));
+        }
+    }
+
+    tasks.push_str(
+        r#"
+- name: Install the binary
+  ansible.builtin.copy:
+    src: "{{ divvun_worker_static_binary }}"
+    dest: "{{ divvun_worker_static_dir }}/divvun-worker-static"
+    mode: "0755"
+  notify: Restart divvun-worker-static
+
+- name: Install the systemd unit
+  ansible.builtin.template:
+    src: divvun-worker-static.service.j2
+    dest: /etc/systemd/system/divvun-worker-static.service
+    mode: "0644"
+  notify: Restart divvun-worker-static
+
+- name: Create the nginx directory
+  ansible.builtin.file:
+    path: "{{ nginx_conf_dir }}/divvun-worker-static"
+    state: directory
+    mode: "0755"
+
+- name: Install the nginx locations
+  ansible.builtin.copy:
+    src: locations.conf
+    dest: "{{ nginx_conf_dir }}/divvun-worker-static/locations.conf"
+    mode: "0644"
+  notify: Reload nginx
+
+# Included by every location relative to the nginx configuration directory
+- name: Install the nginx proxy headers
+  ansible.builtin.copy:
+    src: proxy-headers.conf
+    dest: "{{ nginx_conf_dir }}/proxy-headers.conf"
+    mode: "0644"
+  notify: Reload nginx
+
+- name: Start divvun-worker-static
+  ansible.builtin.systemd:
+    name: divvun-worker-static
+    state: started
+    enabled: true
+    daemon_reload: true
+"#,
+    );
+    tasks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logfile::{LogConfig, Rotation};
+
+    #[test]
+    fn role_carries_the_nginx_files_and_config_directories() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.static_dir = Some("./static".to_string());
+        languages.config.log = Some(LogConfig {
+            file: PathBuf::from("/var/log/divvun/gateway.log"),
+            rotation: Rotation::Daily,
+            max_size: None,
+            keep: 7,
+        });
+        let files = generate_role(&languages, &ServiceRegistry::builtin());
+        let file = |path: &str| {
+            files
+                .iter()
+                .find(|(name, _)| name == Path::new(path))
+                .map(|(_, contents)| contents.as_str())
+                .unwrap()
+        };
+
+        assert!(file("roles/divvun_worker_static/files/locations.conf")
+            .contains("location /grammar/se {"));
+        let tasks = file("roles/divvun_worker_static/tasks/main.yml");
+        assert!(tasks.contains(
+            "  loop:\n    - \"{{ divvun_worker_static_dir }}/static\"\n    - \"/var/log/divvun\"\n"
+        ));
+    }
+}
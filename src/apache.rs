@@ -0,0 +1,157 @@
+//! Apache httpd configuration generation, equivalent to the nginx locations
+//! for deployments fronted by Apache.
+//!
+//! The output needs `mod_proxy`, `mod_proxy_http`, `mod_rewrite` and
+//! `mod_headers`, plus `mod_proxy_balancer` and the `mod_lbmethod_*` module
+//! of the balancing method for languages with several backend instances,
+//! and is meant to be included inside a virtual host:
+//!
+//! ```text
+//! <VirtualHost *:443>
+//!     ServerName api.example.org
+//!     Include /etc/apache2/divvun/locations.apache.conf
+//! </VirtualHost>
+//! ```
+
+use crate::balance::Balance;
+use crate::config::{Instance, LanguagesConfig};
+use crate::services::{Location, ServiceRegistry};
+
+/// Render one `<Location>` section per configured service, language and voice,
+/// after a `<Proxy balancer://…>` section for each language with several
+/// backend instances.
+pub fn generate_apache_config(languages: &LanguagesConfig, services: &ServiceRegistry) -> String {
+    let sections = services
+        .proxy_locations(languages)
+        .into_iter()
+        .map(|location| {
+            let headers = languages.response_headers(&location.path);
+            generate_location_section(&location, &headers)
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "# Generated by divvun-worker-static. Include inside a VirtualHost.\n\n{}\n",
+        sections.join("\n\n")
+    )
+}
+
+fn generate_location_section(location: &Location, headers: &[(String, String)]) -> String {
+    let servers = location.servers();
+    let (balancer, backend) = match servers.as_slice() {
+        [server] => (String::new(), format!("http://{}/", server)),
+        servers => {
+            let name = location.path.trim_start_matches('/').replace('/', "-");
+            (
+                format!(
+                    "{}\n\n",
+                    generate_balancer_section(&name, servers, location.balance)
+                ),
+                format!("balancer://{}/", name),
+            )
+        }
+    };
+    // ProxyPass can't add a query string, so those locations are rewritten
+    // to the backend URL and proxied by mod_rewrite instead
+    let proxy = if location.query.is_empty() {
+        format!("    ProxyPass \"{}\"\n", backend)
+    } else {
+        format!(
+            "    RewriteEngine On\n    RewriteRule \"^\" \"{}?{}\" [P]\n",
+            backend,
+            location.query_string()
+        )
+    };
+    let headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("    Header always set {} \"{}\"\n", name, value))
+        .collect();
+    format!(
+        "{}<Location \"{}\">\n{}    ProxyPassReverse \"{}\"\n    \
+         RequestHeader set X-Real-IP \"%{{REMOTE_ADDR}}s\"\n{}</Location>",
+        balancer, location.path, proxy, backend, headers
+    )
+}
+
+/// A balancer spreading requests over `servers` like the gateway would,
+/// leaving out a member that failed for a while.
+fn generate_balancer_section(name: &str, servers: &[Instance], balance: Balance) -> String {
+    let members: String = servers
+        .iter()
+        .map(|server| {
+            let loadfactor = match server.weight {
+                1 => String::new(),
+                weight => format!(" loadfactor={}", weight),
+            };
+            format!(
+                "    BalancerMember \"http://{}\"{} retry=10\n",
+                server, loadfactor
+            )
+        })
+        .collect();
+    let method = match balance {
+        Balance::RoundRobin => "byrequests",
+        Balance::LeastOutstanding => "bybusyness",
+    };
+    format!(
+        "<Proxy \"balancer://{}\">\n{}    ProxySet lbmethod={}\n</Proxy>",
+        name, members, method
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_sections_mirror_the_nginx_locations() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.cache = vec![crate::cache::CacheRoute {
+            path: "/tts".to_string(),
+            max_age: Some(3600),
+            immutable: false,
+            no_store: false,
+        }];
+        let config = generate_apache_config(&languages, &ServiceRegistry::builtin());
+        let section = |path: &str| {
+            config
+                .split("\n\n")
+                .find(|section| section.starts_with(&format!("<Location \"{}\">", path)))
+                .unwrap()
+        };
+
+        assert_eq!(
+            section("/grammar/se"),
+            "<Location \"/grammar/se\">\n    ProxyPass \"http://127.0.0.1:10000/\"\n    \
+             ProxyPassReverse \"http://127.0.0.1:10000/\"\n    \
+             RequestHeader set X-Real-IP \"%{REMOTE_ADDR}s\"\n</Location>"
+        );
+        let tts = section("/tts/smj/sigga");
+        assert!(tts.contains(
+            "    RewriteRule \"^\" \"http://127.0.0.1:40001/?language=2&speaker=3\" [P]\n"
+        ));
+        assert!(tts.contains("    Header always set Cache-Control \"max-age=3600\"\n"));
+    }
+
+    #[test]
+    fn instances_are_members_of_a_balancer() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().ports = vec![
+            Instance::local(10000),
+            Instance {
+                host: "10.0.1.4".to_string(),
+                port: 10000,
+                weight: 2,
+            },
+        ];
+        let config = generate_apache_config(&languages, &ServiceRegistry::builtin());
+
+        assert!(config.contains(
+            "<Proxy \"balancer://grammar-se\">\n    \
+             BalancerMember \"http://127.0.0.1:10000\" retry=10\n    \
+             BalancerMember \"http://10.0.1.4:10000\" loadfactor=2 retry=10\n    \
+             ProxySet lbmethod=bybusyness\n</Proxy>\n\n\
+             <Location \"/grammar/se\">\n    ProxyPass \"balancer://grammar-se/\"\n    \
+             ProxyPassReverse \"balancer://grammar-se/\"\n"
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+//! Opt-in normalization of apostrophe look-alikes.
+//!
+//! Sámi orthographies use modifier letters such as ʼ (U+02BC) or ʹ (U+02B9)
+//! that users often type as `'`, `’` or `´`. For languages configured with
+//! an `apostrophe`, every variant is replaced by that character before
+//! checking, and the response is mapped back to the variant the user typed
+//! so suggestions can be applied as-is.
+
+use serde_json::Value;
+
+const VARIANTS: &[char] = &['\'', '´', 'ʹ', 'ʼ', '’', '′'];
+
+/// Replace every apostrophe variant in `text` with `canonical`. Also returns
+/// the variant the user typed most often, unless that is `canonical` itself.
+pub fn normalize(text: &str, canonical: char) -> (String, Option<char>) {
+    let mut counts = [0usize; VARIANTS.len()];
+    let normalized = text
+        .chars()
+        .map(|c| match VARIANTS.iter().position(|v| *v == c) {
+            Some(i) => {
+                counts[i] += 1;
+                canonical
+            }
+            None => c,
+        })
+        .collect();
+
+    let preferred = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .max_by_key(|(_, count)| **count)
+        .map(|(i, _)| VARIANTS[i])
+        .filter(|variant| *variant != canonical);
+    (normalized, preferred)
+}
+
+/// Replace `canonical` with `preferred` in every string of `value`.
+pub fn restore(value: &mut Value, canonical: char, preferred: char) {
+    match value {
+        Value::String(text) if text.contains(canonical) => {
+            *text = text.replace(canonical, &preferred.to_string());
+        }
+        Value::Array(items) => {
+            for item in items {
+                restore(item, canonical, preferred);
+            }
+        }
+        Value::Object(object) => {
+            for value in object.values_mut() {
+                restore(value, canonical, preferred);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn variants_are_normalized_and_restored() {
+        let (text, preferred) = normalize("sää'mǩiõll ja ’ ja '", 'ʹ');
+        assert_eq!(text, "sääʹmǩiõll ja ʹ ja ʹ");
+        assert_eq!(preferred, Some('\''));
+
+        let mut response = json!({ "errs": [{ "suggestions": ["sääʹmǩiõll"] }] });
+        restore(&mut response, 'ʹ', '\'');
+        assert_eq!(
+            response,
+            json!({ "errs": [{ "suggestions": ["sää'mǩiõll"] }] })
+        );
+    }
+
+    #[test]
+    fn canonical_input_needs_no_restoring() {
+        assert_eq!(
+            normalize("sääʹmǩiõll", 'ʹ'),
+            ("sääʹmǩiõll".to_string(), None)
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! The CSS and JavaScript of the docs page, served under `/assets/`.
+//!
+//! The files in `assets/` are embedded in the binary. Pages link them with
+//! the [`version`] of the embedded set in the query, which browsers may cache
+//! for good; other requests revalidate with an `ETag`. `serve --assets-dir`
+//! reads the files from a directory instead, so the UI can be worked on
+//! without rebuilding, and sends a `.br` or `.gz` file next to an asset to
+//! clients accepting that encoding.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use poem::{
+    handler,
+    http::{header, StatusCode},
+    web::Path,
+    IntoResponse, Request, Response,
+};
+
+use crate::etag;
+use crate::problem::Problem;
+
+const EMBEDDED: &[(&str, &[u8])] = &[
+    ("docs.css", include_bytes!("../assets/docs.css")),
+    ("docs.js", include_bytes!("../assets/docs.js")),
+];
+
+/// Encodings of precompressed files, by preference.
+const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+/// Where the assets are read from, `.data()` for [`asset_get`]; without
+/// one, the embedded files are served.
+#[derive(Debug, Clone, Default)]
+pub struct Assets {
+    dir: Option<PathBuf>,
+}
+
+impl Assets {
+    pub fn embedded() -> Self {
+        Self::default()
+    }
+
+    pub fn from_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+        }
+    }
+
+    /// Fail if the assets are to come from a directory that isn't one.
+    pub fn check(&self) -> anyhow::Result<()> {
+        match &self.dir {
+            Some(dir) if !dir.is_dir() => {
+                anyhow::bail!("assets dir {} is not a directory", dir.display())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The asset `name` and its content encoding, if any.
+    fn load(&self, name: &str, accept_encoding: &str) -> Option<(Vec<u8>, Option<&'static str>)> {
+        let Some(dir) = &self.dir else {
+            return EMBEDDED
+                .iter()
+                .find(|(embedded, _)| *embedded == name)
+                .map(|(_, body)| (body.to_vec(), None));
+        };
+        let path = dir.join(name);
+        let precompressed = ENCODINGS
+            .iter()
+            .filter(|(encoding, _)| accepts(accept_encoding, encoding))
+            .find_map(|(encoding, extension)| {
+                let mut compressed = path.clone().into_os_string();
+                compressed.push(format!(".{}", extension));
+                let body = std::fs::read(compressed).ok()?;
+                Some((body, Some(*encoding)))
+            });
+        precompressed.or_else(|| Some((std::fs::read(&path).ok()?, None)))
+    }
+}
+
+/// A fingerprint of the embedded assets, for the `v` query of their URLs.
+pub fn version() -> &'static str {
+    static VERSION: OnceLock<String> = OnceLock::new();
+    VERSION.get_or_init(|| format!("{:016x}", hash(EMBEDDED)))
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub(crate) fn accepts(accept_encoding: &str, encoding: &str) -> bool {
+    accept_encoding.split(',').any(|accepted| {
+        let mut params = accepted.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let refused = params.any(|param| matches!(param, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+        name.eq_ignore_ascii_case(encoding) && !refused
+    })
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Names of files under the assets directory: no absolute paths, `..` or
+/// hidden files.
+fn is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains('\\')
+        && name
+            .split('/')
+            .all(|segment| !segment.is_empty() && !segment.starts_with('.'))
+}
+
+#[handler]
+pub(crate) async fn asset_get(req: &Request, Path(name): Path<String>) -> Response {
+    let assets = req.data::<Assets>().cloned().unwrap_or_default();
+    let accept_encoding = req
+        .header(header::ACCEPT_ENCODING)
+        .unwrap_or_default()
+        .to_string();
+    let loaded = match is_valid(&name) {
+        true => assets.load(&name, &accept_encoding),
+        false => None,
+    };
+    let Some((body, encoding)) = loaded else {
+        return Problem::new(StatusCode::NOT_FOUND, "Unknown asset")
+            .detail(format!("There is no asset {}", name))
+            .into_response();
+    };
+
+    let etag = format!("\"{:016x}\"", hash((&body, encoding)));
+    let versioned = assets.dir.is_none()
+        && req.uri().query().is_some_and(|query| {
+            query
+                .split('&')
+                .any(|pair| pair == format!("v={}", version()))
+        });
+    let cache_control = match versioned {
+        true => "public, max-age=31536000, immutable",
+        false => "no-cache",
+    };
+    let response = match req
+        .header(header::IF_NONE_MATCH)
+        .is_some_and(|header| etag::matches(header, &etag))
+    {
+        true => Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .finish(),
+        false => {
+            let response = Response::builder().header(header::CONTENT_TYPE, content_type(&name));
+            match encoding {
+                Some(encoding) => response.header(header::CONTENT_ENCODING, encoding),
+                None => response,
+            }
+            .body(body)
+        }
+    };
+    let response = response
+        .with_header(header::ETAG, etag)
+        .with_header(header::CACHE_CONTROL, cache_control);
+    match assets.dir.is_some() {
+        true => response
+            .with_header(header::VARY, "Accept-Encoding")
+            .into_response(),
+        false => response.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precompressed_files_are_sent_to_clients_accepting_them() {
+        let dir = std::env::temp_dir().join(format!("assets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("docs.css"), "body {}").unwrap();
+        std::fs::write(dir.join("docs.css.gz"), "gzipped").unwrap();
+        let assets = Assets::from_dir(&dir);
+
+        assert_eq!(
+            assets.load("docs.css", "gzip, deflate, br"),
+            Some((b"gzipped".to_vec(), Some("gzip")))
+        );
+        assert_eq!(
+            assets.load("docs.css", "br, gzip;q=0"),
+            Some((b"body {}".to_vec(), None))
+        );
+        assert_eq!(assets.load("docs.js", ""), None);
+        assert!(!is_valid("../languages.toml") && !is_valid("/etc/passwd"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+//! API keys for the service endpoints, enabled by `[config.auth]`:
+//!
+//! ```toml
+//! [[config.auth.keys]]
+//! name = "reading-app"
+//! key = "change-me"
+//! scopes = ["tts"]
+//! ```
+//!
+//! Clients send a key as `Authorization: Bearer <key>` or `X-Api-Key: <key>`.
+//! Only paths under a service category, the LanguageTool API on top of the
+//! grammar checkers and the combined spelling and grammar check need one;
+//! the index, health and documentation pages stay public.
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use poem::{
+    http::{header, Method, StatusCode},
+    Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::problem::Problem;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub keys: Vec<ApiKey>,
+    /// A TOML file with more `[[keys]]`, so keys can be kept out of the
+    /// main configuration.
+    #[serde(default)]
+    pub keys_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// Who the key was given to, for the logs.
+    pub name: String,
+    pub key: String,
+    /// Service categories the key may call; all of them if empty.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct KeysFile {
+    #[serde(default)]
+    keys: Vec<ApiKey>,
+}
+
+impl AuthConfig {
+    /// The configured keys and those in the keys file.
+    pub fn load_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        let mut keys = self.keys.clone();
+        if let Some(path) = &self.keys_file {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("can't read API keys from {}", path.display()))?;
+            let file: KeysFile = toml::from_str(&content)
+                .with_context(|| format!("can't parse API keys in {}", path.display()))?;
+            keys.extend(file.keys);
+        }
+        Ok(keys)
+    }
+}
+
+impl ApiKey {
+    fn allows(&self, service: &str) -> bool {
+        self.scopes.is_empty() || self.scopes.iter().any(|scope| scope == service)
+    }
+}
+
+/// Compare every byte, so the time taken doesn't reveal how much of a secret
+/// matched.
+pub(crate) fn constant_time_eq(given: &str, secret: &str) -> bool {
+    given.len() == secret.len()
+        && given
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The key a request was sent with.
+fn request_key(req: &Request) -> Option<&str> {
+    req.header(header::AUTHORIZATION)
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| req.header("X-Api-Key"))
+}
+
+/// Middleware requiring a key with the right scope for requests to
+/// `services`' paths.
+#[derive(Default)]
+pub struct ApiKeyAuth {
+    pub keys: Vec<ApiKey>,
+    /// Names of the service categories, the first path segment of their
+    /// endpoints.
+    pub services: Vec<String>,
+}
+
+impl<E: Endpoint> Middleware<E> for ApiKeyAuth {
+    type Output = ApiKeyAuthEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ApiKeyAuthEndpoint {
+            inner: ep,
+            keys: self.keys.clone(),
+            services: self.services.clone(),
+        }
+    }
+}
+
+pub struct ApiKeyAuthEndpoint<E> {
+    inner: E,
+    keys: Vec<ApiKey>,
+    services: Vec<String>,
+}
+
+impl<E> ApiKeyAuthEndpoint<E> {
+    fn authorize(&self, req: &Request) -> std::result::Result<(), Problem> {
+        let services: &[&str] = match req.uri().path().trim_start_matches('/').split('/').next() {
+            Some("v2") => &["grammar"],
+            Some("check") => &["speller", "grammar"],
+            Some(service) if self.services.iter().any(|s| s == service) => &[service],
+            _ => return Ok(()),
+        };
+        // Preflights carry no credentials
+        if req.method() == Method::OPTIONS {
+            return Ok(());
+        }
+
+        let key = request_key(req).and_then(|given| {
+            self.keys
+                .iter()
+                .find(|key| constant_time_eq(given, &key.key))
+        });
+        let Some(key) = key else {
+            return Err(Problem::new(StatusCode::UNAUTHORIZED, "Unauthorized")
+                .detail("A valid API key is required, as a bearer token or X-Api-Key header"));
+        };
+        if let Some(service) = services.iter().find(|service| !key.allows(service)) {
+            tracing::debug!("API key {} is not allowed to call {}", key.name, service);
+            return Err(Problem::new(StatusCode::FORBIDDEN, "Forbidden")
+                .detail(format!("This API key can't call {}", service)));
+        }
+        Ok(())
+    }
+}
+
+impl<E: Endpoint> Endpoint for ApiKeyAuthEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        if let Err(problem) = self.authorize(&req) {
+            let mut response = problem.into_response();
+            if response.status() == StatusCode::UNAUTHORIZED {
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    header::HeaderValue::from_static("Bearer"),
+                );
+            }
+            return Ok(response);
+        }
+        self.inner.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{endpoint::make, test::TestClient, EndpointExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn service_paths_need_a_key_in_scope() {
+        let endpoint = make(|_| async { "done" }).with(ApiKeyAuth {
+            keys: vec![ApiKey {
+                name: "reader".to_string(),
+                key: "secret".to_string(),
+                scopes: vec!["tts".to_string()],
+            }],
+            services: vec!["grammar".to_string(), "tts".to_string()],
+        });
+        let client = TestClient::new(endpoint);
+
+        client.get("/health").send().await.assert_status_is_ok();
+
+        let missing = client.post("/tts/se/biret").send().await;
+        missing.assert_status(StatusCode::UNAUTHORIZED);
+        missing.assert_header(header::WWW_AUTHENTICATE, "Bearer");
+        client
+            .post("/tts/se/biret")
+            .header("X-Api-Key", "wrong")
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        client
+            .post("/tts/se/biret")
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .send()
+            .await
+            .assert_status_is_ok();
+        client
+            .post("/grammar/se")
+            .header("X-Api-Key", "secret")
+            .send()
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,292 @@
+//! Client-side load balancing for languages with several backend instances
+//! (`ports`). The gateway picks an instance per request, by weighted round
+//! robin or by the fewest requests in flight per unit of weight, and leaves
+//! out instances that keep failing for a while, as the generated nginx
+//! upstreams do.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Instance;
+
+/// Failures in a row that take an instance out of rotation.
+const MAX_FAILS: u32 = 3;
+/// How long an instance stays out of rotation.
+const FAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Balance {
+    /// Each instance in turn, as often as its weight says.
+    RoundRobin,
+    /// The instance with the fewest requests in flight for its weight.
+    #[default]
+    LeastOutstanding,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Slot {
+    instance: Instance,
+    outstanding: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+impl Slot {
+    fn is_up(&self, now: Instant) -> bool {
+        let health = self.health.lock().unwrap();
+        health.down_until.is_none_or(|until| until <= now)
+    }
+}
+
+/// Spreads the requests of one location over its instances.
+#[derive(Debug)]
+pub struct Balancer {
+    strategy: Balance,
+    slots: Vec<Slot>,
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    pub fn new(instances: &[Instance], strategy: Balance) -> Self {
+        Self {
+            strategy,
+            slots: instances
+                .iter()
+                .map(|instance| Slot {
+                    instance: instance.clone(),
+                    outstanding: AtomicUsize::new(0),
+                    health: Mutex::default(),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pick the instance for a request, which counts as in flight until the
+    /// [`Pick`] is dropped. Instances out of rotation are only picked when
+    /// all of them are.
+    pub fn pick(&self) -> Pick<'_> {
+        self.pick_except(&[])
+    }
+
+    /// Pick an instance other than the `failed` ones, such as for a retry,
+    /// unless no other is left.
+    pub fn pick_except(&self, failed: &[Instance]) -> Pick<'_> {
+        let now = Instant::now();
+        let untried: Vec<usize> = (0..self.slots.len())
+            .filter(|&i| !failed.contains(&self.slots[i].instance))
+            .collect();
+        let untried = if untried.is_empty() {
+            (0..self.slots.len()).collect()
+        } else {
+            untried
+        };
+        let up: Vec<usize> = untried
+            .iter()
+            .copied()
+            .filter(|&i| self.slots[i].is_up(now))
+            .collect();
+        let candidates = if up.is_empty() { untried } else { up };
+        let turn = self.next.fetch_add(1, Ordering::Relaxed);
+
+        let index = match self.strategy {
+            Balance::RoundRobin => {
+                let total: usize = candidates
+                    .iter()
+                    .map(|&i| self.slots[i].instance.weight as usize)
+                    .sum();
+                let mut target = turn % total.max(1);
+                *candidates
+                    .iter()
+                    .find(|&&i| {
+                        let weight = self.slots[i].instance.weight as usize;
+                        if target < weight {
+                            return true;
+                        }
+                        target -= weight;
+                        false
+                    })
+                    .unwrap_or(&candidates[0])
+            }
+            Balance::LeastOutstanding => {
+                // Start at a different instance each time to spread ties
+                let start = turn % candidates.len();
+                let load = |i: usize| {
+                    let slot = &self.slots[i];
+                    (
+                        slot.outstanding.load(Ordering::Relaxed),
+                        slot.instance.weight,
+                    )
+                };
+                candidates[start..]
+                    .iter()
+                    .chain(&candidates[..start])
+                    .copied()
+                    .min_by(|&a, &b| {
+                        let ((a_load, a_weight), (b_load, b_weight)) = (load(a), load(b));
+                        (a_load * b_weight as usize).cmp(&(b_load * a_weight as usize))
+                    })
+                    .unwrap_or(0)
+            }
+        };
+        self.slots[index]
+            .outstanding
+            .fetch_add(1, Ordering::Relaxed);
+        Pick {
+            balancer: self,
+            index,
+        }
+    }
+
+    /// Each instance with whether it is in rotation and its requests in
+    /// flight.
+    pub fn status(&self) -> Vec<(&Instance, bool, usize)> {
+        let now = Instant::now();
+        self.slots
+            .iter()
+            .map(|slot| {
+                (
+                    &slot.instance,
+                    slot.is_up(now),
+                    slot.outstanding.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+}
+
+/// An instance picked for a request.
+pub struct Pick<'a> {
+    balancer: &'a Balancer,
+    index: usize,
+}
+
+impl Pick<'_> {
+    pub fn instance(&self) -> &Instance {
+        &self.balancer.slots[self.index].instance
+    }
+
+    /// Count the outcome of a request to the instance: enough failures in a
+    /// row take it out of rotation, and a success resets the count.
+    pub fn record(&self, failed: bool) {
+        let slot = &self.balancer.slots[self.index];
+        let mut health = slot.health.lock().unwrap();
+        if !failed {
+            *health = Health::default();
+            return;
+        }
+        health.failures += 1;
+        if health.failures >= MAX_FAILS {
+            tracing::warn!(
+                "Taking {} out of rotation for {}s after {} failures in a row",
+                slot.instance,
+                FAIL_TIMEOUT.as_secs(),
+                health.failures
+            );
+            health.failures = 0;
+            health.down_until = Some(Instant::now() + FAIL_TIMEOUT);
+        }
+    }
+}
+
+impl Drop for Pick<'_> {
+    fn drop(&mut self) {
+        self.balancer.slots[self.index]
+            .outstanding
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instances(weights: &[u32]) -> Vec<Instance> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| Instance {
+                weight,
+                ..Instance::local(4001 + i as u16)
+            })
+            .collect()
+    }
+
+    fn port(pick: Pick<'_>) -> u16 {
+        pick.instance().port
+    }
+
+    #[test]
+    fn round_robin_follows_the_weights() {
+        let balancer = Balancer::new(&instances(&[2, 1]), Balance::RoundRobin);
+        let ports: Vec<u16> = (0..6).map(|_| port(balancer.pick())).collect();
+        assert_eq!(ports, [4001, 4001, 4002, 4001, 4001, 4002]);
+    }
+
+    #[test]
+    fn least_outstanding_avoids_busy_instances() {
+        let balancer = Balancer::new(&instances(&[1, 1, 2]), Balance::LeastOutstanding);
+        let busy = [balancer.pick(), balancer.pick(), balancer.pick()];
+        let mut ports: Vec<u16> = busy.iter().map(|pick| pick.instance().port).collect();
+        ports.sort();
+        assert_eq!(ports, [4001, 4002, 4003]);
+        // 4003 has one request in flight for twice the weight
+        assert_eq!(port(balancer.pick()), 4003);
+    }
+
+    #[test]
+    fn retries_pick_another_instance() {
+        let balancer = Balancer::new(&instances(&[1, 1, 1]), Balance::RoundRobin);
+        let failed = [Instance::local(4001), Instance::local(4002)];
+        assert!((0..3).all(|_| port(balancer.pick_except(&failed)) == 4003));
+        let all = [failed[0].clone(), failed[1].clone(), Instance::local(4003)];
+        // With every instance failed, any is tried again
+        let mut ports: Vec<u16> = (0..3).map(|_| port(balancer.pick_except(&all))).collect();
+        ports.sort();
+        assert_eq!(ports, [4001, 4002, 4003]);
+    }
+
+    #[test]
+    fn failing_instances_leave_the_rotation() {
+        let balancer = Balancer::new(&instances(&[1, 1]), Balance::RoundRobin);
+        for _ in 0..MAX_FAILS {
+            balancer.pick_port(4001).record(true);
+        }
+        assert!((0..4).all(|_| port(balancer.pick()) == 4002));
+        assert_eq!(
+            balancer
+                .status()
+                .iter()
+                .map(|(_, up, _)| *up)
+                .collect::<Vec<_>>(),
+            [false, true]
+        );
+
+        // With every instance out, they are all tried anyway
+        for _ in 0..MAX_FAILS {
+            balancer.pick_port(4002).record(true);
+        }
+        let ports: Vec<u16> = (0..2).map(|_| port(balancer.pick())).collect();
+        assert!(ports.contains(&4001) && ports.contains(&4002));
+    }
+
+    impl Balancer {
+        fn pick_port(&self, port: u16) -> Pick<'_> {
+            loop {
+                let pick = self.pick();
+                if pick.instance().port == port {
+                    return pick;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! `Cache-Control` policies by path prefix, configured as `[[config.cache]]`.
+//!
+//! The longest matching prefix decides the header of successful responses,
+//! replacing any the handler or backend set. The generated nginx locations
+//! get the same policy as `expires` and `add_header` directives.
+
+use poem::{
+    http::{header, HeaderValue},
+    Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::cors::matches_prefix;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheRoute {
+    pub path: String,
+    /// Seconds clients and proxies may reuse a response.
+    #[serde(default)]
+    pub max_age: Option<u32>,
+    /// The response never changes, so it needn't be revalidated.
+    #[serde(default)]
+    pub immutable: bool,
+    /// Never store responses, e.g. for texts users check.
+    #[serde(default)]
+    pub no_store: bool,
+}
+
+impl CacheRoute {
+    pub fn header_value(&self) -> Option<String> {
+        if self.no_store {
+            return Some("no-store".to_string());
+        }
+        let max_age = self.max_age?;
+        Some(if self.immutable {
+            format!("max-age={}, immutable", max_age)
+        } else {
+            format!("max-age={}", max_age)
+        })
+    }
+
+    /// The policy as nginx directives.
+    pub fn nginx_directives(&self) -> Vec<String> {
+        if self.header_value().is_none() {
+            return Vec::new();
+        }
+        let mut directives = vec!["proxy_hide_header Cache-Control;".to_string()];
+        if self.no_store {
+            directives.push("add_header Cache-Control \"no-store\";".to_string());
+            return directives;
+        }
+        if let Some(max_age) = self.max_age {
+            directives.push(format!("expires {}s;", max_age));
+        }
+        // nginx sends this next to the max-age from `expires`
+        if self.immutable {
+            directives.push("add_header Cache-Control \"immutable\";".to_string());
+        }
+        directives
+    }
+}
+
+/// The policy for `path`, if one matches.
+pub fn policy<'a>(routes: &'a [CacheRoute], path: &str) -> Option<&'a CacheRoute> {
+    routes
+        .iter()
+        .filter(|route| matches_prefix(path, &route.path))
+        .max_by_key(|route| route.path.len())
+}
+
+/// Middleware setting `Cache-Control` on successful responses.
+pub struct CacheControl(pub Vec<CacheRoute>);
+
+impl<E: Endpoint> Middleware<E> for CacheControl {
+    type Output = CacheControlEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CacheControlEndpoint {
+            inner: ep,
+            routes: self.0.clone(),
+        }
+    }
+}
+
+pub struct CacheControlEndpoint<E> {
+    inner: E,
+    routes: Vec<CacheRoute>,
+}
+
+impl<E: Endpoint> Endpoint for CacheControlEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let value = policy(&self.routes, req.uri().path()).and_then(CacheRoute::header_value);
+        let mut response = self.inner.call(req).await?.into_response();
+        if let Some(value) = value {
+            let status = response.status();
+            if status.is_success() || status.is_redirection() {
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    response.headers_mut().insert(header::CACHE_CONTROL, value);
+                }
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_render_for_clients_and_nginx() {
+        #[derive(Deserialize)]
+        struct Config {
+            cache: Vec<CacheRoute>,
+        }
+        let Config { cache: routes } = toml::from_str(
+            r#"
+[[cache]]
+path = "/tts"
+max_age = 31536000
+immutable = true
+
+[[cache]]
+path = "/languages"
+max_age = 300
+
+[[cache]]
+path = "/grammar"
+no_store = true
+"#,
+        )
+        .unwrap();
+
+        let tts = policy(&routes, "/tts/se/biret").unwrap();
+        assert_eq!(
+            tts.header_value().as_deref(),
+            Some("max-age=31536000, immutable")
+        );
+        assert_eq!(
+            tts.nginx_directives(),
+            [
+                "proxy_hide_header Cache-Control;",
+                "expires 31536000s;",
+                "add_header Cache-Control \"immutable\";",
+            ]
+        );
+        assert_eq!(
+            policy(&routes, "/grammar/se").unwrap().nginx_directives(),
+            [
+                "proxy_hide_header Cache-Control;",
+                "add_header Cache-Control \"no-store\";",
+            ]
+        );
+        assert!(policy(&routes, "/speller/se").is_none());
+    }
+}
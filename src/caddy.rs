@@ -0,0 +1,138 @@
+//! Caddyfile generation, equivalent to the nginx locations for deployments
+//! fronted by Caddy.
+//!
+//! The output is meant to be imported inside a site block:
+//!
+//! ```text
+//! api.example.org {
+//!     import locations.caddy
+//! }
+//! ```
+
+use crate::balance::Balance;
+use crate::config::{Instance, LanguagesConfig};
+use crate::services::{Location, ServiceRegistry};
+
+/// Render one `handle` block per configured service, language and voice,
+/// spreading the requests of a language with several backend instances
+/// over all of them.
+pub fn generate_caddy_config(languages: &LanguagesConfig, services: &ServiceRegistry) -> String {
+    let blocks = services
+        .proxy_locations(languages)
+        .into_iter()
+        .map(|location| {
+            let headers = languages.response_headers(&location.path);
+            generate_handle_block(&location, &headers)
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "# Generated by divvun-worker-static. Import inside a site block.\n\n{}\n",
+        blocks.join("\n\n")
+    )
+}
+
+fn generate_handle_block(location: &Location, headers: &[(String, String)]) -> String {
+    let query = location.query_string();
+    let target = if query.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/?{}", query)
+    };
+    let servers = location.servers();
+    let upstreams = servers
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let balancing = if servers.len() > 1 {
+        format!(
+            "\t\tlb_policy {}\n\t\tfail_duration 10s\n\t\tmax_fails 3\n",
+            lb_policy(location.balance, &servers)
+        )
+    } else {
+        String::new()
+    };
+    let headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("\n\t\theader_down {} \"{}\"", name, value))
+        .collect();
+    format!(
+        "handle {} {{\n\trewrite * {}\n\treverse_proxy {} {{\n{}\t\t\
+         header_up X-Real-IP {{remote_host}}{}\n\t}}\n}}",
+        location.path, target, upstreams, balancing, headers
+    )
+}
+
+/// The `lb_policy` spreading requests over `servers` like the gateway would.
+fn lb_policy(balance: Balance, servers: &[Instance]) -> String {
+    match balance {
+        Balance::LeastOutstanding => "least_conn".to_string(),
+        Balance::RoundRobin if servers.iter().all(|server| server.weight == 1) => {
+            "round_robin".to_string()
+        }
+        Balance::RoundRobin => {
+            let weights: Vec<String> = servers
+                .iter()
+                .map(|server| server.weight.to_string())
+                .collect();
+            format!("weighted_round_robin {}", weights.join(" "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_blocks_mirror_the_nginx_locations() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.cache = vec![crate::cache::CacheRoute {
+            path: "/tts".to_string(),
+            max_age: Some(3600),
+            immutable: false,
+            no_store: false,
+        }];
+        let config = generate_caddy_config(&languages, &ServiceRegistry::builtin());
+        let block = |path: &str| {
+            config
+                .split("\n\n")
+                .find(|block| block.starts_with(&format!("handle {} ", path)))
+                .unwrap()
+        };
+
+        assert_eq!(
+            block("/grammar/se"),
+            "handle /grammar/se {\n\trewrite * /\n\treverse_proxy 127.0.0.1:10000 {\n\t\t\
+             header_up X-Real-IP {remote_host}\n\t}\n}"
+        );
+        assert!(block("/tts/smj/sigga").contains("\trewrite * /?language=2&speaker=3\n"));
+        assert!(
+            block("/tts/smj/sigga").contains("\t\theader_down Cache-Control \"max-age=3600\"\n")
+        );
+    }
+
+    #[test]
+    fn instances_share_a_reverse_proxy() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        let se = languages.grammar.get_mut("se").unwrap();
+        se.ports = vec![
+            Instance::local(10000),
+            Instance {
+                host: "10.0.1.4".to_string(),
+                port: 10000,
+                weight: 2,
+            },
+        ];
+        let config = generate_caddy_config(&languages, &ServiceRegistry::builtin());
+        assert!(config.contains(
+            "handle /grammar/se {\n\trewrite * /\n\t\
+             reverse_proxy 127.0.0.1:10000 10.0.1.4:10000 {\n\t\tlb_policy least_conn\n\t\t\
+             fail_duration 10s\n\t\tmax_fails 3\n\t\theader_up X-Real-IP {remote_host}\n\t}\n}"
+        ));
+
+        languages.grammar.get_mut("se").unwrap().balance = Balance::RoundRobin;
+        let config = generate_caddy_config(&languages, &ServiceRegistry::builtin());
+        assert!(config.contains("\t\tlb_policy weighted_round_robin 1 2\n"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Transcoding of request bodies to the UTF-8 the backends expect.
+//!
+//! Some CAT tools still send Latin-1 or Windows-1252, which the checkers
+//! read as garbage. Bodies are decoded using, in order, a byte order mark,
+//! the `charset` of the `Content-Type` and, for bodies that aren't valid
+//! UTF-8, Windows-1252 (the WHATWG reading of Latin-1).
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+/// Decode `body` to UTF-8 without a byte order mark. Returns a description
+/// of the change when one was made, for the `Warning` header.
+pub fn to_utf8(body: Vec<u8>, content_type: Option<&str>) -> (Vec<u8>, Option<String>) {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(&body) {
+        if encoding == UTF_8 {
+            return (
+                body[bom_len..].to_vec(),
+                Some("byte order mark removed".to_string()),
+            );
+        }
+        return transcode(&body[bom_len..], encoding);
+    }
+
+    let declared = content_type
+        .and_then(charset)
+        .and_then(|label| Encoding::for_label(label.as_bytes()));
+    match declared {
+        Some(encoding) if encoding != UTF_8 => transcode(&body, encoding),
+        _ if std::str::from_utf8(&body).is_ok() => (body, None),
+        _ => transcode(&body, WINDOWS_1252),
+    }
+}
+
+fn transcode(body: &[u8], encoding: &'static Encoding) -> (Vec<u8>, Option<String>) {
+    let (text, _) = encoding.decode_without_bom_handling(body);
+    (
+        text.into_owned().into_bytes(),
+        Some(format!("transcoded from {} to UTF-8", encoding.name())),
+    )
+}
+
+fn charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// `content_type` with its `charset` parameter, if any, replaced by UTF-8.
+pub fn utf8_content_type(content_type: &str) -> String {
+    if charset(content_type).is_none() {
+        return content_type.to_string();
+    }
+    content_type
+        .split(';')
+        .map(str::trim)
+        .filter(|param| {
+            !param
+                .split_once('=')
+                .is_some_and(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        })
+        .chain(["charset=utf-8"])
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_utf8_is_read_as_windows_1252() {
+        let (body, warning) = to_utf8(b"{\"text\":\"\xe1 \x93x\x94\"}".to_vec(), None);
+        assert_eq!(String::from_utf8(body).unwrap(), "{\"text\":\"á “x”\"}");
+        assert_eq!(
+            warning.as_deref(),
+            Some("transcoded from windows-1252 to UTF-8")
+        );
+    }
+
+    #[test]
+    fn declared_latin1_and_boms_are_honoured() {
+        let (body, _) = to_utf8(
+            b"\xe1".to_vec(),
+            Some("application/json; charset=ISO-8859-1"),
+        );
+        assert_eq!(body, "á".as_bytes());
+
+        let (body, warning) = to_utf8(b"\xff\xfe\xe1\x00".to_vec(), None);
+        assert_eq!(body, "á".as_bytes());
+        assert_eq!(
+            warning.as_deref(),
+            Some("transcoded from UTF-16LE to UTF-8")
+        );
+    }
+
+    #[test]
+    fn utf8_passes_through() {
+        let (body, warning) = to_utf8("čáđ".as_bytes().to_vec(), Some("application/json"));
+        assert_eq!(body, "čáđ".as_bytes());
+        assert_eq!(warning, None);
+        assert_eq!(
+            utf8_content_type("application/json; charset=latin1"),
+            "application/json; charset=utf-8"
+        );
+    }
+}
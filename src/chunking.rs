@@ -0,0 +1,170 @@
+//! Long TTS texts: split on sentence boundaries into pieces the backend
+//! handles well, and the audio synthesized for the pieces joined again.
+
+use anyhow::{bail, Context};
+
+/// `text` split into chunks of at most `max` characters, ending on sentence
+/// boundaries where possible, then on spaces, and only as a last resort
+/// within a word.
+pub fn split_sentences(text: &str, max: usize) -> Vec<String> {
+    let max = max.max(1);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for sentence in sentences(text) {
+        for piece in pieces(sentence, max) {
+            let length = current.chars().count() + piece.trim_end().chars().count();
+            if !current.is_empty() && length > max {
+                chunks.push(std::mem::take(&mut current).trim().to_string());
+            }
+            current.push_str(piece);
+        }
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current.trim().to_string());
+    }
+    chunks.retain(|chunk| !chunk.is_empty());
+    chunks
+}
+
+/// Sentences with their trailing whitespace: text up to `.`, `!`, `?` or
+/// `…` followed by whitespace, or up to a line break.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let ends = c == '\n'
+            || (matches!(c, '.' | '!' | '?' | '…')
+                && chars.peek().is_some_and(|(_, next)| next.is_whitespace()));
+        if ends {
+            // Keep the whitespace after the sentence with it
+            let mut end = i + c.len_utf8();
+            while let Some((j, next)) = chars.peek().copied() {
+                if !next.is_whitespace() {
+                    break;
+                }
+                end = j + next.len_utf8();
+                chars.next();
+            }
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+/// `sentence` cut at spaces, or within words longer than `max`, into pieces
+/// of at most `max` characters.
+fn pieces(sentence: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = sentence;
+    while rest.chars().count() > max {
+        let limit = rest.char_indices().nth(max).map_or(rest.len(), |(i, _)| i);
+        let cut = match rest[..limit].rfind(char::is_whitespace) {
+            Some(space) if space > 0 => space + 1,
+            _ => limit,
+        };
+        pieces.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    if !rest.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+/// The `fmt ` chunk and sample data of a WAV file.
+fn wav_parts(wav: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        bail!("not a WAV file");
+    }
+    let mut format = None;
+    let mut offset = 12;
+    while offset + 8 <= wav.len() {
+        let id = &wav[offset..offset + 4];
+        let size = u32::from_le_bytes(wav[offset + 4..offset + 8].try_into()?) as usize;
+        let body = offset + 8;
+        // Streamed WAVs leave the data size unset; the data runs to the end
+        let end = body.saturating_add(size).min(wav.len());
+        match id {
+            b"fmt " => format = Some(&wav[offset..end]),
+            b"data" => {
+                let format = format.context("WAV data before its format")?;
+                return Ok((format, &wav[body..end]));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        offset = end + (size % 2);
+    }
+    bail!("WAV file without data")
+}
+
+/// One WAV file with the samples of all of `wavs` in order, which must share
+/// their format.
+pub fn join_wav(wavs: &[Vec<u8>]) -> anyhow::Result<Vec<u8>> {
+    let mut format: Option<&[u8]> = None;
+    let mut data = Vec::new();
+    for wav in wavs {
+        let (wav_format, samples) = wav_parts(wav)?;
+        match format {
+            Some(format) if format != wav_format => bail!("WAV files differ in format"),
+            Some(_) => {}
+            None => format = Some(wav_format),
+        }
+        data.extend_from_slice(samples);
+    }
+    let format = format.context("no WAV files to join")?;
+    let data_size = u32::try_from(data.len()).context("joined WAV is too long")?;
+
+    let mut joined = Vec::with_capacity(20 + format.len() + data.len());
+    joined.extend_from_slice(b"RIFF");
+    joined.extend_from_slice(&(4 + format.len() as u32 + 8 + data_size).to_le_bytes());
+    joined.extend_from_slice(b"WAVE");
+    joined.extend_from_slice(format);
+    if format.len() % 2 == 1 {
+        joined.push(0);
+    }
+    joined.extend_from_slice(b"data");
+    joined.extend_from_slice(&data_size.to_le_bytes());
+    joined.extend_from_slice(&data);
+    Ok(joined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::wav;
+
+    #[test]
+    fn texts_split_on_sentences_then_spaces() {
+        assert_eq!(
+            split_sentences("Bures! Mo manná? Bures boahtin.", 16),
+            ["Bures! Mo manná?", "Bures boahtin."]
+        );
+        assert_eq!(split_sentences("Vers. 1.2 ok", 100), ["Vers. 1.2 ok"]);
+        assert_eq!(
+            split_sentences("Guhkes cealkka mas eai leat čuoggát", 12),
+            ["Guhkes", "cealkka mas", "eai leat", "čuoggát"]
+        );
+        assert_eq!(split_sentences("abcdefgh", 3), ["abc", "def", "gh"]);
+        assert!(split_sentences("  \n ", 10).is_empty());
+    }
+
+    #[test]
+    fn wavs_join_with_a_rewritten_header() {
+        let joined = join_wav(&[wav(&[1, 2]), wav(&[3, 4, 5, 6])]).unwrap();
+        assert_eq!(joined, wav(&[1, 2, 3, 4, 5, 6]));
+
+        let mut streamed = wav(&[7, 8]);
+        streamed[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            join_wav(&[wav(&[1, 2]), streamed]).unwrap(),
+            wav(&[1, 2, 7, 8])
+        );
+        assert!(join_wav(&[b"ID3 mp3".to_vec()]).is_err());
+    }
+}
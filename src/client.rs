@@ -0,0 +1,447 @@
+//! Thin typed API clients generated from an [`ApiSchema`].
+//!
+//! Each client exposes one method per service category taking the route
+//! target (`se`, or `se/biret` for voices) as a closed set of the configured
+//! values, so a client only compiles against languages the gateway serves.
+
+use crate::schema::{ApiSchema, ResponseSchema, SchemaType, ServiceSchema, TypeDef};
+
+const HEADER: &str = "Generated by divvun-worker-static. Do not edit.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ClientLanguage {
+    Typescript,
+    Python,
+    Rust,
+}
+
+pub fn generate_client(schema: &ApiSchema, language: ClientLanguage) -> String {
+    match language {
+        ClientLanguage::Typescript => typescript::generate(schema),
+        ClientLanguage::Python => python::generate(schema),
+        ClientLanguage::Rust => rust::generate(schema),
+    }
+}
+
+/// Name of the type listing a service's route targets, e.g. `GrammarTag`.
+fn tag_type(service: &ServiceSchema) -> String {
+    format!("{}Tag", pascal_case(&service.name))
+}
+
+fn pascal_case(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn snake_case(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+fn camel_case(name: &str) -> String {
+    let pascal = pascal_case(name);
+    let mut chars = pascal.chars();
+    chars
+        .next()
+        .map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
+
+fn quoted_targets(service: &ServiceSchema) -> Vec<String> {
+    service
+        .routes
+        .iter()
+        .map(|route| format!("\"{}\"", route.target))
+        .collect()
+}
+
+mod typescript {
+    use super::*;
+
+    pub(super) fn generate(schema: &ApiSchema) -> String {
+        let types = schema.types.iter().map(interface).collect::<Vec<_>>();
+
+        let tags = schema
+            .services
+            .iter()
+            .map(|service| {
+                format!(
+                    "export type {} = {};",
+                    tag_type(service),
+                    quoted_targets(service).join(" | ")
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let methods = schema.services.iter().map(method).collect::<Vec<_>>();
+
+        format!(
+            r#"// {header}
+
+{types}
+
+{tags}
+
+export class DivvunClient {{
+  constructor(private readonly baseUrl: string = "{base_url}") {{}}
+
+  private async post(path: string, text: string): Promise<Response> {{
+    const body: TextRequest = {{ text }};
+    const response = await fetch(this.baseUrl + path, {{
+      method: "POST",
+      headers: {{ "Content-Type": "application/json" }},
+      body: JSON.stringify(body),
+    }});
+    if (!response.ok) {{
+      throw new Error(`${{path}}: ${{response.status}} ${{response.statusText}}`);
+    }}
+    return response;
+  }}
+
+{methods}
+}}
+"#,
+            header = HEADER,
+            types = types.join("\n\n"),
+            tags = tags.join("\n"),
+            base_url = schema.base_url,
+            methods = methods.join("\n\n"),
+        )
+    }
+
+    fn interface(def: &TypeDef) -> String {
+        let fields = def
+            .fields
+            .iter()
+            .map(|field| format!("  {}: {};", field.name, type_name(&field.ty)))
+            .collect::<Vec<_>>();
+        format!(
+            "export interface {} {{\n{}\n}}",
+            def.name,
+            fields.join("\n")
+        )
+    }
+
+    fn type_name(ty: &SchemaType) -> String {
+        match ty {
+            SchemaType::String => "string".to_string(),
+            SchemaType::Integer | SchemaType::Number => "number".to_string(),
+            SchemaType::Boolean => "boolean".to_string(),
+            SchemaType::Array(item) => format!("{}[]", type_name(item)),
+            SchemaType::Named(name) => name.to_string(),
+            SchemaType::Any => "unknown".to_string(),
+        }
+    }
+
+    fn method(service: &ServiceSchema) -> String {
+        let (returns, read) = match &service.response {
+            ResponseSchema::Json(body) => (type_name(body), "json"),
+            ResponseSchema::Audio => ("Blob".to_string(), "blob"),
+        };
+        format!(
+            r#"  async {}(tag: {}, text: string): Promise<{}> {{
+    return (await this.post(`/{}/${{tag}}`, text)).{}();
+  }}"#,
+            camel_case(&service.name),
+            tag_type(service),
+            returns,
+            service.name,
+            read
+        )
+    }
+}
+
+mod python {
+    use super::*;
+
+    pub(super) fn generate(schema: &ApiSchema) -> String {
+        let types = schema.types.iter().map(typed_dict).collect::<Vec<_>>();
+
+        let tags = schema
+            .services
+            .iter()
+            .map(|service| {
+                format!(
+                    "{} = Literal[{}]",
+                    tag_type(service),
+                    quoted_targets(service).join(", ")
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let methods = schema.services.iter().map(method).collect::<Vec<_>>();
+
+        format!(
+            r#"# {header}
+from __future__ import annotations
+
+import json
+import urllib.request
+from typing import Any, List, Literal, TypedDict
+
+
+{types}
+
+
+{tags}
+
+
+class DivvunClient:
+    def __init__(self, base_url: str = "{base_url}") -> None:
+        self.base_url = base_url.rstrip("/")
+
+    def _post(self, path: str, text: str) -> bytes:
+        body: TextRequest = {{"text": text}}
+        request = urllib.request.Request(
+            self.base_url + path,
+            data=json.dumps(body).encode("utf-8"),
+            headers={{"Content-Type": "application/json"}},
+            method="POST",
+        )
+        with urllib.request.urlopen(request) as response:
+            return response.read()
+
+{methods}
+"#,
+            header = HEADER,
+            types = types.join("\n\n\n"),
+            tags = tags.join("\n"),
+            base_url = schema.base_url,
+            methods = methods.join("\n\n"),
+        )
+    }
+
+    fn typed_dict(def: &TypeDef) -> String {
+        let fields = def
+            .fields
+            .iter()
+            .map(|field| format!("    {}: {}", field.name, type_name(&field.ty)))
+            .collect::<Vec<_>>();
+        format!("class {}(TypedDict):\n{}", def.name, fields.join("\n"))
+    }
+
+    fn type_name(ty: &SchemaType) -> String {
+        match ty {
+            SchemaType::String => "str".to_string(),
+            SchemaType::Integer => "int".to_string(),
+            SchemaType::Number => "float".to_string(),
+            SchemaType::Boolean => "bool".to_string(),
+            SchemaType::Array(item) => format!("List[{}]", type_name(item)),
+            SchemaType::Named(name) => name.to_string(),
+            SchemaType::Any => "Any".to_string(),
+        }
+    }
+
+    fn method(service: &ServiceSchema) -> String {
+        let (returns, body) = match &service.response {
+            ResponseSchema::Json(body) => (type_name(body), "json.loads(self._post(path, text))"),
+            ResponseSchema::Audio => ("bytes".to_string(), "self._post(path, text)"),
+        };
+        format!(
+            r#"    def {}(self, tag: {}, text: str) -> {}:
+        path = f"/{}/{{tag}}"
+        return {}"#,
+            snake_case(&service.name),
+            tag_type(service),
+            returns,
+            service.name,
+            body
+        )
+    }
+}
+
+mod rust {
+    use super::*;
+
+    pub(super) fn generate(schema: &ApiSchema) -> String {
+        let types = schema.types.iter().map(structure).collect::<Vec<_>>();
+        let tags = schema.services.iter().map(tag_enum).collect::<Vec<_>>();
+        let methods = schema.services.iter().map(method).collect::<Vec<_>>();
+
+        format!(
+            r#"//! {header}
+//!
+//! Requires `reqwest` (with the `json` feature), `serde` and `serde_json`.
+
+use serde::{{Deserialize, Serialize}};
+
+{types}
+
+{tags}
+
+pub struct DivvunClient {{
+    base_url: String,
+    http: reqwest::Client,
+}}
+
+impl DivvunClient {{
+    pub const DEFAULT_BASE_URL: &'static str = "{base_url}";
+
+    pub fn new(base_url: impl Into<String>) -> Self {{
+        Self {{
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+        }}
+    }}
+
+    async fn post(&self, path: String, text: &str) -> reqwest::Result<reqwest::Response> {{
+        self.http
+            .post(format!("{{}}{{}}", self.base_url, path))
+            .json(&TextRequest {{
+                text: text.to_string(),
+            }})
+            .send()
+            .await?
+            .error_for_status()
+    }}
+
+{methods}
+}}
+
+impl Default for DivvunClient {{
+    fn default() -> Self {{
+        Self::new(Self::DEFAULT_BASE_URL)
+    }}
+}}
+"#,
+            header = HEADER,
+            types = types.join("\n\n"),
+            tags = tags.join("\n\n"),
+            base_url = schema.base_url,
+            methods = methods.join("\n\n"),
+        )
+    }
+
+    fn structure(def: &TypeDef) -> String {
+        let fields = def
+            .fields
+            .iter()
+            .map(|field| format!("    pub {}: {},", field.name, type_name(&field.ty)))
+            .collect::<Vec<_>>();
+        format!(
+            "#[derive(Debug, Clone, Serialize, Deserialize)]\npub struct {} {{\n{}\n}}",
+            def.name,
+            fields.join("\n")
+        )
+    }
+
+    fn type_name(ty: &SchemaType) -> String {
+        match ty {
+            SchemaType::String => "String".to_string(),
+            SchemaType::Integer => "i64".to_string(),
+            SchemaType::Number => "f64".to_string(),
+            SchemaType::Boolean => "bool".to_string(),
+            SchemaType::Array(item) => format!("Vec<{}>", type_name(item)),
+            SchemaType::Named(name) => name.to_string(),
+            SchemaType::Any => "serde_json::Value".to_string(),
+        }
+    }
+
+    fn tag_enum(service: &ServiceSchema) -> String {
+        let variants = service
+            .routes
+            .iter()
+            .map(|route| format!("    {},", pascal_case(&route.target)))
+            .collect::<Vec<_>>();
+        let arms = service
+            .routes
+            .iter()
+            .map(|route| {
+                format!(
+                    "            Self::{} => \"{}\",",
+                    pascal_case(&route.target),
+                    route.target
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            r#"#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum {name} {{
+{variants}
+}}
+
+impl {name} {{
+    pub fn as_str(&self) -> &'static str {{
+        match self {{
+{arms}
+        }}
+    }}
+}}"#,
+            name = tag_type(service),
+            variants = variants.join("\n"),
+            arms = arms.join("\n"),
+        )
+    }
+
+    fn method(service: &ServiceSchema) -> String {
+        let (returns, read) = match &service.response {
+            ResponseSchema::Json(body) => (type_name(body), "json().await"),
+            ResponseSchema::Audio => (
+                "Vec<u8>".to_string(),
+                "bytes().await.map(|bytes| bytes.to_vec())",
+            ),
+        };
+        format!(
+            r#"    pub async fn {}(&self, tag: {}, text: &str) -> reqwest::Result<{}> {{
+        self.post(format!("/{}/{{}}", tag.as_str()), text)
+            .await?
+            .{}
+    }}"#,
+            snake_case(&service.name),
+            tag_type(service),
+            returns,
+            service.name,
+            read
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LanguagesConfig;
+    use crate::services::ServiceRegistry;
+
+    fn schema() -> ApiSchema {
+        ApiSchema::new(
+            &LanguagesConfig::embedded().unwrap(),
+            &ServiceRegistry::builtin(),
+        )
+    }
+
+    #[test]
+    fn typescript_tags_cover_configured_voices() {
+        let client = generate_client(&schema(), ClientLanguage::Typescript);
+        assert!(client.contains("export type TtsTag = \"se/biret\""));
+        assert!(client.contains("async tts(tag: TtsTag, text: string): Promise<Blob>"));
+        assert!(client
+            .contains("async grammar(tag: GrammarTag, text: string): Promise<GrammarResponse>"));
+    }
+
+    #[test]
+    fn rust_tags_become_enum_variants() {
+        let client = generate_client(&schema(), ClientLanguage::Rust);
+        assert!(client.contains("pub enum TtsTag {\n    SeBiret,"));
+        assert!(client.contains("Self::SeBiret => \"se/biret\","));
+    }
+
+    #[test]
+    fn python_uses_literal_tags() {
+        let client = generate_client(&schema(), ClientLanguage::Python);
+        assert!(client.contains("TtsTag = Literal[\"se/biret\""));
+        assert!(
+            client.contains("def speller(self, tag: SpellerTag, text: str) -> SpellerResponse:")
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! A docker-compose file running the gateway and every backend it forwards
+//! to.
+//!
+//! The gateway reaches the backends on this host at 127.0.0.1, so each joins
+//! the gateway container's network namespace and listens on its configured
+//! port, passed as `PORT`. Backends configured on other hosts are left out.
+
+use crate::config::LanguagesConfig;
+use crate::services::{workers, ServiceRegistry};
+
+pub const GATEWAY_IMAGE: &str = "ghcr.io/divvun/divvun-worker-static:latest";
+
+/// Default backend image; see [`Worker::expand`] for the placeholders.
+pub const BACKEND_IMAGE: &str = "ghcr.io/divvun/divvun-worker-{name}:latest";
+
+/// Render `docker-compose.yml`. Backend images come from `image_template`,
+/// e.g. [`BACKEND_IMAGE`].
+pub fn generate_compose(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    image_template: &str,
+) -> String {
+    let backends: String = workers(languages, services)
+        .iter()
+        .map(|worker| {
+            format!(
+                r#"
+  {name}:
+    image: {image}
+    network_mode: "service:gateway"
+    environment:
+      PORT: "{port}"
+    restart: unless-stopped
+"#,
+                name = worker.id,
+                image = worker.expand(image_template),
+                port = worker.port,
+            )
+        })
+        .collect();
+
+    format!(
+        r#"# Generated by divvun-worker-static. Do not edit.
+services:
+  gateway:
+    image: {gateway}
+    ports:
+      - "4000:4000"
+    volumes:
+      - ./languages.toml:/app/languages.toml:ro
+    restart: unless-stopped
+{backends}"#,
+        gateway = GATEWAY_IMAGE,
+        backends = backends,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_service_per_backend_and_one_for_tts() {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let compose = generate_compose(&languages, &ServiceRegistry::builtin(), BACKEND_IMAGE);
+
+        assert!(compose.contains(
+            "\n  grammar-se:\n    image: ghcr.io/divvun/divvun-worker-grammar-se:latest\n    \
+             network_mode: \"service:gateway\"\n    environment:\n      PORT: \"10000\"\n"
+        ));
+        assert_eq!(compose.matches("\n  tts:\n").count(), 1);
+        assert!(compose.contains("      PORT: \"40001\"\n"));
+        assert!(compose.contains(&format!("\n  gateway:\n    image: {}\n", GATEWAY_IMAGE)));
+    }
+
+    #[test]
+    fn each_instance_gets_a_container() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.speller.get_mut("se").unwrap().ports = vec![
+            crate::config::Instance::local(4001),
+            crate::config::Instance::local(4002),
+        ];
+        let compose = generate_compose(&languages, &ServiceRegistry::builtin(), BACKEND_IMAGE);
+
+        assert!(!compose.contains("\n  speller-se:\n"));
+        for port in [4001, 4002] {
+            assert!(compose.contains(&format!(
+                "\n  speller-se-{}:\n    image: ghcr.io/divvun/divvun-worker-speller-se:latest\n    \
+                 network_mode: \"service:gateway\"\n    environment:\n      PORT: \"{}\"\n",
+                port, port
+            )));
+        }
+    }
+
+    #[test]
+    fn backends_on_other_hosts_are_left_out() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().host = "10.0.0.9".to_string();
+        languages.config.tts.host = "tts".to_string();
+        let compose = generate_compose(&languages, &ServiceRegistry::builtin(), BACKEND_IMAGE);
+
+        assert!(!compose.contains("\n  grammar-se:\n"));
+        assert!(!compose.contains("\n  tts:\n"));
+        assert!(compose.contains("\n  grammar-fo:\n"));
+    }
+}
@@ -0,0 +1,174 @@
+//! Gzip for text responses. Speller and grammar results for long documents
+//! run to hundreds of kilobytes of JSON, which shrink several times over, so
+//! JSON, the docs page and its assets are compressed for clients sending
+//! `Accept-Encoding: gzip`. Small responses aren't worth it, and audio is
+//! compressed already.
+
+use poem::{
+    http::{header, HeaderValue, Method, StatusCode},
+    Body, Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::assets::accepts;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Smallest body compressed, in bytes.
+    pub min_size: usize,
+    /// From 1, fastest, to 9, smallest.
+    pub level: u8,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: 1024,
+            level: 6,
+        }
+    }
+}
+
+/// Whether bodies of `content_type` are worth compressing.
+fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    (media_type.starts_with("text/") && media_type != "text/event-stream")
+        || matches!(media_type, "application/json" | "application/javascript")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+}
+
+/// `data` as a gzip member (RFC 1952).
+pub fn gzip(data: &[u8], level: u8) -> Vec<u8> {
+    let deflated = miniz_oxide::deflate::compress_to_vec(data, level.clamp(1, 9));
+    let mut out = Vec::with_capacity(deflated.len() + 18);
+    // Magic, deflate, no flags, no mtime, no extra flags, unknown OS
+    out.extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255]);
+    out.extend_from_slice(&deflated);
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = match crc & 1 {
+                    1 => 0xedb8_8320 ^ (crc >> 1),
+                    _ => crc >> 1,
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0, |crc, &b| {
+        TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Middleware compressing text responses for clients accepting gzip.
+pub struct Compression(pub CompressionConfig);
+
+impl<E: Endpoint> Middleware<E> for Compression {
+    type Output = CompressionEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CompressionEndpoint {
+            inner: ep,
+            config: self.0.clone(),
+        }
+    }
+}
+
+pub struct CompressionEndpoint<E> {
+    inner: E,
+    config: CompressionConfig,
+}
+
+impl<E: Endpoint> Endpoint for CompressionEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let accepted = req.method() != Method::HEAD
+            && accepts(
+                req.header(header::ACCEPT_ENCODING).unwrap_or_default(),
+                "gzip",
+            );
+        let mut response = self.inner.call(req).await?.into_response();
+        let skipped = matches!(
+            response.status(),
+            StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED | StatusCode::PARTIAL_CONTENT
+        ) || response.headers().contains_key(header::CONTENT_ENCODING)
+            || !response.content_type().is_some_and(is_compressible);
+        if skipped {
+            return Ok(response);
+        }
+
+        let body = response.take_body().into_vec().await?;
+        if body.len() < self.config.min_size {
+            response.set_body(body);
+            return Ok(response);
+        }
+        let headers = response.headers_mut();
+        headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+        if !accepted {
+            response.set_body(body);
+            return Ok(response);
+        }
+
+        // The compressed body is a different representation of the same
+        // resource, which a strong tag would claim is byte-identical
+        let weak = headers
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .filter(|etag| !etag.starts_with("W/"))
+            .and_then(|etag| HeaderValue::from_str(&format!("W/{}", etag)).ok());
+        if let Some(weak) = weak {
+            headers.insert(header::ETAG, weak);
+        }
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        headers.remove(header::CONTENT_LENGTH);
+        response.set_body(Body::from(gzip(&body, self.config.level)));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_members_carry_the_checksum_and_length() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let text = "Mun lean sami ja mun hálan sámegiela. ".repeat(100);
+        let compressed = gzip(text.as_bytes(), 6);
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(compressed[..3], [0x1f, 0x8b, 8]);
+        let deflated = &compressed[10..compressed.len() - 8];
+        assert_eq!(
+            miniz_oxide::inflate::decompress_to_vec(deflated).unwrap(),
+            text.as_bytes()
+        );
+        assert_eq!(
+            compressed[compressed.len() - 4..],
+            (text.len() as u32).to_le_bytes()
+        );
+
+        assert!(is_compressible("application/json; charset=utf-8"));
+        assert!(is_compressible("application/problem+json"));
+        assert!(!is_compressible("audio/wav"));
+        assert!(!is_compressible("text/event-stream"));
+    }
+}
@@ -0,0 +1,984 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::AuthConfig;
+use crate::balance::Balance;
+use crate::cache::CacheRoute;
+use crate::compression::CompressionConfig;
+use crate::cors::CorsConfig;
+use crate::faults::FaultConfig;
+use crate::limits::{LocationLimits, RateLimitConfig};
+use crate::logfile::LogConfig;
+use crate::overrides;
+use crate::pool::PoolConfig;
+use crate::responses::ResponseCacheConfig;
+use crate::retry::{CircuitBreakerConfig, RetryConfig};
+use crate::sanitize::SanitizePolicy;
+use crate::stats::StatsConfig;
+use crate::statsd::StatsdConfig;
+use crate::tenants::TenantConfig;
+
+/// The `languages.toml` shipped with this crate.
+pub const EMBEDDED_CONFIG: &str = include_str!("../languages.toml");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguagesConfig {
+    #[serde(default)]
+    pub branding: Branding,
+    pub config: Config,
+    pub grammar: HashMap<String, ServiceConfig>,
+    pub speller: HashMap<String, ServiceConfig>,
+    pub hyphenation: HashMap<String, ServiceConfig>,
+    pub tts: HashMap<String, TtsConfig>,
+    /// Any other top-level table, keyed by category name, for service
+    /// categories registered by downstream crates.
+    #[serde(flatten)]
+    pub custom: HashMap<String, HashMap<String, ServiceConfig>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Branding {
+    pub title: String,
+    pub organization: Option<String>,
+    pub logo_url: Option<String>,
+    pub base_url: String,
+    pub links: Vec<BrandingLink>,
+    pub footer: Option<String>,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            title: "Divvun API".to_string(),
+            organization: None,
+            logo_url: None,
+            base_url: "https://api-giellalt.uit.no".to_string(),
+            links: Vec::new(),
+            footer: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrandingLink {
+    pub label: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub tts: ConfigTts,
+    /// Directory served under `/static/`, e.g. for demo frontends.
+    #[serde(default)]
+    pub static_dir: Option<String>,
+    #[serde(default)]
+    pub announcement: Option<Announcement>,
+    /// Maximum request text length in characters, keyed by service category.
+    #[serde(default)]
+    pub limits: HashMap<String, usize>,
+    /// Most texts one `/speller/<tag>/batch` request may hold.
+    #[serde(default = "default_max_batch")]
+    pub max_batch: usize,
+    /// Seconds the gateway waits for a backend, keyed by service category.
+    #[serde(default)]
+    pub timeouts: HashMap<String, u64>,
+    /// Requests a minute each client may send.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// What to do with control and zero-width characters in request texts.
+    #[serde(default)]
+    pub sanitize: SanitizePolicy,
+    /// Write logs to a rotated file instead of standard output.
+    #[serde(default)]
+    pub log: Option<LogConfig>,
+    /// Enables the `/admin` endpoints.
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    /// Require API keys for the service endpoints.
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// Push request metrics to a StatsD agent.
+    #[serde(default)]
+    pub statsd: Option<StatsdConfig>,
+    /// Count requests per language for `GET /stats`.
+    #[serde(default)]
+    pub stats: Option<StatsConfig>,
+    /// Cross-origin policies; any origin may call the gateway without one.
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    /// `Cache-Control` policies by path prefix.
+    #[serde(default)]
+    pub cache: Vec<CacheRoute>,
+    /// Backend responses reused by the gateway, keyed by service category.
+    #[serde(default)]
+    pub response_cache: HashMap<String, ResponseCacheConfig>,
+    /// Other configurations served to the hosts they name.
+    #[serde(default)]
+    pub tenants: HashMap<String, TenantConfig>,
+    /// Faults injected when the server runs with fault injection enabled.
+    #[serde(default)]
+    pub faults: Vec<FaultConfig>,
+    /// Keep-alive connections to the backends.
+    #[serde(default)]
+    pub pool: PoolConfig,
+    /// Gzip for large text responses.
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// Send requests that failed to reach a backend again.
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+    /// Turn requests for failing backends away for a while.
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Bearer token required by the `/admin` endpoints.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    pub message: String,
+    /// Also send the message as an `X-Announcement` header on every response.
+    #[serde(default)]
+    pub header: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigTts {
+    /// The machine or container the TTS backend runs on; this host by
+    /// default.
+    #[serde(default = "default_host")]
+    pub host: String,
+    pub port: u16,
+    /// Longest text sent to the backend at once, in characters. The gateway
+    /// splits longer ones on sentence boundaries and joins the audio.
+    #[serde(default)]
+    pub chunk_length: Option<usize>,
+    /// ffmpeg binary converting audio for requests with a `format`; the
+    /// one on the `PATH` by default.
+    #[serde(default)]
+    pub ffmpeg: Option<PathBuf>,
+    #[serde(default)]
+    pub fallback: Option<Fallback>,
+    #[serde(default)]
+    pub shadow: Option<Shadow>,
+}
+
+/// A secondary backend, used by the gateway while health checks find the
+/// primary down.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fallback {
+    pub host: String,
+    pub port: u16,
+}
+
+/// One instance of a backend: a port on this host, a `host:port` pair, or a
+/// table with a `weight` as well.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "InstanceAddress", into = "InstanceAddress")]
+pub struct Instance {
+    pub host: String,
+    pub port: u16,
+    /// The instance's share of the requests relative to the others.
+    pub weight: u32,
+}
+
+impl Instance {
+    pub fn local(port: u16) -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port,
+            weight: 1,
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        is_local(&self.host)
+    }
+}
+
+impl std::fmt::Display for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum InstanceAddress {
+    Port(u16),
+    Address(String),
+    Weighted {
+        #[serde(default = "default_host")]
+        host: String,
+        port: u16,
+        #[serde(default = "default_weight")]
+        weight: u32,
+    },
+}
+
+fn default_max_batch() -> usize {
+    100
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+/// Whether `host` is this machine, whose ports the backends mustn't share.
+pub fn is_local(host: &str) -> bool {
+    matches!(host, "127.0.0.1" | "localhost" | "::1")
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl TryFrom<InstanceAddress> for Instance {
+    type Error = String;
+
+    fn try_from(address: InstanceAddress) -> Result<Self, String> {
+        match address {
+            InstanceAddress::Port(port) => Ok(Self::local(port)),
+            InstanceAddress::Address(address) => {
+                let (host, port) = address
+                    .rsplit_once(':')
+                    .filter(|(host, _)| !host.is_empty())
+                    .ok_or_else(|| format!("expected host:port, not {:?}", address))?;
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("invalid port in {:?}", address))?;
+                Ok(Self {
+                    host: host.to_string(),
+                    port,
+                    weight: 1,
+                })
+            }
+            InstanceAddress::Weighted { weight: 0, .. } => {
+                Err("an instance's weight must be at least 1".to_string())
+            }
+            InstanceAddress::Weighted { host, port, weight } => Ok(Self { host, port, weight }),
+        }
+    }
+}
+
+impl From<Instance> for InstanceAddress {
+    fn from(instance: Instance) -> Self {
+        if instance.weight != 1 {
+            Self::Weighted {
+                host: instance.host,
+                port: instance.port,
+                weight: instance.weight,
+            }
+        } else if instance.host == "127.0.0.1" {
+            Self::Port(instance.port)
+        } else {
+            Self::Address(instance.to_string())
+        }
+    }
+}
+
+/// A staging backend the gateway copies a share of requests to, ignoring
+/// its responses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shadow {
+    pub host: String,
+    pub port: u16,
+    /// Share of requests to copy, from 0 to 100.
+    #[serde(default = "default_shadow_percent")]
+    pub percent: f64,
+}
+
+fn default_shadow_percent() -> f64 {
+    100.0
+}
+
+/// English names of the languages GiellaLT tools exist for, by tag.
+const ENGLISH_NAMES: &[(&str, &str)] = &[
+    ("chr", "Cherokee"),
+    ("crk", "Plains Cree"),
+    ("da", "Danish"),
+    ("en", "English"),
+    ("et", "Estonian"),
+    ("fi", "Finnish"),
+    ("fkv", "Kven"),
+    ("fo", "Faroese"),
+    ("ga", "Irish"),
+    ("is", "Icelandic"),
+    ("kl", "Greenlandic"),
+    ("kpv", "Komi-Zyrian"),
+    ("mhr", "Eastern Mari"),
+    ("myv", "Erzya"),
+    ("nb", "Norwegian Bokmål"),
+    ("nn", "Norwegian Nynorsk"),
+    ("se", "Northern Sami"),
+    ("sjd", "Kildin Sami"),
+    ("sje", "Pite Sami"),
+    ("sju", "Ume Sami"),
+    ("sma", "Southern Sami"),
+    ("smj", "Lule Sami"),
+    ("smn", "Inari Sami"),
+    ("sms", "Skolt Sami"),
+    ("sv", "Swedish"),
+    ("udm", "Udmurt"),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceConfig {
+    pub name: String,
+    /// The machine or container the backend on `port` runs on; this host by
+    /// default.
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// The backend's port on `host`; with `ports`, the first of them.
+    #[serde(default)]
+    pub port: u16,
+    /// Several instances of the backend sharing the language's requests.
+    #[serde(default)]
+    pub ports: Vec<Instance>,
+    /// How the gateway spreads requests over `ports`.
+    #[serde(default)]
+    pub balance: Balance,
+    /// Other tags for the language, e.g. `sme` for `se`, served the same.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// The language's name in English, if not a well-known one.
+    #[serde(default)]
+    pub english_name: Option<String>,
+    /// Example text prefilled on the language's demo page.
+    #[serde(default)]
+    pub example: Option<String>,
+    /// WASM module rewriting requests and responses proxied by the gateway.
+    #[serde(default)]
+    pub wasm: Option<String>,
+    /// The orthography's apostrophe; look-alikes are replaced by it before
+    /// checking.
+    #[serde(default)]
+    pub apostrophe: Option<char>,
+    #[serde(default)]
+    pub limits: LocationLimits,
+    #[serde(default)]
+    pub fallback: Option<Fallback>,
+    #[serde(default)]
+    pub shadow: Option<Shadow>,
+    /// Directory to record the requests forwarded by the gateway to, for
+    /// `replay`.
+    #[serde(default)]
+    pub record: Option<PathBuf>,
+}
+
+impl ServiceConfig {
+    /// The URL of the backend on `host` and `port`.
+    pub fn url(&self) -> String {
+        format!("http://{}:{}/", self.host, self.port)
+    }
+
+    /// The backend instances serving the language: `ports`, or else `port`
+    /// on `host`.
+    pub fn instances(&self) -> Vec<Instance> {
+        if self.ports.is_empty() {
+            vec![Instance {
+                host: self.host.clone(),
+                port: self.port,
+                weight: 1,
+            }]
+        } else {
+            self.ports.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TtsConfig {
+    pub name: String,
+    pub voices: HashMap<String, VoiceConfig>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub english_name: Option<String>,
+    #[serde(default)]
+    pub example: Option<String>,
+    #[serde(default)]
+    pub wasm: Option<String>,
+    #[serde(default)]
+    pub apostrophe: Option<char>,
+    /// Whether the backend takes SSML; it gets only the text otherwise.
+    #[serde(default)]
+    pub ssml: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoiceConfig {
+    pub name: String,
+    pub gender: String,
+    pub model: String,
+    #[serde(default)]
+    pub speaker: Option<u32>,
+    #[serde(default)]
+    pub language: Option<u32>,
+    /// The host of a backend serving just this voice, if not `config.tts`'s.
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub limits: LocationLimits,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegacyLanguagesConfig {
+    pub grammar: HashMap<String, String>,
+    pub speller: HashMap<String, String>,
+    pub hyphenation: HashMap<String, String>,
+}
+
+impl From<&LanguagesConfig> for LegacyLanguagesConfig {
+    fn from(languages: &LanguagesConfig) -> Self {
+        Self {
+            grammar: languages
+                .grammar
+                .iter()
+                .map(|(k, v)| (k.clone(), v.name.clone()))
+                .collect(),
+            speller: languages
+                .speller
+                .iter()
+                .map(|(k, v)| (k.clone(), v.name.clone()))
+                .collect(),
+            hyphenation: languages
+                .hyphenation
+                .iter()
+                .map(|(k, v)| (k.clone(), v.name.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl LanguagesConfig {
+    /// Parse and validate the embedded `languages.toml`.
+    pub fn embedded() -> anyhow::Result<Self> {
+        Self::from_toml(EMBEDDED_CONFIG)
+    }
+
+    /// A hash of the whole configuration, which changes whenever any of it
+    /// does.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        // Objects in a Value are sorted by key, where serializing `self`
+        // directly would follow each HashMap's per-process order
+        serde_json::to_value(self)
+            .unwrap_or_default()
+            .to_string()
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Read, parse and validate a `languages.toml` file.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("can't read {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    /// Read `path` if given, the embedded `languages.toml` otherwise, with
+    /// the values the `DIVVUN_*` environment variables override.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let vars = std::env::vars();
+        match path {
+            Some(path) => {
+                let source = std::fs::read_to_string(path)
+                    .with_context(|| format!("can't read {}", path.display()))?;
+                Self::from_layers(&source, vars)
+                    .with_context(|| format!("invalid {}", path.display()))
+            }
+            None => Self::from_layers(EMBEDDED_CONFIG, vars),
+        }
+    }
+
+    /// Parse and validate a `languages.toml` document.
+    pub fn from_toml(source: &str) -> anyhow::Result<Self> {
+        Self::from_layers(source, [])
+    }
+
+    /// Parse `source`, override it with the `DIVVUN_*` variables among `vars`
+    /// and validate the result.
+    pub fn from_layers(
+        source: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> anyhow::Result<Self> {
+        let languages = Self::parse(source, vars)?;
+        languages.validate()?;
+        Ok(languages)
+    }
+
+    /// Parse `source` with the `DIVVUN_*` variables among `vars` applied,
+    /// without validating the result, to report every problem at once.
+    pub fn parse(
+        source: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> anyhow::Result<Self> {
+        let mut document: toml::Table = toml::from_str(source)?;
+        overrides::apply(&mut document, vars)?;
+        let mut languages: Self = document.try_into()?;
+        let services = [
+            &mut languages.grammar,
+            &mut languages.speller,
+            &mut languages.hyphenation,
+        ]
+        .into_iter()
+        .chain(languages.custom.values_mut());
+        for service in services.flat_map(|services| services.values_mut()) {
+            if let (0, Some(first)) = (service.port, service.ports.first()) {
+                service.host = first.host.clone();
+                service.port = first.port;
+            }
+        }
+        Ok(languages)
+    }
+
+    /// The WASM hook configured for `tag` of the `service` category, if any.
+    pub fn wasm_hook(&self, service: &str, tag: &str) -> Option<&str> {
+        let services = match service {
+            "grammar" => &self.grammar,
+            "speller" => &self.speller,
+            "hyphenation" => &self.hyphenation,
+            "tts" => return self.tts.get(tag)?.wasm.as_deref(),
+            other => self.custom.get(other)?,
+        };
+        services.get(tag)?.wasm.as_deref()
+    }
+
+    /// The apostrophe configured for `tag` of the `service` category, if any.
+    pub fn apostrophe(&self, service: &str, tag: &str) -> Option<char> {
+        let services = match service {
+            "grammar" => &self.grammar,
+            "speller" => &self.speller,
+            "hyphenation" => &self.hyphenation,
+            "tts" => return self.tts.get(tag)?.apostrophe,
+            other => self.custom.get(other)?,
+        };
+        services.get(tag)?.apostrophe
+    }
+
+    /// The directory requests for `tag` of the `service` category are
+    /// recorded to, if any.
+    pub fn record_dir(&self, service: &str, tag: &str) -> Option<&Path> {
+        let services = match service {
+            "grammar" => &self.grammar,
+            "speller" => &self.speller,
+            "hyphenation" => &self.hyphenation,
+            "tts" => return None,
+            other => self.custom.get(other)?,
+        };
+        services.get(tag)?.record.as_deref()
+    }
+
+    /// Response headers the generated proxy configs set for `path`,
+    /// replacing whatever the backend sends: the preflight `max_age` and
+    /// the `Cache-Control` policy.
+    pub fn response_headers(&self, path: &str) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        let max_age = self
+            .config
+            .cors
+            .as_ref()
+            .and_then(|cors| cors.policy(path).max_age);
+        if let Some(max_age) = max_age {
+            headers.push(("Access-Control-Max-Age".to_string(), max_age.to_string()));
+        }
+        if let Some(value) =
+            crate::cache::policy(&self.config.cache, path).and_then(CacheRoute::header_value)
+        {
+            headers.push(("Cache-Control".to_string(), value));
+        }
+        headers
+    }
+
+    /// The configured tag of the `service` category each alias stands for.
+    pub fn aliases(&self, service: &str) -> BTreeMap<String, String> {
+        self.tag_aliases(service)
+            .into_iter()
+            .flat_map(|(tag, aliases)| aliases.iter().map(|alias| (alias.clone(), tag.clone())))
+            .collect()
+    }
+
+    /// Each tag of the `service` category with its aliases.
+    fn tag_aliases(&self, service: &str) -> Vec<(&String, &Vec<String>)> {
+        let services = match service {
+            "grammar" => &self.grammar,
+            "speller" => &self.speller,
+            "hyphenation" => &self.hyphenation,
+            "tts" => {
+                return self
+                    .tts
+                    .iter()
+                    .map(|(tag, tts)| (tag, &tts.aliases))
+                    .collect()
+            }
+            other => match self.custom.get(other) {
+                Some(services) => services,
+                None => return Vec::new(),
+            },
+        };
+        services
+            .iter()
+            .map(|(tag, service)| (tag, &service.aliases))
+            .collect()
+    }
+
+    /// The configured tag of the `service` category that `tag` names:
+    /// itself, or the tag it is an alias of.
+    pub fn canonical_tag(&self, service: &str, tag: &str) -> String {
+        self.aliases(service)
+            .remove(tag)
+            .unwrap_or_else(|| tag.to_string())
+    }
+
+    /// Longest text the gateway sends to a backend of the `service` category
+    /// at once, if it splits longer ones.
+    pub fn chunk_length(&self, service: &str) -> Option<usize> {
+        match service {
+            "tts" => self.config.tts.chunk_length,
+            _ => None,
+        }
+    }
+
+    /// The maximum text length for the `service` category, if limited.
+    pub fn max_length(&self, service: &str) -> Option<usize> {
+        self.config.limits.get(service).copied()
+    }
+
+    /// How long the gateway waits for a backend of the `service` category:
+    /// as configured, or long enough for that kind of backend.
+    pub fn timeout(&self, service: &str) -> Option<Duration> {
+        let seconds = match (self.config.timeouts.get(service), service) {
+            (Some(seconds), _) => *seconds,
+            (None, "speller" | "hyphenation") => 5,
+            (None, "grammar") => 15,
+            (None, "tts") => 60,
+            (None, _) => return None,
+        };
+        Some(Duration::from_secs(seconds))
+    }
+
+    /// The name of every configured language by tag, from whichever service
+    /// category names it first.
+    pub fn language_names(&self) -> BTreeMap<String, String> {
+        let mut names = BTreeMap::new();
+        let mut custom: Vec<_> = self.custom.iter().collect();
+        custom.sort_by_key(|(service, _)| *service);
+        let services = [&self.grammar, &self.speller, &self.hyphenation]
+            .into_iter()
+            .chain(custom.into_iter().map(|(_, services)| services));
+        for services in services {
+            for (tag, service) in services {
+                names
+                    .entry(tag.clone())
+                    .or_insert_with(|| service.name.clone());
+            }
+        }
+        for (tag, tts) in &self.tts {
+            names.entry(tag.clone()).or_insert_with(|| tts.name.clone());
+        }
+        names
+    }
+
+    /// The name `tag` is configured with for the `service` category.
+    pub fn language_name(&self, service: &str, tag: &str) -> Option<&str> {
+        let services = match service {
+            "grammar" => &self.grammar,
+            "speller" => &self.speller,
+            "hyphenation" => &self.hyphenation,
+            "tts" => return self.tts.get(tag).map(|tts| tts.name.as_str()),
+            other => self.custom.get(other)?,
+        };
+        services.get(tag).map(|service| service.name.as_str())
+    }
+
+    /// The English name of `tag`: as configured for any service category,
+    /// or else from the names of languages GiellaLT works with.
+    pub fn english_name(&self, tag: &str) -> Option<&str> {
+        let configured = [&self.grammar, &self.speller, &self.hyphenation]
+            .into_iter()
+            .chain(self.custom.values())
+            .filter_map(|services| services.get(tag)?.english_name.as_deref())
+            .chain(
+                self.tts
+                    .get(tag)
+                    .and_then(|tts| tts.english_name.as_deref()),
+            )
+            .next();
+        configured.or_else(|| {
+            ENGLISH_NAMES
+                .iter()
+                .find(|(known, _)| *known == tag)
+                .map(|(_, name)| *name)
+        })
+    }
+
+    /// Check invariants that the TOML schema alone can't express.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut ports: HashMap<u16, String> = HashMap::new();
+        if is_local(&self.config.tts.host) {
+            ports.insert(self.config.tts.port, "config.tts".to_string());
+        }
+
+        let mut custom: Vec<_> = self.custom.iter().collect();
+        custom.sort_by_key(|(service, _)| *service);
+
+        let services = [
+            ("grammar", &self.grammar),
+            ("speller", &self.speller),
+            ("hyphenation", &self.hyphenation),
+        ]
+        .into_iter()
+        .chain(
+            custom
+                .into_iter()
+                .map(|(service, configs)| (service.as_str(), configs)),
+        );
+        for (service, configs) in services {
+            let mut sorted: Vec<_> = configs.iter().collect();
+            sorted.sort_by_key(|(tag, _)| *tag);
+            for (tag, config) in sorted {
+                let name = format!("{}.{}", service, tag);
+                if config.port == 0 {
+                    bail!("{} has no port", name);
+                }
+                for instance in config.instances() {
+                    if !instance.is_local() {
+                        continue;
+                    }
+                    match ports.insert(instance.port, name.clone()) {
+                        Some(existing) if existing == name => {
+                            bail!("{} lists port {} twice", name, instance.port)
+                        }
+                        Some(existing) => bail!(
+                            "{} and {} are both configured on port {}",
+                            existing,
+                            name,
+                            instance.port
+                        ),
+                        None => {}
+                    }
+                }
+            }
+        }
+
+        let mut categories = vec!["grammar", "speller", "hyphenation", "tts"];
+        categories.extend(self.custom.keys().map(String::as_str));
+        categories[4..].sort_unstable();
+        for service in categories {
+            let mut aliases = self.tag_aliases(service);
+            aliases.sort_by_key(|(tag, _)| *tag);
+            let mut tags: HashMap<&str, &str> = aliases
+                .iter()
+                .map(|(tag, _)| (tag.as_str(), tag.as_str()))
+                .collect();
+            for (tag, aliases) in aliases {
+                for alias in aliases {
+                    match tags.insert(alias, tag) {
+                        Some(existing) if existing == alias => bail!(
+                            "{}.{} has the alias {}, which is a tag of its own",
+                            service,
+                            tag,
+                            alias
+                        ),
+                        Some(existing) => bail!(
+                            "{}.{} and {}.{} both have the alias {}",
+                            service,
+                            existing,
+                            service,
+                            tag,
+                            alias
+                        ),
+                        None => {}
+                    }
+                }
+            }
+        }
+
+        let mut tts: Vec<_> = self.tts.iter().collect();
+        tts.sort_by_key(|(tag, _)| *tag);
+        for (tag, config) in tts {
+            if config.voices.contains_key("voices") {
+                bail!(
+                    "tts.{} has a voice named voices, which clashes with /tts/{}/voices",
+                    tag,
+                    tag
+                );
+            }
+        }
+
+        let mut hosts: HashMap<String, &str> = HashMap::new();
+        let mut tenants: Vec<_> = self.config.tenants.iter().collect();
+        tenants.sort_by_key(|(name, _)| *name);
+        for (name, tenant) in tenants {
+            for host in &tenant.hosts {
+                if let Some(existing) = hosts.insert(host.to_ascii_lowercase(), name) {
+                    bail!(
+                        "tenants {} and {} both serve the host {}",
+                        existing,
+                        name,
+                        host
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"
+[config.tts]
+port = 40001
+
+[grammar.se]
+name = "davvisámegiella"
+port = 10000
+
+[speller.se]
+name = "davvisámegiella"
+port = 11000
+
+[hyphenation]
+
+[tts]
+"#;
+
+    #[test]
+    fn embedded_config_is_valid() {
+        let languages = LanguagesConfig::embedded().unwrap();
+        assert!(languages.grammar.contains_key("se"));
+        assert!(languages.tts["se"].voices.contains_key("biret"));
+    }
+
+    #[test]
+    fn branding_defaults_when_omitted() {
+        let languages = LanguagesConfig::from_toml(MINIMAL).unwrap();
+        assert_eq!(languages.branding.title, "Divvun API");
+        assert!(languages.config.announcement.is_none());
+    }
+
+    #[test]
+    fn duplicate_ports_are_rejected() {
+        let source = MINIMAL.replace("port = 11000", "port = 10000");
+        let err = LanguagesConfig::from_toml(&source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "grammar.se and speller.se are both configured on port 10000"
+        );
+    }
+
+    #[test]
+    fn instances_come_from_ports() {
+        let source = MINIMAL.replace(
+            "port = 10000",
+            "ports = [10000, { port = 10010, weight = 3 }, \"10.0.1.4:10000\"]",
+        );
+        let languages = LanguagesConfig::from_toml(&source).unwrap();
+        let se = &languages.grammar["se"];
+        assert_eq!(se.port, 10000);
+        assert_eq!(
+            se.instances()
+                .iter()
+                .map(Instance::to_string)
+                .collect::<Vec<_>>(),
+            ["127.0.0.1:10000", "127.0.0.1:10010", "10.0.1.4:10000"]
+        );
+        assert_eq!(se.ports[1].weight, 3);
+        assert_eq!(
+            languages.speller["se"].instances(),
+            [Instance::local(11000)]
+        );
+
+        let source = MINIMAL.replace("port = 10000", "ports = [10000, 11000]");
+        let err = LanguagesConfig::from_toml(&source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "grammar.se and speller.se are both configured on port 11000"
+        );
+        let source = MINIMAL.replace("port = 10000", "ports = [\"10.0.1.4\"]");
+        assert!(LanguagesConfig::from_toml(&source).is_err());
+    }
+
+    #[test]
+    fn fingerprints_ignore_map_order() {
+        let first = LanguagesConfig::embedded().unwrap();
+        let second = LanguagesConfig::embedded().unwrap();
+        assert_eq!(first.fingerprint(), second.fingerprint());
+
+        let mut changed = LanguagesConfig::embedded().unwrap();
+        changed.grammar.get_mut("se").unwrap().port = 10100;
+        assert_ne!(first.fingerprint(), changed.fingerprint());
+    }
+
+    #[test]
+    fn backends_on_other_hosts_share_ports() {
+        let source = MINIMAL.replace("port = 11000", "host = \"speller-se\"\nport = 10000");
+        let languages = LanguagesConfig::from_toml(&source).unwrap();
+        let se = &languages.speller["se"];
+        assert_eq!(se.url(), "http://speller-se:10000/");
+        assert_eq!(se.instances()[0].to_string(), "speller-se:10000");
+        assert_eq!(languages.grammar["se"].host, "127.0.0.1");
+
+        let source = MINIMAL.replace("port = 10000", "ports = [\"grammar-se:5000\"]");
+        let languages = LanguagesConfig::from_toml(&source).unwrap();
+        assert_eq!(languages.grammar["se"].url(), "http://grammar-se:5000/");
+    }
+
+    #[test]
+    fn environment_overrides_are_validated_with_the_file() {
+        let vars = |name: &str, value: &str| [(name.to_string(), value.to_string())];
+        let languages = LanguagesConfig::from_layers(
+            MINIMAL,
+            vars("DIVVUN_GRAMMAR__SE__PORTS", "[\"grammar-se:5000\"]"),
+        )
+        .unwrap();
+        assert_eq!(languages.grammar["se"].ports.len(), 1);
+
+        let err = LanguagesConfig::from_layers(MINIMAL, vars("DIVVUN_SPELLER__SE__PORT", "10000"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "grammar.se and speller.se are both configured on port 10000"
+        );
+    }
+
+    #[test]
+    fn aliases_resolve_to_their_tag() {
+        let source = MINIMAL.replace("port = 10000", "port = 10000\naliases = [\"sme\"]");
+        let languages = LanguagesConfig::from_toml(&source).unwrap();
+        assert_eq!(languages.canonical_tag("grammar", "sme"), "se");
+        assert_eq!(languages.canonical_tag("grammar", "se"), "se");
+        assert_eq!(languages.canonical_tag("speller", "sme"), "sme");
+
+        let source = MINIMAL.replace("port = 10000", "port = 10000\naliases = [\"se\"]");
+        let err = LanguagesConfig::from_toml(&source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "grammar.se has the alias se, which is a tag of its own"
+        );
+    }
+
+    #[test]
+    fn legacy_config_maps_tags_to_names() {
+        let languages = LanguagesConfig::from_toml(MINIMAL).unwrap();
+        let legacy = LegacyLanguagesConfig::from(&languages);
+        assert_eq!(legacy.grammar["se"], "davvisámegiella");
+        assert!(legacy.hyphenation.is_empty());
+    }
+}
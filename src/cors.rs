@@ -0,0 +1,166 @@
+//! Cross-origin policies, configured in `[config.cors]`.
+//!
+//! `max_age` lets browsers cache preflight answers instead of sending an
+//! `OPTIONS` request before every call. Routes can override the defaults by
+//! path prefix; the longest matching prefix wins.
+
+use std::sync::Arc;
+
+use poem::endpoint::BoxEndpoint;
+use poem::{middleware::Cors, Endpoint, EndpointExt, Middleware, Request, Response, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Seconds browsers may cache a preflight answer.
+    #[serde(default)]
+    pub max_age: Option<u32>,
+    /// Origins allowed to call the gateway; any origin if empty.
+    #[serde(default)]
+    pub allow_origins: Vec<String>,
+    #[serde(default)]
+    pub routes: Vec<CorsRoute>,
+}
+
+/// Overrides for routes under a path prefix, e.g. `/speller`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsRoute {
+    pub path: String,
+    #[serde(default)]
+    pub max_age: Option<u32>,
+    #[serde(default)]
+    pub allow_origins: Option<Vec<String>>,
+}
+
+/// The effective policy for one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsPolicy {
+    pub max_age: Option<u32>,
+    pub allow_origins: Vec<String>,
+}
+
+impl CorsConfig {
+    /// Index of the route overriding the policy for `path`, if any.
+    fn route(&self, path: &str) -> Option<usize> {
+        self.routes
+            .iter()
+            .enumerate()
+            .filter(|(_, route)| matches_prefix(path, &route.path))
+            .max_by_key(|(_, route)| route.path.len())
+            .map(|(index, _)| index)
+    }
+
+    pub fn policy(&self, path: &str) -> CorsPolicy {
+        let route = self.route(path).map(|index| &self.routes[index]);
+        CorsPolicy {
+            max_age: route.and_then(|route| route.max_age).or(self.max_age),
+            allow_origins: route
+                .and_then(|route| route.allow_origins.clone())
+                .unwrap_or_else(|| self.allow_origins.clone()),
+        }
+    }
+}
+
+/// Whether `path` is `prefix` or below it.
+pub(crate) fn matches_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl CorsPolicy {
+    fn middleware(&self) -> Cors {
+        let cors = Cors::default().allow_origins(self.allow_origins.iter().map(String::as_str));
+        match self.max_age {
+            Some(max_age) => cors.max_age(max_age.try_into().unwrap_or(i32::MAX)),
+            None => cors,
+        }
+    }
+}
+
+/// Middleware applying the [`CorsConfig`] policy of each request's path.
+pub struct CorsPolicies(pub CorsConfig);
+
+impl<E: Endpoint + 'static> Middleware<E> for CorsPolicies {
+    type Output = CorsPoliciesEndpoint;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        let ep = Arc::new(ep.map_to_response());
+        let default = CorsPolicy {
+            max_age: self.0.max_age,
+            allow_origins: self.0.allow_origins.clone(),
+        };
+        CorsPoliciesEndpoint {
+            config: self.0.clone(),
+            default: default.middleware().transform(ep.clone()).boxed(),
+            routes: self
+                .0
+                .routes
+                .iter()
+                .map(|route| {
+                    let policy = self.0.policy(&route.path);
+                    policy.middleware().transform(ep.clone()).boxed()
+                })
+                .collect(),
+        }
+    }
+}
+
+pub struct CorsPoliciesEndpoint {
+    config: CorsConfig,
+    default: BoxEndpoint<'static>,
+    /// One endpoint per entry of `config.routes`, in order.
+    routes: Vec<BoxEndpoint<'static>>,
+}
+
+impl Endpoint for CorsPoliciesEndpoint {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        match self.config.route(req.uri().path()) {
+            Some(index) => self.routes[index].call(req).await,
+            None => self.default.call(req).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_longest_matching_route_wins() {
+        let config: CorsConfig = toml::from_str(
+            r#"
+max_age = 600
+allow_origins = ["https://example.org"]
+
+[[routes]]
+path = "/speller"
+max_age = 86400
+
+[[routes]]
+path = "/speller/se"
+allow_origins = []
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.policy("/grammar/se"),
+            CorsPolicy {
+                max_age: Some(600),
+                allow_origins: vec!["https://example.org".to_string()],
+            }
+        );
+        assert_eq!(config.policy("/speller/sma").max_age, Some(86400));
+        assert_eq!(
+            config.policy("/speller/se"),
+            CorsPolicy {
+                max_age: Some(600),
+                allow_origins: Vec::new(),
+            }
+        );
+        assert_eq!(config.policy("/spellers").max_age, Some(600));
+    }
+}
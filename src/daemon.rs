@@ -0,0 +1,105 @@
+//! Classic unix daemon support: detaching from the terminal, pid files and
+//! signals.
+//!
+//! SIGTERM and SIGINT stop the server gracefully. SIGHUP asks it to reload,
+//! which reopens the log file so external log rotation works and re-reads
+//! the `--config` file.
+
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use daemonize::Daemonize;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Detach from the terminal. Must be called before any threads are started,
+/// so before the tokio runtime. Keeps the working directory, so relative
+/// paths in the config still resolve.
+pub fn daemonize() -> anyhow::Result<()> {
+    Daemonize::new()
+        .working_directory(std::env::current_dir()?)
+        .umask(0o027)
+        .start()
+        .context("can't daemonize")
+}
+
+/// A file holding the server's process id, removed when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the current process id to `path`, unless it names a process
+    /// that is still running.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        if let Some(pid) = read_pid(path) {
+            if is_running(pid) {
+                bail!("already running with pid {} (from {})", pid, path.display());
+            }
+        }
+        fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("can't write {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The pid in `path`, if it holds one.
+pub fn read_pid(path: &Path) -> Option<i32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn is_running(pid: i32) -> bool {
+    // Signal 0 only checks whether the process exists
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Completes on SIGTERM or SIGINT.
+pub fn shutdown_signal() -> io::Result<impl Future<Output = ()>> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(async move {
+        tokio::select! {
+            _ = terminate.recv() => tracing::info!("Received SIGTERM, shutting down"),
+            _ = interrupt.recv() => tracing::info!("Received SIGINT, shutting down"),
+        }
+    })
+}
+
+/// Call `reload` on every SIGHUP for the lifetime of the process.
+pub fn on_reload(reload: impl Fn() + Send + 'static) -> io::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            tracing::info!("Received SIGHUP, reloading");
+            reload();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_files_guard_against_a_second_instance() {
+        let path = std::env::temp_dir().join(format!("pidfile-{}", std::process::id()));
+        let pidfile = PidFile::create(&path).unwrap();
+        assert_eq!(read_pid(&path), Some(std::process::id() as i32));
+        assert!(PidFile::create(&path).is_err());
+
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,307 @@
+//! One-stop diagnostics for a deployment: configuration, ports, backends,
+//! generated nginx files and the paths the server reads.
+
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::config::LanguagesConfig;
+use crate::health;
+use crate::nginx;
+use crate::ports;
+use crate::services::ServiceRegistry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The check that produced the finding, e.g. `backends`.
+    pub check: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// What to do about it.
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    pub(crate) fn ok(check: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            severity: Severity::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    pub(crate) fn problem(
+        check: &'static str,
+        severity: Severity,
+        message: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            check,
+            severity,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+pub struct DoctorOptions {
+    /// Directory holding the nginx files written by `generate`, if any.
+    pub nginx_dir: Option<PathBuf>,
+    /// Ports backends may use.
+    pub range: RangeInclusive<u16>,
+    /// Send each text backend a short text instead of only connecting.
+    pub canned: bool,
+}
+
+/// Run every check against `languages`.
+pub async fn diagnose(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    options: &DoctorOptions,
+) -> Vec<Finding> {
+    let mut findings = vec![check_config(languages)];
+    findings.extend(check_ports(languages, services, &options.range));
+    findings.extend(check_backends(languages, services, options.canned).await);
+    if let Some(dir) = &options.nginx_dir {
+        findings.extend(check_nginx(languages, services, dir));
+    }
+    findings.extend(check_paths(languages, services));
+    findings
+}
+
+fn check_config(languages: &LanguagesConfig) -> Finding {
+    match languages.validate() {
+        Ok(()) => Finding::ok("config", "languages.toml is valid"),
+        Err(err) => Finding::problem(
+            "config",
+            Severity::Error,
+            format!("languages.toml is invalid: {}", err),
+            "Correct languages.toml",
+        ),
+    }
+}
+
+fn check_ports(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    range: &RangeInclusive<u16>,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for report in ports::audit(languages, services, range) {
+        if report.duplicate {
+            findings.push(Finding::problem(
+                "ports",
+                Severity::Error,
+                format!(
+                    "Port {} is used by {}",
+                    report.port,
+                    report.users.join(", ")
+                ),
+                "Give each backend its own port",
+            ));
+        }
+        if report.out_of_range {
+            findings.push(Finding::problem(
+                "ports",
+                Severity::Warning,
+                format!(
+                    "Port {} ({}) is outside {}-{}",
+                    report.port,
+                    report.users.join(", "),
+                    range.start(),
+                    range.end()
+                ),
+                "Move the backend into the allowed range",
+            ));
+        }
+    }
+    if findings.is_empty() {
+        findings.push(Finding::ok("ports", "Every backend has its own port"));
+    }
+    findings
+}
+
+async fn check_backends(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    canned: bool,
+) -> Vec<Finding> {
+    let statuses = health::check(languages, services, canned).await;
+    let mut findings: Vec<_> = statuses
+        .iter()
+        .filter(|status| status.up != Some(true))
+        .map(|status| {
+            Finding::problem(
+                "backends",
+                Severity::Error,
+                format!(
+                    "{} {} (port {}) is down: {}",
+                    status.service,
+                    status.tag,
+                    status.port,
+                    status.last_error.as_deref().unwrap_or("not probed")
+                ),
+                format!(
+                    "Start the {} {} backend or correct its port in languages.toml",
+                    status.service, status.tag
+                ),
+            )
+        })
+        .collect();
+    if findings.is_empty() {
+        findings.push(Finding::ok(
+            "backends",
+            format!("All {} backends are up", statuses.len()),
+        ));
+    }
+    findings
+}
+
+fn check_nginx(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    dir: &Path,
+) -> Vec<Finding> {
+    let fix = format!("Run `divvun-worker-static generate {}`", dir.display());
+    let expected = [
+        (
+            "locations.conf",
+            nginx::generate_nginx_config(languages, services),
+        ),
+        ("proxy-headers.conf", nginx::generate_proxy_headers_config()),
+    ];
+
+    let mut findings = Vec::new();
+    for (name, expected) in expected {
+        let path = dir.join(name);
+        match fs::read_to_string(&path) {
+            Ok(actual) if actual == expected => {}
+            Ok(_) => findings.push(Finding::problem(
+                "nginx",
+                Severity::Warning,
+                format!("{} differs from the current config", path.display()),
+                fix.clone(),
+            )),
+            Err(err) => findings.push(Finding::problem(
+                "nginx",
+                Severity::Error,
+                format!("{} can't be read: {}", path.display(), err),
+                fix.clone(),
+            )),
+        }
+    }
+    if findings.is_empty() {
+        findings.push(Finding::ok(
+            "nginx",
+            format!("{} is up to date", dir.display()),
+        ));
+    }
+    findings
+}
+
+fn check_paths(languages: &LanguagesConfig, services: &ServiceRegistry) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if let Some(dir) = &languages.config.static_dir {
+        if let Err(err) = fs::read_dir(dir) {
+            findings.push(Finding::problem(
+                "paths",
+                Severity::Error,
+                format!("static_dir {} can't be read: {}", dir, err),
+                "Create the directory or make it readable by the server's user",
+            ));
+        }
+    }
+
+    let mut hooks: Vec<_> = services
+        .iter()
+        .flat_map(|kind| {
+            kind.locations(languages)
+                .into_iter()
+                .filter_map(|location| languages.wasm_hook(kind.name(), &location.tag))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    hooks.sort_unstable();
+    hooks.dedup();
+    for hook in hooks {
+        if let Err(err) = fs::File::open(hook) {
+            findings.push(Finding::problem(
+                "paths",
+                Severity::Error,
+                format!("WASM hook {} can't be read: {}", hook, err),
+                "Build the hook or correct its path in languages.toml",
+            ));
+        }
+    }
+
+    if findings.is_empty() {
+        findings.push(Finding::ok("paths", "Configured files are readable"));
+    }
+    findings
+}
+
+/// Human-readable findings, each problem followed by its fix.
+pub fn report(findings: &[Finding]) -> String {
+    let mut report = String::new();
+    for finding in findings {
+        let label = match finding.severity {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        report.push_str(&format!(
+            "{:9} {}: {}\n",
+            format!("[{}]", label),
+            finding.check,
+            finding.message
+        ));
+        if let Some(fix) = &finding.fix {
+            report.push_str(&format!("{:9} fix: {}\n", "", fix));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nginx_drift_and_unreadable_paths_are_reported() {
+        let dir = std::env::temp_dir().join(format!("doctor-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        let services = ServiceRegistry::builtin();
+        fs::write(
+            dir.join("locations.conf"),
+            nginx::generate_nginx_config(&languages, &services),
+        )
+        .unwrap();
+
+        let findings = check_nginx(&languages, &services, &dir);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0]
+            .message
+            .contains("proxy-headers.conf can't be read"));
+
+        languages.grammar.remove("se");
+        let findings = check_nginx(&languages, &services, &dir);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        fs::remove_dir_all(&dir).unwrap();
+
+        languages.config.static_dir = Some(dir.display().to_string());
+        let findings = check_paths(&languages, &services);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert!(report(&findings).contains("\n          fix: Create the directory"));
+    }
+}
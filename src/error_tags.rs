@@ -0,0 +1,125 @@
+//! Grammar findings by category. A check request may list `error_code`
+//! prefixes in `ignore_tags` and `include_tags`, e.g. for categories a user
+//! turned off in an editor plugin; the gateway drops the matching `errs`
+//! from the backend's answer.
+
+use anyhow::bail;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    /// Codes starting with any of these are dropped.
+    ignore: Vec<String>,
+    /// If given, only codes starting with one of these are kept.
+    include: Option<Vec<String>>,
+}
+
+impl TagFilter {
+    /// The filter a JSON request body asks for, and the body without the
+    /// filter's fields.
+    pub fn requested(body: Vec<u8>) -> anyhow::Result<(Vec<u8>, Option<Self>)> {
+        let Ok(Value::Object(mut json)) = serde_json::from_slice(&body) else {
+            return Ok((body, None));
+        };
+        let ignore = prefixes(json.remove("ignore_tags"), "ignore_tags")?;
+        let include = prefixes(json.remove("include_tags"), "include_tags")?;
+        if ignore.is_none() && include.is_none() {
+            return Ok((body, None));
+        }
+        let filter = Self {
+            ignore: ignore.unwrap_or_default(),
+            include,
+        };
+        Ok((Value::Object(json).to_string().into_bytes(), Some(filter)))
+    }
+
+    fn keeps(&self, code: &str) -> bool {
+        let included = self.include.as_ref().is_none_or(|include| {
+            include
+                .iter()
+                .any(|prefix| code.starts_with(prefix.as_str()))
+        });
+        included
+            && !self
+                .ignore
+                .iter()
+                .any(|prefix| code.starts_with(prefix.as_str()))
+    }
+
+    /// `body` without the `errs` the filter drops. Findings without an
+    /// `error_code` are only dropped by `include_tags`.
+    pub fn apply(&self, body: Vec<u8>) -> Vec<u8> {
+        let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+            return body;
+        };
+        let Some(errs) = json.get_mut("errs").and_then(Value::as_array_mut) else {
+            return body;
+        };
+        errs.retain(|err| match err.get("error_code").and_then(Value::as_str) {
+            Some(code) => self.keeps(code),
+            None => self.include.is_none(),
+        });
+        json.to_string().into_bytes()
+    }
+}
+
+fn prefixes(value: Option<Value>, field: &str) -> anyhow::Result<Option<Vec<String>>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let Value::Array(items) = value else {
+        bail!("`{}` must be an array of error codes", field);
+    };
+    items
+        .into_iter()
+        .map(|item| match item {
+            Value::String(prefix) => Ok(prefix),
+            _ => bail!("`{}` must be an array of error codes", field),
+        })
+        .collect::<anyhow::Result<_>>()
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn errs_are_filtered_by_code_prefix() {
+        let body =
+            json!({ "text": "sami", "ignore_tags": ["msyn-"], "include_tags": ["msyn", "typo"] });
+        let (body, filter) = TagFilter::requested(body.to_string().into_bytes()).unwrap();
+        assert_eq!(body, br#"{"text":"sami"}"#);
+
+        let response = json!({
+            "text": "sami",
+            "errs": [
+                { "error_code": "typo" },
+                { "error_code": "msyn-agr" },
+                { "error_code": "msyn" },
+                { "error_code": "punct-space" },
+                { "title": "?" },
+            ],
+        });
+        let filtered: Value =
+            serde_json::from_slice(&filter.unwrap().apply(response.to_string().into_bytes()))
+                .unwrap();
+        assert_eq!(
+            filtered["errs"],
+            json!([{ "error_code": "typo" }, { "error_code": "msyn" }])
+        );
+
+        assert_eq!(
+            TagFilter::requested(b"{\"text\":\"sami\"}".to_vec())
+                .unwrap()
+                .1,
+            None
+        );
+        let err = TagFilter::requested(br#"{"ignore_tags":"typo"}"#.to_vec()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`ignore_tags` must be an array of error codes"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! `ETag`s for responses that only change with the configuration, such as
+//! `/languages`. The tag hashes the loaded config together with what else the
+//! response varies by, so a request with a matching `If-None-Match` gets a
+//! 304 without the page being rendered at all.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use poem::{
+    http::{header, HeaderValue, Method, StatusCode},
+    Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+
+use crate::config::LanguagesConfig;
+
+/// Middleware answering conditional `GET`s for a config-derived route.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigETag {
+    fingerprint: u64,
+}
+
+impl ConfigETag {
+    pub fn new(languages: &LanguagesConfig) -> Self {
+        Self {
+            fingerprint: languages.fingerprint(),
+        }
+    }
+
+    /// The tag for `req`: the config, the gateway version and the query and
+    /// `Accept-Language` the response may depend on.
+    fn tag(&self, req: &Request) -> String {
+        let mut hasher = DefaultHasher::new();
+        (
+            self.fingerprint,
+            env!("CARGO_PKG_VERSION"),
+            req.uri().path(),
+            req.uri().query(),
+            req.header(header::ACCEPT_LANGUAGE),
+        )
+            .hash(&mut hasher);
+        format!("\"{:016x}\"", hasher.finish())
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for ConfigETag {
+    type Output = ConfigETagEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ConfigETagEndpoint {
+            inner: ep,
+            etag: *self,
+        }
+    }
+}
+
+pub struct ConfigETagEndpoint<E> {
+    inner: E,
+    etag: ConfigETag,
+}
+
+impl<E: Endpoint> Endpoint for ConfigETagEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return Ok(self.inner.call(req).await?.into_response());
+        }
+        let tag = self.etag.tag(&req);
+        let Ok(value) = HeaderValue::from_str(&tag) else {
+            return Ok(self.inner.call(req).await?.into_response());
+        };
+
+        if req
+            .header(header::IF_NONE_MATCH)
+            .is_some_and(|header| matches(header, &tag))
+        {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, value)
+                .finish());
+        }
+
+        let mut response = self.inner.call(req).await?.into_response();
+        if response.status().is_success() {
+            response.headers_mut().insert(header::ETAG, value);
+        }
+        Ok(response)
+    }
+}
+
+/// Whether an `If-None-Match` header lists `tag`, compared weakly.
+pub(crate) fn matches(header: &str, tag: &str) -> bool {
+    header.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == tag
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_none_match_lists_are_compared_weakly() {
+        assert!(matches("\"a\", W/\"b\"", "\"b\""));
+        assert!(matches("*", "\"b\""));
+        assert!(!matches("\"a\"", "\"b\""));
+    }
+}
@@ -0,0 +1,153 @@
+//! Injected faults, for client teams to test their retry and error handling
+//! against the real API. Configured per path prefix as `[[config.faults]]`,
+//! but only in effect when the server is started with fault injection
+//! enabled, so a copied config can't break production.
+//!
+//! Responses with an injected fault carry an `X-Injected-Fault` header
+//! naming it.
+
+use std::time::Duration;
+
+use poem::{
+    http::{header, StatusCode},
+    Body, Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::cors::matches_prefix;
+use crate::problem::Problem;
+use crate::sampling::Sampler;
+
+const HEADER: &str = "X-Injected-Fault";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultConfig {
+    pub path: String,
+    /// Delay every request by this many milliseconds.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Share of requests answered with `error_status` instead, from 0 to 100.
+    #[serde(default)]
+    pub error_percent: f64,
+    #[serde(default = "default_error_status")]
+    pub error_status: u16,
+    /// Share of responses cut off halfway, from 0 to 100.
+    #[serde(default)]
+    pub truncate_percent: f64,
+}
+
+fn default_error_status() -> u16 {
+    503
+}
+
+struct Faults {
+    config: FaultConfig,
+    errors: Sampler,
+    truncations: Sampler,
+}
+
+/// Middleware injecting the configured faults.
+pub struct FaultInjection(pub Vec<FaultConfig>);
+
+impl<E: Endpoint> Middleware<E> for FaultInjection {
+    type Output = FaultInjectionEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        FaultInjectionEndpoint {
+            inner: ep,
+            routes: self
+                .0
+                .iter()
+                .map(|config| Faults {
+                    config: config.clone(),
+                    errors: Sampler::new(config.error_percent),
+                    truncations: Sampler::new(config.truncate_percent),
+                })
+                .collect(),
+        }
+    }
+}
+
+pub struct FaultInjectionEndpoint<E> {
+    inner: E,
+    routes: Vec<Faults>,
+}
+
+impl<E: Endpoint> Endpoint for FaultInjectionEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let Some(faults) = self
+            .routes
+            .iter()
+            .filter(|faults| matches_prefix(req.uri().path(), &faults.config.path))
+            .max_by_key(|faults| faults.config.path.len())
+        else {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        };
+
+        if faults.config.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(faults.config.latency_ms)).await;
+        }
+        if faults.errors.sample() {
+            let status = StatusCode::from_u16(faults.config.error_status)
+                .unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+            let mut response = Problem::new(status, "Injected fault")
+                .detail("Fault injection is enabled for this route")
+                .into_response();
+            response
+                .headers_mut()
+                .insert(HEADER, "error".parse().unwrap());
+            return Ok(response);
+        }
+
+        let response = match self.inner.call(req).await {
+            Ok(response) => response.into_response(),
+            Err(err) => err.into_response(),
+        };
+        if !faults.truncations.sample() {
+            return Ok(response);
+        }
+        let (mut parts, body) = response.into_parts();
+        let mut body = body.into_vec().await?;
+        body.truncate(body.len() / 2);
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.insert(HEADER, "truncated".parse().unwrap());
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{endpoint::make_sync, test::TestClient, EndpointExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn faults_are_injected_in_the_configured_share() {
+        let client =
+            TestClient::new(
+                make_sync(|_| "0123456789").with(FaultInjection(vec![FaultConfig {
+                    path: "/grammar".to_string(),
+                    latency_ms: 0,
+                    error_percent: 50.0,
+                    error_status: 502,
+                    truncate_percent: 100.0,
+                }])),
+            );
+
+        client
+            .get("/health")
+            .send()
+            .await
+            .assert_text("0123456789")
+            .await;
+
+        let response = client.get("/grammar/se").send().await;
+        response.assert_header(HEADER, "truncated");
+        response.assert_text("01234").await;
+        let response = client.get("/grammar/se").send().await;
+        response.assert_status(StatusCode::BAD_GATEWAY);
+        response.assert_header(HEADER, "error");
+    }
+}
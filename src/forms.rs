@@ -0,0 +1,123 @@
+//! Request bodies other than JSON. Shell scripts and older clients can send
+//! the bare text as `text/plain`, or an HTML form's fields as
+//! `application/x-www-form-urlencoded`; both are turned into the JSON body
+//! the backends expect.
+
+use anyhow::bail;
+use serde_json::{Map, Value};
+
+/// The content type of the bodies [`to_json`] makes.
+pub const JSON: &str = "application/json";
+
+/// `body` as a JSON object, if its `content_type` is plain text or a form.
+/// The plain text is the `text`; form fields keep their names, with numbers
+/// and booleans as such and repeated fields as arrays.
+pub fn to_json(body: &[u8], content_type: Option<&str>) -> anyhow::Result<Option<Vec<u8>>> {
+    let media_type = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|media_type| media_type.trim().to_ascii_lowercase());
+    let json = match media_type.as_deref() {
+        Some("text/plain") => {
+            let text = String::from_utf8_lossy(body);
+            let mut json = Map::new();
+            json.insert("text".to_string(), Value::String(text.into_owned()));
+            json
+        }
+        Some("application/x-www-form-urlencoded") => form_fields(body)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(Value::Object(json).to_string().into_bytes()))
+}
+
+fn form_fields(body: &[u8]) -> anyhow::Result<Map<String, Value>> {
+    let mut json = Map::new();
+    for pair in body.split(|&b| b == b'&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = match pair.iter().position(|&b| b == b'=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, &[][..]),
+        };
+        let (name, value) = (decode(name)?, decode(value)?);
+        let value = match name.as_str() {
+            "text" | "ssml" => Value::String(value),
+            _ => scalar(value),
+        };
+        match json.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(first) => *first = Value::Array(vec![first.take(), value]),
+            None => {
+                json.insert(name, value);
+            }
+        }
+    }
+    if !json.contains_key("text") && !json.contains_key("ssml") {
+        bail!("the form has no `text` field");
+    }
+    Ok(json)
+}
+
+fn scalar(value: String) -> Value {
+    match serde_json::from_str(&value) {
+        Ok(value @ (Value::Number(_) | Value::Bool(_))) => value,
+        _ => Value::String(value),
+    }
+}
+
+/// Undo the percent-encoding of a form field, with `+` for spaces.
+fn decode(encoded: &[u8]) -> anyhow::Result<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded;
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                let Some(byte) = hex else {
+                    bail!("invalid percent-encoding in the form");
+                };
+                bytes.push(byte);
+                rest = &rest[2..];
+            }
+            _ => bytes.push(b),
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parsed(body: &str, content_type: &str) -> Value {
+        let json = to_json(body.as_bytes(), Some(content_type))
+            .unwrap()
+            .unwrap();
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    #[test]
+    fn plain_text_and_forms_become_json() {
+        assert_eq!(
+            parsed("Mun lean \"sami\"\n", "text/plain; charset=utf-8"),
+            json!({ "text": "Mun lean \"sami\"\n" })
+        );
+        assert_eq!(
+            parsed(
+                "text=12+s%C3%A1mi%26&max_suggestions=3&ignore_tags=typo&ignore_tags=punct",
+                "application/x-www-form-urlencoded"
+            ),
+            json!({
+                "text": "12 sámi&",
+                "max_suggestions": 3,
+                "ignore_tags": ["typo", "punct"],
+            })
+        );
+        assert_eq!(to_json(b"{}", Some("application/json")).unwrap(), None);
+        assert!(to_json(b"voice=biret", Some("application/x-www-form-urlencoded")).is_err());
+        assert!(to_json(b"text=%E", Some("application/x-www-form-urlencoded")).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! HAProxy configuration generation, an alternative to the nginx locations:
+//! one ACL and backend per configured service, language and voice, checked
+//! the way the gateway's health monitor probes them, with a server for each
+//! of its backend instances. Everything else goes to the gateway.
+
+use crate::balance::Balance;
+use crate::config::LanguagesConfig;
+use crate::health::PROBE_INTERVAL;
+use crate::services::{Location, ServiceRegistry, PROBE_TIMEOUT};
+
+/// Render a frontend routing each location to its backend and `gateway`,
+/// e.g. `http://127.0.0.1:4000`, for the rest.
+pub fn generate_haproxy_config(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    gateway: &str,
+) -> String {
+    let locations = services.proxy_locations(languages);
+    let acls: Vec<String> = locations
+        .iter()
+        .map(|location| {
+            format!(
+                "    acl {} path {}\n    use_backend {} if {}",
+                backend_name(location),
+                location.path,
+                backend_name(location),
+                backend_name(location)
+            )
+        })
+        .collect();
+    let mut backends: Vec<String> = locations
+        .iter()
+        .map(|location| {
+            let headers = languages.response_headers(&location.path);
+            backend_block(location, &headers)
+        })
+        .collect();
+    backends.push(format!(
+        "backend gateway\n    mode http\n    server gateway {} check inter {}s",
+        address(gateway),
+        PROBE_INTERVAL.as_secs()
+    ));
+
+    format!(
+        "# Generated by divvun-worker-static. Adjust the bind line to your deployment.\n\n\
+         frontend divvun\n    mode http\n    bind :80\n    \
+         http-request set-header X-Real-IP %[src]\n{}\n    default_backend gateway\n\n{}\n",
+        acls.join("\n"),
+        backends.join("\n\n")
+    )
+}
+
+fn backend_block(location: &Location, headers: &[(String, String)]) -> String {
+    let name = backend_name(location);
+    let inter = PROBE_INTERVAL.as_secs();
+    let mut lines = vec![
+        format!("backend {}", name),
+        "    mode http".to_string(),
+        "    http-request set-path /".to_string(),
+    ];
+    if !location.query.is_empty() {
+        lines.push(format!(
+            "    http-request set-query {}",
+            location.query_string()
+        ));
+    }
+    lines.extend(
+        headers
+            .iter()
+            .map(|(name, value)| format!("    http-response set-header {} \"{}\"", name, value)),
+    );
+    let servers = location.servers();
+    if servers.len() > 1 {
+        lines.push(match location.balance {
+            Balance::RoundRobin => "    balance roundrobin".to_string(),
+            Balance::LeastOutstanding => "    balance leastconn".to_string(),
+        });
+    }
+    lines.push(format!("    timeout check {}s", PROBE_TIMEOUT.as_secs()));
+    for (index, server) in servers.iter().enumerate() {
+        let server_name = match servers.len() {
+            1 => name.clone(),
+            _ => format!("{}-{}", name, index + 1),
+        };
+        let weight = match server.weight {
+            1 => String::new(),
+            weight => format!(" weight {}", weight),
+        };
+        lines.push(format!(
+            "    server {} {} check inter {}s{}",
+            server_name, server, inter, weight
+        ));
+    }
+    if let Some(fallback) = &location.fallback {
+        lines.push(format!(
+            "    server fallback {}:{} check inter {}s backup",
+            fallback.host, fallback.port, inter
+        ));
+    }
+    lines.join("\n")
+}
+
+/// ACL and backend name for a location, e.g. `tts-se-biret`.
+fn backend_name(location: &Location) -> String {
+    location.path.trim_start_matches('/').replace('/', "-")
+}
+
+/// The `host:port` of a URL such as `http://127.0.0.1:4000/`.
+fn address(url: &str) -> &str {
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    authority.split('/').next().unwrap_or(authority)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_are_checked_like_the_health_monitor_does() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().fallback = Some(crate::config::Fallback {
+            host: "10.0.0.2".to_string(),
+            port: 10000,
+        });
+        let config = generate_haproxy_config(
+            &languages,
+            &ServiceRegistry::builtin(),
+            "http://127.0.0.1:4000/",
+        );
+        let block = |name: &str| {
+            config
+                .split("\n\n")
+                .find(|block| block.starts_with(&format!("backend {}\n", name)))
+                .unwrap()
+                .trim_end()
+        };
+
+        assert!(config.contains(
+            "    acl grammar-se path /grammar/se\n    use_backend grammar-se if grammar-se\n"
+        ));
+        assert_eq!(
+            block("grammar-se"),
+            "backend grammar-se\n    mode http\n    http-request set-path /\n    \
+             timeout check 2s\n    server grammar-se 127.0.0.1:10000 check inter 30s\n    \
+             server fallback 10.0.0.2:10000 check inter 30s backup"
+        );
+        assert!(
+            block("tts-smj-sigga").contains("    http-request set-query language=2&speaker=3\n")
+        );
+        assert_eq!(
+            block("gateway"),
+            "backend gateway\n    mode http\n    server gateway 127.0.0.1:4000 check inter 30s"
+        );
+    }
+
+    #[test]
+    fn instances_are_servers_of_one_backend() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().ports = vec![
+            crate::config::Instance::local(10000),
+            crate::config::Instance {
+                host: "10.0.1.4".to_string(),
+                port: 10000,
+                weight: 2,
+            },
+        ];
+        let config = generate_haproxy_config(
+            &languages,
+            &ServiceRegistry::builtin(),
+            "http://127.0.0.1:4000/",
+        );
+
+        assert!(config.contains(
+            "backend grammar-se\n    mode http\n    http-request set-path /\n    \
+             balance leastconn\n    timeout check 2s\n    \
+             server grammar-se-1 127.0.0.1:10000 check inter 30s\n    \
+             server grammar-se-2 10.0.1.4:10000 check inter 30s weight 2\n"
+        ));
+    }
+}
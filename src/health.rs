@@ -0,0 +1,422 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use serde_json::json;
+use tokio::task::JoinSet;
+
+use crate::config::LanguagesConfig;
+use crate::services::{Backend, Location, ServiceKind, ServiceRegistry, PROBE_TIMEOUT};
+use crate::table;
+
+pub(crate) const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Text sent to backends by [`check`] when canned requests are enabled.
+const CANNED_TEXT: &str = "Bures";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendStatus {
+    pub service: String,
+    pub tag: String,
+    pub port: u16,
+    /// `None` until the first probe has completed.
+    pub up: Option<bool>,
+    pub latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+    /// Unix timestamp of the last completed probe.
+    pub last_checked: Option<u64>,
+}
+
+/// A language an operator took out of service through the admin API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Disabled {
+    pub service: String,
+    pub tag: String,
+    /// Seconds until it is enabled again by itself, if ever.
+    pub remaining: Option<u64>,
+}
+
+/// Periodically probes every configured backend and keeps the latest result
+/// per service and tag, along with the languages disabled by operators.
+#[derive(Clone)]
+pub struct HealthMonitor {
+    targets: Arc<RwLock<Targets>>,
+    backends: Arc<RwLock<Vec<BackendStatus>>>,
+    /// Disabled languages by service and tag, with when they come back.
+    drained: Arc<RwLock<Vec<Drained>>>,
+}
+
+type Targets = Arc<Vec<(Arc<dyn ServiceKind>, Backend)>>;
+type Drained = (String, String, Option<Instant>);
+
+impl HealthMonitor {
+    pub fn new(languages: &LanguagesConfig, services: &ServiceRegistry) -> Self {
+        let (targets, backends) = targets(languages, services);
+        Self {
+            targets: Arc::new(RwLock::new(targets)),
+            backends: Arc::new(RwLock::new(backends)),
+            drained: Arc::default(),
+        }
+    }
+
+    /// Probe the backends of a new configuration from now on. Statuses of
+    /// backends that kept their service, tag and port are carried over.
+    pub fn retarget(&self, languages: &LanguagesConfig, services: &ServiceRegistry) {
+        let (targets, mut backends) = targets(languages, services);
+        let mut current_targets = self.targets.write().unwrap();
+        let mut current = self.backends.write().unwrap();
+        for backend in &mut backends {
+            if let Some(previous) = current.iter().find(|previous| {
+                previous.service == backend.service
+                    && previous.tag == backend.tag
+                    && previous.port == backend.port
+            }) {
+                *backend = previous.clone();
+            }
+        }
+        *current_targets = targets;
+        *current = backends;
+    }
+
+    pub fn snapshot(&self) -> Vec<BackendStatus> {
+        self.backends.read().unwrap().clone()
+    }
+
+    /// Whether the last probe of `service`'s backend for `tag` on `port`
+    /// failed.
+    pub fn is_down(&self, service: &str, tag: &str, port: u16) -> bool {
+        self.backends.read().unwrap().iter().any(|backend| {
+            backend.service == service
+                && backend.tag == tag
+                && backend.port == port
+                && backend.up == Some(false)
+        })
+    }
+
+    /// Turn requests for `service`'s `tag` away, for `duration` or until
+    /// [`enable`](Self::enable)d.
+    pub fn disable(&self, service: &str, tag: &str, duration: Option<Duration>) {
+        let until = duration.map(|duration| Instant::now() + duration);
+        let mut disabled = self.drained.write().unwrap();
+        disabled.retain(|(s, t, _)| (s.as_str(), t.as_str()) != (service, tag));
+        disabled.push((service.to_string(), tag.to_string(), until));
+    }
+
+    /// Serve `service`'s `tag` again. Returns whether it was disabled.
+    pub fn enable(&self, service: &str, tag: &str) -> bool {
+        let was_disabled = self.disabled(service, tag).is_some();
+        let mut disabled = self.drained.write().unwrap();
+        disabled.retain(|(s, t, _)| (s.as_str(), t.as_str()) != (service, tag));
+        was_disabled
+    }
+
+    /// Whether `service`'s `tag` is disabled, and for how long.
+    pub fn disabled(&self, service: &str, tag: &str) -> Option<Disabled> {
+        self.disabled_languages()
+            .into_iter()
+            .find(|disabled| disabled.service == service && disabled.tag == tag)
+    }
+
+    /// The languages disabled now, in the order they were disabled.
+    pub fn disabled_languages(&self) -> Vec<Disabled> {
+        let now = Instant::now();
+        self.drained
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, _, until)| until.is_none_or(|until| until > now))
+            .map(|(service, tag, until)| Disabled {
+                service: service.clone(),
+                tag: tag.clone(),
+                remaining: until.map(|until| (until - now).as_secs_f64().ceil() as u64),
+            })
+            .collect()
+    }
+
+    /// Whether the gateway can serve traffic: true unless backends have been
+    /// probed and none of them is up.
+    pub fn ready(&self) -> bool {
+        let backends = self.backends.read().unwrap();
+        let mut probed = backends.iter().filter_map(|backend| backend.up).peekable();
+        probed.peek().is_none() || probed.any(|up| up)
+    }
+
+    /// Start probing in the background for the lifetime of the process.
+    pub fn spawn(&self) {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROBE_INTERVAL);
+            loop {
+                interval.tick().await;
+                monitor.probe_all().await;
+            }
+        });
+    }
+
+    pub(crate) async fn probe_all(&self) {
+        let targets = self.targets.read().unwrap().clone();
+        let mut probes = JoinSet::new();
+        for index in 0..targets.len() {
+            let targets = targets.clone();
+            probes.spawn(async move {
+                let (kind, backend) = &targets[index];
+                (index, kind.probe(backend).await)
+            });
+        }
+
+        let checked_at = now();
+        while let Some(Ok((index, result))) = probes.join_next().await {
+            // Results for targets replaced while probing are stale
+            if !Arc::ptr_eq(&targets, &self.targets.read().unwrap()) {
+                return;
+            }
+            let mut backends = self.backends.write().unwrap();
+            let backend = &mut backends[index];
+            if let Err(err) = &result {
+                if backend.up != Some(false) {
+                    tracing::warn!(
+                        "{} {} (port {}) is down: {}",
+                        backend.service,
+                        backend.tag,
+                        backend.port,
+                        err
+                    );
+                }
+            }
+            backend.record(result, checked_at);
+        }
+    }
+}
+
+fn targets(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+) -> (Targets, Vec<BackendStatus>) {
+    let targets: Vec<_> = services
+        .iter()
+        .flat_map(|kind| {
+            kind.backends(languages)
+                .into_iter()
+                .map(|backend| (kind.clone(), backend))
+        })
+        .collect();
+
+    let backends = targets
+        .iter()
+        .map(|(kind, backend)| BackendStatus::pending(kind.name(), &backend.tag, backend.port))
+        .collect();
+    (Arc::new(targets), backends)
+}
+
+/// Probe every configured backend once. With `canned`, backends of
+/// categories that take a text also have to answer a short text
+/// successfully, and the latency is that of the request.
+pub async fn check(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    canned: bool,
+) -> Vec<BackendStatus> {
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let mut statuses = Vec::new();
+    let mut probes = JoinSet::new();
+
+    for kind in services.iter() {
+        let locations = kind.locations(languages);
+        for backend in kind.backends(languages) {
+            let location = locations
+                .iter()
+                .find(|location| {
+                    location.tag == backend.tag
+                        && location.host == backend.host
+                        && location.port == backend.port
+                })
+                .filter(|_| canned && kind.request_schema().is_some())
+                .cloned();
+            statuses.push(BackendStatus::pending(
+                kind.name(),
+                &backend.tag,
+                backend.port,
+            ));
+
+            let index = statuses.len() - 1;
+            let kind = kind.clone();
+            let client = client.clone();
+            probes.spawn(async move {
+                let result = match (kind.probe(&backend).await, location) {
+                    (Ok(_), Some(location)) => send_canned(&client, &location).await,
+                    (result, _) => result,
+                };
+                (index, result)
+            });
+        }
+    }
+
+    let checked_at = now();
+    while let Some(Ok((index, result))) = probes.join_next().await {
+        statuses[index].record(result, checked_at);
+    }
+    statuses
+}
+
+/// Probe every backend once, logging the unreachable ones, and fail if there
+/// are any, so a misconfigured deployment stops before it serves 502s.
+pub async fn preflight(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+) -> anyhow::Result<()> {
+    let statuses = check(languages, services, false).await;
+    let down: Vec<_> = statuses
+        .iter()
+        .filter(|status| status.up != Some(true))
+        .collect();
+    for status in &down {
+        tracing::error!(
+            "{} {} (port {}) is unreachable: {}",
+            status.service,
+            status.tag,
+            status.port,
+            status.last_error.as_deref().unwrap_or("no answer")
+        );
+    }
+    if !down.is_empty() {
+        anyhow::bail!(
+            "{} of {} backends are unreachable",
+            down.len(),
+            statuses.len()
+        );
+    }
+    tracing::info!("All {} backends are reachable", statuses.len());
+    Ok(())
+}
+
+async fn send_canned(client: &reqwest::Client, location: &Location) -> Result<Duration, String> {
+    let start = Instant::now();
+    let response = client
+        .post(location.backend_url())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(json!({ "text": CANNED_TEXT }).to_string())
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    match response.status() {
+        status if status.is_success() => Ok(start.elapsed()),
+        status => Err(format!("answered {}", status)),
+    }
+}
+
+/// A plain-text table of `statuses`, one backend per line.
+pub fn table(statuses: &[BackendStatus]) -> String {
+    let rows: Vec<Vec<String>> = statuses
+        .iter()
+        .map(|status| {
+            vec![
+                status.service.clone(),
+                status.tag.clone(),
+                status.port.to_string(),
+                match status.up {
+                    Some(true) => "up",
+                    Some(false) => "DOWN",
+                    None => "pending",
+                }
+                .to_string(),
+                status
+                    .latency_ms
+                    .map(|ms| format!("{}ms", ms))
+                    .unwrap_or_default(),
+                status.last_error.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    table::render(
+        &["SERVICE", "TAG", "PORT", "STATUS", "LATENCY", "ERROR"],
+        &rows,
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl BackendStatus {
+    fn pending(service: &str, tag: &str, port: u16) -> Self {
+        Self {
+            service: service.to_string(),
+            tag: tag.to_string(),
+            port,
+            up: None,
+            latency_ms: None,
+            last_error: None,
+            last_checked: None,
+        }
+    }
+
+    fn record(&mut self, result: Result<Duration, String>, checked_at: u64) {
+        self.last_checked = Some(checked_at);
+        match result {
+            Ok(latency) => {
+                self.up = Some(true);
+                self.latency_ms = Some(latency.as_millis() as u64);
+            }
+            Err(err) => {
+                self.up = Some(false);
+                self.latency_ms = None;
+                self.last_error = Some(err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockBackend;
+
+    #[tokio::test]
+    async fn check_sends_canned_texts() {
+        let backend = MockBackend::grammar().await.unwrap();
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.speller.clear();
+        languages.hyphenation.clear();
+        languages.tts.clear();
+        languages.custom.clear();
+        languages
+            .grammar
+            .retain(|tag, _| tag == "se" || tag == "sma");
+        languages.grammar.get_mut("se").unwrap().port = backend.port();
+        languages.grammar.get_mut("sma").unwrap().port = 1;
+
+        let mut statuses = check(&languages, &ServiceRegistry::builtin(), true).await;
+        statuses.sort_by(|a, b| a.tag.cmp(&b.tag));
+        assert_eq!(statuses[0].up, Some(true));
+        assert_eq!(statuses[1].up, Some(false));
+        assert_eq!(backend.requests()[0].text().as_deref(), Some(CANNED_TEXT));
+
+        let table = table(&statuses);
+        assert!(table.starts_with("SERVICE  TAG  PORT"));
+        assert!(table
+            .lines()
+            .any(|line| line.contains("sma") && line.contains("DOWN")));
+    }
+
+    #[tokio::test]
+    async fn not_ready_once_every_backend_is_down() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.speller.clear();
+        languages.hyphenation.clear();
+        languages.tts.clear();
+        languages.custom.clear();
+        languages.grammar.retain(|tag, _| tag == "se");
+        languages.grammar.get_mut("se").unwrap().port = 1;
+
+        let monitor = HealthMonitor::new(&languages, &ServiceRegistry::builtin());
+        assert!(monitor.ready());
+        monitor.probe_all().await;
+        assert!(!monitor.ready());
+    }
+}
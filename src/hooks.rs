@@ -0,0 +1,149 @@
+//! WASM modules that rewrite proxied request and response bodies.
+//!
+//! A hook module exports its `memory`, an `alloc(len: i32) -> i32` function
+//! and either or both of
+//!
+//! - `transform_request(ptr: i32, len: i32) -> i64`
+//! - `transform_response(ptr: i32, len: i32) -> i64`
+//!
+//! The gateway copies the body into memory returned by `alloc` and calls the
+//! transform, which returns the output location packed as `ptr << 32 | len`.
+//! Missing transforms pass the body through unchanged. Every call runs in a
+//! fresh instance with a fuel limit, so hooks can't keep state between
+//! requests or stall the gateway.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use wasmi::{Config, Engine, Linker, Module, Store};
+
+/// Default fuel a single transform may consume before it is aborted.
+const FUEL_LIMIT: u64 = 50_000_000;
+
+pub struct WasmHook {
+    engine: Engine,
+    module: Module,
+    fuel_limit: u64,
+}
+
+impl WasmHook {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let wasm = std::fs::read(path)
+            .with_context(|| format!("failed to read WASM hook {}", path.display()))?;
+        Self::from_bytes(&wasm).with_context(|| format!("invalid WASM hook {}", path.display()))
+    }
+
+    pub fn from_bytes(wasm: &[u8]) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(|err| anyhow!(err))?;
+        Ok(Self {
+            engine,
+            module,
+            fuel_limit: FUEL_LIMIT,
+        })
+    }
+
+    pub fn with_fuel_limit(mut self, fuel_limit: u64) -> Self {
+        self.fuel_limit = fuel_limit;
+        self
+    }
+
+    pub fn transform_request(&self, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        self.call("transform_request", body)
+    }
+
+    pub fn transform_response(&self, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        self.call("transform_response", body)
+    }
+
+    fn call(&self, export: &str, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if self.module.get_export(export).is_none() {
+            return Ok(body);
+        }
+
+        let mut store = Store::new(&self.engine, ());
+        store
+            .set_fuel(self.fuel_limit)
+            .map_err(|err| anyhow!(err))?;
+
+        let instance = Linker::<()>::new(&self.engine)
+            .instantiate_and_start(&mut store, &self.module)
+            .map_err(|err| anyhow!(err))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow!("hook does not export its memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|err| anyhow!(err))?;
+        let transform = instance
+            .get_typed_func::<(i32, i32), i64>(&store, export)
+            .map_err(|err| anyhow!(err))?;
+
+        let len = i32::try_from(body.len()).context("body too large for WASM hook")?;
+        let ptr = alloc.call(&mut store, len).map_err(|err| anyhow!(err))?;
+        memory
+            .write(&mut store, ptr as u32 as usize, &body)
+            .map_err(|err| anyhow!(err))?;
+
+        let packed = transform
+            .call(&mut store, (ptr, len))
+            .map_err(|err| anyhow!("{} failed: {}", export, err))?;
+        let out_ptr = (packed as u64 >> 32) as usize;
+        let out_len = (packed as u64 & 0xffff_ffff) as usize;
+
+        let mut out = vec![0; out_len];
+        memory
+            .read(&store, out_ptr, &mut out)
+            .map_err(|err| anyhow!(err))?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLACING: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (data (i32.const 1024) "replaced")
+            (func (export "alloc") (param i32) (result i32)
+                i32.const 0)
+            (func (export "transform_response") (param i32 i32) (result i64)
+                i64.const 4398046511112))
+    "#;
+
+    #[test]
+    fn transform_output_is_read_back() {
+        let hook = WasmHook::from_bytes(&wat::parse_str(REPLACING).unwrap()).unwrap();
+        let out = hook.transform_response(b"original".to_vec()).unwrap();
+        assert_eq!(out, b"replaced");
+    }
+
+    #[test]
+    fn missing_transform_passes_body_through() {
+        let hook = WasmHook::from_bytes(&wat::parse_str(REPLACING).unwrap()).unwrap();
+        let out = hook.transform_request(b"original".to_vec()).unwrap();
+        assert_eq!(out, b"original");
+    }
+
+    #[test]
+    fn runaway_hooks_run_out_of_fuel() {
+        let looping = r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32)
+                    i32.const 0)
+                (func (export "transform_request") (param i32 i32) (result i64)
+                    (loop (br 0))
+                    i64.const 0))
+        "#;
+        let hook = WasmHook::from_bytes(&wat::parse_str(looping).unwrap())
+            .unwrap()
+            .with_fuel_limit(10_000);
+        assert!(hook.transform_request(b"text".to_vec()).is_err());
+    }
+}
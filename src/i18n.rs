@@ -0,0 +1,296 @@
+//! Localized strings for the HTML pages, from the Fluent files in `locales/`,
+//! and the choice of locale for a request.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use fluent_langneg::{negotiate_languages, NegotiationStrategy};
+use fluent_syntax::ast::Entry;
+use unic_langid::LanguageIdentifier;
+
+const DEFAULT_LOCALE: &str = "en";
+
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("nb", include_str!("../locales/nb.ftl")),
+    ("se", include_str!("../locales/se.ftl")),
+];
+
+struct Catalog {
+    bundle: FluentBundle<FluentResource>,
+    /// The id of every message, as bundles can't list them.
+    ids: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Catalogs {
+    catalogs: Arc<HashMap<String, Catalog>>,
+    /// The locales with a catalog, in a stable order for negotiation.
+    available: Arc<Vec<LanguageIdentifier>>,
+}
+
+impl Catalogs {
+    pub fn load() -> anyhow::Result<Self> {
+        let mut catalogs = HashMap::new();
+        let mut available = Vec::new();
+        for (tag, source) in CATALOGS {
+            let catalog =
+                Catalog::parse(tag, source).with_context(|| format!("locales/{}.ftl", tag))?;
+            catalogs.insert(tag.to_string(), catalog);
+            available.push(tag.parse()?);
+        }
+        Ok(Self {
+            catalogs: Arc::new(catalogs),
+            available: Arc::new(available),
+        })
+    }
+
+    /// Pick a locale from an explicit `?lang=` override, falling back to the
+    /// `Accept-Language` header and finally to English.
+    pub fn negotiate(&self, lang: Option<&str>, accept_language: Option<&str>) -> Localizer<'_> {
+        let requested: Vec<_> = lang
+            .into_iter()
+            .map(str::to_string)
+            .chain(
+                accept_language
+                    .map(parse_accept_language)
+                    .unwrap_or_default(),
+            )
+            .filter_map(|tag| requested(&tag))
+            .collect();
+        let default = self
+            .available
+            .iter()
+            .find(|locale| locale.language == DEFAULT_LOCALE);
+        let tag = negotiate_languages(
+            &requested,
+            &self.available,
+            default,
+            NegotiationStrategy::Lookup,
+        )
+        .first()
+        .map_or(DEFAULT_LOCALE.to_string(), |locale| locale.to_string());
+
+        let (tag, catalog) = self
+            .catalogs
+            .get_key_value(&tag)
+            .unwrap_or_else(|| self.catalogs.get_key_value(DEFAULT_LOCALE).unwrap());
+        Localizer {
+            tag,
+            catalog,
+            fallback: &self.catalogs[DEFAULT_LOCALE],
+        }
+    }
+}
+
+impl Catalog {
+    fn parse(tag: &str, source: &str) -> anyhow::Result<Self> {
+        let resource = FluentResource::try_new(source.to_string()).map_err(|(_, errors)| {
+            anyhow::anyhow!(
+                "{}",
+                errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            )
+        })?;
+        let ids = resource
+            .entries()
+            .filter_map(|entry| match entry {
+                Entry::Message(message) => Some(message.id.name.to_string()),
+                _ => None,
+            })
+            .collect();
+
+        let mut bundle = FluentBundle::new_concurrent(vec![tag.parse()?]);
+        // The messages are HTML, where Unicode isolation marks would only show
+        bundle.set_use_isolating(false);
+        if let Err(errors) = bundle.add_resource(resource) {
+            bail!(
+                "{}",
+                errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+        }
+        Ok(Self { bundle, ids })
+    }
+}
+
+/// The locale a requested tag stands for, with the older and macrolanguage
+/// codes of the catalogs' languages mapped to theirs.
+fn requested(tag: &str) -> Option<LanguageIdentifier> {
+    let mut locale: LanguageIdentifier = tag.replace('_', "-").parse().ok()?;
+    let language = match locale.language.as_str() {
+        "no" | "nn" | "nob" | "nno" => "nb",
+        "sme" => "se",
+        "eng" => "en",
+        _ => return Some(locale),
+    };
+    locale.language = language.parse().ok()?;
+    Some(locale)
+}
+
+/// Accept-Language tags ordered by descending quality.
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut parts = part.trim().split(';');
+            let tag = parts.next()?.trim();
+            if tag.is_empty() || tag == "*" {
+                return None;
+            }
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            Some((tag.to_string(), q))
+        })
+        .collect();
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+pub struct Localizer<'a> {
+    tag: &'a str,
+    catalog: &'a Catalog,
+    fallback: &'a Catalog,
+}
+
+impl<'a> Localizer<'a> {
+    pub fn tag(&self) -> &'a str {
+        self.tag
+    }
+
+    /// The message `key`, or the key itself if no catalog has it.
+    pub fn t<'k>(&self, key: &'k str) -> Cow<'k, str>
+    where
+        'a: 'k,
+    {
+        self.format(key, None).unwrap_or(Cow::Borrowed(key))
+    }
+
+    /// The message `key` with its `{ $name }` variables set from `args`.
+    pub fn t_args<'k>(&self, key: &'k str, args: &[(&str, FluentValue<'_>)]) -> Cow<'k, str>
+    where
+        'a: 'k,
+    {
+        let args = args.iter().cloned().collect::<FluentArgs>();
+        self.format(key, Some(&args))
+            .map(|message| Cow::Owned(message.into_owned()))
+            .unwrap_or(Cow::Borrowed(key))
+    }
+
+    /// Every message, by key, for templates.
+    pub fn messages(&self) -> HashMap<&'a str, Cow<'a, str>> {
+        self.fallback
+            .ids
+            .iter()
+            .chain(&self.catalog.ids)
+            .filter_map(|key| Some((key.as_str(), self.format(key, None)?)))
+            .collect()
+    }
+
+    fn format(&self, key: &str, args: Option<&FluentArgs>) -> Option<Cow<'a, str>> {
+        [self.catalog, self.fallback]
+            .into_iter()
+            .find_map(|catalog| {
+                let pattern = catalog.bundle.get_message(key)?.value()?;
+                let mut errors = Vec::new();
+                let message = catalog.bundle.format_pattern(pattern, args, &mut errors);
+                if !errors.is_empty() {
+                    tracing::warn!("message {} of {}: {:?}", key, self.tag, errors);
+                }
+                Some(message)
+            })
+    }
+
+    /// Replace every `{{key}}` placeholder in `template` with its message.
+    /// Placeholders without a message are left in place for later passes.
+    pub fn localize(&self, template: &str) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                break;
+            };
+            out.push_str(&rest[..start]);
+            let key = &rest[start + 2..start + end];
+            match self.format(key, None) {
+                Some(message) => out.push_str(&message),
+                None => out.push_str(&rest[start..start + end + 2]),
+            }
+            rest = &rest[start + end + 2..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lang_override_wins_over_accept_language() {
+        let catalogs = Catalogs::load().unwrap();
+        let l = catalogs.negotiate(Some("se"), Some("nb-NO"));
+        assert_eq!(l.tag(), "se");
+    }
+
+    #[test]
+    fn accept_language_respects_quality_and_aliases() {
+        let catalogs = Catalogs::load().unwrap();
+        let l = catalogs.negotiate(None, Some("de;q=0.9, en;q=0.2, no;q=0.5"));
+        assert_eq!(l.tag(), "nb");
+        let l = catalogs.negotiate(Some("xx"), Some("fr"));
+        assert_eq!(l.tag(), "en");
+    }
+
+    #[test]
+    fn fluent_messages_are_formatted() {
+        let catalog = Catalog::parse(
+            "nb",
+            "# Docs page\ntitle = Divvun API\nhint = Send <code>{\"{\"}\"text\": …{\"}\"}</code>\n    or plain text.\nago = { $seconds ->\n    [one] { $seconds } sekund siden\n   *[other] { $seconds } sekunder siden\n}\n",
+        )
+        .unwrap();
+        assert_eq!(catalog.ids, ["title", "hint", "ago"]);
+        let l = Localizer {
+            tag: "nb",
+            catalog: &catalog,
+            fallback: &catalog,
+        };
+        assert_eq!(l.t("title"), "Divvun API");
+        assert_eq!(
+            l.t("hint"),
+            "Send <code>{\"text\": …}</code>\nor plain text."
+        );
+        assert_eq!(l.t_args("ago", &[("seconds", 1.into())]), "1 sekund siden");
+        assert_eq!(
+            l.t_args("ago", &[("seconds", 5.into())]),
+            "5 sekunder siden"
+        );
+        assert_eq!(l.t("missing"), "missing");
+
+        assert!(Catalog::parse("en", "hint = {").is_err());
+        assert!(Catalog::parse("en", "hint = A\nhint = B").is_err());
+    }
+
+    #[test]
+    fn unknown_placeholders_are_left_in_place() {
+        let catalogs = Catalogs::load().unwrap();
+        let l = catalogs.negotiate(Some("en"), None);
+        assert_eq!(
+            l.localize("<h2>{{endpoints_title}}</h2>{{unknown}}"),
+            "<h2>Endpoints</h2>{{unknown}}"
+        );
+    }
+}
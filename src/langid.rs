@@ -0,0 +1,225 @@
+//! Language identification for routing text to the right checker.
+//!
+//! The built-in [`OrthographyIdentifier`] is a heuristic: it scores each
+//! candidate by letters and short function words characteristic of its
+//! orthography. That is enough to tell Sámi paragraphs from Norwegian ones,
+//! but deployments with a proper classifier can supply their own
+//! [`LanguageIdentifier`] through the server builder.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+pub trait LanguageIdentifier: Send + Sync {
+    /// The most likely of `candidates` for `text`, or `None` if there is no
+    /// evidence either way.
+    fn identify(&self, text: &str, candidates: &[&str]) -> Option<String>;
+
+    /// The likely `candidates` for `text` with scores between 0 and 1, most
+    /// likely first. Identifiers without scores rank their one answer.
+    fn rank(&self, text: &str, candidates: &[&str]) -> Vec<(String, f64)> {
+        self.identify(text, candidates)
+            .map(|tag| vec![(tag, 1.0)])
+            .unwrap_or_default()
+    }
+}
+
+struct Orthography {
+    tag: &'static str,
+    letters: &'static str,
+    words: &'static [&'static str],
+}
+
+const ORTHOGRAPHIES: &[Orthography] = &[
+    Orthography {
+        tag: "se",
+        letters: "áčđŋšŧž",
+        words: &["ja", "lea", "leat", "ii", "go", "dat", "mii", "das", "dán"],
+    },
+    Orthography {
+        tag: "smj",
+        letters: "áŋäå",
+        words: &["ja", "la", "li", "ij", "gå", "dat", "mij", "dan"],
+    },
+    Orthography {
+        tag: "sma",
+        letters: "ïöäæøå",
+        words: &["jïh", "lea", "ij", "dle", "dan", "mij", "gaajhkh"],
+    },
+    Orthography {
+        tag: "smn",
+        letters: "áâčđäŋšž",
+        words: &["já", "lii", "lává", "ij", "tot", "mii", "tast"],
+    },
+    Orthography {
+        tag: "sms",
+        letters: "âčǯǥǧǩŋõšžåäʹđ",
+        words: &["da", "lij", "ij", "tõt", "mõõn", "leʹbe"],
+    },
+    Orthography {
+        tag: "nb",
+        letters: "æøå",
+        words: &[
+            "og", "er", "det", "som", "på", "ikke", "en", "til", "med", "jeg",
+        ],
+    },
+    Orthography {
+        tag: "fo",
+        letters: "ðøáíóúýæ",
+        words: &["og", "er", "tað", "sum", "á", "ikki", "við", "eg"],
+    },
+    Orthography {
+        tag: "ga",
+        letters: "áéíóú",
+        words: &["agus", "is", "tá", "an", "na", "ar", "le", "go"],
+    },
+    Orthography {
+        tag: "kl",
+        letters: "",
+        words: &["aamma", "una", "taanna", "naak", "qanoq"],
+    },
+];
+
+/// Scores candidates by characteristic letters and function words. Letters
+/// shared by several candidates count for less.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OrthographyIdentifier;
+
+impl LanguageIdentifier for OrthographyIdentifier {
+    fn identify(&self, text: &str, candidates: &[&str]) -> Option<String> {
+        self.rank(text, candidates)
+            .into_iter()
+            .next()
+            .map(|(tag, _)| tag)
+    }
+
+    /// Scores are each candidate's share of the evidence found.
+    fn rank(&self, text: &str, candidates: &[&str]) -> Vec<(String, f64)> {
+        let orthographies: Vec<_> = ORTHOGRAPHIES
+            .iter()
+            .filter(|o| candidates.contains(&o.tag))
+            .collect();
+
+        let mut sharing: HashMap<char, usize> = HashMap::new();
+        for orthography in &orthographies {
+            for c in orthography.letters.chars() {
+                *sharing.entry(c).or_default() += 1;
+            }
+        }
+
+        let lower = text.to_lowercase();
+        let words: Vec<_> = lower
+            .split(|c: char| !c.is_alphabetic() && c != 'ʹ')
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        let mut scores: Vec<_> = orthographies
+            .iter()
+            .map(|orthography| {
+                let letters: f64 = lower
+                    .chars()
+                    .filter(|c| orthography.letters.contains(*c))
+                    .map(|c| 1.0 / sharing[&c] as f64)
+                    .sum();
+                let words = words
+                    .iter()
+                    .filter(|word| orthography.words.contains(word))
+                    .count() as f64;
+                (orthography.tag, letters + 2.0 * words)
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        // Ties go to the candidate later in the table
+        scores.reverse();
+        scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        let total: f64 = scores.iter().map(|(_, score)| score).sum();
+        scores
+            .into_iter()
+            .map(|(tag, score)| (tag.to_string(), score / total))
+            .collect()
+    }
+}
+
+/// A run of `text` identified as one language, in character offsets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    pub language: String,
+}
+
+/// Split `text` into runs of lines of the same language. Lines without
+/// evidence join the run before them (or after them, at the start).
+/// Returns no segments if no line could be identified.
+pub fn segment(
+    identifier: &dyn LanguageIdentifier,
+    text: &str,
+    candidates: &[&str],
+) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let len = line.chars().count();
+        let language = identifier.identify(line, candidates);
+        offset += len;
+
+        let Some(language) = language else {
+            if let Some(last) = segments.last_mut() {
+                last.end = offset;
+            }
+            continue;
+        };
+
+        match segments.last_mut() {
+            Some(last) if last.language == language => last.end = offset,
+            last => {
+                // Leading unidentified lines belong to the first segment
+                let start = if last.is_some() { offset - len } else { 0 };
+                segments.push(Segment {
+                    start,
+                    end: offset,
+                    language,
+                });
+            }
+        }
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sami_and_norwegian_paragraphs_are_told_apart() {
+        let text = "Mun lean čállán dán girjji.\n\nDet er en bok om språk.\nJeg liker den.\n";
+        let segments = segment(&OrthographyIdentifier, text, &["se", "nb"]);
+        assert_eq!(
+            segments,
+            vec![
+                Segment {
+                    start: 0,
+                    end: 29,
+                    language: "se".to_string()
+                },
+                Segment {
+                    start: 29,
+                    end: text.chars().count(),
+                    language: "nb".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn text_without_evidence_is_unidentified() {
+        assert_eq!(
+            OrthographyIdentifier.identify("123 456", &["se", "nb"]),
+            None
+        );
+        assert!(segment(&OrthographyIdentifier, "123\n", &["se", "nb"]).is_empty());
+    }
+}
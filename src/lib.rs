@@ -0,0 +1,76 @@
+//! Gateway for the Divvun language services: documentation pages, language
+//! listings and a reverse proxy to the backends, or nginx configuration doing
+//! the proxying, all generated from `languages.toml`.
+
+pub mod access;
+pub mod admin;
+pub mod aliases;
+pub mod ansible;
+pub mod apache;
+pub mod apostrophe;
+pub mod assets;
+pub mod auth;
+pub mod balance;
+pub mod cache;
+pub mod caddy;
+pub mod charset;
+pub mod chunking;
+pub mod client;
+pub mod compose;
+pub mod compression;
+pub mod config;
+pub mod cors;
+#[cfg(all(unix, feature = "cli"))]
+pub mod daemon;
+pub mod doctor;
+pub mod error_tags;
+pub mod etag;
+pub mod faults;
+pub mod forms;
+pub mod haproxy;
+pub mod health;
+#[cfg(feature = "wasm")]
+pub mod hooks;
+pub mod i18n;
+pub mod langid;
+pub mod limits;
+pub mod logfile;
+pub mod markup;
+pub mod nginx;
+pub mod openapi;
+pub mod overrides;
+mod pages;
+pub mod pool;
+pub mod ports;
+pub mod problem;
+pub mod proxy;
+pub mod recording;
+pub mod responses;
+pub mod retry;
+mod sampling;
+pub mod sanitize;
+pub mod schema;
+pub mod server;
+pub mod services;
+#[cfg(unix)]
+pub mod socket;
+pub mod ssml;
+pub mod stats;
+pub mod statsd;
+pub mod suggestions;
+pub mod systemd;
+mod table;
+pub mod template;
+pub mod tenants;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod traefik;
+pub mod transcode;
+pub mod upstream;
+pub mod validate;
+#[cfg(all(windows, feature = "windows-service"))]
+pub mod winservice;
+
+pub use config::LanguagesConfig;
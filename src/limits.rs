@@ -0,0 +1,297 @@
+//! Rate and concurrency limits for a single location, such as one language's
+//! checker or one TTS voice, shared by every client, and rate limits for each
+//! client, configured as `[config.rate_limit]`.
+//!
+//! Requests over a limit are turned away with a problem response and a
+//! `Retry-After` header rather than queued, so a slow backend can't hold up
+//! the gateway's connections.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use poem::{
+    http::{header, HeaderValue, StatusCode},
+    Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::problem::Problem;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocationLimits {
+    /// Requests forwarded to the backend at the same time.
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    /// Requests accepted per minute, allowing bursts of up to this many.
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+}
+
+/// Requests a minute each client may send, in total and to each service
+/// category.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Requests a minute from one address to the whole gateway.
+    #[serde(default)]
+    pub per_ip: Option<u32>,
+    /// Requests a minute from one address to a service category, e.g.
+    /// `tts = 10`.
+    #[serde(default)]
+    pub services: HashMap<String, u32>,
+    /// Take the client's address from `X-Real-IP` or `X-Forwarded-For`, for
+    /// a gateway behind a proxy that sets them.
+    #[serde(default)]
+    pub trust_forwarded: bool,
+}
+
+/// A token bucket refilled continuously at `per_minute` tokens a minute.
+struct Bucket {
+    per_minute: u32,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Bucket {
+    fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            state: Mutex::new((per_minute as f64, Instant::now())),
+        }
+    }
+
+    /// Take a token, or return the seconds until one is available.
+    fn take(&self) -> std::result::Result<(), u64> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let (tokens, refilled) = &mut *state;
+        let per_second = self.per_minute as f64 / 60.0;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*refilled).as_secs_f64() * per_second)
+            .min(self.per_minute as f64);
+        *refilled = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - *tokens) / per_second).ceil() as u64)
+        }
+    }
+
+    /// Whether the bucket has refilled, so it is as good as a new one.
+    fn is_full(&self) -> bool {
+        let (tokens, refilled) = *self.state.lock().unwrap_or_else(|err| err.into_inner());
+        tokens + refilled.elapsed().as_secs_f64() * self.per_minute as f64 / 60.0
+            >= self.per_minute as f64
+    }
+}
+
+/// Enforces a location's [`LocationLimits`] on each call to its backend, by
+/// whichever route the call came in on; lets every call through when they
+/// are empty.
+pub struct LocationLimiter {
+    concurrency: Option<Arc<Semaphore>>,
+    rate: Option<Bucket>,
+}
+
+impl LocationLimiter {
+    pub fn new(limits: LocationLimits) -> Self {
+        Self {
+            concurrency: limits
+                .max_concurrent
+                .map(|permits| Arc::new(Semaphore::new(permits))),
+            rate: limits.requests_per_minute.map(Bucket::new),
+        }
+    }
+
+    /// Let a backend call through, returning the place it takes among the
+    /// concurrent calls until dropped, or the problem to answer with.
+    pub fn admit(&self) -> std::result::Result<Option<OwnedSemaphorePermit>, Problem> {
+        if let Some(rate) = &self.rate {
+            if let Err(wait) = rate.take() {
+                return Err(
+                    Problem::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests")
+                        .detail(format!(
+                            "This service accepts at most {} requests a minute",
+                            rate.per_minute
+                        ))
+                        .extension("retry_after", wait.max(1)),
+                );
+            }
+        }
+        match &self.concurrency {
+            Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => Ok(Some(permit)),
+                Err(_) => Err(Problem::new(StatusCode::SERVICE_UNAVAILABLE, "Service busy")
+                    .detail(
+                        "This service is handling as many requests as it can; try again shortly",
+                    )
+                    .extension("retry_after", 1)),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Paths never limited per client, so load balancers can poll them.
+const UNLIMITED: &[&str] = &["/health", "/readyz"];
+
+/// Client buckets kept before refilled ones are dropped.
+const PRUNE_AT: usize = 10_000;
+
+/// A client address and, for per-service limits, the service.
+type ClientKey = (IpAddr, Option<String>);
+
+/// Middleware enforcing a [`RateLimitConfig`] with a bucket per client address
+/// and one per address and service category.
+pub struct ClientRateLimit(pub RateLimitConfig);
+
+impl<E: Endpoint> Middleware<E> for ClientRateLimit {
+    type Output = ClientRateLimitEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ClientRateLimitEndpoint {
+            inner: ep,
+            config: self.0.clone(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+pub struct ClientRateLimitEndpoint<E> {
+    inner: E,
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<ClientKey, Arc<Bucket>>>,
+}
+
+impl<E> ClientRateLimitEndpoint<E> {
+    fn client(&self, req: &Request) -> Option<IpAddr> {
+        if self.config.trust_forwarded {
+            let forwarded = req.header("X-Real-IP").or_else(|| {
+                req.header("X-Forwarded-For")
+                    .and_then(|value| value.split(',').next())
+            });
+            if let Some(ip) = forwarded.and_then(|value| value.trim().parse().ok()) {
+                return Some(ip);
+            }
+        }
+        req.remote_addr().as_socket_addr().map(|addr| addr.ip())
+    }
+
+    fn bucket(&self, client: IpAddr, service: Option<&str>, per_minute: u32) -> Arc<Bucket> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        if buckets.len() >= PRUNE_AT {
+            buckets.retain(|_, bucket| !bucket.is_full());
+        }
+        buckets
+            .entry((client, service.map(str::to_string)))
+            .or_insert_with(|| Arc::new(Bucket::new(per_minute)))
+            .clone()
+    }
+}
+
+impl<E: Endpoint> Endpoint for ClientRateLimitEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let path = req.uri().path();
+        let client = self.client(&req);
+        if let (Some(client), false) = (client, UNLIMITED.contains(&path)) {
+            if let Some(per_minute) = self.config.per_ip {
+                if let Err(wait) = self.bucket(client, None, per_minute).take() {
+                    return Ok(rejected(
+                        Problem::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests").detail(
+                            format!("You may send at most {} requests a minute", per_minute),
+                        ),
+                        wait,
+                    ));
+                }
+            }
+
+            let service = path.trim_start_matches('/').split('/').next();
+            let limit = service.and_then(|service| {
+                let (service, per_minute) = self.config.services.get_key_value(service)?;
+                Some((service.as_str(), *per_minute))
+            });
+            if let Some((service, per_minute)) = limit {
+                if let Err(wait) = self.bucket(client, Some(service), per_minute).take() {
+                    return Ok(rejected(
+                        Problem::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests").detail(
+                            format!(
+                                "You may send at most {} {} requests a minute",
+                                per_minute, service
+                            ),
+                        ),
+                        wait,
+                    ));
+                }
+            }
+        }
+        self.inner.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+fn rejected(problem: Problem, retry_after: u64) -> Response {
+    let mut response = problem.into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{endpoint::make, test::TestClient, EndpointExt};
+
+    use super::*;
+
+    #[test]
+    fn calls_over_the_limits_are_turned_away() {
+        let limiter = LocationLimiter::new(LocationLimits {
+            max_concurrent: Some(1),
+            requests_per_minute: Some(2),
+        });
+
+        let first = limiter.admit().unwrap();
+        let busy = limiter.admit().unwrap_err().into_response();
+        assert_eq!(busy.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(busy.headers()[header::RETRY_AFTER], "1");
+
+        drop(first);
+        let limited = limiter.admit().unwrap_err().into_response();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers()[header::RETRY_AFTER], "30");
+
+        let unlimited = LocationLimiter::new(LocationLimits::default());
+        assert!((0..100).all(|_| unlimited.admit().is_ok()));
+    }
+
+    #[tokio::test]
+    async fn clients_are_limited_separately() {
+        let endpoint = make(|_| async { "done" }).with(ClientRateLimit(RateLimitConfig {
+            per_ip: Some(3),
+            services: HashMap::from([("tts".to_string(), 1)]),
+            trust_forwarded: true,
+        }));
+        let client = TestClient::new(endpoint);
+        let send =
+            |ip: &'static str, path: &'static str| client.post(path).header("X-Real-IP", ip).send();
+
+        send("10.0.0.1", "/tts/se/biret")
+            .await
+            .assert_status_is_ok();
+        let limited = send("10.0.0.1", "/tts/se/sunna").await;
+        limited.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        limited.assert_header(header::RETRY_AFTER, "60");
+        send("10.0.0.2", "/tts/se/biret")
+            .await
+            .assert_status_is_ok();
+
+        send("10.0.0.1", "/speller/se").await.assert_status_is_ok();
+        send("10.0.0.1", "/health").await.assert_status_is_ok();
+        let limited = send("10.0.0.1", "/speller/se").await;
+        limited.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        limited.assert_header(header::RETRY_AFTER, "20");
+    }
+}
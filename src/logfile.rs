@@ -0,0 +1,197 @@
+//! Log output to a file with rotation, for hosts without journald where
+//! standard output is discarded.
+//!
+//! Rotation renames `gateway.log` to `gateway.log.1`, shifting older files
+//! up by one, like logrotate. It happens when the hour or day changes, when
+//! the file would grow past `max_size`, or both.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Hourly,
+    #[default]
+    Daily,
+    /// Only rotate by size.
+    Never,
+}
+
+impl Rotation {
+    /// The period `unix_secs` falls in; a change means it's time to rotate.
+    fn period(self, unix_secs: u64) -> u64 {
+        match self {
+            Rotation::Hourly => unix_secs / 3600,
+            Rotation::Daily => unix_secs / 86400,
+            Rotation::Never => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogConfig {
+    pub file: PathBuf,
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// Rotate before the file grows past this many bytes.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Rotated files to keep besides the current one.
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+fn default_keep() -> usize {
+    7
+}
+
+struct State {
+    file: File,
+    size: u64,
+    period: u64,
+}
+
+/// A log file that rotates itself according to a [`LogConfig`]. Clones
+/// share the file.
+#[derive(Clone)]
+pub struct LogFile {
+    config: Arc<LogConfig>,
+    state: Arc<Mutex<State>>,
+}
+
+impl LogFile {
+    pub fn open(config: LogConfig) -> io::Result<Self> {
+        if let Some(dir) = config
+            .file
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            fs::create_dir_all(dir)?;
+        }
+        let (file, size) = append(&config.file)?;
+        let modified = fs::metadata(&config.file)?
+            .modified()
+            .map(unix_secs)
+            .unwrap_or_else(|_| unix_secs(SystemTime::now()));
+        let state = State {
+            file,
+            size,
+            period: config.rotation.period(modified),
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    /// Reopen the file by name, after an external tool has moved it away.
+    pub fn reopen(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let (file, size) = append(&self.config.file)?;
+        state.file = file;
+        state.size = size;
+        Ok(())
+    }
+
+    fn rotate(&self, state: &mut State) -> io::Result<()> {
+        state.file.flush()?;
+        let path = &self.config.file;
+        let _ = fs::remove_file(numbered(path, self.config.keep));
+        for n in (1..self.config.keep).rev() {
+            let from = numbered(path, n);
+            if from.exists() {
+                fs::rename(from, numbered(path, n + 1))?;
+            }
+        }
+        if self.config.keep > 0 {
+            fs::rename(path, numbered(path, 1))?;
+        } else {
+            fs::remove_file(path)?;
+        }
+
+        let (file, size) = append(path)?;
+        state.file = file;
+        state.size = size;
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let period = self.config.rotation.period(unix_secs(SystemTime::now()));
+        let too_big = self
+            .config
+            .max_size
+            .is_some_and(|max| state.size > 0 && state.size + buf.len() as u64 > max);
+        if period != state.period || too_big {
+            self.rotate(&mut state)?;
+            state.period = period;
+        }
+
+        let written = state.file.write(buf)?;
+        state.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .file
+            .flush()
+    }
+}
+
+fn append(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_rotate_by_size_and_old_ones_are_dropped() {
+        let dir = std::env::temp_dir().join(format!("logfile-{}", std::process::id()));
+        let path = dir.join("gateway.log");
+        let mut log = LogFile::open(LogConfig {
+            file: path.clone(),
+            rotation: Rotation::Never,
+            max_size: Some(10),
+            keep: 2,
+        })
+        .unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(numbered(&path, 1)).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(numbered(&path, 2)).unwrap(), "second\n");
+        assert!(!numbered(&path, 3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
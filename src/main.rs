@@ -1,314 +1,277 @@
-use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-
-use clap::Parser;
-use poem::{
-    get, handler,
-    listener::TcpListener,
-    middleware::Cors,
-    web::{Data, Html, Json},
-    EndpointExt, IntoResponse, Route, Server,
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
+use divvun_worker_static::client::{self, ClientLanguage};
+#[cfg(unix)]
+use divvun_worker_static::daemon::{self, PidFile};
+use divvun_worker_static::doctor::{self, DoctorOptions, Severity};
+use divvun_worker_static::logfile::LogFile;
+use divvun_worker_static::schema::ApiSchema;
+use divvun_worker_static::server::ServerBuilder;
+use divvun_worker_static::services::ServiceRegistry;
+#[cfg(unix)]
+use divvun_worker_static::socket::{self, UnixSocket};
+#[cfg(feature = "tls")]
+use divvun_worker_static::tls::TlsConfig;
+use divvun_worker_static::{
+    ansible, apache, caddy, compose, haproxy, health, nginx, openapi, ports, recording, server,
+    systemd, traefik, validate, LanguagesConfig,
 };
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct LanguagesConfig {
-    config: Config,
-    grammar: HashMap<String, ServiceConfig>,
-    speller: HashMap<String, ServiceConfig>,
-    hyphenation: HashMap<String, ServiceConfig>,
-    tts: HashMap<String, TtsConfig>,
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Config {
-    tts: ConfigTts,
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ConfigTts {
-    port: u16,
-}
+#[derive(Parser)]
+enum Commands {
+    /// Start the web server
+    Serve {
+        /// Host to bind the server to
+        #[arg(long, env = "DIVVUN_HOST", default_value = "127.0.0.1")]
+        host: String,
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ServiceConfig {
-    name: String,
-    port: u16,
-}
+        /// Port to run the server on
+        #[arg(long, env = "DIVVUN_PORT", default_value_t = 4000)]
+        port: u16,
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct TtsConfig {
-    name: String,
-    voices: HashMap<String, VoiceConfig>,
-}
+        /// languages.toml to use instead of the one built into the binary.
+        /// DIVVUN_<SECTION>__<KEY> variables override its values, e.g.
+        /// DIVVUN_CONFIG__TTS__PORT=5001
+        #[arg(long, env = "DIVVUN_CONFIG")]
+        config: Option<PathBuf>,
+
+        /// Check the config and address, print the routes and exit
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Inject the faults in [[config.faults]], to test clients against
+        #[arg(long)]
+        inject_faults: bool,
+
+        /// Serve the docs page's CSS and JavaScript from this directory
+        /// instead of the copies built into the binary
+        #[arg(long)]
+        assets_dir: Option<PathBuf>,
+
+        /// Probe every backend before listening and exit if any is unreachable
+        #[arg(long, conflicts_with = "dry_run")]
+        preflight: bool,
+
+        /// Requests each HTTP/2 connection may have in flight at once.
+        /// HTTP/2 is served alongside HTTP/1.1: negotiated over TLS, or in
+        /// plain text to clients starting with it
+        #[arg(long, value_name = "N", conflicts_with = "dry_run")]
+        http2_max_streams: Option<u32>,
+
+        #[command(flatten)]
+        socket: SocketArgs,
+
+        #[command(flatten)]
+        tls: TlsArgs,
+
+        /// Detach from the terminal; configure [config.log] to keep the logs
+        #[cfg(unix)]
+        #[arg(long)]
+        daemon: bool,
+
+        /// File to write the process id to while running
+        #[cfg(unix)]
+        #[arg(long)]
+        pidfile: Option<PathBuf>,
+    },
+    /// Generate reverse proxy configuration files, for nginx by default
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Generate {
+        #[command(subcommand)]
+        target: Option<GenerateTarget>,
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct VoiceConfig {
-    name: String,
-    gender: String,
-    model: String,
-    #[serde(default)]
-    speaker: Option<u32>,
-    #[serde(default)]
-    language: Option<u32>,
-}
+        /// Reverse proxy to generate the configuration for
+        #[arg(long, value_enum, default_value_t = ProxyFormat::Nginx)]
+        format: ProxyFormat,
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct LegacyLanguagesConfig {
-    grammar: HashMap<String, String>,
-    speller: HashMap<String, String>,
-    hyphenation: HashMap<String, String>,
-}
+        /// languages.toml to use instead of the one built into the binary
+        #[arg(long, global = true)]
+        config: Option<PathBuf>,
 
-impl From<&LanguagesConfig> for LegacyLanguagesConfig {
-    fn from(languages: &LanguagesConfig) -> Self {
-        Self {
-            grammar: languages
-                .grammar
-                .iter()
-                .map(|(k, v)| (k.clone(), v.name.clone()))
-                .collect(),
-            speller: languages
-                .speller
-                .iter()
-                .map(|(k, v)| (k.clone(), v.name.clone()))
-                .collect(),
-            hyphenation: languages
-                .hyphenation
-                .iter()
-                .map(|(k, v)| (k.clone(), v.name.clone()))
-                .collect(),
-        }
-    }
-}
+        /// Directory path to output the configuration files
+        #[arg(required = true)]
+        path: Option<String>,
 
-#[handler]
-async fn languages_get(Data(languages): Data<&LanguagesConfig>) -> impl IntoResponse {
-    Json(serde_json::json!({ "available": LegacyLanguagesConfig::from(languages) })).into_response()
-}
+        /// URL of the gateway, for the routes Traefik and HAProxy can't
+        /// forward to a backend themselves
+        #[arg(long, default_value = "http://127.0.0.1:4000")]
+        gateway: String,
 
-#[handler]
-async fn health_get() -> impl IntoResponse {
-    Json(json!({ "status": "ok" })).into_response()
-}
+        /// Reload nginx after writing the files
+        #[arg(long)]
+        reload: bool,
 
-#[handler]
-async fn index_get(Data(languages): Data<&LanguagesConfig>) -> impl IntoResponse {
-    let mut html = include_str!("../index.html").to_string();
-
-    // Find the position to insert the generated sections
-    if let Some(pos) = html.find("<h2>Endpoints</h2>") {
-        let insert_pos = html[pos..].find("</section>").unwrap_or(0) + pos;
-
-        let mut sections = Vec::new();
-
-        // Grammar section
-        if !languages.grammar.is_empty() {
-            let mut sorted_langs: Vec<_> = languages.grammar.iter().collect();
-            sorted_langs.sort_by_key(|(tag, _)| *tag);
-
-            sections.push(format!(
-                r#"            <div class="endpoint" id="grammar">
-                <h3>Grammar Check</h3>
-                <p><span class="method post">POST</span> <code>/grammar/:tag</code> <span class="response-type">application/json</span></p>
-                <p>Check grammar for text. Available languages:</p>
-                <ul>
-{}
-                </ul>
-                <details>
-                    <summary>Request <code>application/json</code></summary>
-                    <pre><code>{{
-    "text": "sami"
-}}</code></pre>
-                </details>
-                <details>
-                    <summary>Response <code>application/json</code></summary>
-                    <pre><code>{{
-  "text": "sami",
-  "errs": [
-    {{
-      "error_text": "sami",
-      "start_index": 0,
-      "end_index": 4,
-      "error_code": "typo",
-      "description": "Ii leat sátnelisttus",
-      "suggestions": [
-        "sámi"
-      ],
-      "title": "Čállinmeattáhus"
-    }}
-  ]
-}}</code></pre>
-                </details>
-            </div>"#,
-                sorted_langs.iter()
-                    .map(|(tag, service)| format!(
-                        "                <li><a href=\"/grammar/{}\"><code>{}</code></a> - {}</li>",
-                        tag, tag, service.name
-                    ))
-                    .collect::<Vec<_>>()
-                    .join("\n")
-            ));
-        }
+        /// nginx binary used to test and reload the configuration
+        #[arg(long, default_value = "nginx", requires = "reload")]
+        nginx: String,
 
-        // Speller section
-        if !languages.speller.is_empty() {
-            let mut sorted_langs: Vec<_> = languages.speller.iter().collect();
-            sorted_langs.sort_by_key(|(tag, _)| *tag);
-
-            sections.push(format!(
-                r#"            <div class="endpoint" id="speller">
-                <h3>Spell Check</h3>
-                <p><span class="method post">POST</span> <code>/speller/:tag</code> <span class="response-type">application/json</span></p>
-                <p>Check spelling for text. Available languages:</p>
-                <ul>
-{}
-                </ul>
-                <details>
-                    <summary>Request <code>application/json</code></summary>
-                    <pre><code>{{
-    "text": "sami"
-}}</code></pre>
-                </details>
-                <details>
-                    <summary>Response <code>application/json</code></summary>
-                    <pre><code>{{
-  "text": "sami",
-  "results": [
-    {{
-      "word": "sami",
-      "is_correct": false,
-      "suggestions": [
-        {{
-          "value": "sámi",
-          "weight": 14.529631
-        }},
-        {{
-          "value": "sama",
-          "weight": 40.2973
-        }},
-        {{
-          "value": "sáme",
-          "weight": 45.896103
-        }},
-        {{
-          "value": "sabmi",
-          "weight": 50.2973
-        }},
-        {{
-          "value": "samai",
-          "weight": 50.2973
-        }},
-        {{
-          "value": "sapmi",
-          "weight": 50.2973
-        }},
-        {{
-          "value": "satmi",
-          "weight": 50.2973
-        }},
-        {{
-          "value": "samo",
-          "weight": 55.2973
-        }},
-        {{
-          "value": "samu",
-          "weight": 55.2973
-        }},
-        {{
-          "value": "somá",
-          "weight": 56.623154
-        }}
-      ]
-    }}
-  ]
-}}</code></pre>
-                </details>
-            </div>"#,
-                sorted_langs.iter()
-                    .map(|(tag, service)| format!(
-                        "                <li><a href=\"/speller/{}\"><code>{}</code></a> - {}</li>",
-                        tag, tag, service.name
-                    ))
-                    .collect::<Vec<_>>()
-                    .join("\n")
-            ));
-        }
+        /// Send SIGHUP to the pid in this file instead of running `nginx -s reload`
+        #[arg(long, requires = "reload")]
+        nginx_pid: Option<PathBuf>,
+    },
+    /// Probe every configured backend and exit non-zero if any is down
+    #[command(alias = "check-backends")]
+    Check {
+        /// Also send each text backend a short text to check
+        #[arg(long)]
+        request: bool,
+
+        /// languages.toml to use instead of the one built into the binary
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+    /// Ask a running server whether it is ready and exit non-zero if not,
+    /// for container healthchecks
+    Healthcheck {
+        /// Host the server listens on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
 
-        // TTS section
-        if !languages.tts.is_empty() {
-            let mut sorted_langs: Vec<_> = languages.tts.iter().collect();
-            sorted_langs.sort_by_key(|(tag, _)| *tag);
-
-            sections.push(format!(
-                r#"            <div class="endpoint" id="tts">
-                <h3>Text-to-Speech</h3>
-                <p><span class="method post">POST</span> <code>/tts/:tag/:voice</code> <span class="response-type">audio/wav</span></p>
-                <p><strong>MP3:</strong> add <code>Accept: audio/mpeg</code> header to get MP3 audio instead of WAV.</p>
-                <p>Convert text to speech. Available languages and voices:</p>
-                <ul>
-{}
-                </ul>
-                <details>
-                    <summary>Request <code>application/json</code></summary>
-                    <pre><code>{{
-    "text": "Sample text to convert to speech"
-}}</code></pre>
-                </details>
-                <details>
-                    <summary>Response <code>audio/wav</code></summary>
-                    <p>WAV audio file containing the synthesized speech.</p>
-                </details>
-                <details>
-                    <summary>Response <code>audio/mpeg</code></summary>
-                    <p>MP3 audio file containing the synthesized speech (if <code>Accept: audio/mpeg</code> header provided)</p>
-                </details>
-            </div>"#,
-                sorted_langs.iter()
-                    .map(|(tag, config)| {
-                        let mut voices: Vec<_> = config.voices.iter().collect();
-                        voices.sort_by_key(|(voice_id, _)| *voice_id);
-
-                        let voices = voices
-                            .iter()
-                            .map(|(voice_id, voice)| {
-                                let gender_icon = if voice.gender == "female" { "♀" } else { "♂" };
-                                format!(
-                                    "<code>{}</code> <a href=\"/tts/{}/{}\">{} {}</a>",
-                                    voice_id, tag, voice_id, voice.name, gender_icon
-                                )
-                            })
-                            .collect::<Vec<_>>()
-                            .join(", ");
-                        format!(
-                            "                <li><code>{}</code> - {} (voices: {})</li>",
-                            tag, config.name, voices
-                        )
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n")
-            ));
-        }
+        /// Port the server listens on
+        #[arg(long, default_value_t = 4000)]
+        port: u16,
 
-        html.insert_str(insert_pos, &format!("\n{}\n", sections.join("\n\n")));
-    }
+        /// Seconds to wait for an answer
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+    },
+    /// Send recorded requests to a backend and report where its answers
+    /// differ from the recorded ones
+    Replay {
+        /// Recording file, e.g. recordings/grammar-se.jsonl
+        file: PathBuf,
+
+        /// Backend to send the requests to
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        backend: String,
+    },
+    /// List the ports used by the config and whether anything listens on them
+    Ports {
+        /// languages.toml to audit instead of the one built into the binary
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Ports backends may use, as START-END
+        #[arg(long, value_parser = ports::parse_range, default_value = "1024-65535")]
+        range: RangeInclusive<u16>,
+    },
+    /// Check languages.toml and report every problem found, exiting non-zero
+    /// on errors
+    Validate {
+        /// languages.toml to check instead of the one built into the binary
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+    /// Run every check and suggest fixes for what is wrong
+    Doctor {
+        /// languages.toml to check instead of the one built into the binary
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Directory the nginx configuration was generated into
+        #[arg(long)]
+        nginx_dir: Option<PathBuf>,
+
+        /// Ports backends may use, as START-END
+        #[arg(long, value_parser = ports::parse_range, default_value = "1024-65535")]
+        range: RangeInclusive<u16>,
+
+        /// Also send each text backend a short text to check
+        #[arg(long)]
+        request: bool,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to complete in
+        shell: Shell,
+    },
+    /// Install, remove or run as a Windows service
+    #[cfg(all(windows, feature = "windows-service"))]
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+    /// Write man pages for the command and each subcommand
+    Manpages {
+        /// Directory to write the pages to
+        path: PathBuf,
+    },
+}
 
-    Html(html).into_response()
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ProxyFormat {
+    Nginx,
+    Apache,
+    Caddy,
+    Haproxy,
+    Traefik,
 }
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
+enum GenerateTarget {
+    /// Generate a typed API client for the configured languages and voices
+    Client {
+        /// Client language
+        #[arg(long)]
+        lang: ClientLanguage,
+
+        /// File to write the client to, instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Generate an OpenAPI 3.1 document describing the configured routes
+    Openapi {
+        /// File to write the document to, instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Generate a docker-compose file running the gateway and its backends
+    Compose {
+        /// File to write the compose file to, instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+
+        /// Backend image; {name} is e.g. grammar-se or tts, {service} and
+        /// {tag} its parts and {port} the backend's port
+        #[arg(long, default_value = compose::BACKEND_IMAGE)]
+        image: String,
+    },
+    /// Generate a systemd unit per backend worker
+    Systemd {
+        /// Directory to write the units to
+        path: PathBuf,
+
+        /// Worker command; {name} is e.g. grammar-se or tts, {service} and
+        /// {tag} its parts and {port} the backend's port
+        #[arg(long, default_value = systemd::EXEC_START)]
+        exec_start: String,
+    },
+    /// Generate an Ansible role and playbook installing the gateway, its
+    /// nginx locations and a systemd unit
+    Ansible {
+        /// Directory to write the playbook and role to
+        path: PathBuf,
+    },
 }
 
+#[cfg(all(windows, feature = "windows-service"))]
 #[derive(Parser)]
-enum Commands {
-    /// Start the web server
-    Serve {
+enum ServiceAction {
+    /// Register the service to start with Windows
+    Install {
         /// Host to bind the server to
         #[arg(long, default_value = "127.0.0.1")]
         host: String,
@@ -317,164 +280,466 @@ enum Commands {
         #[arg(long, default_value_t = 4000)]
         port: u16,
     },
-    /// Generate nginx configuration files
-    Generate {
-        /// Directory path to output the configuration files
-        path: String,
+    /// Stop and remove the service
+    Uninstall,
+    /// Run as the service; started by the service control manager
+    Run {
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+
+        #[arg(long, default_value_t = 4000)]
+        port: u16,
     },
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
+    // Forking is only safe before the runtime starts its threads
+    #[cfg(unix)]
+    if let Commands::Serve {
+        daemon: true,
+        dry_run: false,
+        ..
+    } = &cli.command
+    {
+        daemon::daemonize()?;
+    }
+
+    tokio::runtime::Runtime::new()?.block_on(run(cli))
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
     match cli.command {
-        Commands::Serve { host, port } => {
-            run_server(host, port).await?;
+        Commands::Serve {
+            host,
+            port,
+            config,
+            dry_run: true,
+            inject_faults,
+            assets_dir,
+            socket,
+            tls,
+            ..
+        } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let entries = tls
+                .apply(socket.apply(server_builder(languages, config)))
+                .bind(host, port)
+                .fault_injection(inject_faults)
+                .assets_dir(assets_dir)
+                .dry_run()?;
+            print!("{}", server::route_listing(&entries));
+        }
+        Commands::Serve {
+            host,
+            port,
+            config,
+            inject_faults,
+            assets_dir,
+            preflight,
+            http2_max_streams,
+            socket,
+            tls,
+            #[cfg(unix)]
+            pidfile,
+            ..
+        } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let log_file = match &languages.config.log {
+                Some(log) => Some(LogFile::open(log.clone())?),
+                None => None,
+            };
+            match log_file.clone() {
+                Some(file) => tracing_subscriber::fmt()
+                    .with_ansi(false)
+                    .with_writer(move || file.clone())
+                    .init(),
+                None => tracing_subscriber::fmt::init(),
+            }
+
+            #[cfg(unix)]
+            {
+                let _pidfile = pidfile.as_deref().map(PidFile::create).transpose()?;
+                let (reload, triggers) = tokio::sync::mpsc::unbounded_channel();
+                daemon::on_reload(move || {
+                    if let Some(file) = &log_file {
+                        if let Err(err) = file.reopen() {
+                            tracing::error!("Can't reopen the log file: {}", err);
+                        }
+                    }
+                    let _ = reload.send(());
+                })?;
+                tls.apply(socket.apply(server_builder(languages, config)))
+                    .bind(host, port)
+                    .fault_injection(inject_faults)
+                    .assets_dir(assets_dir)
+                    .preflight(preflight)
+                    .http2_max_streams(http2_max_streams)
+                    .reload_on(triggers)
+                    .serve_until(daemon::shutdown_signal()?)
+                    .await?;
+            }
+            #[cfg(not(unix))]
+            tls.apply(socket.apply(server_builder(languages, config)))
+                .bind(host, port)
+                .fault_injection(inject_faults)
+                .assets_dir(assets_dir)
+                .preflight(preflight)
+                .http2_max_streams(http2_max_streams)
+                .serve()
+                .await?;
+        }
+        Commands::Generate {
+            target: Some(GenerateTarget::Client { lang, output }),
+            config,
+            ..
+        } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let schema = ApiSchema::new(&languages, &ServiceRegistry::builtin());
+            let source = client::generate_client(&schema, lang);
+            match output {
+                Some(output) => fs::write(output, source)?,
+                None => print!("{}", source),
+            }
+        }
+        Commands::Generate {
+            target: Some(GenerateTarget::Openapi { output }),
+            config,
+            ..
+        } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let schema = ApiSchema::new(&languages, &ServiceRegistry::builtin());
+            let document = serde_json::to_string_pretty(&openapi::generate_openapi(&schema))?;
+            match output {
+                Some(output) => fs::write(output, document + "\n")?,
+                None => println!("{}", document),
+            }
+        }
+        Commands::Generate {
+            target: Some(GenerateTarget::Compose { output, image }),
+            config,
+            ..
+        } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let source = compose::generate_compose(&languages, &ServiceRegistry::builtin(), &image);
+            match output {
+                Some(output) => fs::write(output, source)?,
+                None => print!("{}", source),
+            }
+        }
+        Commands::Generate {
+            target: Some(GenerateTarget::Systemd { path, exec_start }),
+            config,
+            ..
+        } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            fs::create_dir_all(&path)?;
+            let units =
+                systemd::generate_units(&languages, &ServiceRegistry::builtin(), &exec_start);
+            for (file, unit) in &units {
+                fs::write(path.join(file), unit)?;
+            }
+            println!("Generated {} units in: {}", units.len(), path.display());
+        }
+        Commands::Generate {
+            target: Some(GenerateTarget::Ansible { path }),
+            config,
+            ..
+        } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            for (file, contents) in ansible::generate_role(&languages, &ServiceRegistry::builtin())
+            {
+                let file = path.join(file);
+                if let Some(dir) = file.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(file, contents)?;
+            }
+            println!(
+                "Generated the Ansible playbook and role in: {}",
+                path.display()
+            );
+        }
+        Commands::Generate {
+            path,
+            config,
+            format:
+                format @ (ProxyFormat::Apache
+                | ProxyFormat::Caddy
+                | ProxyFormat::Haproxy
+                | ProxyFormat::Traefik),
+            gateway,
+            reload,
+            ..
+        } => {
+            if reload {
+                anyhow::bail!("--reload only works with --format nginx");
+            }
+            let path = path.unwrap_or_default();
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let services = ServiceRegistry::builtin();
+            fs::create_dir_all(&path)?;
+            let (file, contents) = match format {
+                ProxyFormat::Apache => (
+                    "locations.apache.conf",
+                    apache::generate_apache_config(&languages, &services),
+                ),
+                ProxyFormat::Haproxy => (
+                    "haproxy.cfg",
+                    haproxy::generate_haproxy_config(&languages, &services, &gateway),
+                ),
+                ProxyFormat::Traefik => (
+                    "traefik.yml",
+                    traefik::generate_traefik_config(&languages, &services, &gateway),
+                ),
+                _ => (
+                    "locations.caddy",
+                    caddy::generate_caddy_config(&languages, &services),
+                ),
+            };
+            fs::write(Path::new(&path).join(file), contents)?;
+            println!("Generated configuration files in: {}", path);
         }
-        Commands::Generate { path } => {
-            // Parse languages from TOML
-            let languages: LanguagesConfig = toml::from_str(LANGUAGES)?;
+        Commands::Generate {
+            path,
+            config,
+            reload,
+            nginx,
+            nginx_pid,
+            ..
+        } => {
+            // Required unless a subcommand is given
+            let path = path.unwrap_or_default();
+            let languages = LanguagesConfig::load(config.as_deref())?;
 
             // Create directory if it doesn't exist
             fs::create_dir_all(&path)?;
 
             // Write nginx locations config
-            let nginx_config = generate_nginx_config(&languages);
+            let nginx_config =
+                nginx::generate_nginx_config(&languages, &ServiceRegistry::builtin());
             let nginx_path = Path::new(&path).join("locations.conf");
             fs::write(nginx_path, nginx_config)?;
 
             // Write proxy headers config
-            let proxy_headers = generate_proxy_headers_config();
+            let proxy_headers = nginx::generate_proxy_headers_config();
             let proxy_path = Path::new(&path).join("proxy-headers.conf");
             fs::write(proxy_path, proxy_headers)?;
 
             println!("Generated configuration files in: {}", path);
+
+            if reload {
+                let method = match nginx_pid {
+                    Some(pidfile) => nginx::ReloadMethod::Pid(pidfile),
+                    None => nginx::ReloadMethod::Command(nginx),
+                };
+                nginx::reload(&method)?;
+                println!("Reloaded nginx");
+            }
+        }
+        Commands::Check { request, config } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let statuses = health::check(&languages, &ServiceRegistry::builtin(), request).await;
+            print!("{}", health::table(&statuses));
+            if statuses.iter().any(|status| status.up != Some(true)) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Healthcheck {
+            host,
+            port,
+            timeout,
+        } => {
+            let url = format!("http://{}:{}/readyz", host, port);
+            let result = reqwest::Client::builder()
+                .timeout(Duration::from_secs(timeout))
+                .build()?
+                .get(&url)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            if let Err(err) = result {
+                eprintln!("{}: {}", url, err);
+                std::process::exit(1);
+            }
+        }
+        Commands::Replay { file, backend } => {
+            let recordings = recording::load(&file)?;
+            let client = reqwest::Client::new();
+            let mut differing = 0;
+            for (index, recorded) in recordings.iter().enumerate() {
+                let label = format!("#{} {} {}", index + 1, recorded.service, recorded.tag);
+                match recording::replay(&client, &backend, recorded).await {
+                    Ok(differences) if differences.is_empty() => println!("{}: same", label),
+                    Ok(differences) => {
+                        differing += 1;
+                        println!("{}: {} differences", label, differences.len());
+                        for difference in differences {
+                            println!(
+                                "    {}: expected {}, got {}",
+                                difference.field, difference.expected, difference.actual
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        differing += 1;
+                        println!("{}: failed: {:#}", label, err);
+                    }
+                }
+            }
+            println!("{} of {} recordings differ", differing, recordings.len());
+            if differing > 0 {
+                std::process::exit(1);
+            }
+        }
+        Commands::Ports { config, range } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let mut reports = ports::audit(&languages, &ServiceRegistry::builtin(), &range);
+            ports::probe_listening(&mut reports).await;
+            print!("{}", ports::table(&reports));
+            if !reports.iter().all(ports::PortReport::is_ok) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Validate { config } => {
+            let source = match &config {
+                Some(path) => fs::read_to_string(path)
+                    .with_context(|| format!("can't read {}", path.display()))?,
+                None => divvun_worker_static::config::EMBEDDED_CONFIG.to_string(),
+            };
+            let findings =
+                validate::validate(&source, std::env::vars(), &ServiceRegistry::builtin());
+            print!("{}", doctor::report(&findings));
+            if findings.iter().any(|f| f.severity == Severity::Error) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Doctor {
+            config,
+            nginx_dir,
+            range,
+            request,
+        } => {
+            let languages = LanguagesConfig::load(config.as_deref())?;
+            let options = DoctorOptions {
+                nginx_dir,
+                range,
+                canned: request,
+            };
+            let findings =
+                doctor::diagnose(&languages, &ServiceRegistry::builtin(), &options).await;
+            print!("{}", doctor::report(&findings));
+            if findings.iter().any(|f| f.severity == Severity::Error) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        }
+        #[cfg(all(windows, feature = "windows-service"))]
+        Commands::Service { action } => match action {
+            ServiceAction::Install { host, port } => {
+                divvun_worker_static::winservice::install(&host, port)?;
+                println!(
+                    "Installed the {} service",
+                    divvun_worker_static::winservice::SERVICE_NAME
+                );
+            }
+            ServiceAction::Uninstall => divvun_worker_static::winservice::uninstall()?,
+            // The dispatcher blocks; keep it off the runtime's worker threads
+            ServiceAction::Run { host, port } => {
+                tokio::task::spawn_blocking(move || {
+                    divvun_worker_static::winservice::run(host, port)
+                })
+                .await??
+            }
+        },
+        Commands::Manpages { path } => {
+            fs::create_dir_all(&path)?;
+            clap_mangen::generate_to(Cli::command(), &path)?;
+            println!("Generated man pages in: {}", path.display());
         }
     }
 
     Ok(())
 }
 
-const LANGUAGES: &str = include_str!("../languages.toml");
-
-async fn run_server(host: String, port: u16) -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
-
-    // Parse languages from TOML
-    let languages: LanguagesConfig = toml::from_str(LANGUAGES)?;
-
-    let app = Route::new()
-        .at("/", get(index_get))
-        .at("/health", get(health_get))
-        .at("/languages", get(languages_get))
-        .data(languages)
-        .with(Cors::default());
-
-    Server::new(TcpListener::bind((host, port)))
-        .run(app)
-        .await?;
-
-    Ok(())
+/// Unix socket options for `serve`.
+#[derive(clap::Args)]
+struct SocketArgs {
+    /// Listen on this unix socket instead of --host and --port
+    #[cfg(unix)]
+    #[arg(long, conflicts_with_all = ["host", "port"])]
+    unix_socket: Option<PathBuf>,
+
+    /// File mode of --unix-socket, in octal
+    #[cfg(unix)]
+    #[arg(long, default_value = "660", value_parser = socket::parse_mode, requires = "unix_socket")]
+    unix_socket_mode: u32,
 }
 
-fn generate_nginx_config(languages: &LanguagesConfig) -> String {
-    let mut configs = Vec::new();
-
-    // Generate grammar service configs
-    let mut grammar_services: Vec<_> = languages.grammar.iter().collect();
-    grammar_services.sort_by_key(|(tag, _)| *tag);
-    for (tag, service) in grammar_services {
-        configs.push(generate_location_block(
-            &format!("/grammar/{}", tag),
-            service.port,
-            "",
-            &HashMap::new(),
-        ));
-    }
-
-    // Generate speller service configs
-    let mut speller_services: Vec<_> = languages.speller.iter().collect();
-    speller_services.sort_by_key(|(tag, _)| *tag);
-    for (tag, service) in speller_services {
-        configs.push(generate_location_block(
-            &format!("/speller/{}", tag),
-            service.port,
-            "",
-            &HashMap::new(),
-        ));
-    }
-
-    // Generate hyphenation service configs
-    let mut hyphenation_services: Vec<_> = languages.hyphenation.iter().collect();
-    hyphenation_services.sort_by_key(|(tag, _)| *tag);
-    for (tag, service) in hyphenation_services {
-        configs.push(generate_location_block(
-            &format!("/hyphenation/{}", tag),
-            service.port,
-            "",
-            &HashMap::new(),
-        ));
-    }
-
-    // Generate TTS service configs
-    let mut tts_services: Vec<_> = languages.tts.iter().collect();
-    tts_services.sort_by_key(|(tag, _)| *tag);
-    for (tag, tts_config) in tts_services {
-        let mut voices: Vec<_> = tts_config.voices.iter().collect();
-        voices.sort_by_key(|(voice_id, _)| *voice_id);
-        for (voice_id, voice) in voices {
-            let mut query = HashMap::new();
-            if let Some(language) = voice.language {
-                query.insert("language".to_string(), language.to_string());
-            }
-            if let Some(speaker) = voice.speaker {
-                query.insert("speaker".to_string(), speaker.to_string());
-            }
-            configs.push(generate_location_block(
-                &format!("/tts/{}/{}", tag, voice_id),
-                languages.config.tts.port,
-                "",
-                &query,
-            ));
+impl SocketArgs {
+    #[allow(unused_mut)]
+    fn apply(self, mut builder: ServerBuilder) -> ServerBuilder {
+        #[cfg(unix)]
+        if let Some(path) = self.unix_socket {
+            builder = builder.unix_socket(UnixSocket::new(path).mode(self.unix_socket_mode));
         }
+        builder
     }
+}
 
-    configs.join("\n\n")
+/// HTTPS options for `serve`.
+#[derive(clap::Args)]
+struct TlsArgs {
+    /// PEM certificate chain to serve HTTPS with
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Domain to get a Let's Encrypt certificate for; repeat for more.
+    /// Let's Encrypt must reach this server on port 443
+    #[cfg(feature = "acme")]
+    #[arg(long, conflicts_with = "tls_cert", requires = "acme_cache")]
+    acme_domain: Vec<String>,
+
+    /// Directory keeping the ACME account and certificates across restarts
+    #[cfg(feature = "acme")]
+    #[arg(long)]
+    acme_cache: Option<PathBuf>,
 }
 
-fn generate_location_block(
-    fe_path: &str,
-    port: u16,
-    be_path: &str,
-    query: &HashMap<String, String>,
-) -> String {
-    let mut query = query
-        .iter()
-        .map(|(k, v)| format!("{}={}", k, v))
-        .collect::<Vec<_>>()
-        .join("&");
-    if !query.is_empty() {
-        query = format!("?{}", query);
+impl TlsArgs {
+    #[allow(unused_mut)]
+    fn apply(self, mut builder: ServerBuilder) -> ServerBuilder {
+        #[cfg(feature = "tls")]
+        if let (Some(cert), Some(key)) = (self.tls_cert, self.tls_key) {
+            builder = builder.tls(TlsConfig::Files { cert, key });
+        }
+        #[cfg(feature = "acme")]
+        if let (false, Some(cache_dir)) = (self.acme_domain.is_empty(), self.acme_cache) {
+            builder = builder.tls(TlsConfig::Acme {
+                domains: self.acme_domain,
+                cache_dir,
+            });
+        }
+        builder
     }
-
-    format!(
-        r#"location {} {{
-    proxy_pass http://127.0.0.1:{}/{}{};
-    include proxy-headers.conf;
-}}"#,
-        fe_path, port, be_path, query
-    )
 }
 
-fn generate_proxy_headers_config() -> String {
-    r#"proxy_http_version 1.1;
-proxy_set_header Upgrade $http_upgrade;
-proxy_set_header Connection 'upgrade';
-proxy_set_header Host $host;
-proxy_cache_bypass $http_upgrade;
-proxy_set_header X-Real-IP $remote_addr;
-proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
-proxy_set_header X-Forwarded-Proto $scheme;"#
-        .to_string()
+/// A builder serving `languages`, re-read from `config` on reload if given.
+fn server_builder(languages: LanguagesConfig, config: Option<PathBuf>) -> ServerBuilder {
+    let builder = ServerBuilder::new().languages(languages);
+    match config {
+        Some(config) => builder.config_file(config),
+        None => builder,
+    }
 }
@@ -0,0 +1,464 @@
+//! Rich text in check requests. With `"format": "html"` or `"markdown"`, the
+//! markup is stripped from the text before it goes to the backend, and the
+//! offsets in the answer are mapped back to the original document, so CMS
+//! integrations need no text extraction of their own.
+//!
+//! Tags and syntax are removed, block elements become line breaks so their
+//! words stay apart, entities are decoded and code is left out. Every
+//! character of the text is one of the document's, or takes the place of the
+//! first character of a tag or entity, so an [`OffsetMap`] of removals maps
+//! offsets back; an error ending at an entity ends after all of it.
+
+use std::ops::Range;
+
+use anyhow::bail;
+use serde_json::Value;
+
+use crate::sanitize::OffsetMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Markup {
+    Html,
+    Markdown,
+}
+
+impl Markup {
+    /// The markup a JSON request body's `format` names, and the body without
+    /// the field. `"text"` is the same as no `format`.
+    pub fn requested(body: Vec<u8>) -> anyhow::Result<(Vec<u8>, Option<Self>)> {
+        let Ok(Value::Object(mut json)) = serde_json::from_slice(&body) else {
+            return Ok((body, None));
+        };
+        let markup = match json.remove("format") {
+            None => return Ok((body, None)),
+            Some(Value::String(name)) => match name.as_str() {
+                "html" => Some(Markup::Html),
+                "markdown" => Some(Markup::Markdown),
+                "text" => None,
+                _ => bail!("Unknown text format {}; use html, markdown or text", name),
+            },
+            Some(_) => bail!("`format` must be a string"),
+        };
+        Ok((Value::Object(json).to_string().into_bytes(), markup))
+    }
+
+    /// The text of `document`, and the map from offsets in the text to
+    /// offsets in the document.
+    pub fn strip(self, document: &str) -> (String, OffsetMap) {
+        let mut text = Text::new(document);
+        match self {
+            Markup::Html => html(&mut text),
+            Markup::Markdown => markdown(&mut text),
+        }
+        text.finish()
+    }
+}
+
+/// A document's characters, with the markup marked for removal.
+struct Text {
+    chars: Vec<char>,
+    kept: Vec<Option<char>>,
+    /// Removed characters that are part of a decoded one.
+    joined: Vec<usize>,
+}
+
+impl Text {
+    fn new(document: &str) -> Self {
+        let chars: Vec<char> = document.chars().collect();
+        let kept = chars.iter().copied().map(Some).collect();
+        Self {
+            chars,
+            kept,
+            joined: Vec::new(),
+        }
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        self.kept[range].fill(None);
+    }
+
+    /// Replace the characters in `range` with `c`, in place of the first.
+    fn replace(&mut self, range: Range<usize>, c: char) {
+        let start = range.start;
+        self.remove(range);
+        self.kept[start] = Some(c);
+    }
+
+    /// Replace the characters in `range`, which spell `c`, with it.
+    fn decode(&mut self, range: Range<usize>, c: char) {
+        self.joined.extend(range.start + 1..range.end);
+        self.replace(range, c);
+    }
+
+    fn starts_with(&self, at: usize, prefix: &str) -> bool {
+        let mut chars = self.chars[at..].iter();
+        prefix
+            .chars()
+            .all(|c| chars.next().is_some_and(|d| d.eq_ignore_ascii_case(&c)))
+    }
+
+    /// Where `needle` next occurs at or after `from`, ignoring ASCII case.
+    fn find(&self, from: usize, needle: &str) -> Option<usize> {
+        (from..self.chars.len()).find(|&at| self.starts_with(at, needle))
+    }
+
+    fn run(&self, at: usize, end: usize) -> usize {
+        let c = self.chars[at];
+        self.chars[at..end].iter().take_while(|&&d| d == c).count()
+    }
+
+    fn finish(mut self) -> (String, OffsetMap) {
+        let removed = (0..self.kept.len())
+            .filter(|&i| self.kept[i].is_none())
+            .collect();
+        self.joined.sort_unstable();
+        let text = self.kept.into_iter().flatten().collect();
+        (text, OffsetMap::removing(removed, self.joined))
+    }
+}
+
+/// Elements that start a new line.
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+/// Elements whose content isn't text.
+const HIDDEN: &[&str] = &["code", "script", "style", "template"];
+
+fn html(text: &mut Text) {
+    let len = text.chars.len();
+    let mut i = 0;
+    while i < len {
+        match text.chars[i] {
+            '<' if text.starts_with(i, "<!--") => {
+                let end = text.find(i + 4, "-->").map_or(len, |end| end + 3);
+                text.remove(i..end);
+                i = end;
+            }
+            '<' if text
+                .chars
+                .get(i + 1)
+                .is_some_and(|&c| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?')) =>
+            {
+                let end = tag_end(&text.chars, i);
+                let name = tag_name(&text.chars[i + 1..end]);
+                let end = match text.chars[i + 1] != '/' && HIDDEN.contains(&name.as_str()) {
+                    true => text
+                        .find(end, &format!("</{}", name))
+                        .map_or(len, |close| tag_end(&text.chars, close)),
+                    false => end,
+                };
+                match BLOCKS.contains(&name.as_str()) {
+                    true => text.replace(i..end, '\n'),
+                    false => text.remove(i..end),
+                }
+                i = end;
+            }
+            '&' => match entity(&text.chars, i) {
+                Some((end, c)) => {
+                    text.decode(i..end, c);
+                    i = end;
+                }
+                None => i += 1,
+            },
+            _ => i += 1,
+        }
+    }
+}
+
+/// The offset after the `>` closing the tag at `start`, skipping quoted
+/// attribute values.
+fn tag_end(chars: &[char], start: usize) -> usize {
+    let mut quote = None;
+    for (i, &c) in chars.iter().enumerate().skip(start + 1) {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if matches!(c, '"' | '\'') => quote = Some(c),
+            None if c == '>' => return i + 1,
+            None => {}
+        }
+    }
+    chars.len()
+}
+
+fn tag_name(tag: &[char]) -> String {
+    tag.iter()
+        .skip_while(|&&c| c == '/')
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The end and character of the entity at `start`, if it is one.
+fn entity(chars: &[char], start: usize) -> Option<(usize, char)> {
+    let semicolon = start + 1 + chars[start + 1..].iter().take(10).position(|&c| c == ';')?;
+    let name: String = chars[start + 1..semicolon].iter().collect();
+    let c = match name.strip_prefix('#') {
+        Some(number) => {
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+        None => match name.as_str() {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            "nbsp" => '\u{a0}',
+            "ndash" => '–',
+            "mdash" => '—',
+            "hellip" => '…',
+            "lsquo" => '‘',
+            "rsquo" => '’',
+            "ldquo" => '“',
+            "rdquo" => '”',
+            _ => return None,
+        },
+    };
+    Some((semicolon + 1, c))
+}
+
+fn markdown(text: &mut Text) {
+    let len = text.chars.len();
+    let mut fence: Option<String> = None;
+    let mut start = 0;
+    while start < len {
+        let end = text.chars[start..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(len, |i| start + i);
+        let line: String = text.chars[start..end].iter().collect();
+        let trimmed = line.trim_start();
+        let indent = line.chars().count() - trimmed.chars().count();
+        let marker = ["```", "~~~"]
+            .into_iter()
+            .find(|marker| trimmed.starts_with(marker));
+
+        match (&fence, marker) {
+            (Some(open), _) => {
+                if trimmed.starts_with(open.as_str()) {
+                    fence = None;
+                }
+                text.remove(start..end);
+            }
+            (None, Some(marker)) => {
+                fence = Some(marker.to_string());
+                text.remove(start..end);
+            }
+            (None, None) if is_rule(trimmed) => text.remove(start..end),
+            (None, None) => {
+                let content = line_content(text, start + indent, end);
+                text.remove(start + indent..content);
+                inline(text, content, end);
+            }
+        }
+        start = end + 1;
+    }
+}
+
+/// Thematic breaks and setext heading underlines.
+fn is_rule(line: &str) -> bool {
+    let line = line.trim_end();
+    let Some(first) = line.chars().next() else {
+        return false;
+    };
+    matches!(first, '-' | '*' | '_' | '=')
+        && line.chars().filter(|&c| c == first).count() >= 3
+        && line.chars().all(|c| c == first || c == ' ')
+}
+
+/// Where a line's text starts after its block quote, heading, list and task
+/// markers.
+fn line_content(text: &Text, mut at: usize, end: usize) -> usize {
+    let chars = &text.chars;
+    let space_at = |i: usize| i == end || chars[i] == ' ';
+    while at < end && chars[at] == '>' {
+        at += 1;
+        if at < end && chars[at] == ' ' {
+            at += 1;
+        }
+    }
+    let hashes = text.run(at, end);
+    if at < end && chars[at] == '#' && hashes <= 6 && space_at(at + hashes) {
+        return (at + hashes + 1).min(end);
+    }
+    if at < end && matches!(chars[at], '-' | '*' | '+') && space_at(at + 1) {
+        at = (at + 2).min(end);
+    } else {
+        let digits = chars[at..end]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if (1..=9).contains(&digits)
+            && at + digits < end
+            && matches!(chars[at + digits], '.' | ')')
+            && space_at(at + digits + 1)
+        {
+            at = (at + digits + 2).min(end);
+        }
+    }
+    if ["[ ] ", "[x] ", "[X] "]
+        .iter()
+        .any(|task| at + 4 <= end && text.starts_with(at, task))
+    {
+        at += 4;
+    }
+    at
+}
+
+fn inline(text: &mut Text, start: usize, end: usize) {
+    // The `]` of the link being read, and where its destination ends
+    let mut link: Option<(usize, usize)> = None;
+    let mut i = start;
+    while i < end {
+        if let Some((close, after)) = link {
+            if i == close {
+                link = None;
+                i = after;
+                continue;
+            }
+        }
+        match text.chars[i] {
+            '\\' if i + 1 < end && text.chars[i + 1].is_ascii_punctuation() => {
+                text.remove(i..i + 1);
+                i += 2;
+            }
+            '`' => {
+                let run = text.run(i, end);
+                let ticks = "`".repeat(run);
+                match text.find(i + run, &ticks).filter(|&close| close < end) {
+                    Some(close) => {
+                        text.remove(i..close + run);
+                        i = close + run;
+                    }
+                    None => i += run,
+                }
+            }
+            '!' | '[' if link.is_none() => {
+                let open = if text.chars[i] == '!' { i + 1 } else { i };
+                match link_end(&text.chars, open, end) {
+                    Some((close, after)) => {
+                        text.remove(i..open + 1);
+                        text.remove(close..after);
+                        link = Some((close, after));
+                        i = open + 1;
+                    }
+                    None => i += 1,
+                }
+            }
+            c @ ('*' | '_' | '~') => {
+                let run = text.run(i, end);
+                let word = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric());
+                let before = i.checked_sub(1).and_then(|j| text.chars.get(j));
+                let after = text.chars[..end].get(i + run);
+                if (c != '~' || run >= 2) && word(before) != word(after) {
+                    text.remove(i..i + run);
+                }
+                i += run;
+            }
+            _ => i += 1,
+        }
+    }
+}
+
+/// For a `[` at `open` starting an inline or reference link, the offset of
+/// its `]` and the end of the destination or reference.
+fn link_end(chars: &[char], open: usize, end: usize) -> Option<(usize, usize)> {
+    if chars.get(open) != Some(&'[') {
+        return None;
+    }
+    let close = open + chars[open..end].iter().position(|&c| c == ']')?;
+    let closing = match chars.get(close + 1) {
+        Some('(') => ')',
+        Some('[') => ']',
+        _ => return None,
+    };
+    let after = close + 2 + chars[close + 2..end].iter().position(|&c| c == closing)?;
+    Some((close, after + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of `document`, and the document's text at `range` of it.
+    fn stripped(markup: Markup, document: &str, range: Range<usize>) -> (String, String) {
+        let (text, offsets) = markup.strip(document);
+        let (start, end) = (
+            offsets.original(range.start),
+            offsets.original_end(range.end),
+        );
+        let original = document.chars().skip(start).take(end - start).collect();
+        (text, original)
+    }
+
+    #[test]
+    fn html_offsets_map_back_to_the_document() {
+        let document = "<h1>Bures</h1><p class=\"a>b\">Mun lean <b>sami</b> &amp; \
+                        <i>hálan</i></p><script>let x = 1;</script><!-- x -->ja";
+        let (text, original) = stripped(Markup::Html, document, 17..21);
+        assert_eq!(text, "\nBures\n\nMun lean sami & hálan\nja");
+        assert_eq!(original, "sami");
+        let (_, original) = stripped(Markup::Html, document, 22..23);
+        assert_eq!(original, "&amp;");
+        let (_, original) = stripped(Markup::Html, document, 30..32);
+        assert_eq!(original, "ja");
+    }
+
+    #[test]
+    fn markdown_offsets_map_back_to_the_document() {
+        let document = "# Bures\n\n- Mun lean **sami**, [hálan](https://x.org/a_b) \
+                        `code` snake_case\n```\nlet x;\n```\n> ja";
+        let (text, original) = stripped(Markup::Markdown, document, 16..20);
+        assert_eq!(text, "Bures\n\nMun lean sami, hálan  snake_case\n\n\n\nja");
+        assert_eq!(original, "sami");
+        let (_, original) = stripped(Markup::Markdown, document, 22..27);
+        assert_eq!(original, "hálan");
+        let (_, original) = stripped(Markup::Markdown, document, 43..45);
+        assert_eq!(original, "ja");
+    }
+
+    #[test]
+    fn format_is_taken_from_the_request() {
+        let (body, markup) =
+            Markup::requested(br#"{"text":"<b>sami</b>","format":"html"}"#.to_vec()).unwrap();
+        assert_eq!(body, br#"{"text":"<b>sami</b>"}"#);
+        assert_eq!(markup, Some(Markup::Html));
+        assert!(Markup::requested(br#"{"text":"sami","format":"rtf"}"#.to_vec()).is_err());
+    }
+}
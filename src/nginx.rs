@@ -0,0 +1,315 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+
+use crate::balance::Balance;
+use crate::cache;
+use crate::config::{Instance, LanguagesConfig};
+use crate::services::ServiceRegistry;
+
+/// Render one nginx `location` block per configured service, language and
+/// voice, after an `upstream` block for each language with several backend
+/// instances.
+pub fn generate_nginx_config(languages: &LanguagesConfig, services: &ServiceRegistry) -> String {
+    // Aliases share the upstream of the location they follow
+    let mut upstreams: Vec<(&[Instance], Balance, String)> = Vec::new();
+    let locations = services.proxy_locations(languages);
+    let blocks: Vec<String> = locations
+        .iter()
+        .map(|location| {
+            let target = match location.instances.as_slice() {
+                [] => format!("{}:{}", location.host, location.port),
+                [instance] => instance.to_string(),
+                instances => match upstreams.iter().find(|(known, ..)| *known == instances) {
+                    Some((.., name)) => name.clone(),
+                    None => {
+                        let name = upstream_name(&location.path);
+                        upstreams.push((instances, location.balance, name.clone()));
+                        name
+                    }
+                },
+            };
+            let directives = location_directives(languages, &location.path);
+            generate_location_block(&location.path, &target, "", &location.query, &directives)
+        })
+        .collect();
+
+    upstreams
+        .iter()
+        .map(|(instances, balance, name)| generate_upstream_block(name, instances, *balance))
+        .chain(blocks)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// `/grammar/se` as `grammar_se`.
+fn upstream_name(path: &str) -> String {
+    path.trim_start_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// An `upstream` spreading requests over `instances` like the gateway
+/// would, leaving out instances that keep failing for a while.
+fn generate_upstream_block(name: &str, instances: &[Instance], balance: Balance) -> String {
+    let method = match balance {
+        Balance::RoundRobin => "",
+        Balance::LeastOutstanding => "\n    least_conn;",
+    };
+    let servers: String = instances
+        .iter()
+        .map(|instance| {
+            let weight = match instance.weight {
+                1 => String::new(),
+                weight => format!(" weight={}", weight),
+            };
+            format!(
+                "\n    server {}{} max_fails=3 fail_timeout=10s;",
+                instance, weight
+            )
+        })
+        .collect();
+    format!("upstream {} {{{}{}\n}}", name, method, servers)
+}
+
+/// Directives from the config for one location, besides the proxying.
+fn location_directives(languages: &LanguagesConfig, path: &str) -> Vec<String> {
+    let mut directives = Vec::new();
+    let max_age = languages
+        .config
+        .cors
+        .as_ref()
+        .and_then(|cors| cors.policy(path).max_age);
+    if let Some(max_age) = max_age {
+        // Replace whatever the backend sends so preflights are cached
+        directives.push("proxy_hide_header Access-Control-Max-Age;".to_string());
+        directives.push(format!(
+            "add_header Access-Control-Max-Age {} always;",
+            max_age
+        ));
+    }
+    if let Some(policy) = cache::policy(&languages.config.cache, path) {
+        directives.extend(policy.nginx_directives());
+    }
+    directives
+}
+
+fn generate_location_block(
+    fe_path: &str,
+    target: &str,
+    be_path: &str,
+    query: &[(String, String)],
+    directives: &[String],
+) -> String {
+    let mut query = query
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&");
+    if !query.is_empty() {
+        query = format!("?{}", query);
+    }
+
+    let directives: String = directives
+        .iter()
+        .map(|directive| format!("\n    {}", directive))
+        .collect();
+    format!(
+        r#"location {} {{
+    proxy_pass http://{}/{}{};
+    include proxy-headers.conf;{}
+}}"#,
+        fe_path, target, be_path, query, directives
+    )
+}
+
+/// Shared proxy headers included by every generated `location` block.
+pub fn generate_proxy_headers_config() -> String {
+    r#"proxy_http_version 1.1;
+proxy_set_header Upgrade $http_upgrade;
+proxy_set_header Connection 'upgrade';
+proxy_set_header Host $host;
+proxy_cache_bypass $http_upgrade;
+proxy_set_header X-Real-IP $remote_addr;
+proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+proxy_set_header X-Forwarded-Proto $scheme;"#
+        .to_string()
+}
+
+/// How to make a running nginx pick up regenerated files.
+#[derive(Debug, Clone)]
+pub enum ReloadMethod {
+    /// Test the configuration with `<nginx> -t`, then run `<nginx> -s reload`.
+    Command(String),
+    /// Send SIGHUP to the master process whose pid is in the file.
+    Pid(PathBuf),
+}
+
+/// Reload nginx and check that it survived.
+pub fn reload(method: &ReloadMethod) -> anyhow::Result<()> {
+    match method {
+        ReloadMethod::Command(nginx) => {
+            run(nginx, &["-t"]).context("the new configuration was rejected")?;
+            run(nginx, &["-s", "reload"])
+        }
+        ReloadMethod::Pid(path) => {
+            let pid = read_pid(path)?;
+            run("kill", &["-HUP", &pid])?;
+            // nginx exits if it can't apply a configuration it has already read
+            std::thread::sleep(Duration::from_secs(1));
+            run("kill", &["-0", &pid]).context("nginx stopped after reloading")
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> anyhow::Result<String> {
+    let pid = std::fs::read_to_string(path)
+        .with_context(|| format!("can't read nginx pid file {}", path.display()))?;
+    let pid = pid.trim();
+    if pid.is_empty() || !pid.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("{} doesn't hold a pid", path.display());
+    }
+    Ok(pid.to_string())
+}
+
+fn run(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    let command = format!("{} {}", program, args.join(" "));
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("can't run `{}`", command))?;
+    if !output.status.success() {
+        bail!(
+            "`{}` failed ({}): {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_reloads_are_reported() {
+        assert!(reload(&ReloadMethod::Command("true".to_string())).is_ok());
+        let err = reload(&ReloadMethod::Command("false".to_string())).unwrap_err();
+        assert_eq!(err.to_string(), "the new configuration was rejected");
+
+        let path = std::env::temp_dir().join(format!("nginx-{}.pid", std::process::id()));
+        std::fs::write(&path, "not a pid\n").unwrap();
+        assert!(reload(&ReloadMethod::Pid(path.clone())).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn location_block_without_query() {
+        assert_eq!(
+            generate_location_block("/grammar/se", "127.0.0.1:10000", "", &[], &[]),
+            "location /grammar/se {\n    proxy_pass http://127.0.0.1:10000/;\n    include proxy-headers.conf;\n}"
+        );
+    }
+
+    #[test]
+    fn tts_locations_carry_voice_parameters() {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let config = generate_nginx_config(&languages, &ServiceRegistry::builtin());
+        assert!(config.contains("location /tts/se/biret {"));
+
+        let block = config
+            .split("\n\n")
+            .find(|block| block.starts_with("location /tts/smj/sigga "))
+            .unwrap();
+        assert!(block.contains("proxy_pass http://127.0.0.1:40001/?language=2&speaker=3;"));
+    }
+
+    #[test]
+    fn locations_proxy_to_their_host() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().host = "grammar-se".to_string();
+        languages.config.tts.host = "tts".to_string();
+        let config = generate_nginx_config(&languages, &ServiceRegistry::builtin());
+        assert!(config.contains("proxy_pass http://grammar-se:10000/;"));
+        assert!(config.contains("proxy_pass http://tts:40001/?language=2&speaker=3;"));
+    }
+
+    #[test]
+    fn aliases_get_their_own_locations() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.tts.get_mut("se").unwrap().aliases = vec!["sme".to_string()];
+        let config = generate_nginx_config(&languages, &ServiceRegistry::builtin());
+        let block = |path: &str| {
+            config
+                .split("\n\n")
+                .find(|block| block.starts_with(&format!("location {} ", path)))
+                .unwrap()
+                .replace(path, "")
+        };
+        assert_eq!(block("/tts/sme/biret"), block("/tts/se/biret"));
+        assert!(!config.contains("location /grammar/sme "));
+    }
+
+    #[test]
+    fn locations_are_sorted_by_tag() {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let config = generate_nginx_config(&languages, &ServiceRegistry::builtin());
+        let fo = config.find("location /grammar/fo ").unwrap();
+        let se = config.find("location /grammar/se ").unwrap();
+        assert!(fo < se);
+    }
+
+    #[test]
+    fn instances_share_an_upstream() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        let se = languages.grammar.get_mut("se").unwrap();
+        se.ports = vec![
+            Instance::local(10000),
+            Instance {
+                host: "10.0.1.4".to_string(),
+                port: 10000,
+                weight: 2,
+            },
+        ];
+        se.aliases = vec!["sme".to_string()];
+        let config = generate_nginx_config(&languages, &ServiceRegistry::builtin());
+
+        assert!(config.starts_with(
+            "upstream grammar_se {\n    least_conn;\n    \
+             server 127.0.0.1:10000 max_fails=3 fail_timeout=10s;\n    \
+             server 10.0.1.4:10000 weight=2 max_fails=3 fail_timeout=10s;\n}\n\nlocation "
+        ));
+        assert_eq!(config.matches("upstream ").count(), 1);
+        assert_eq!(config.matches("proxy_pass http://grammar_se/;").count(), 2);
+        assert!(config.contains("proxy_pass http://127.0.0.1:10001/;"));
+
+        languages.grammar.get_mut("se").unwrap().balance = Balance::RoundRobin;
+        let config = generate_nginx_config(&languages, &ServiceRegistry::builtin());
+        assert!(config.starts_with("upstream grammar_se {\n    server 127.0.0.1:10000 "));
+    }
+
+    #[test]
+    fn preflight_max_age_is_mirrored() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.cors =
+            Some(toml::from_str("[[routes]]\npath = \"/speller\"\nmax_age = 86400\n").unwrap());
+        let config = generate_nginx_config(&languages, &ServiceRegistry::builtin());
+        let block = |path: &str| {
+            config
+                .split("\n\n")
+                .find(|block| block.starts_with(&format!("location {} ", path)))
+                .unwrap()
+        };
+        assert!(block("/speller/se").ends_with(
+            "    proxy_hide_header Access-Control-Max-Age;\n    \
+             add_header Access-Control-Max-Age 86400 always;\n}"
+        ));
+        assert!(!block("/grammar/se").contains("Access-Control-Max-Age"));
+    }
+}
@@ -0,0 +1,171 @@
+//! OpenAPI 3.1 documents generated from an [`ApiSchema`], served at
+//! `/openapi.json` and written by `generate openapi`, so clients can generate
+//! SDKs in languages [`client`](crate::client) doesn't cover.
+
+use serde_json::{json, Map, Value};
+
+use crate::schema::{ApiSchema, ResponseSchema, SchemaType, TypeDef};
+use crate::transcode::AudioFormat;
+
+pub fn generate_openapi(schema: &ApiSchema) -> Value {
+    let mut paths = Map::new();
+    for service in &schema.services {
+        for route in &service.routes {
+            let request = match &service.request {
+                Some(ty) => schema_of(ty),
+                None => json!({}),
+            };
+            let mut operation = json!({
+                "operationId": format!("{}-{}", service.name, route.target.replace('/', "-")),
+                "tags": [service.name],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": { "schema": request },
+                        "application/x-www-form-urlencoded": { "schema": request },
+                        "text/plain": { "schema": { "type": "string" } },
+                    },
+                },
+                "responses": responses(&service.response),
+            });
+            if let ResponseSchema::Audio = service.response {
+                operation["parameters"] = json!([{
+                    "name": "format",
+                    "in": "query",
+                    "description": "Audio format to convert the speech to",
+                    "schema": { "type": "string", "enum": AudioFormat::NAMES },
+                }]);
+            }
+            paths.insert(route.path.clone(), json!({ "post": operation }));
+        }
+    }
+
+    let mut schemas: Map<String, Value> = schema
+        .types
+        .iter()
+        .map(|def| (def.name.to_string(), object(def)))
+        .collect();
+    schemas.insert("Problem".to_string(), problem());
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": schema.title,
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": schema.base_url }],
+        "paths": paths,
+        "components": { "schemas": schemas },
+    })
+}
+
+fn responses(response: &ResponseSchema) -> Value {
+    let success = match response {
+        ResponseSchema::Json(ty) => json!({
+            "description": "The result",
+            "content": { "application/json": { "schema": schema_of(ty) } },
+        }),
+        ResponseSchema::Audio => {
+            let audio = |media_type: &str| json!({ "schema": { "type": "string", "contentMediaType": media_type } });
+            json!({
+                "description": "The synthesized speech, as WAV unless `format` or Accept asks for another",
+                "content": {
+                    "audio/wav": audio("audio/wav"),
+                    "audio/mpeg": audio("audio/mpeg"),
+                    "audio/ogg": audio("audio/ogg"),
+                    "audio/flac": audio("audio/flac"),
+                },
+            })
+        }
+    };
+    let problem = |description: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/problem+json": {
+                    "schema": { "$ref": "#/components/schemas/Problem" }
+                }
+            },
+        })
+    };
+    json!({
+        "200": success,
+        "400": problem("The request body is invalid"),
+        "413": problem("The text is too long"),
+        "429": problem("Too many requests"),
+        "502": problem("The backend is unavailable or answered with a malformed response"),
+    })
+}
+
+fn schema_of(ty: &SchemaType) -> Value {
+    match ty {
+        SchemaType::String => json!({ "type": "string" }),
+        SchemaType::Integer => json!({ "type": "integer" }),
+        SchemaType::Number => json!({ "type": "number" }),
+        SchemaType::Boolean => json!({ "type": "boolean" }),
+        SchemaType::Array(item) => json!({ "type": "array", "items": schema_of(item) }),
+        SchemaType::Named(name) => json!({ "$ref": format!("#/components/schemas/{}", name) }),
+        SchemaType::Any => json!({}),
+    }
+}
+
+fn object(def: &TypeDef) -> Value {
+    let properties: Map<String, Value> = def
+        .fields
+        .iter()
+        .map(|field| (field.name.to_string(), schema_of(&field.ty)))
+        .collect();
+    let required: Vec<_> = def.fields.iter().map(|field| field.name).collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+/// RFC 9457 problem details, as [`Problem`](crate::problem::Problem) sends.
+fn problem() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "type": { "type": "string" },
+            "title": { "type": "string" },
+            "status": { "type": "integer" },
+            "detail": { "type": "string" },
+            "instance": { "type": "string" },
+        },
+        "required": ["type", "title", "status"],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LanguagesConfig;
+    use crate::services::ServiceRegistry;
+
+    #[test]
+    fn every_route_is_a_path() {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let document = generate_openapi(&ApiSchema::new(&languages, &ServiceRegistry::builtin()));
+
+        let grammar = &document["paths"]["/grammar/se"]["post"];
+        assert_eq!(grammar["operationId"], "grammar-se");
+        assert!(grammar["requestBody"]["content"]["text/plain"].is_object());
+        assert_eq!(
+            grammar["responses"]["200"]["content"]["application/json"]["schema"],
+            json!({ "$ref": "#/components/schemas/GrammarResponse" })
+        );
+        let tts = &document["paths"]["/tts/se/biret"]["post"];
+        assert_eq!(tts["operationId"], "tts-se-biret");
+        assert!(tts["responses"]["200"]["content"]["audio/mpeg"].is_object());
+        assert_eq!(tts["parameters"][0]["name"], "format");
+        assert_eq!(
+            document["components"]["schemas"]["SpellerResult"]["properties"]["suggestions"],
+            json!({
+                "type": "array",
+                "items": { "$ref": "#/components/schemas/SpellerSuggestion" },
+            })
+        );
+    }
+}
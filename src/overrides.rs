@@ -0,0 +1,119 @@
+//! `DIVVUN_*` environment variables overriding `languages.toml`, so a
+//! container can move a backend or raise a limit without a modified config
+//! file. The name is the path to the value, with `__` between the levels:
+//!
+//! ```text
+//! DIVVUN_CONFIG__TTS__PORT=5001
+//! DIVVUN_CONFIG__RATE_LIMIT__PER_IP=600
+//! DIVVUN_GRAMMAR__SE__PORTS='["grammar-se:5000"]'
+//! ```
+//!
+//! Levels match the config's keys regardless of case, with `_` for `-`, and
+//! values are read as TOML, or else taken as a string. Names without a `__`,
+//! such as `DIVVUN_PORT` for `serve --port`, are left to the command line.
+
+use anyhow::bail;
+use toml::{Table, Value};
+
+pub const PREFIX: &str = "DIVVUN_";
+
+/// Set the values the `DIVVUN_*` variables among `vars` name in `document`.
+pub fn apply(
+    document: &mut Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> anyhow::Result<()> {
+    let mut vars: Vec<_> = vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(PREFIX) && name.contains("__"))
+        .collect();
+    // The same result whatever order the environment lists them in
+    vars.sort();
+    for (name, raw) in vars {
+        let path: Vec<&str> = name[PREFIX.len()..].split("__").collect();
+        if path.iter().any(|segment| segment.is_empty()) {
+            bail!("{} is not a config path", name);
+        }
+        let (last, tables) = path.split_last().expect("split yields a segment");
+        let mut table = &mut *document;
+        for segment in tables {
+            let key = key(table, segment);
+            let value = table
+                .entry(key)
+                .or_insert_with(|| Value::Table(Table::new()));
+            let Value::Table(inner) = value else {
+                bail!("{}: {} is not a table", name, segment.to_ascii_lowercase());
+            };
+            table = inner;
+        }
+        let key = key(table, last);
+        table.insert(key, value(&raw));
+    }
+    Ok(())
+}
+
+/// The key of `table` that `segment` names, or `segment` in lower case for a
+/// new one.
+fn key(table: &Table, segment: &str) -> String {
+    let normalize = |key: &str| key.replace('-', "_").to_ascii_lowercase();
+    let wanted = normalize(segment);
+    table
+        .keys()
+        .find(|key| normalize(key) == wanted)
+        .cloned()
+        .unwrap_or(wanted)
+}
+
+fn value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn variables_override_nested_values() {
+        let mut document: Table = toml::from_str(
+            "[config.tts]\nport = 5000\n\n[grammar.sma-Latn]\nname = \"sma\"\nport = 4001\n",
+        )
+        .unwrap();
+        apply(
+            &mut document,
+            vars(&[
+                ("DIVVUN_CONFIG__TTS__PORT", "5001"),
+                ("DIVVUN_CONFIG__RATE_LIMIT__PER_IP", "600"),
+                ("DIVVUN_GRAMMAR__SMA_LATN__PORTS", "[\"grammar:4001\"]"),
+                ("DIVVUN_BRANDING__TITLE", "Giella API"),
+                ("DIVVUN_PORT", "8080"),
+                ("PATH", "/usr/bin"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(document["config"]["tts"]["port"].as_integer(), Some(5001));
+        assert_eq!(
+            document["config"]["rate_limit"]["per_ip"].as_integer(),
+            Some(600)
+        );
+        assert_eq!(
+            document["grammar"]["sma-Latn"]["ports"][0].as_str(),
+            Some("grammar:4001")
+        );
+        assert_eq!(document["branding"]["title"].as_str(), Some("Giella API"));
+        assert!(!document.contains_key("port"));
+
+        let err = apply(&mut document, vars(&[("DIVVUN_CONFIG__TTS__PORT__X", "1")]));
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "DIVVUN_CONFIG__TTS__PORT__X: port is not a table"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use poem::{
+    handler,
+    http::{header, HeaderMap, StatusCode},
+    web::{Data, Html, Path, Query},
+    IntoResponse,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::assets;
+use crate::config::LanguagesConfig;
+use crate::health::HealthMonitor;
+use crate::i18n::Catalogs;
+use crate::problem::Problem;
+use crate::services::ServiceRegistry;
+use crate::template::{self, escape_html};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct IndexQuery {
+    lang: Option<String>,
+}
+
+#[handler]
+pub(crate) async fn index_get(
+    Data(languages): Data<&LanguagesConfig>,
+    Data(services): Data<&ServiceRegistry>,
+    Data(catalogs): Data<&Catalogs>,
+    Query(query): Query<IndexQuery>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let accept_language = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    let l = catalogs.negotiate(query.lang.as_deref(), accept_language);
+
+    let consoles = console_endpoints(languages, services);
+    let sections: Vec<_> = services
+        .iter()
+        .filter_map(|kind| {
+            let docs = kind.docs(languages, &l)?;
+            Some(json!({
+                "name": kind.name(),
+                "title": docs.title,
+                "html": docs.html,
+                "console": consoles.contains_key(kind.name()),
+            }))
+        })
+        .collect();
+    let branding = &languages.branding;
+    let context = json!({
+        "lang": l.tag(),
+        "t": l.messages(),
+        "assets_version": assets::version(),
+        "announcement": languages.config.announcement.as_ref().map(|a| &a.message),
+        "branding": branding,
+        "organization": branding.organization.is_some() || branding.logo_url.is_some(),
+        "sections": sections,
+        "consoles": Value::Object(consoles).to_string().replace("</", "<\\/"),
+    });
+    let html = template::render("index.html", context);
+    Html(html)
+        .with_header(header::CONTENT_LANGUAGE, l.tag())
+        .with_header(header::VARY, "Accept-Language")
+        .into_response()
+}
+
+/// The endpoints each service's try-it console can send to, by service, with
+/// the example text of their language.
+fn console_endpoints(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+) -> Map<String, Value> {
+    services
+        .iter()
+        .filter_map(|kind| {
+            let endpoints: Vec<_> = kind
+                .locations(languages)
+                .iter()
+                .map(|location| {
+                    let name = languages
+                        .language_name(kind.name(), &location.tag)
+                        .unwrap_or(&location.tag);
+                    let route = location.path.trim_start_matches('/');
+                    let route = route.split_once('/').map_or(route, |(_, route)| route);
+                    json!({
+                        "path": location.path,
+                        "label": format!("{} ({})", name, route),
+                        "example": example(languages, &location.tag).unwrap_or_default(),
+                    })
+                })
+                .collect();
+            (!endpoints.is_empty()).then(|| (kind.name().to_string(), Value::Array(endpoints)))
+        })
+        .collect()
+}
+
+/// The example text configured for `tag`, by any of its services.
+fn example<'a>(languages: &'a LanguagesConfig, tag: &str) -> Option<&'a str> {
+    let grammar = languages.grammar.get(tag);
+    let speller = languages.speller.get(tag);
+    let tts = languages.tts.get(tag);
+    grammar
+        .and_then(|service| service.example.as_deref())
+        .or(speller.and_then(|service| service.example.as_deref()))
+        .or(tts.and_then(|tts| tts.example.as_deref()))
+}
+
+#[handler]
+pub(crate) async fn status_html_get(
+    Data(health): Data<&HealthMonitor>,
+    Data(catalogs): Data<&Catalogs>,
+    Query(query): Query<IndexQuery>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let accept_language = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    let l = catalogs.negotiate(query.lang.as_deref(), accept_language);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let rows = health
+        .snapshot()
+        .iter()
+        .map(|backend| {
+            let (class, state) = match backend.up {
+                Some(true) => ("up", l.t("status_up")),
+                Some(false) => ("down", l.t("status_down")),
+                None => ("pending", l.t("status_pending")),
+            };
+            format!(
+                r#"                    <tr>
+                        <td>{}</td>
+                        <td><code>{}</code></td>
+                        <td><span class="state {}">{}</span></td>
+                        <td>{}</td>
+                        <td>{}</td>
+                        <td class="error">{}</td>
+                    </tr>"#,
+                backend.service,
+                backend.tag,
+                class,
+                state,
+                backend
+                    .latency_ms
+                    .map(|ms| format!("{} ms", ms))
+                    .unwrap_or_default(),
+                backend
+                    .last_checked
+                    .map(|at| {
+                        let seconds = now.saturating_sub(at);
+                        l.t_args("status_seconds_ago", &[("seconds", seconds.into())])
+                    })
+                    .unwrap_or_default(),
+                backend
+                    .last_error
+                    .as_deref()
+                    .map(escape_html)
+                    .unwrap_or_default(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut html = include_str!("../status.html").to_string();
+    if let Some(pos) = html.find("<tbody>") {
+        html.insert_str(pos + "<tbody>".len(), &format!("\n{}", rows));
+    }
+
+    Html(l.localize(&html))
+        .with_header(header::CONTENT_LANGUAGE, l.tag())
+        .with_header(header::VARY, "Accept-Language")
+        .into_response()
+}
+
+#[handler]
+pub(crate) async fn demo_get(
+    Path(tag): Path<String>,
+    Data(languages): Data<&LanguagesConfig>,
+    Data(catalogs): Data<&Catalogs>,
+    Query(query): Query<IndexQuery>,
+    headers: &HeaderMap,
+) -> impl IntoResponse {
+    let grammar = languages.grammar.get(&tag);
+    let speller = languages.speller.get(&tag);
+    let tts = languages.tts.get(&tag);
+
+    let Some(name) = grammar
+        .or(speller)
+        .map(|service| &service.name)
+        .or(tts.map(|tts| &tts.name))
+    else {
+        return Problem::new(StatusCode::NOT_FOUND, "Unknown language")
+            .detail(format!("There is no service for {}", tag))
+            .into_response();
+    };
+
+    let mut voices: Vec<_> = tts
+        .map(|tts| tts.voices.iter().collect())
+        .unwrap_or_default();
+    voices.sort_by_key(|(voice_id, _)| *voice_id);
+
+    let demo = json!({
+        "tag": tag,
+        "name": name,
+        "example": example(languages, &tag).unwrap_or_default(),
+        "grammar": grammar.is_some(),
+        "speller": speller.is_some(),
+        "voices": voices
+            .iter()
+            .map(|(voice_id, voice)| json!({ "id": voice_id, "name": voice.name, "gender": voice.gender }))
+            .collect::<Vec<_>>(),
+    });
+
+    let accept_language = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    let l = catalogs.negotiate(query.lang.as_deref(), accept_language);
+
+    // Inject the data after localizing so user-provided text is never treated as a placeholder
+    let html = l
+        .localize(include_str!("../demo.html"))
+        .replace("/*DEMO*/null", &demo.to_string().replace("</", "<\\/"));
+
+    Html(html)
+        .with_header(header::CONTENT_LANGUAGE, l.tag())
+        .with_header(header::VARY, "Accept-Language")
+        .into_response()
+}
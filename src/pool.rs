@@ -0,0 +1,43 @@
+//! Connections to the backends. The gateway forwards everything through one
+//! HTTP client, which keeps a pool of idle keep-alive connections to each
+//! backend, so the many small requests editors send don't each pay for a new
+//! TCP connection.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Idle connections kept open to each backend; 0 closes every
+    /// connection after its request.
+    pub max_idle: usize,
+    /// Seconds an unused connection stays open.
+    pub idle_timeout: u64,
+    /// Seconds between TCP keep-alive probes on open connections.
+    pub tcp_keepalive: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle: 32,
+            idle_timeout: 90,
+            tcp_keepalive: 60,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// A client pooling connections as configured.
+    pub fn client(&self) -> anyhow::Result<reqwest::Client> {
+        reqwest::Client::builder()
+            .pool_max_idle_per_host(self.max_idle)
+            .pool_idle_timeout(Duration::from_secs(self.idle_timeout))
+            .tcp_keepalive(Duration::from_secs(self.tcp_keepalive))
+            .build()
+            .context("can't set up the backend connection pool")
+    }
+}
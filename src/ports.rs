@@ -0,0 +1,179 @@
+//! Audit of the backend ports referenced by the configuration.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::config::{is_local, LanguagesConfig};
+use crate::services::{tcp_probe, ServiceRegistry};
+use crate::table;
+
+/// Backends are expected on unprivileged ports.
+pub const DEFAULT_RANGE: RangeInclusive<u16> = 1024..=65535;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortReport {
+    /// 127.0.0.1 for any name of this machine.
+    pub host: String,
+    pub port: u16,
+    /// Who uses the port, e.g. `grammar se`, or `tts (se, sma)` for a
+    /// category sharing one port.
+    pub users: Vec<String>,
+    /// Used by more than one backend process on this machine.
+    pub duplicate: bool,
+    pub out_of_range: bool,
+    /// `None` until [`probe_listening`] has run, and for other hosts.
+    pub listening: Option<bool>,
+}
+
+impl PortReport {
+    pub fn is_ok(&self) -> bool {
+        !self.duplicate && !self.out_of_range && self.listening != Some(false)
+    }
+}
+
+/// Every port referenced by `languages`, in ascending order, with the
+/// ports of other hosts apart from this machine's.
+pub fn audit(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    range: &RangeInclusive<u16>,
+) -> Vec<PortReport> {
+    let mut ports: BTreeMap<(u16, String), Vec<String>> = BTreeMap::new();
+    for kind in services.iter() {
+        let mut tags_by_port: BTreeMap<(u16, String), Vec<String>> = BTreeMap::new();
+        for backend in kind.backends(languages) {
+            let host = match is_local(&backend.host) {
+                true => "127.0.0.1".to_string(),
+                false => backend.host,
+            };
+            tags_by_port
+                .entry((backend.port, host))
+                .or_default()
+                .push(backend.tag);
+        }
+
+        for (address, tags) in tags_by_port {
+            let users = ports.entry(address).or_default();
+            if kind.shared_port() {
+                users.push(format!("{} ({})", kind.name(), tags.join(", ")));
+            } else {
+                users.extend(tags.iter().map(|tag| format!("{} {}", kind.name(), tag)));
+            }
+        }
+    }
+
+    ports
+        .into_iter()
+        .map(|((port, host), users)| PortReport {
+            duplicate: users.len() > 1 && is_local(&host),
+            host,
+            port,
+            out_of_range: !range.contains(&port),
+            users,
+            listening: None,
+        })
+        .collect()
+}
+
+/// Check whether anything accepts connections on each reported port of
+/// this machine.
+pub async fn probe_listening(reports: &mut [PortReport]) {
+    for report in reports.iter_mut().filter(|report| is_local(&report.host)) {
+        report.listening = Some(tcp_probe(&report.host, report.port).await.is_ok());
+    }
+}
+
+/// Parse a range such as `10000-19999`.
+pub fn parse_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| format!("expected START-END, got {:?}", range))?;
+    let start: u16 = start.trim().parse().map_err(|err| format!("{}", err))?;
+    let end: u16 = end.trim().parse().map_err(|err| format!("{}", err))?;
+    if start > end {
+        return Err(format!("{} is greater than {}", start, end));
+    }
+    Ok(start..=end)
+}
+
+/// A plain-text table of `reports`, one port per line.
+pub fn table(reports: &[PortReport]) -> String {
+    let rows: Vec<Vec<String>> = reports
+        .iter()
+        .map(|report| {
+            let mut problems = Vec::new();
+            if report.duplicate {
+                problems.push("duplicate");
+            }
+            if report.out_of_range {
+                problems.push("out of range");
+            }
+            if report.listening == Some(false) {
+                problems.push("not listening");
+            }
+            vec![
+                report.host.clone(),
+                report.port.to_string(),
+                match report.listening {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "-",
+                }
+                .to_string(),
+                report.users.join(", "),
+                problems.join(", "),
+            ]
+        })
+        .collect();
+    table::render(&["HOST", "PORT", "LISTENING", "USED BY", "PROBLEMS"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates_and_ranges_are_flagged() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        let se = languages.grammar["se"].port;
+        languages.speller.get_mut("se").unwrap().port = se;
+        languages.grammar.get_mut("sma").unwrap().port = 80;
+
+        let reports = audit(&languages, &ServiceRegistry::builtin(), &DEFAULT_RANGE);
+        let report = |port| reports.iter().find(|r| r.port == port).unwrap();
+        assert!(report(se).duplicate);
+        assert_eq!(report(se).users, vec!["grammar se", "speller se"]);
+        assert!(report(80).out_of_range);
+
+        let tts = report(languages.config.tts.port);
+        assert!(!tts.duplicate);
+        assert!(tts.users[0].starts_with("tts ("));
+    }
+
+    #[test]
+    fn ports_of_other_hosts_are_apart() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        let se = languages.grammar["se"].port;
+        languages.grammar.get_mut("se").unwrap().host = "grammar-se".to_string();
+        languages.speller.get_mut("se").unwrap().host = "speller-se".to_string();
+        languages.speller.get_mut("se").unwrap().port = se;
+
+        let reports = audit(&languages, &ServiceRegistry::builtin(), &DEFAULT_RANGE);
+        let report = |host: &str| {
+            reports
+                .iter()
+                .find(|r| r.host == host && r.port == se)
+                .unwrap()
+        };
+        assert_eq!(report("grammar-se").users, vec!["grammar se"]);
+        assert_eq!(report("speller-se").users, vec!["speller se"]);
+        assert!(reports.iter().all(|report| !report.duplicate));
+    }
+
+    #[test]
+    fn ranges_parse() {
+        assert_eq!(parse_range("10000-19999"), Ok(10000..=19999));
+        assert!(parse_range("2-1").is_err());
+        assert!(parse_range("10000").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! `application/problem+json` error bodies (RFC 9457).
+//!
+//! Handlers answer with a [`Problem`]; [`ProblemErrors`] turns the errors
+//! that don't, e.g. poem's for unknown routes or unparsable queries, into
+//! one too, and points every problem's `instance` at the request path.
+
+use poem::{
+    http::{header, StatusCode},
+    Body, Endpoint, Error, IntoResponse, Middleware, Request, Response, Result,
+};
+use serde_json::{Map, Value};
+
+const CONTENT_TYPE: &str = "application/problem+json";
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    status: StatusCode,
+    title: String,
+    detail: Option<String>,
+    instance: Option<String>,
+    extensions: Map<String, Value>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, title: impl Into<String>) -> Self {
+        Self {
+            status,
+            title: title.into(),
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// The URI of this occurrence of the problem, e.g. the request path.
+    pub fn instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Add a problem-specific member, e.g. the limit that was exceeded.
+    pub fn extension(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.extensions.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// The body of the problem's response, e.g. for an error event in a
+    /// stream that has already started.
+    pub fn to_json(&self) -> Value {
+        let mut body = Map::new();
+        body.insert("type".to_string(), "about:blank".into());
+        body.insert("title".to_string(), self.title.clone().into());
+        body.insert("status".to_string(), self.status.as_u16().into());
+        if let Some(detail) = &self.detail {
+            body.insert("detail".to_string(), detail.clone().into());
+        }
+        if let Some(instance) = &self.instance {
+            body.insert("instance".to_string(), instance.clone().into());
+        }
+        body.extend(self.extensions.clone());
+        Value::Object(body)
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let retry_after = self.extensions.get("retry_after").and_then(Value::as_u64);
+        let mut response = Response::builder()
+            .status(self.status)
+            .content_type(CONTENT_TYPE)
+            .body(self.to_json().to_string());
+        // Clients told when to come back in the body are told in the header
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, seconds.into());
+        }
+        response
+    }
+}
+
+impl From<Problem> for Error {
+    fn from(problem: Problem) -> Self {
+        Error::from_response(problem.into_response())
+    }
+}
+
+/// Middleware answering every error as a [`Problem`]. Error responses with
+/// a body of their own, such as a backend's, are left alone.
+pub struct ProblemErrors;
+
+impl<E: Endpoint> Middleware<E> for ProblemErrors {
+    type Output = ProblemErrorsEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ProblemErrorsEndpoint { inner: ep }
+    }
+}
+
+pub struct ProblemErrorsEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for ProblemErrorsEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let instance = req.uri().path().to_string();
+        let (mut response, raised) = match self.inner.call(req).await {
+            Ok(response) => (response.into_response(), false),
+            Err(err) => (err.into_response(), true),
+        };
+        let is_problem = response
+            .content_type()
+            .is_some_and(|content_type| content_type.starts_with(CONTENT_TYPE));
+        let body = response.take_body();
+        let is_bare = !raised
+            && response.status().as_u16() >= 400
+            && response.content_type().is_none()
+            && body.is_empty();
+        response.set_body(body);
+        match (is_problem, raised || is_bare) {
+            (true, _) => Ok(with_instance(response, &instance).await),
+            (false, true) => Ok(to_problem(response, instance).await),
+            (false, false) => Ok(response),
+        }
+    }
+}
+
+/// `response`, a problem, with its `instance` set unless it has one.
+async fn with_instance(response: Response, instance: &str) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let body = body.into_bytes().await.unwrap_or_default();
+    let body = match serde_json::from_slice(&body) {
+        Ok(Value::Object(mut problem)) if !problem.contains_key("instance") => {
+            problem.insert("instance".to_string(), instance.into());
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Value::Object(problem).to_string().into_bytes()
+        }
+        _ => body.to_vec(),
+    };
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// A problem for the error `response`, with its status and headers, and its
+/// text, if any, as the detail.
+async fn to_problem(response: Response, instance: String) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let text = body.into_string().await.unwrap_or_default();
+    let title = parts.status.canonical_reason().unwrap_or("Error");
+    let problem = Problem::new(parts.status, title).instance(instance);
+    let problem = match text.trim() {
+        "" => problem,
+        detail if detail.eq_ignore_ascii_case(title) => problem,
+        detail => problem.detail(detail),
+    };
+    parts.headers.remove(header::CONTENT_TYPE);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let mut response = problem.into_response();
+    response.headers_mut().extend(parts.headers);
+    response
+}
@@ -0,0 +1,926 @@
+//! Forwarding for service categories the gateway serves itself.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use poem::{
+    http::{header, HeaderValue, StatusCode},
+    Endpoint, Error, Request, Response, Result,
+};
+use serde_json::Value;
+
+use crate::access::REQUEST_ID;
+use crate::apostrophe;
+use crate::charset;
+use crate::chunking;
+use crate::error_tags::TagFilter;
+use crate::forms;
+use crate::health::HealthMonitor;
+#[cfg(feature = "wasm")]
+use crate::hooks::WasmHook;
+use crate::markup::Markup;
+use crate::problem::Problem;
+use crate::recording::{self, Recorder, Recording};
+use crate::responses::{CacheKey, CachedResponse, ResponseCache};
+use crate::retry::{CircuitBreakers, RetryConfig};
+use crate::sampling::Sampler;
+use crate::sanitize::{self, OffsetMap, SanitizePolicy};
+use crate::schema::{self, FieldError, ResponseSchema, SchemaType};
+use crate::services::{Location, ServiceKind};
+use crate::ssml;
+use crate::suggestions::SuggestionLimits;
+use crate::transcode::{self, AudioFormat, Transcoder};
+use crate::upstream::{failed, Upstream};
+
+/// Forwards `POST` requests for one [`Location`] to its backend, passing the
+/// bodies through the service kind's request and response mapping. Request
+/// bodies are transcoded to UTF-8 first, with a `Warning` header on the
+/// response saying so, and checked against the kind's request schema.
+/// Successful backend responses are checked against its response schema, so
+/// a misbehaving backend gets a 502 rather than passing garbage on.
+/// Responses carry a `Server-Timing` header splitting the time taken between
+/// the backend and the gateway itself.
+///
+/// With the `wasm` feature, an optional `WasmHook` sees the client-facing
+/// bodies: it runs before the kind's request mapping and after its response
+/// mapping.
+pub struct ProxyEndpoint {
+    kind: Arc<dyn ServiceKind>,
+    /// The location's backend, with its instances, fallback and circuit.
+    upstream: Upstream,
+    client: reqwest::Client,
+    max_length: Option<usize>,
+    sanitize: SanitizePolicy,
+    apostrophe: Option<char>,
+    /// Picks the requests copied to the shadow backend.
+    shadow_sampler: Option<Sampler>,
+    recorder: Option<Arc<Recorder>>,
+    cache: Option<Arc<ResponseCache>>,
+    chunk_length: Option<usize>,
+    transcoder: Option<Transcoder>,
+    /// Whether SSML is accepted, and if so whether the backend gets it.
+    ssml: Option<bool>,
+    /// Whether requests may filter the `errs` by `error_code`.
+    tag_filtering: bool,
+    /// Whether requests may limit the speller's `suggestions`.
+    suggestion_limits: bool,
+    /// Whether requests may send HTML or Markdown.
+    markup: bool,
+    retry: Option<RetryConfig>,
+    #[cfg(feature = "wasm")]
+    hook: Option<Arc<WasmHook>>,
+}
+
+impl ProxyEndpoint {
+    pub fn new(kind: Arc<dyn ServiceKind>, location: Location, client: reqwest::Client) -> Self {
+        Self {
+            shadow_sampler: location
+                .shadow
+                .as_ref()
+                .map(|shadow| Sampler::new(shadow.percent)),
+            upstream: Upstream::new(kind.name(), location),
+            kind,
+            client,
+            max_length: None,
+            sanitize: SanitizePolicy::Keep,
+            apostrophe: None,
+            recorder: None,
+            cache: None,
+            chunk_length: None,
+            transcoder: None,
+            ssml: None,
+            tag_filtering: false,
+            suggestion_limits: false,
+            markup: false,
+            retry: None,
+            #[cfg(feature = "wasm")]
+            hook: None,
+        }
+    }
+
+    /// Reject texts longer than `max_length` characters with a 413.
+    pub fn with_max_length(mut self, max_length: Option<usize>) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    #[cfg(feature = "wasm")]
+    pub fn with_hook(mut self, hook: Arc<WasmHook>) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    pub fn with_sanitize(mut self, sanitize: SanitizePolicy) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    /// Normalize apostrophe look-alikes to `apostrophe` before forwarding.
+    pub fn with_apostrophe(mut self, apostrophe: Option<char>) -> Self {
+        self.apostrophe = apostrophe;
+        self
+    }
+
+    /// Send requests to the location's fallback while `health` finds the
+    /// backend down, and back once it recovers.
+    pub fn with_failover(mut self, health: HealthMonitor) -> Self {
+        self.upstream = self.upstream.with_failover(health);
+        self
+    }
+
+    /// Record every exchange with the backend.
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Answer repeated requests from `cache` instead of the backend.
+    pub fn with_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Split texts longer than `chunk_length` characters on sentence
+    /// boundaries, synthesize the pieces one after another and join the
+    /// audio.
+    pub fn with_chunking(mut self, chunk_length: Option<usize>) -> Self {
+        self.chunk_length = chunk_length;
+        self
+    }
+
+    /// Convert the backend's WAV audio to the `format` a request asks for.
+    pub fn with_transcoder(mut self, transcoder: Transcoder) -> Self {
+        self.transcoder = Some(transcoder);
+        self
+    }
+
+    /// Accept SSML in an `ssml` field instead of `text`. The sanitized
+    /// markup goes to the backend if it `supported` it, else its text does.
+    pub fn with_ssml(mut self, supported: bool) -> Self {
+        self.ssml = Some(supported);
+        self
+    }
+
+    /// Drop the findings whose `error_code` a request's `ignore_tags` or
+    /// `include_tags` leave out.
+    pub fn with_tag_filter(mut self) -> Self {
+        self.tag_filtering = true;
+        self
+    }
+
+    /// Cut each word's suggestions to a request's `max_suggestions` and
+    /// `max_weight`.
+    pub fn with_suggestion_limits(mut self) -> Self {
+        self.suggestion_limits = true;
+        self
+    }
+
+    /// Accept HTML or Markdown texts, named by a request's `format`, and
+    /// check the text without the markup.
+    pub fn with_markup(mut self) -> Self {
+        self.markup = true;
+        self
+    }
+
+    /// Send requests that fail to reach the backend again, if the kind's
+    /// requests are idempotent.
+    pub fn with_retry(mut self, retry: Option<RetryConfig>) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Give up on the backend after `timeout` with a 504.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.upstream = self.upstream.with_timeout(timeout);
+        self
+    }
+
+    /// Answer 503 right away while `breakers` has the backend's circuit open.
+    pub fn with_circuit_breakers(mut self, breakers: Arc<CircuitBreakers>) -> Self {
+        self.upstream = self.upstream.with_circuit_breakers(breakers);
+        self
+    }
+
+    /// The location's backend, to share with the handlers calling it too.
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    /// Copy the request to the location's shadow backend in the background,
+    /// if it is sampled.
+    fn mirror(&self, body: &[u8], content_type: Option<&str>) {
+        let (Some(sampler), Some(url)) =
+            (&self.shadow_sampler, self.upstream.location().shadow_url())
+        else {
+            return;
+        };
+        if !sampler.sample() {
+            return;
+        }
+
+        let mut request = self.client.post(&url).body(body.to_vec());
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let (service, tag) = (self.kind.name(), self.upstream.location().tag.clone());
+        tokio::spawn(async move {
+            if let Err(err) = request.send().await.and_then(|r| r.error_for_status()) {
+                tracing::debug!("{} {} shadow request failed: {}", service, tag, err);
+            }
+        });
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    async fn run_hook(&self, body: Vec<u8>, _response: bool) -> Result<Vec<u8>> {
+        Ok(body)
+    }
+
+    #[cfg(feature = "wasm")]
+    async fn run_hook(&self, body: Vec<u8>, response: bool) -> Result<Vec<u8>> {
+        let Some(hook) = self.hook.clone() else {
+            return Ok(body);
+        };
+
+        let result = tokio::task::spawn_blocking(move || {
+            if response {
+                hook.transform_response(body)
+            } else {
+                hook.transform_request(body)
+            }
+        })
+        .await
+        .map_err(|err| Error::from_string(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))?;
+
+        result.map_err(|err| {
+            tracing::warn!(
+                "{} {} WASM hook failed: {:#}",
+                self.kind.name(),
+                self.upstream.location().tag,
+                err
+            );
+            Error::from_string(
+                "body transformation failed",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        })
+    }
+
+    /// Reject bodies the backend can't understand, naming each bad field,
+    /// rather than forwarding them to fail with an opaque error.
+    fn validate(&self, body: &[u8]) -> std::result::Result<(), Problem> {
+        let Some(schema) = self.kind.request_schema() else {
+            return Ok(());
+        };
+        let errors = match serde_json::from_slice::<Value>(body) {
+            Ok(json) => schema::validate(&json, &schema),
+            Err(err) => vec![FieldError {
+                field: "body".to_string(),
+                message: format!("invalid JSON: {}", err),
+            }],
+        };
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        Err(
+            Problem::new(StatusCode::BAD_REQUEST, "Invalid request body")
+                .detail(describe(&errors))
+                .extension("errors", serde_json::to_value(errors).unwrap_or_default()),
+        )
+    }
+
+    /// Check a successful backend response against the kind's response
+    /// schema, answering 502 with the fields that don't match if it fails.
+    fn validate_response(&self, body: &[u8]) -> std::result::Result<(), Problem> {
+        let schema = match self.kind.response_schema() {
+            ResponseSchema::Json(SchemaType::Any) | ResponseSchema::Audio => return Ok(()),
+            ResponseSchema::Json(schema) => schema,
+        };
+        let errors = match serde_json::from_slice::<Value>(body) {
+            Ok(json) => schema::validate(&json, &schema),
+            Err(err) => vec![FieldError {
+                field: "body".to_string(),
+                message: format!("invalid JSON: {}", err),
+            }],
+        };
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        let detail = describe(&errors);
+        tracing::warn!(
+            "{} {} backend sent a malformed response: {}",
+            self.kind.name(),
+            self.upstream.location().tag,
+            detail
+        );
+        Err(
+            Problem::new(StatusCode::BAD_GATEWAY, "Invalid backend response")
+                .detail(format!(
+                    "The {} backend for {} answered with a malformed response: {}",
+                    self.kind.name(),
+                    self.upstream.location().tag,
+                    detail
+                ))
+                .extension("errors", serde_json::to_value(errors).unwrap_or_default()),
+        )
+    }
+
+    fn check_length(&self, body: &[u8]) -> std::result::Result<(), Problem> {
+        let Some(limit) = self.max_length else {
+            return Ok(());
+        };
+        let length = request_text(body).1.chars().count();
+        if length <= limit {
+            return Ok(());
+        }
+
+        Err(Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "Text too long")
+            .detail(format!(
+                "The text is {} characters long, but {} accepts at most {}",
+                length,
+                self.kind.name(),
+                limit
+            ))
+            .extension("limit", limit)
+            .extension("length", length))
+    }
+
+    /// Prepare the text for checking and apply the sanitation policy,
+    /// returning the body to forward and the offsets of anything removed.
+    fn sanitize(&self, body: Vec<u8>) -> std::result::Result<(Vec<u8>, OffsetMap), Problem> {
+        let (json, original) = request_text(&body);
+        let (text, prepared) = sanitize::prepare(&original);
+
+        let (text, offsets) = match self.sanitize {
+            SanitizePolicy::Keep => (text, prepared),
+            SanitizePolicy::Strip => {
+                let (text, stripped) = sanitize::strip(&text);
+                (text, prepared.then(&stripped))
+            }
+            SanitizePolicy::Reject => {
+                if let Some((offset, c)) = sanitize::find_junk(&text) {
+                    let offset = prepared.original(offset);
+                    let character = format!("U+{:04X}", c as u32);
+                    return Err(
+                        Problem::new(StatusCode::BAD_REQUEST, "Invalid character in text")
+                            .detail(format!(
+                                "{} at offset {} is a control or zero-width character",
+                                character, offset
+                            ))
+                            .extension("offset", offset)
+                            .extension("character", character),
+                    );
+                }
+                (text, prepared)
+            }
+        };
+        let (text, offsets) = if self.kind.trims_leading_whitespace() {
+            let (text, leading) = sanitize::strip_leading(&text);
+            (text, offsets.then(&leading))
+        } else {
+            (text, offsets)
+        };
+
+        if text == original {
+            Ok((body, offsets))
+        } else {
+            Ok((with_request_text(json, text), offsets))
+        }
+    }
+
+    /// Returns the body to forward and the apostrophe variant the client
+    /// used, if it differs from the configured one.
+    fn normalize_apostrophes(&self, body: Vec<u8>) -> (Vec<u8>, Option<char>) {
+        let Some(canonical) = self.apostrophe else {
+            return (body, None);
+        };
+        let (json, text) = request_text(&body);
+        let (normalized, preferred) = apostrophe::normalize(&text, canonical);
+        if normalized == text {
+            (body, preferred)
+        } else {
+            (with_request_text(json, normalized), preferred)
+        }
+    }
+
+    /// Strip the `markup` from the request text, returning the map from
+    /// offsets in the text to offsets in the document.
+    fn strip_markup(&self, body: Vec<u8>, markup: Option<Markup>) -> (Vec<u8>, OffsetMap) {
+        let Some(markup) = markup else {
+            return (body, OffsetMap::default());
+        };
+        let (json, document) = request_text(&body);
+        let (text, offsets) = markup.strip(&document);
+        (with_request_text(json, text), offsets)
+    }
+
+    /// Map offsets and apostrophes in a JSON response back to the client's
+    /// original text.
+    fn restore(&self, body: Vec<u8>, offsets: &OffsetMap, preferred: Option<char>) -> Vec<u8> {
+        let fields = self.kind.offset_fields();
+        let remap = !offsets.is_empty() && !fields.is_empty();
+        let apostrophes = self.apostrophe.zip(preferred);
+        if !remap && apostrophes.is_none() {
+            return body;
+        }
+
+        let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+            return body;
+        };
+        if remap {
+            offsets.remap(&mut json, fields);
+        }
+        if let Some((canonical, preferred)) = apostrophes {
+            apostrophe::restore(&mut json, canonical, preferred);
+        }
+        json.to_string().into_bytes()
+    }
+
+    /// Send the prepared request to the backend, returning its answer and
+    /// how long it took.
+    async fn forward(
+        &self,
+        body: Vec<u8>,
+        content_type: Option<String>,
+        accept: Option<String>,
+        request_id: Option<String>,
+    ) -> Result<(CachedResponse, Duration)> {
+        let tag = &self.upstream.location().tag;
+        let _permit = self.upstream.admit()?;
+        self.mirror(&body, content_type.as_deref());
+        let recorded = self.recorder.as_ref().map(|_| {
+            (
+                String::from_utf8_lossy(&body).into_owned(),
+                content_type.clone(),
+            )
+        });
+        let request = |url: &str| {
+            let mut request = self.client.post(url).body(body.clone());
+            if let Some(content_type) = &content_type {
+                request = request.header(header::CONTENT_TYPE, content_type);
+            }
+            if let Some(accept) = &accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+            if let Some(request_id) = &request_id {
+                request = request.header(REQUEST_ID, request_id);
+            }
+            request
+        };
+
+        let sent = Instant::now();
+        let response = self.send(request).await?.map_err(|err| {
+            tracing::warn!(
+                "{} {} backend request failed: {}",
+                self.kind.name(),
+                tag,
+                err
+            );
+            Error::from_string(
+                format!("{} backend for {} is unavailable", self.kind.name(), tag),
+                StatusCode::BAD_GATEWAY,
+            )
+        })?;
+
+        let status = response.status();
+        let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_GATEWAY))?
+            .to_vec();
+        let backend = sent.elapsed();
+        if let (Some(recorder), Some((request, content_type))) = (&self.recorder, recorded) {
+            recorder.record(&Recording {
+                time: recording::now(),
+                service: self.kind.name().to_string(),
+                tag: tag.clone(),
+                query: self.upstream.location().query_string(),
+                content_type,
+                request,
+                status: status.as_u16(),
+                response: String::from_utf8(body.clone()).ok(),
+                response_length: body.len(),
+            });
+        }
+
+        Ok((
+            CachedResponse {
+                status,
+                content_type,
+                body,
+            },
+            backend,
+        ))
+    }
+
+    /// Send the request `build` makes for a backend URL, again after a
+    /// backoff while it fails and retries are left. A retry goes to another
+    /// instance than those that failed, if the location has one, and each
+    /// attempt counts towards the backend's circuit and the instance's health.
+    async fn send(
+        &self,
+        build: impl Fn(&str) -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Result<reqwest::Response>> {
+        let retries = self.retry.as_ref().filter(|_| self.kind.idempotent());
+        let mut failed_instances = Vec::new();
+        let mut last = None;
+        let mut retry = 0;
+        loop {
+            let (url, pick) = self.upstream.backend_except(&failed_instances);
+            if let Some(open) = self.upstream.open_for(&url) {
+                return match last {
+                    Some(result) => Ok(result),
+                    None => Err(self.upstream.circuit_open(open).into()),
+                };
+            }
+            let result = build(&url).send().await;
+            let failure = failed(&result);
+            self.upstream.record(&url, pick.as_ref(), failure);
+            let Some(config) = retries.filter(|config| failure && retry < config.retries) else {
+                return Ok(result);
+            };
+            failed_instances.extend(pick.map(|pick| pick.instance().clone()));
+            last = Some(result);
+            let backoff = config.backoff(retry);
+            tracing::debug!(
+                "{} {} backend failed, retrying in {:?}",
+                self.kind.name(),
+                self.upstream.location().tag,
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+            retry += 1;
+        }
+    }
+
+    /// Forward a text too long for the backend as `chunks`, one after
+    /// another, joining the audio of the answers. The first failing answer
+    /// is returned as it is.
+    async fn forward_chunks(
+        &self,
+        json: Option<Value>,
+        chunks: Vec<String>,
+        content_type: Option<String>,
+        accept: Option<String>,
+        request_id: Option<String>,
+    ) -> Result<(CachedResponse, Duration)> {
+        let mut audio = Vec::with_capacity(chunks.len());
+        let mut audio_type = None;
+        let mut backend = Duration::ZERO;
+        for chunk in chunks {
+            let body = with_request_text(json.clone(), chunk);
+            let (response, took) = self
+                .forward(
+                    body,
+                    content_type.clone(),
+                    accept.clone(),
+                    request_id.clone(),
+                )
+                .await?;
+            backend += took;
+            if !response.status.is_success() {
+                return Ok((response, backend));
+            }
+            audio_type = response.content_type;
+            audio.push(response.body);
+        }
+
+        // MP3 frames stand alone; WAV needs one header for all the samples
+        let is_mp3 = audio_type
+            .as_ref()
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("audio/mpeg"));
+        let body = match is_mp3 {
+            true => audio.concat(),
+            false => chunking::join_wav(&audio).map_err(|err| {
+                tracing::warn!(
+                    "{} {} audio can't be joined: {:#}",
+                    self.kind.name(),
+                    self.upstream.location().tag,
+                    err
+                );
+                Error::from_string(
+                    format!(
+                        "{} backend for {} sent audio that can't be joined",
+                        self.kind.name(),
+                        self.upstream.location().tag
+                    ),
+                    StatusCode::BAD_GATEWAY,
+                )
+            })?,
+        };
+        Ok((
+            CachedResponse {
+                status: StatusCode::OK,
+                content_type: audio_type,
+                body,
+            },
+            backend,
+        ))
+    }
+}
+
+impl Endpoint for ProxyEndpoint {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let started = Instant::now();
+        let tag = &self.upstream.location().tag;
+        let content_type = req.header(header::CONTENT_TYPE).map(ToString::to_string);
+        let accept = req.header(header::ACCEPT).map(ToString::to_string);
+        let request_id = req.header(REQUEST_ID).map(ToString::to_string);
+        let query = req.uri().query().map(ToString::to_string);
+
+        let body = req.into_body().into_vec().await?;
+        let (body, transcoded) = charset::to_utf8(body, content_type.as_deref());
+        let content_type = match &transcoded {
+            Some(_) => content_type.as_deref().map(charset::utf8_content_type),
+            None => content_type,
+        };
+        let json = forms::to_json(&body, content_type.as_deref()).map_err(|err| {
+            Problem::new(StatusCode::BAD_REQUEST, "Invalid form").detail(err.to_string())
+        })?;
+        let (body, content_type) = match json {
+            Some(json) => (json, Some(forms::JSON.to_string())),
+            None => (body, content_type),
+        };
+        let (body, format) = match &self.transcoder {
+            Some(_) => transcode::requested(query.as_deref(), body).map_err(|err| {
+                Problem::new(StatusCode::BAD_REQUEST, "Unsupported audio format")
+                    .detail(err.to_string())
+            })?,
+            None => (body, None),
+        };
+        let (body, markup) = match self.ssml {
+            Some(_) => ssml::extract(body).map_err(|err| {
+                Problem::new(StatusCode::BAD_REQUEST, "Invalid SSML").detail(err.to_string())
+            })?,
+            None => (body, None),
+        };
+        let (body, tag_filter) = match self.tag_filtering {
+            true => TagFilter::requested(body).map_err(|err| {
+                Problem::new(StatusCode::BAD_REQUEST, "Invalid error tag filter")
+                    .detail(err.to_string())
+            })?,
+            false => (body, None),
+        };
+        let (body, limits) = match self.suggestion_limits {
+            true => SuggestionLimits::requested(body).map_err(|err| {
+                Problem::new(StatusCode::BAD_REQUEST, "Invalid suggestion limits")
+                    .detail(err.to_string())
+            })?,
+            false => (body, None),
+        };
+        let (body, rich_text) = match self.markup {
+            true => Markup::requested(body).map_err(|err| {
+                Problem::new(StatusCode::BAD_REQUEST, "Unsupported text format")
+                    .detail(err.to_string())
+            })?,
+            false => (body, None),
+        };
+        let (body, stripped) = self.strip_markup(body, rich_text);
+        // Transcoding starts from WAV
+        let accept = match format {
+            Some(_) => Some(AudioFormat::Wav.content_type().to_string()),
+            None => accept,
+        };
+        self.validate(&body)?;
+        self.check_length(&body)?;
+        let (body, offsets) = self.sanitize(body)?;
+        let offsets = stripped.then(&offsets);
+        let (body, preferred) = self.normalize_apostrophes(body);
+        let body = self.run_hook(body, false).await?;
+        let body = self
+            .kind
+            .map_request(tag, body)
+            .map_err(|err| Error::from_string(err.to_string(), StatusCode::BAD_REQUEST))?;
+        let (body, markup) = match (markup, self.ssml) {
+            (Some(markup), Some(true)) => (ssml::with_markup(body, markup), true),
+            _ => (body, false),
+        };
+
+        let key = self
+            .cache
+            .as_ref()
+            .map(|_| CacheKey::new(tag, content_type.as_deref(), accept.as_deref(), &body));
+        let cached = match (&self.cache, &key) {
+            (Some(cache), Some(key)) => cache.get(key),
+            _ => None,
+        };
+        let hit = cached.is_some();
+        let (response, backend) = match cached {
+            Some(response) => (response, Duration::ZERO),
+            None => {
+                let (json, text) = request_text(&body);
+                // Markup can't be split on sentences
+                let chunks = self
+                    .chunk_length
+                    .filter(|limit| !markup && text.chars().count() > *limit)
+                    .map(|limit| chunking::split_sentences(&text, limit));
+                let forwarded = async {
+                    match chunks {
+                        Some(chunks) => {
+                            self.forward_chunks(json, chunks, content_type, accept, request_id)
+                                .await
+                        }
+                        None => self.forward(body, content_type, accept, request_id).await,
+                    }
+                };
+                let (response, backend) = match self.upstream.timeout() {
+                    Some(timeout) => tokio::time::timeout(timeout, forwarded)
+                        .await
+                        .map_err(|_| Error::from(self.upstream.timed_out(timeout)))??,
+                    None => forwarded.await?,
+                };
+                if let (Some(cache), Some(key)) = (&self.cache, key) {
+                    cache.insert(key, response.clone());
+                }
+                (response, backend)
+            }
+        };
+        let CachedResponse {
+            status,
+            mut content_type,
+            mut body,
+        } = response;
+        if let (Some(transcoder), Some(format), true) =
+            (&self.transcoder, format, status.is_success())
+        {
+            if format != AudioFormat::Wav {
+                body = transcoder.transcode(body, format).await.map_err(|err| {
+                    tracing::warn!("{} {} transcoding failed: {:#}", self.kind.name(), tag, err);
+                    Error::from_string(
+                        format!("can't convert the audio to {}", format.content_type()),
+                        StatusCode::BAD_GATEWAY,
+                    )
+                })?;
+            }
+            content_type = Some(HeaderValue::from_static(format.content_type()));
+        }
+
+        let body = if status.is_success() {
+            let body = self.kind.map_response(tag, body).map_err(|err| {
+                tracing::warn!(
+                    "{} {} response mapping failed: {}",
+                    self.kind.name(),
+                    tag,
+                    err
+                );
+                Error::from_string(err.to_string(), StatusCode::BAD_GATEWAY)
+            })?;
+            self.validate_response(&body)?;
+            let body = self.run_hook(body, true).await?;
+            let body = self.restore(body, &offsets, preferred);
+            let body = match &tag_filter {
+                Some(filter) => filter.apply(body),
+                None => body,
+            };
+            match &limits {
+                Some(limits) => limits.apply(body),
+                None => body,
+            }
+        } else {
+            body
+        };
+
+        let mut builder = Response::builder().status(status);
+        if let Some(content_type) = content_type {
+            builder = builder.header(header::CONTENT_TYPE, content_type);
+        }
+        if let Some(transcoded) = transcoded {
+            builder = builder.header(header::WARNING, format!("214 - \"{}\"", transcoded));
+        }
+        if self.cache.is_some() {
+            builder = builder.header("X-Cache", if hit { "HIT" } else { "MISS" });
+        }
+        Ok(builder
+            .header("Server-Timing", server_timing(backend, started.elapsed()))
+            // Let browsers show the timings to pages on other origins
+            .header("Timing-Allow-Origin", "*")
+            .body(body))
+    }
+}
+
+fn describe(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("{}: {}", error.field, error.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// `backend` is the time from sending the request until the whole response
+/// arrived; `gateway` is the rest of `total`.
+fn server_timing(backend: Duration, total: Duration) -> String {
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    format!(
+        "backend;dur={:.1}, gateway;dur={:.1}",
+        millis(backend),
+        millis(total.saturating_sub(backend))
+    )
+}
+
+/// The text of a request: the `text` field of a JSON body, returned with the
+/// parsed body, or the whole body otherwise.
+fn request_text(body: &[u8]) -> (Option<Value>, String) {
+    let json: Option<Value> = serde_json::from_slice(body).ok();
+    match json.as_ref().and_then(|json| json.get("text")?.as_str()) {
+        Some(text) => {
+            let text = text.to_string();
+            (json, text)
+        }
+        None => (None, String::from_utf8_lossy(body).into_owned()),
+    }
+}
+
+/// Put `text` back where [`request_text`] found it.
+fn with_request_text(json: Option<Value>, text: String) -> Vec<u8> {
+    match json {
+        Some(mut json) => {
+            json["text"] = text.into();
+            json.to_string().into_bytes()
+        }
+        None => text.into_bytes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::test::TestClient;
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::LanguagesConfig;
+    use crate::services::Grammar;
+    use crate::testing::{MockBackend, MockResponse};
+
+    fn grammar(backend: &MockBackend) -> TestClient<ProxyEndpoint> {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let mut location = Grammar.locations(&languages).remove(0);
+        location.port = backend.port();
+        TestClient::new(ProxyEndpoint::new(
+            Arc::new(Grammar),
+            location,
+            reqwest::Client::new(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn malformed_backend_responses_are_bad_gateways() {
+        let backend = MockBackend::start(|request| {
+            MockResponse::json(json!({
+                "text": request.text().unwrap_or_default(),
+                "errs": [{ "error_text": "Bures", "start_index": "0" }],
+            }))
+        })
+        .await
+        .unwrap();
+        let client = grammar(&backend);
+
+        let response = client.post("/").body(r#"{"text":"Bures"}"#).send().await;
+        response.assert_status(StatusCode::BAD_GATEWAY);
+        response.assert_content_type("application/problem+json");
+        let body: Value =
+            serde_json::from_str(&response.0.into_body().into_string().await.unwrap()).unwrap();
+        assert_eq!(body["title"], "Invalid backend response");
+        assert_eq!(
+            body["errors"][0],
+            json!({ "field": "errs[0].start_index", "message": "expected integer, got string" })
+        );
+        assert_eq!(body["errors"][1]["field"], "errs[0].end_index");
+    }
+
+    #[tokio::test]
+    async fn offsets_count_the_leading_whitespace_backends_trim() {
+        let backend = MockBackend::start(|request| {
+            let text = request.text().unwrap_or_default();
+            let err = json!({
+                "error_text": "Bures",
+                "start_index": 0,
+                "end_index": 5,
+                "error_code": "typo",
+                "description": "",
+                "suggestions": [],
+                "title": "",
+            });
+            MockResponse::json(json!({ "text": text.trim_start(), "errs": [err] }))
+        })
+        .await
+        .unwrap();
+        let client = grammar(&backend);
+
+        let response = client
+            .post("/")
+            .body(r#"{"text":"\n  Bures"}"#)
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        let body: Value =
+            serde_json::from_str(&response.0.into_body().into_string().await.unwrap()).unwrap();
+        assert_eq!(body["errs"][0]["start_index"], 3);
+        assert_eq!(body["errs"][0]["end_index"], 8);
+        assert_eq!(backend.requests()[0].text().as_deref(), Some("Bures"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Recording what the gateway exchanges with a backend, and replaying it.
+//!
+//! With `record` set on a language, every request the gateway forwards for
+//! it is appended to `<service>-<tag>.jsonl` in that directory, as sent to
+//! the backend: after sanitizing and mapping, without the client's headers
+//! or address. `replay` sends the recorded requests to any backend and
+//! reports where its answers differ from the recorded ones.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// One request and the backend's answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    /// Unix timestamp of the request.
+    pub time: u64,
+    pub service: String,
+    pub tag: String,
+    /// Query string of the backend URL, e.g. a TTS voice's speaker.
+    #[serde(default)]
+    pub query: String,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    pub request: String,
+    pub status: u16,
+    /// The response body, unless it isn't text (e.g. audio).
+    pub response: Option<String>,
+    pub response_length: usize,
+}
+
+/// Appends [`Recording`]s to a location's file.
+pub struct Recorder {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn open(dir: &Path, service: &str, tag: &str) -> anyhow::Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("can't create {}", dir.display()))?;
+        let path = dir.join(format!("{}-{}.jsonl", service, tag));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("can't open {}", path.display()))?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, recording: &Recording) {
+        let Ok(mut line) = serde_json::to_string(recording) else {
+            return;
+        };
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            tracing::warn!("Can't record to {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Read the recordings in a `.jsonl` file.
+pub fn load(path: &Path) -> anyhow::Result<Vec<Recording>> {
+    let file = File::open(path).with_context(|| format!("can't open {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("{} line {}", path.display(), index + 1))
+        })
+        .collect()
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Where a replayed answer differs from the recorded one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Path of the differing JSON value, e.g. `errs[0].title`, or `status`
+    /// and `body` for the response as a whole.
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Send `recording` to `backend` (e.g. `http://127.0.0.1:10000`) and
+/// compare the answer.
+pub async fn replay(
+    client: &reqwest::Client,
+    backend: &str,
+    recording: &Recording,
+) -> anyhow::Result<Vec<Difference>> {
+    let mut url = format!("{}/", backend.trim_end_matches('/'));
+    if !recording.query.is_empty() {
+        url = format!("{}?{}", url, recording.query);
+    }
+    let mut request = client.post(&url).body(recording.request.clone());
+    if let Some(content_type) = &recording.content_type {
+        request = request.header("Content-Type", content_type);
+    }
+    let response = request.send().await?;
+    let status = response.status().as_u16();
+    let body = response.bytes().await?;
+
+    let mut differences = Vec::new();
+    if status != recording.status {
+        differences.push(Difference {
+            field: "status".to_string(),
+            expected: recording.status.to_string(),
+            actual: status.to_string(),
+        });
+    }
+    match (&recording.response, std::str::from_utf8(&body)) {
+        (Some(expected), Ok(actual)) => {
+            match (
+                serde_json::from_str::<Value>(expected),
+                serde_json::from_str::<Value>(actual),
+            ) {
+                (Ok(expected), Ok(actual)) => {
+                    compare(&expected, &actual, String::new(), &mut differences)
+                }
+                _ if expected != actual => differences.push(Difference {
+                    field: "body".to_string(),
+                    expected: expected.clone(),
+                    actual: actual.to_string(),
+                }),
+                _ => {}
+            }
+        }
+        _ if body.len() != recording.response_length => differences.push(Difference {
+            field: "body".to_string(),
+            expected: format!("{} bytes", recording.response_length),
+            actual: format!("{} bytes", body.len()),
+        }),
+        _ => {}
+    }
+    Ok(differences)
+}
+
+fn compare(expected: &Value, actual: &Value, path: String, differences: &mut Vec<Difference>) {
+    let field = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            let mut keys: Vec<_> = expected.keys().chain(actual.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                compare(
+                    expected.get(key).unwrap_or(&Value::Null),
+                    actual.get(key).unwrap_or(&Value::Null),
+                    field(key),
+                    differences,
+                );
+            }
+        }
+        (Value::Array(items), Value::Array(others)) if items.len() == others.len() => {
+            for (index, (item, other)) in items.iter().zip(others).enumerate() {
+                compare(item, other, format!("{}[{}]", path, index), differences);
+            }
+        }
+        _ if expected != actual => differences.push(Difference {
+            field: if path.is_empty() {
+                "body".to_string()
+            } else {
+                path
+            },
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn differences_are_reported_by_field() {
+        let mut differences = Vec::new();
+        compare(
+            &json!({ "text": "Bures", "errs": [{ "title": "Typo", "start": 0 }] }),
+            &json!({ "text": "Bures", "errs": [{ "title": "Spelling", "start": 0 }], "new": 1 }),
+            String::new(),
+            &mut differences,
+        );
+        assert_eq!(
+            differences,
+            [
+                Difference {
+                    field: "errs[0].title".to_string(),
+                    expected: "\"Typo\"".to_string(),
+                    actual: "\"Spelling\"".to_string(),
+                },
+                Difference {
+                    field: "new".to_string(),
+                    expected: "null".to_string(),
+                    actual: "1".to_string(),
+                },
+            ]
+        );
+    }
+}
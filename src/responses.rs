@@ -0,0 +1,202 @@
+//! In-memory caches of backend responses, configured per service category as
+//! `[config.response_cache.<service>]`.
+//!
+//! Editors check the same texts over and over, and spellers, grammar checkers
+//! and hyphenators always answer a text the same way, so the gateway can
+//! answer repeats itself. Entries are keyed by the language and a hash of the
+//! request as forwarded, expire after `ttl` seconds and the least recently
+//! used ones are evicted beyond `max_entries`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use poem::http::{HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// Seconds a response is reused.
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+    /// Responses kept; the least recently used are dropped beyond it.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_ttl() -> u64 {
+    3600
+}
+
+fn default_max_entries() -> usize {
+    10_000
+}
+
+/// Identifies a request to a service's backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey(u64);
+
+impl CacheKey {
+    pub fn new(tag: &str, content_type: Option<&str>, accept: Option<&str>, body: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        (tag, content_type, accept, body).hash(&mut hasher);
+        Self(hasher.finish())
+    }
+}
+
+/// A backend's answer, as the proxy received it.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub content_type: Option<HeaderValue>,
+    pub body: Vec<u8>,
+}
+
+struct Entry {
+    response: CachedResponse,
+    stored: Instant,
+    /// The entry's place in [`Entries::order`].
+    used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<CacheKey, Entry>,
+    /// Keys by when they were last used, oldest first.
+    order: BTreeMap<u64, CacheKey>,
+    clock: u64,
+}
+
+/// A least-recently-used cache shared by all languages of a service.
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+impl ResponseCache {
+    pub fn new(config: &ResponseCacheConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl),
+            max_entries: config.max_entries,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let Entries {
+            entries,
+            order,
+            clock,
+        } = &mut *entries;
+
+        let entry = entries.get_mut(key)?;
+        order.remove(&entry.used);
+        if entry.stored.elapsed() >= self.ttl {
+            entries.remove(key);
+            return None;
+        }
+        *clock += 1;
+        entry.used = *clock;
+        order.insert(*clock, *key);
+        Some(entry.response.clone())
+    }
+
+    /// Store a successful response, evicting the least recently used ones
+    /// if the cache is full.
+    pub fn insert(&self, key: CacheKey, response: CachedResponse) {
+        if self.max_entries == 0 || !response.status.is_success() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let Entries {
+            entries,
+            order,
+            clock,
+        } = &mut *entries;
+
+        *clock += 1;
+        let entry = Entry {
+            response,
+            stored: Instant::now(),
+            used: *clock,
+        };
+        if let Some(replaced) = entries.insert(key, entry) {
+            order.remove(&replaced.used);
+        }
+        order.insert(*clock, key);
+
+        while entries.len() > self.max_entries {
+            let Some((_, oldest)) = order.pop_first() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::OK,
+            content_type: None,
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn key(body: &str) -> CacheKey {
+        CacheKey::new("se", None, None, body.as_bytes())
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let cache = ResponseCache::new(&ResponseCacheConfig {
+            ttl: 60,
+            max_entries: 2,
+        });
+        cache.insert(key("a"), response("A"));
+        cache.insert(key("b"), response("B"));
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(key("c"), response("C"));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&key("b")).is_none());
+        assert_eq!(cache.get(&key("a")).unwrap().body, b"A");
+        assert_eq!(cache.get(&key("c")).unwrap().body, b"C");
+    }
+
+    #[test]
+    fn expired_and_failed_responses_are_not_served() {
+        let cache = ResponseCache::new(&ResponseCacheConfig {
+            ttl: 0,
+            max_entries: 2,
+        });
+        cache.insert(key("a"), response("A"));
+        assert!(cache.get(&key("a")).is_none());
+        assert!(cache.is_empty());
+
+        let cache = ResponseCache::new(&ResponseCacheConfig {
+            ttl: 60,
+            max_entries: 2,
+        });
+        let mut failed = response("down");
+        failed.status = StatusCode::SERVICE_UNAVAILABLE;
+        cache.insert(key("a"), failed);
+        assert!(cache.is_empty());
+        assert_ne!(key("a"), CacheKey::new("sma", None, None, b"a"));
+    }
+}
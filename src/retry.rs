@@ -0,0 +1,162 @@
+//! Flaky and dead backends. Requests that don't reach a backend, or that it
+//! answers with a 502, 503 or 504, are sent again with exponential backoff
+//! per `[config.retry]`. After `[config.circuit_breaker]`'s number of such
+//! failures in a row, the backend's circuit opens: requests for it get a 503
+//! straight away until the cooldown has passed and one gets through again.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use poem::http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Times a failed request is sent again.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Milliseconds before the first retry, doubling for each further one.
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_backoff_ms() -> u64 {
+    100
+}
+
+impl RetryConfig {
+    /// The wait before retry number `retry`, counting from 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        Duration::from_millis(self.backoff_ms.saturating_mul(1 << retry.min(16)))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Failures in a row that open a backend's circuit.
+    #[serde(default = "default_failures")]
+    pub failures: u32,
+    /// Seconds an open circuit turns requests away before trying one again.
+    #[serde(default = "default_cooldown")]
+    pub cooldown: u64,
+}
+
+fn default_failures() -> u32 {
+    5
+}
+
+fn default_cooldown() -> u64 {
+    30
+}
+
+/// Whether a backend's answer means it is failing rather than rejecting
+/// the request.
+pub fn is_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// The circuits of all backends, by `host:port`, shared by the locations
+/// they serve.
+pub struct CircuitBreakers {
+    config: CircuitBreakerConfig,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How much longer the circuit of the backend at `url` is open, if it
+    /// is.
+    pub fn open_for(&self, url: &str) -> Option<Duration> {
+        let circuits = self.circuits.lock().unwrap();
+        let open_until = circuits.get(backend(url))?.open_until?;
+        let remaining = open_until.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Count a request to the backend at `url`: a success closes its
+    /// circuit, and enough failures in a row open it. Once the cooldown has
+    /// passed, a single failure opens it again.
+    pub fn record(&self, url: &str, failed: bool) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(backend(url).to_string()).or_default();
+        if !failed {
+            *circuit = Circuit::default();
+            return;
+        }
+
+        circuit.failures += 1;
+        if circuit.failures < self.config.failures {
+            return;
+        }
+        let now = Instant::now();
+        if circuit.open_until.is_none_or(|until| until <= now) {
+            tracing::warn!(
+                "Opening the circuit of {} for {}s after {} failures in a row",
+                backend(url),
+                self.config.cooldown,
+                circuit.failures
+            );
+        }
+        circuit.open_until = Some(now + Duration::from_secs(self.config.cooldown));
+    }
+
+    /// The circuits of the backends requests have been sent to, by
+    /// `host:port`.
+    pub fn snapshot(&self) -> Vec<CircuitStatus> {
+        let now = Instant::now();
+        let circuits = self.circuits.lock().unwrap();
+        let mut statuses: Vec<_> = circuits
+            .iter()
+            .map(|(backend, circuit)| {
+                let open_for = circuit
+                    .open_until
+                    .map(|until| until.saturating_duration_since(now))
+                    .filter(|remaining| !remaining.is_zero());
+                CircuitStatus {
+                    backend: backend.clone(),
+                    open: open_for.is_some(),
+                    failures: circuit.failures,
+                    open_for: open_for.map(|open| open.as_secs_f64().ceil() as u64),
+                }
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.backend.cmp(&b.backend));
+        statuses
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CircuitStatus {
+    pub backend: String,
+    pub open: bool,
+    /// Failures in a row so far.
+    pub failures: u32,
+    /// Seconds until a request is let through again, while open.
+    pub open_for: Option<u64>,
+}
+
+/// The `host:port` of a backend URL.
+fn backend(url: &str) -> &str {
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    authority.split(['/', '?']).next().unwrap_or(authority)
+}
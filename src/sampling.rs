@@ -0,0 +1,24 @@
+//! Deterministic sampling of a share of requests, without randomness.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Picks `percent` of the calls to [`Sampler::sample`]: every call's share is
+/// added up, and a call is picked each time the sum passes a whole one.
+pub struct Sampler {
+    share: f64,
+    seen: AtomicU64,
+}
+
+impl Sampler {
+    pub fn new(percent: f64) -> Self {
+        Self {
+            share: percent.clamp(0.0, 100.0) / 100.0,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    pub fn sample(&self) -> bool {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * self.share).floor() > (seen * self.share).floor()
+    }
+}
@@ -0,0 +1,264 @@
+//! Removal of invisible characters that confuse the checkers.
+//!
+//! C0/C1 control characters (other than tab and line breaks), byte order
+//! marks inside the text and zero-width characters shift the backends'
+//! offsets relative to what the user sees. Depending on the configured
+//! [`SanitizePolicy`] they are kept, stripped before forwarding (with offsets
+//! in the response mapped back to the original text), or rejected.
+//!
+//! Independently of the policy, [`prepare`] always sends CRLF and CR line
+//! endings to the backends as LF, since some of them count a CRLF as two
+//! positions and others as one, and removes directional formatting
+//! characters, which skew offsets around right-to-left quotations. Offsets
+//! are in characters of the logical order, so RTL runs need no other
+//! handling.
+//!
+//! For backends that trim the text before checking it, [`strip_leading`]
+//! removes leading whitespace up front, so their offsets can be mapped back
+//! like any other removal.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SanitizePolicy {
+    /// Forward the text as sent.
+    #[default]
+    Keep,
+    Strip,
+    Reject,
+}
+
+pub fn is_junk(c: char) -> bool {
+    matches!(c, '\u{0}'..='\u{8}' | '\u{b}' | '\u{c}' | '\u{e}'..='\u{1f}' | '\u{7f}'..='\u{9f}')
+        || matches!(c, '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}')
+}
+
+/// Maps character offsets in sanitized text back to the original.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    /// Original character offsets of the removed characters, ascending.
+    removed: Vec<usize>,
+    /// Those of the removed characters that were part of the kept character
+    /// before them, like the rest of an HTML entity.
+    joined: Vec<usize>,
+}
+
+impl OffsetMap {
+    /// The map for text that lost the characters at the `removed` original
+    /// offsets, of which the `joined` ones belong to the character before
+    /// them. Both must be ascending.
+    pub fn removing(removed: Vec<usize>, joined: Vec<usize>) -> Self {
+        Self { removed, joined }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+    }
+
+    pub fn original(&self, offset: usize) -> usize {
+        let mut original = offset;
+        for &removed in &self.removed {
+            if removed > original {
+                break;
+            }
+            original += 1;
+        }
+        original
+    }
+
+    /// The original offset of an exclusive end: after the character before
+    /// `offset`, and anything joined to it, rather than after the characters
+    /// removed up to the next one.
+    pub fn original_end(&self, offset: usize) -> usize {
+        if offset == 0 {
+            return self.original(0);
+        }
+        let mut end = self.original(offset - 1) + 1;
+        while self.joined.binary_search(&end).is_ok() {
+            end += 1;
+        }
+        end
+    }
+
+    /// The map for text that went through `self` and then `later`.
+    pub fn then(&self, later: &OffsetMap) -> OffsetMap {
+        let mut removed: Vec<_> = later
+            .removed
+            .iter()
+            .map(|&offset| self.original(offset))
+            .chain(self.removed.iter().copied())
+            .collect();
+        removed.sort_unstable();
+        let mut joined: Vec<_> = later
+            .joined
+            .iter()
+            .map(|&offset| self.original(offset))
+            .chain(self.joined.iter().copied())
+            .collect();
+        joined.sort_unstable();
+        OffsetMap { removed, joined }
+    }
+
+    /// Rewrite every integer member named in `fields`, at any depth of
+    /// `value`, from a sanitized offset to the original one. Fields named
+    /// `end…` are exclusive ends.
+    pub fn remap(&self, value: &mut Value, fields: &[&str]) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object.iter_mut() {
+                    match value.as_u64() {
+                        Some(offset) if fields.contains(&key.as_str()) => {
+                            let offset = offset as usize;
+                            let original = match key.starts_with("end") {
+                                true => self.original_end(offset),
+                                false => self.original(offset),
+                            };
+                            *value = (original as u64).into();
+                        }
+                        _ => self.remap(value, fields),
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.remap(item, fields);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Bidirectional formatting characters: marks, embeddings, overrides and
+/// isolates.
+pub fn is_bidi_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{61c}' | '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+pub fn strip(text: &str) -> (String, OffsetMap) {
+    strip_matching(text, is_junk)
+}
+
+fn strip_matching(text: &str, matches: impl Fn(char) -> bool) -> (String, OffsetMap) {
+    let mut map = OffsetMap::default();
+    let stripped = text
+        .chars()
+        .enumerate()
+        .filter(|(offset, c)| {
+            let matched = matches(*c);
+            if matched {
+                map.removed.push(*offset);
+            }
+            !matched
+        })
+        .map(|(_, c)| c)
+        .collect();
+    (stripped, map)
+}
+
+/// Normalization applied to every text regardless of the policy: line
+/// endings become LF and bidirectional formatting characters are removed.
+pub fn prepare(text: &str) -> (String, OffsetMap) {
+    let (text, endings) = normalize_line_endings(text);
+    let (text, bidi) = strip_matching(&text, is_bidi_control);
+    (text, endings.then(&bidi))
+}
+
+/// Remove leading whitespace.
+pub fn strip_leading(text: &str) -> (String, OffsetMap) {
+    let trimmed = text.trim_start();
+    let removed = text[..text.len() - trimmed.len()].chars().count();
+    let map = OffsetMap::removing((0..removed).collect(), Vec::new());
+    (trimmed.to_string(), map)
+}
+
+/// Replace CRLF and lone CR line endings with LF.
+pub fn normalize_line_endings(text: &str) -> (String, OffsetMap) {
+    let mut map = OffsetMap::default();
+    if !text.contains('\r') {
+        return (text.to_string(), map);
+    }
+
+    let mut normalized = String::with_capacity(text.len());
+    let mut chars = text.chars().enumerate().peekable();
+    while let Some((offset, c)) = chars.next() {
+        if c != '\r' {
+            normalized.push(c);
+        } else if chars.peek().is_some_and(|(_, next)| *next == '\n') {
+            map.removed.push(offset);
+        } else {
+            normalized.push('\n');
+        }
+    }
+    (normalized, map)
+}
+
+/// The character offset and value of the first junk character in `text`.
+pub fn find_junk(text: &str) -> Option<(usize, char)> {
+    text.chars().enumerate().find(|(_, c)| is_junk(*c))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn stripped_offsets_map_back_to_the_original() {
+        let (text, map) = strip("a\u{200b}b\u{1}\u{feff}c\td");
+        assert_eq!(text, "abc\td");
+        assert_eq!(map.original(0), 0);
+        assert_eq!(map.original(1), 2);
+        assert_eq!(map.original(2), 5);
+        assert_eq!(map.original(4), 7);
+
+        let mut response = json!({ "errs": [{ "start_index": 2, "end_index": 4, "weight": 1 }] });
+        map.remap(&mut response, &["start_index", "end_index"]);
+        assert_eq!(
+            response,
+            json!({ "errs": [{ "start_index": 5, "end_index": 7, "weight": 1 }] })
+        );
+    }
+
+    #[test]
+    fn line_endings_become_lf_with_offsets_kept() {
+        let (text, endings) = normalize_line_endings("a\r\nb\rc\r\n\u{200b}d");
+        assert_eq!(text, "a\nb\nc\n\u{200b}d");
+        assert_eq!(endings.original(2), 3);
+
+        let (text, stripped) = strip(&text);
+        assert_eq!(text, "a\nb\nc\nd");
+        let map = endings.then(&stripped);
+        assert_eq!(map.original(6), 9);
+        assert_eq!(map.original(2), 3);
+    }
+
+    #[test]
+    fn directional_marks_are_removed() {
+        let (text, map) = prepare("Son celkkii \u{2067}שלום\u{2069} ja\r\nmannai.");
+        assert_eq!(text, "Son celkkii שלום ja\nmannai.");
+        assert_eq!(map.original(12), 13);
+        assert_eq!(map.original(20), 23);
+    }
+
+    #[test]
+    fn leading_whitespace_shifts_offsets() {
+        let (text, map) = strip_leading(" \n\u{a0}Bures  boahtin");
+        assert_eq!(text, "Bures  boahtin");
+        assert_eq!(map.original(0), 3);
+        assert_eq!(map.original(7), 10);
+        assert!(strip_leading("Bures").1.is_empty());
+    }
+
+    #[test]
+    fn line_breaks_are_not_junk() {
+        assert_eq!(find_junk("Bures\r\nboahtin\n"), None);
+        assert_eq!(find_junk("Bures\u{85}"), Some((5, '\u{85}')));
+    }
+}
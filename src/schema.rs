@@ -0,0 +1,295 @@
+//! Machine-readable description of the API exposed for a configuration,
+//! shared by the client generators and request validation.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::LanguagesConfig;
+use crate::services::{Location, ServiceRegistry};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Array(Box<SchemaType>),
+    /// A named type from [`ApiSchema::types`].
+    Named(&'static str),
+    /// Arbitrary JSON.
+    Any,
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: &'static str,
+    pub ty: SchemaType,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeDef {
+    pub name: &'static str,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseSchema {
+    Json(SchemaType),
+    /// Binary audio, WAV unless MP3 is requested.
+    Audio,
+}
+
+/// One service category and the routes it exposes.
+#[derive(Debug, Clone)]
+pub struct ServiceSchema {
+    pub name: String,
+    pub routes: Vec<Route>,
+    /// Body the routes accept; any body if `None`.
+    pub request: Option<SchemaType>,
+    pub response: ResponseSchema,
+}
+
+/// A concrete `POST` route, e.g. `/tts/se/biret`.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub path: String,
+    /// The part of the path after the service name, e.g. `se/biret`.
+    pub target: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiSchema {
+    pub title: String,
+    pub base_url: String,
+    pub services: Vec<ServiceSchema>,
+    pub types: Vec<TypeDef>,
+}
+
+impl ApiSchema {
+    pub fn new(languages: &LanguagesConfig, services: &ServiceRegistry) -> Self {
+        let services = services
+            .iter()
+            .map(|kind| {
+                let prefix = format!("/{}/", kind.name());
+                ServiceSchema {
+                    name: kind.name().to_string(),
+                    routes: kind
+                        .locations(languages)
+                        .into_iter()
+                        .map(|Location { tag, path, .. }| Route {
+                            target: path.strip_prefix(&prefix).unwrap_or(&path).to_string(),
+                            path,
+                            tag,
+                        })
+                        .collect(),
+                    request: kind.request_schema(),
+                    response: kind.response_schema(),
+                }
+            })
+            .filter(|service| !service.routes.is_empty())
+            .collect();
+
+        Self {
+            title: languages.branding.title.clone(),
+            base_url: languages.branding.base_url.clone(),
+            services,
+            types: builtin_types(),
+        }
+    }
+}
+
+fn field(name: &'static str, ty: SchemaType) -> Field {
+    Field { name, ty }
+}
+
+fn builtin_types() -> Vec<TypeDef> {
+    use SchemaType::*;
+
+    vec![
+        TypeDef {
+            name: "TextRequest",
+            fields: vec![field("text", String)],
+        },
+        TypeDef {
+            name: "GrammarError",
+            fields: vec![
+                field("error_text", String),
+                field("start_index", Integer),
+                field("end_index", Integer),
+                field("error_code", String),
+                field("description", String),
+                field("suggestions", Array(Box::new(String))),
+                field("title", String),
+            ],
+        },
+        TypeDef {
+            name: "GrammarResponse",
+            fields: vec![
+                field("text", String),
+                field("errs", Array(Box::new(Named("GrammarError")))),
+            ],
+        },
+        TypeDef {
+            name: "HyphenationPattern",
+            fields: vec![field("value", String), field("weight", Number)],
+        },
+        TypeDef {
+            name: "HyphenationResult",
+            fields: vec![
+                field("word", String),
+                field("patterns", Array(Box::new(Named("HyphenationPattern")))),
+                field("positions", Array(Box::new(Integer))),
+            ],
+        },
+        TypeDef {
+            name: "HyphenationResponse",
+            fields: vec![
+                field("text", String),
+                field("results", Array(Box::new(Named("HyphenationResult")))),
+            ],
+        },
+        TypeDef {
+            name: "SpellerSuggestion",
+            fields: vec![field("value", String), field("weight", Number)],
+        },
+        TypeDef {
+            name: "SpellerResult",
+            fields: vec![
+                field("word", String),
+                field("is_correct", Boolean),
+                field("suggestions", Array(Box::new(Named("SpellerSuggestion")))),
+            ],
+        },
+        TypeDef {
+            name: "SpellerResponse",
+            fields: vec![
+                field("text", String),
+                field("results", Array(Box::new(Named("SpellerResult")))),
+            ],
+        },
+    ]
+}
+
+/// A problem with one field of a request body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    /// Path to the field, e.g. `text` or `items[2].text`.
+    pub field: String,
+    pub message: String,
+}
+
+/// Check `value` against `ty`, reporting every field that doesn't match.
+pub fn validate(value: &Value, ty: &SchemaType) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    check(value, ty, "", &builtin_types(), &mut errors);
+    errors
+}
+
+fn check(
+    value: &Value,
+    ty: &SchemaType,
+    path: &str,
+    types: &[TypeDef],
+    errors: &mut Vec<FieldError>,
+) {
+    let matches = match ty {
+        SchemaType::String => value.is_string(),
+        SchemaType::Integer => value.is_i64() || value.is_u64(),
+        SchemaType::Number => value.is_number(),
+        SchemaType::Boolean => value.is_boolean(),
+        SchemaType::Any => true,
+        SchemaType::Array(item) => match value.as_array() {
+            Some(items) => {
+                for (i, value) in items.iter().enumerate() {
+                    check(value, item, &format!("{}[{}]", path, i), types, errors);
+                }
+                true
+            }
+            None => false,
+        },
+        SchemaType::Named(name) => match value.as_object() {
+            Some(object) => {
+                let def = types.iter().find(|def| def.name == *name);
+                for field in def.map(|def| def.fields.as_slice()).unwrap_or_default() {
+                    let path = match path {
+                        "" => field.name.to_string(),
+                        path => format!("{}.{}", path, field.name),
+                    };
+                    match object.get(field.name) {
+                        Some(value) => check(value, &field.ty, &path, types, errors),
+                        None => errors.push(FieldError {
+                            field: path,
+                            message: "required".to_string(),
+                        }),
+                    }
+                }
+                true
+            }
+            None => false,
+        },
+    };
+
+    if !matches {
+        errors.push(FieldError {
+            field: if path.is_empty() { "body" } else { path }.to_string(),
+            message: format!("expected {}, got {}", expected(ty), json_kind(value)),
+        });
+    }
+}
+
+fn expected(ty: &SchemaType) -> &'static str {
+    match ty {
+        SchemaType::String => "string",
+        SchemaType::Integer => "integer",
+        SchemaType::Number => "number",
+        SchemaType::Boolean => "boolean",
+        SchemaType::Array(_) => "array",
+        SchemaType::Named(_) => "object",
+        SchemaType::Any => "any value",
+    }
+}
+
+fn json_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn field_errors_name_the_field() {
+        let request = SchemaType::Named("TextRequest");
+        assert_eq!(validate(&json!({ "text": "Bures" }), &request), vec![]);
+        assert_eq!(
+            validate(&json!({ "text": 5 }), &request),
+            vec![FieldError {
+                field: "text".to_string(),
+                message: "expected string, got number".to_string(),
+            }]
+        );
+        assert_eq!(
+            validate(&json!({}), &request)[0].message,
+            "required".to_string()
+        );
+        assert_eq!(validate(&json!([]), &request)[0].field, "body");
+    }
+
+    #[test]
+    fn nested_paths_include_indices() {
+        let response = json!({ "text": "", "errs": [{ "error_text": 1 }] });
+        let errors = validate(&response, &SchemaType::Named("GrammarResponse"));
+        assert_eq!(errors[0].field, "errs[0].error_text");
+        assert_eq!(errors[1].field, "errs[0].start_index");
+    }
+}
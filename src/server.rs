@@ -0,0 +1,3439 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::Context;
+use poem::endpoint::BoxEndpoint;
+use poem::{
+    get, handler,
+    http::StatusCode,
+    listener::{Listener, TcpListener},
+    middleware::{Cors, SetHeader},
+    post,
+    web::{Data, Json, Query},
+    Endpoint, EndpointExt, IntoResponse, Request, Response, Route, Server,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::access::AccessLog;
+use crate::admin::{self, AdminToken, DisabledLanguages, Reloader};
+use crate::aliases::TagAliases;
+use crate::assets::{asset_get, Assets};
+use crate::auth::ApiKeyAuth;
+use crate::cache::CacheControl;
+use crate::compression::Compression;
+use crate::config::{LanguagesConfig, LegacyLanguagesConfig};
+use crate::cors::CorsPolicies;
+use crate::etag::ConfigETag;
+use crate::faults::FaultInjection;
+use crate::health::{self, HealthMonitor};
+#[cfg(feature = "wasm")]
+use crate::hooks::WasmHook;
+use crate::i18n::Catalogs;
+use crate::langid::{LanguageIdentifier, OrthographyIdentifier};
+use crate::limits::ClientRateLimit;
+use crate::openapi::generate_openapi;
+use crate::pages::{demo_get, index_get, status_html_get};
+use crate::problem::{Problem, ProblemErrors};
+use crate::proxy::ProxyEndpoint;
+use crate::recording::Recorder;
+use crate::responses::ResponseCache;
+use crate::retry::CircuitBreakers;
+use crate::schema::ApiSchema;
+use crate::services::{voice_details, ServiceKind, ServiceRegistry};
+#[cfg(unix)]
+use crate::socket::UnixSocket;
+use crate::stats::{self, UsageStats, UsageTracking};
+use crate::statsd::{Statsd, StatsdMetrics};
+use crate::table;
+use crate::tenants::{self, HostRouter};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::transcode::Transcoder;
+use crate::upstream::Upstreams;
+
+#[derive(Debug, Deserialize)]
+struct LanguagesQuery {
+    /// Response version; 1 unless asked for.
+    v: Option<u32>,
+}
+
+#[handler]
+async fn languages_get(
+    Data(languages): Data<&LanguagesConfig>,
+    Data(services): Data<&ServiceRegistry>,
+    Query(query): Query<LanguagesQuery>,
+) -> poem::Result<Response> {
+    match query.v {
+        None | Some(1) => {}
+        Some(2) => return Ok(Json(languages_v2(languages, services)).into_response()),
+        Some(v) => {
+            return Err(Problem::new(StatusCode::BAD_REQUEST, "Unsupported version")
+                .detail(format!("/languages has versions 1 and 2, not {}", v))
+                .into())
+        }
+    }
+
+    let aliases: BTreeMap<&str, BTreeMap<String, String>> = services
+        .iter()
+        .map(|kind| (kind.name(), languages.aliases(kind.name())))
+        .filter(|(_, aliases)| !aliases.is_empty())
+        .collect();
+    Ok(Json(serde_json::json!({
+        "available": LegacyLanguagesConfig::from(languages),
+        "aliases": aliases,
+        "limits": languages.config.limits,
+    }))
+    .into_response())
+}
+
+/// The `/languages?v=2` listing: each service category's languages with
+/// their names, aliases and API paths, and the voices of TTS languages.
+fn languages_v2(languages: &LanguagesConfig, services: &ServiceRegistry) -> serde_json::Value {
+    let mut categories = serde_json::Map::new();
+    for kind in services.iter() {
+        let mut paths: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for location in kind.locations(languages) {
+            paths.entry(location.tag).or_default().push(location.path);
+        }
+        if paths.is_empty() {
+            continue;
+        }
+        let mut aliases: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (alias, tag) in languages.aliases(kind.name()) {
+            aliases.entry(tag).or_default().push(alias);
+        }
+
+        let entries = paths
+            .into_iter()
+            .map(|(tag, paths)| {
+                let mut entry = json!({
+                    "name": languages.language_name(kind.name(), &tag),
+                    "english_name": languages.english_name(&tag),
+                    "aliases": aliases.remove(&tag).unwrap_or_default(),
+                    "paths": paths,
+                });
+                if let Some(tts) = languages.tts.get(&tag).filter(|_| kind.name() == "tts") {
+                    entry["voices"] = voice_details(&tag, tts).into();
+                }
+                (tag, entry)
+            })
+            .collect();
+        categories.insert(kind.name().to_string(), serde_json::Value::Object(entries));
+    }
+    json!({
+        "version": 2,
+        "services": categories,
+        "limits": languages.config.limits,
+    })
+}
+
+#[handler]
+async fn openapi_get(
+    Data(languages): Data<&LanguagesConfig>,
+    Data(services): Data<&ServiceRegistry>,
+) -> impl IntoResponse {
+    Json(generate_openapi(&ApiSchema::new(languages, services))).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct DetectRequest {
+    text: String,
+    /// Languages to choose between; all configured ones if omitted.
+    #[serde(default)]
+    languages: Option<Vec<String>>,
+}
+
+/// Rank the configured languages the text may be in, most likely first.
+#[handler]
+async fn detect_post(
+    Data(languages): Data<&LanguagesConfig>,
+    Data(identifier): Data<&Arc<dyn LanguageIdentifier>>,
+    Json(request): Json<DetectRequest>,
+) -> impl IntoResponse {
+    let names = languages.language_names();
+    let candidates: Vec<&str> = match &request.languages {
+        Some(tags) => tags
+            .iter()
+            .map(String::as_str)
+            .filter(|tag| names.contains_key(*tag))
+            .collect(),
+        None => names.keys().map(String::as_str).collect(),
+    };
+    let ranked: Vec<_> = identifier
+        .rank(&request.text, &candidates)
+        .into_iter()
+        .filter_map(|(tag, score)| {
+            let name = names.get(&tag)?;
+            Some(json!({ "tag": tag, "name": name, "score": score }))
+        })
+        .collect();
+    Json(json!({ "candidates": ranked })).into_response()
+}
+
+#[handler]
+async fn health_get() -> impl IntoResponse {
+    Json(json!({ "status": "ok" })).into_response()
+}
+
+#[handler]
+async fn readyz_get(Data(health): Data<&HealthMonitor>) -> poem::Result<Response> {
+    if health.ready() {
+        Ok(Json(json!({ "status": "ready" })).into_response())
+    } else {
+        Err(Problem::new(StatusCode::SERVICE_UNAVAILABLE, "Not ready")
+            .detail("No backend is responding")
+            .into())
+    }
+}
+
+#[handler]
+async fn status_get(Data(health): Data<&HealthMonitor>) -> impl IntoResponse {
+    Json(health.snapshot()).into_response()
+}
+
+/// Build the gateway's route table for `languages` and the given service
+/// categories, reporting backend state from `health`.
+pub fn app(
+    languages: LanguagesConfig,
+    services: ServiceRegistry,
+    health: HealthMonitor,
+) -> anyhow::Result<impl Endpoint> {
+    let breakers = circuit_breakers(&languages);
+    build_app(
+        languages,
+        services,
+        health,
+        breakers,
+        Arc::new(OrthographyIdentifier),
+        true,
+        false,
+    )
+}
+
+/// The circuit breakers of a configuration, if it has them.
+fn circuit_breakers(languages: &LanguagesConfig) -> Option<Arc<CircuitBreakers>> {
+    languages
+        .config
+        .circuit_breaker
+        .as_ref()
+        .map(|config| Arc::new(CircuitBreakers::new(config)))
+}
+
+fn build_app(
+    languages: LanguagesConfig,
+    services: ServiceRegistry,
+    health: HealthMonitor,
+    breakers: Option<Arc<CircuitBreakers>>,
+    identifier: Arc<dyn LanguageIdentifier>,
+    cors: bool,
+    faults: bool,
+) -> anyhow::Result<impl Endpoint> {
+    if let Some(name) = services.unclaimed(&languages).first() {
+        anyhow::bail!("no service handles [{}] in languages.toml", name);
+    }
+    let catalogs = Catalogs::load()?;
+
+    let etag = ConfigETag::new(&languages);
+    let mut routes = Route::new()
+        .at("/", get(index_get).with(etag))
+        .at("/health", get(health_get))
+        .at("/readyz", get(readyz_get))
+        .at("/status", get(status_get))
+        .at("/status.html", get(status_html_get))
+        .at("/languages", get(languages_get).with(etag))
+        .at("/openapi.json", get(openapi_get).with(etag))
+        .at("/detect", post(detect_post))
+        .at("/demo/:tag", get(demo_get))
+        .at("/assets/*name", get(asset_get));
+
+    let client = languages.config.pool.client()?;
+    let caches: HashMap<&str, Arc<ResponseCache>> = languages
+        .config
+        .response_cache
+        .iter()
+        .map(|(service, config)| (service.as_str(), Arc::new(ResponseCache::new(config))))
+        .collect();
+    #[cfg(feature = "wasm")]
+    let mut hooks: HashMap<String, Arc<WasmHook>> = HashMap::new();
+    let mut upstreams = Vec::new();
+    for kind in services.iter() {
+        routes = kind.routes(routes, &languages);
+
+        if kind.proxied() {
+            for location in kind.locations(&languages) {
+                let path = location.path.clone();
+                let hook_path = languages
+                    .wasm_hook(kind.name(), &location.tag)
+                    .map(str::to_string);
+                let apostrophe = languages.apostrophe(kind.name(), &location.tag);
+                let ssml = languages.tts.get(&location.tag).is_some_and(|tts| tts.ssml);
+                let recorder = match languages.record_dir(kind.name(), &location.tag) {
+                    Some(dir) => Some(Arc::new(Recorder::open(dir, kind.name(), &location.tag)?)),
+                    None => None,
+                };
+                #[cfg(not(feature = "wasm"))]
+                if let Some(hook_path) = hook_path {
+                    anyhow::bail!(
+                        "{} needs the WASM hook {}, but this build lacks the `wasm` feature",
+                        path,
+                        hook_path
+                    );
+                }
+                let endpoint = ProxyEndpoint::new(kind.clone(), location, client.clone())
+                    .with_max_length(languages.max_length(kind.name()))
+                    .with_chunking(languages.chunk_length(kind.name()))
+                    .with_sanitize(languages.config.sanitize)
+                    .with_apostrophe(apostrophe)
+                    .with_retry(languages.config.retry.clone())
+                    .with_timeout(languages.timeout(kind.name()))
+                    .with_failover(health.clone());
+                let endpoint = match kind.name() {
+                    "tts" => endpoint
+                        .with_transcoder(Transcoder::new(languages.config.tts.ffmpeg.clone()))
+                        .with_ssml(ssml),
+                    "grammar" => endpoint.with_tag_filter().with_markup(),
+                    "speller" => endpoint.with_suggestion_limits().with_markup(),
+                    _ => endpoint,
+                };
+                let endpoint = match recorder {
+                    Some(recorder) => endpoint.with_recorder(recorder),
+                    None => endpoint,
+                };
+                let endpoint = match &breakers {
+                    Some(breakers) => endpoint.with_circuit_breakers(breakers.clone()),
+                    None => endpoint,
+                };
+                let endpoint = match caches.get(kind.name()) {
+                    Some(cache) if kind.deterministic() => endpoint.with_cache(cache.clone()),
+                    _ => endpoint,
+                };
+                #[cfg(feature = "wasm")]
+                let endpoint = match hook_path {
+                    Some(hook_path) => {
+                        let hook = match hooks.get(&hook_path) {
+                            Some(hook) => hook.clone(),
+                            None => {
+                                tracing::info!("Loading WASM hook {} for {}", hook_path, path);
+                                let hook = Arc::new(WasmHook::load(&hook_path)?);
+                                hooks.insert(hook_path, hook.clone());
+                                hook
+                            }
+                        };
+                        endpoint.with_hook(hook)
+                    }
+                    None => endpoint,
+                };
+                upstreams.push(endpoint.upstream().clone());
+                routes = routes.at(path, post(endpoint));
+            }
+        }
+    }
+
+    if let Some(dir) = &languages.config.static_dir {
+        #[cfg(feature = "static-files")]
+        {
+            tracing::info!("Serving static files from {}", dir);
+            routes = routes.nest(
+                "/static",
+                poem::endpoint::StaticFilesEndpoint::new(dir)
+                    .index_file("index.html")
+                    .redirect_to_slash_directory(),
+            );
+        }
+        #[cfg(not(feature = "static-files"))]
+        anyhow::bail!(
+            "static_dir {} is configured, but this build lacks the `static-files` feature",
+            dir
+        );
+    }
+
+    let announcement_header = languages
+        .config
+        .announcement
+        .as_ref()
+        .filter(|announcement| announcement.header)
+        .map(|announcement| announcement.message.clone());
+
+    let cors_config = languages.config.cors.clone();
+    let rate_limit = languages.config.rate_limit.clone();
+    let auth = match &languages.config.auth {
+        Some(auth) => Some(ApiKeyAuth {
+            keys: auth.load_keys()?,
+            services: services
+                .iter()
+                .map(|kind| kind.name().to_string())
+                .collect(),
+        }),
+        None => None,
+    };
+    let cache = languages.config.cache.clone();
+    let faults = match faults {
+        true if !languages.config.faults.is_empty() => {
+            tracing::warn!(
+                "Injecting faults on {} routes",
+                languages.config.faults.len()
+            );
+            languages.config.faults.clone()
+        }
+        _ => Vec::new(),
+    };
+    let aliases = TagAliases::new(&languages, &services);
+    let compression = languages.config.compression.clone();
+    let statsd = match &languages.config.statsd {
+        Some(config) => Some(Arc::new(Statsd::new(config)?)),
+        None => None,
+    };
+
+    Ok(routes
+        .with_if(
+            announcement_header.is_some(),
+            SetHeader::new().overriding("X-Announcement", announcement_header.unwrap_or_default()),
+        )
+        .data(languages)
+        .data(services)
+        .data(catalogs)
+        .data(health.clone())
+        .data(client)
+        .data(Upstreams::new(upstreams))
+        .data(identifier)
+        .with(DisabledLanguages(health))
+        .with_if(auth.is_some(), auth.unwrap_or_default())
+        .with_if(cors && cors_config.is_none(), Cors::default())
+        .with_if(
+            cors && cors_config.is_some(),
+            CorsPolicies(cors_config.unwrap_or_default()),
+        )
+        .with(CacheControl(cache))
+        .with_if(
+            rate_limit.is_some(),
+            ClientRateLimit(rate_limit.unwrap_or_default()),
+        )
+        .with_if(!faults.is_empty(), FaultInjection(faults))
+        .with_if(!aliases.is_empty(), aliases)
+        .with(StatsdMetrics(statsd))
+        .with(ProblemErrors)
+        .with_if(compression.enabled, Compression(compression))
+        .with(AccessLog))
+}
+
+/// A configuration and the routes built from it. Never modified: changes
+/// build a new snapshot and swap it in.
+pub struct RoutingSnapshot {
+    pub languages: LanguagesConfig,
+    /// The circuit breakers of the main configuration's backends.
+    pub breakers: Option<Arc<CircuitBreakers>>,
+    app: BoxEndpoint<'static>,
+}
+
+type Build = dyn Fn(LanguagesConfig) -> anyhow::Result<RoutingSnapshot> + Send + Sync;
+
+/// The gateway's current [`RoutingSnapshot`]. Each request is routed by the
+/// snapshot current when it arrived, so requests in flight during a swap
+/// finish against the old routes and later ones see only the new.
+#[derive(Clone)]
+pub struct RoutingTable {
+    current: Arc<RwLock<Arc<RoutingSnapshot>>>,
+    build: Arc<Build>,
+    /// Serializes swaps so none is lost between building and storing.
+    swapping: Arc<Mutex<()>>,
+}
+
+impl RoutingTable {
+    fn new(
+        languages: LanguagesConfig,
+        build: impl Fn(LanguagesConfig) -> anyhow::Result<RoutingSnapshot> + Send + Sync + 'static,
+    ) -> anyhow::Result<Self> {
+        let snapshot = build(languages)?;
+        Ok(Self {
+            current: Arc::new(RwLock::new(Arc::new(snapshot))),
+            build: Arc::new(build),
+            swapping: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn snapshot(&self) -> Arc<RoutingSnapshot> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Build the routes for `languages` and make them current. Returns the
+    /// snapshot that was replaced; on error the current one stays.
+    pub fn apply(&self, languages: LanguagesConfig) -> anyhow::Result<Arc<RoutingSnapshot>> {
+        let _swapping = self.swapping.lock().unwrap_or_else(|err| err.into_inner());
+        let snapshot = Arc::new((self.build)(languages)?);
+        Ok(std::mem::replace(
+            &mut *self.current.write().unwrap(),
+            snapshot,
+        ))
+    }
+}
+
+impl Endpoint for RoutingTable {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> poem::Result<Response> {
+        self.snapshot().app.call(req).await
+    }
+}
+
+/// How long a stopping server waits for requests in flight.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One route of the gateway, as listed by [`ServerBuilder::dry_run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteEntry {
+    pub method: &'static str,
+    pub path: String,
+    /// What answers the route: the gateway, a backend URL, or the fronting
+    /// nginx for locations the gateway doesn't forward itself.
+    pub target: String,
+}
+
+/// Every route served for `languages`, in registration order.
+pub fn route_table(languages: &LanguagesConfig, services: &ServiceRegistry) -> Vec<RouteEntry> {
+    let gateway = |method, path: &str| RouteEntry {
+        method,
+        path: path.to_string(),
+        target: "gateway".to_string(),
+    };
+    let mut entries: Vec<_> = [
+        "/",
+        "/health",
+        "/readyz",
+        "/status",
+        "/status.html",
+        "/languages",
+        "/openapi.json",
+        "/demo/:tag",
+        "/assets/*",
+    ]
+    .into_iter()
+    .map(|path| gateway("GET", path))
+    .collect();
+    entries.push(gateway("POST", "/detect"));
+
+    for kind in services.iter() {
+        for (method, path) in kind.route_paths(languages) {
+            entries.push(gateway(method, &path));
+        }
+        for location in kind.locations(languages) {
+            let backend = location.backend_url();
+            entries.push(RouteEntry {
+                method: "POST",
+                path: location.path,
+                target: if kind.proxied() {
+                    backend
+                } else {
+                    format!("nginx -> {}", backend)
+                },
+            });
+        }
+    }
+
+    if let Some(dir) = &languages.config.static_dir {
+        entries.push(RouteEntry {
+            method: "GET",
+            path: "/static/*".to_string(),
+            target: format!("files in {}", dir),
+        });
+    }
+    entries
+}
+
+/// A plain-text table of `entries`, one route per line.
+pub fn route_listing(entries: &[RouteEntry]) -> String {
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| {
+            vec![
+                entry.method.to_string(),
+                entry.path.clone(),
+                entry.target.clone(),
+            ]
+        })
+        .collect();
+    table::render(&["METHOD", "PATH", "TARGET"], &rows)
+}
+
+/// Serve the gateway on `host:port` until the process is stopped.
+pub async fn serve(languages: LanguagesConfig, host: String, port: u16) -> anyhow::Result<()> {
+    ServerBuilder::new()
+        .languages(languages)
+        .bind(host, port)
+        .serve()
+        .await
+}
+
+/// Configures the gateway, either to run on its own with [`serve`] or to be
+/// mounted inside another poem application with [`build`].
+///
+/// ```no_run
+/// use divvun_worker_static::server::ServerBuilder;
+///
+/// # async fn run() -> anyhow::Result<()> {
+/// let gateway = ServerBuilder::new().cors(false).build()?;
+/// let app = poem::Route::new().nest("/divvun", gateway);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`serve`]: ServerBuilder::serve
+/// [`build`]: ServerBuilder::build
+pub struct ServerBuilder {
+    languages: Option<LanguagesConfig>,
+    config_file: Option<PathBuf>,
+    services: ServiceRegistry,
+    identifier: Arc<dyn LanguageIdentifier>,
+    cors: bool,
+    health_checks: bool,
+    fault_injection: bool,
+    assets: Assets,
+    preflight: bool,
+    reload_triggers: Option<UnboundedReceiver<()>>,
+    http2_max_streams: Option<u32>,
+    host: String,
+    port: u16,
+    #[cfg(unix)]
+    unix_socket: Option<UnixSocket>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            languages: None,
+            config_file: None,
+            services: ServiceRegistry::builtin(),
+            identifier: Arc::new(OrthographyIdentifier),
+            cors: true,
+            health_checks: true,
+            fault_injection: false,
+            assets: Assets::embedded(),
+            preflight: false,
+            reload_triggers: None,
+            http2_max_streams: None,
+            host: "127.0.0.1".to_string(),
+            port: 4000,
+            #[cfg(unix)]
+            unix_socket: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `languages` instead of the embedded `languages.toml`.
+    pub fn languages(mut self, languages: LanguagesConfig) -> Self {
+        self.languages = Some(languages);
+        self
+    }
+
+    /// Load the configuration from `path`, which `POST /admin/reload` and
+    /// [`reload_on`] re-read, with the `DIVVUN_*` environment variables'
+    /// overrides. Takes precedence over [`languages`].
+    ///
+    /// [`languages`]: ServerBuilder::languages
+    /// [`reload_on`]: ServerBuilder::reload_on
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Use `services` instead of the built-in service categories.
+    pub fn services(mut self, services: ServiceRegistry) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Add a service category alongside the configured ones.
+    pub fn register(mut self, kind: impl ServiceKind + 'static) -> Self {
+        self.services.register(kind);
+        self
+    }
+
+    /// Use `identifier` to split mixed-language texts instead of the built-in
+    /// heuristic.
+    pub fn language_identifier(mut self, identifier: impl LanguageIdentifier + 'static) -> Self {
+        self.identifier = Arc::new(identifier);
+        self
+    }
+
+    /// Whether to answer CORS requests. Disable when the host application
+    /// applies its own CORS policy.
+    pub fn cors(mut self, cors: bool) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Whether to probe backends in the background for `/status`.
+    pub fn health_checks(mut self, health_checks: bool) -> Self {
+        self.health_checks = health_checks;
+        self
+    }
+
+    /// Whether to inject the faults in `[[config.faults]]`. For testing
+    /// clients only; the config alone doesn't enable them.
+    pub fn fault_injection(mut self, fault_injection: bool) -> Self {
+        self.fault_injection = fault_injection;
+        self
+    }
+
+    /// Serve the docs page's assets from `dir`, if given, instead of the
+    /// files built into the binary.
+    pub fn assets_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.assets = match dir {
+            Some(dir) => Assets::from_dir(dir),
+            None => Assets::embedded(),
+        };
+        self
+    }
+
+    /// Probe every backend before listening and fail to start if any is
+    /// unreachable.
+    pub fn preflight(mut self, preflight: bool) -> Self {
+        self.preflight = preflight;
+        self
+    }
+
+    /// Re-read the [`config_file`] whenever `triggers` receives, e.g. on
+    /// SIGHUP. A file that fails to load is logged and leaves the running
+    /// configuration in place.
+    ///
+    /// [`config_file`]: ServerBuilder::config_file
+    pub fn reload_on(mut self, triggers: UnboundedReceiver<()>) -> Self {
+        self.reload_triggers = Some(triggers);
+        self
+    }
+
+    /// Requests each HTTP/2 connection may have in flight at once; hyper's
+    /// default if `None`. [`serve`](ServerBuilder::serve) speaks HTTP/2 to
+    /// clients negotiating it over TLS and, in plain text, to those starting
+    /// the connection with it, so an editor can send all its checks over one
+    /// connection.
+    pub fn http2_max_streams(mut self, max: Option<u32>) -> Self {
+        self.http2_max_streams = max;
+        self
+    }
+
+    /// Address [`serve`](ServerBuilder::serve) listens on.
+    pub fn bind(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.host = host.into();
+        self.port = port;
+        self
+    }
+
+    /// Listen on `socket` instead of the [`bind`](ServerBuilder::bind)
+    /// address.
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, socket: UnixSocket) -> Self {
+        self.unix_socket = Some(socket);
+        self
+    }
+
+    /// Serve HTTPS with `tls` instead of plain HTTP.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Build the gateway's endpoint. Starts the health monitor if enabled, so
+    /// this must be called from within a tokio runtime.
+    pub fn build(mut self) -> anyhow::Result<impl Endpoint> {
+        let languages = self.load_languages()?;
+        self.assets.check()?;
+        let admin = languages.config.admin.clone();
+        let stats = match &languages.config.stats {
+            Some(config) => {
+                let stats = Arc::new(UsageStats::load(config)?);
+                if config.file.is_some() {
+                    stats.spawn_flush(Duration::from_secs(config.flush_interval.max(1)));
+                }
+                Some(stats)
+            }
+            None => None,
+        };
+        let health = HealthMonitor::new(&languages, &self.services);
+        if self.health_checks {
+            health.spawn();
+        }
+
+        let admin_health = health.clone();
+        let services = self.services.clone();
+        let (identifier, cors, health_checks) = (self.identifier, self.cors, self.health_checks);
+        let faults = self.fault_injection;
+        let base = self
+            .config_file
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf);
+        // Kept across reloads like the main monitor, by tenant name
+        let tenant_health: Mutex<HashMap<String, HealthMonitor>> = Mutex::default();
+        let routing = RoutingTable::new(languages, move |languages: LanguagesConfig| {
+            let tenants = tenants::load(&languages, base.as_deref())?;
+            let breakers = circuit_breakers(&languages);
+            let app = build_app(
+                languages.clone(),
+                services.clone(),
+                health.clone(),
+                breakers.clone(),
+                identifier.clone(),
+                cors,
+                faults,
+            )?;
+            let mut router = HostRouter::new(app.map_to_response().boxed());
+            let mut monitors = tenant_health.lock().unwrap_or_else(|err| err.into_inner());
+            let mut apps = Vec::new();
+            for tenant in &tenants {
+                let health = monitors
+                    .entry(tenant.name.clone())
+                    .or_insert_with(|| {
+                        let health = HealthMonitor::new(&tenant.languages, &services);
+                        if health_checks {
+                            health.spawn();
+                        }
+                        health
+                    })
+                    .clone();
+                let app = build_app(
+                    tenant.languages.clone(),
+                    services.clone(),
+                    health.clone(),
+                    circuit_breakers(&tenant.languages),
+                    identifier.clone(),
+                    cors,
+                    faults,
+                )?;
+                apps.push((tenant, health, app.map_to_response().boxed()));
+            }
+
+            // Only once every app has been built, so a failed reload changes nothing
+            health.retarget(&languages, &services);
+            for (tenant, health, app) in apps {
+                health.retarget(&tenant.languages, &services);
+                router = router.tenant(&tenant.hosts, app);
+            }
+            Ok(RoutingSnapshot {
+                languages,
+                breakers,
+                app: router.boxed(),
+            })
+        })?;
+
+        let reloader = Arc::new(Reloader::new(
+            self.config_file,
+            routing.clone(),
+            self.services.clone(),
+        ));
+        if let Some(triggers) = self.reload_triggers {
+            tokio::spawn(reloader.clone().run(triggers));
+        }
+        let app = routing
+            .clone()
+            .with(UsageTracking {
+                stats: stats.clone(),
+                routing: routing.clone(),
+            })
+            .data(self.assets);
+        if admin.is_none() && stats.is_none() {
+            return Ok(app.boxed());
+        }
+
+        let mut route = Route::new();
+        if let Some(stats) = stats {
+            route = route.at("/stats", get(stats::stats_get).data(stats));
+        }
+        if let Some(admin) = admin {
+            route = route.nest(
+                "/admin",
+                admin::routes()
+                    .data(AdminToken(admin.token))
+                    .data(reloader)
+                    .data(routing.clone())
+                    .data(self.services)
+                    .data(admin_health),
+            );
+        }
+        Ok(route.nest("/", app).map_to_response().boxed())
+    }
+
+    fn load_languages(&mut self) -> anyhow::Result<LanguagesConfig> {
+        match (&self.config_file, self.languages.take()) {
+            (Some(path), _) => LanguagesConfig::load(Some(path)),
+            (None, Some(languages)) => Ok(languages),
+            (None, None) => LanguagesConfig::load(None),
+        }
+    }
+
+    /// Check everything [`serve`] would need without serving: the
+    /// configuration, the gateway's endpoint (including WASM hooks and static
+    /// files), the TLS certificate and that the address can be bound. Returns
+    /// the route table.
+    ///
+    /// [`serve`]: ServerBuilder::serve
+    pub fn dry_run(mut self) -> anyhow::Result<Vec<RouteEntry>> {
+        let languages = self.load_languages()?;
+        self.assets.check()?;
+        let entries = route_table(&languages, &self.services);
+        let base = self.config_file.as_deref().and_then(Path::parent);
+        for tenant in tenants::load(&languages, base)? {
+            let health = HealthMonitor::new(&tenant.languages, &self.services);
+            let breakers = circuit_breakers(&tenant.languages);
+            build_app(
+                tenant.languages,
+                self.services.clone(),
+                health,
+                breakers,
+                self.identifier.clone(),
+                self.cors,
+                self.fault_injection,
+            )
+            .with_context(|| format!("invalid tenant {}", tenant.name))?;
+        }
+        let health = HealthMonitor::new(&languages, &self.services);
+        let breakers = circuit_breakers(&languages);
+        build_app(
+            languages,
+            self.services,
+            health,
+            breakers,
+            self.identifier,
+            self.cors,
+            self.fault_injection,
+        )?;
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            tls.check()?;
+        }
+        #[cfg(unix)]
+        if let Some(socket) = &self.unix_socket {
+            socket.check()?;
+            return Ok(entries);
+        }
+        std::net::TcpListener::bind((self.host.as_str(), self.port))
+            .with_context(|| format!("can't listen on {}:{}", self.host, self.port))?;
+        Ok(entries)
+    }
+
+    /// Serve the gateway until the process is stopped.
+    pub async fn serve(self) -> anyhow::Result<()> {
+        self.serve_until(std::future::pending()).await
+    }
+
+    /// Serve the gateway until `shutdown` completes, then finish the
+    /// requests in flight for up to [`SHUTDOWN_TIMEOUT`].
+    #[allow(unused_mut)]
+    pub async fn serve_until(mut self, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
+        if self.preflight {
+            let languages = self.load_languages()?;
+            health::preflight(&languages, &self.services).await?;
+            self.languages = Some(languages);
+        }
+        let listener = TcpListener::bind((self.host.clone(), self.port)).boxed();
+        // Removes the socket once the server has stopped
+        #[cfg(unix)]
+        let (listener, _socket) = match self.unix_socket.take() {
+            Some(socket) => {
+                let (unix, guard) = socket.listener()?;
+                (unix.boxed(), Some(guard))
+            }
+            None => (listener, None),
+        };
+        #[cfg(feature = "tls")]
+        let listener = match self.tls.take() {
+            Some(tls) => tls.listener(listener)?,
+            None => listener,
+        };
+        Server::new(listener)
+            .http2_max_concurrent_streams(self.http2_max_streams)
+            .run_with_graceful_shutdown(self.build()?, shutdown, Some(SHUTDOWN_TIMEOUT))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{http::StatusCode, test::TestClient};
+
+    use super::*;
+    use crate::i18n::Localizer;
+    use crate::responses::ResponseCacheConfig;
+    use crate::sanitize::SanitizePolicy;
+    use crate::services::{
+        service_backends, service_locations, Backend, DocsSection, EndpointDocs, Location,
+        ServiceKind,
+    };
+    use crate::testing::TestGateway;
+
+    fn client() -> TestClient<impl Endpoint> {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let services = ServiceRegistry::builtin();
+        let health = HealthMonitor::new(&languages, &services);
+        TestClient::new(app(languages, services, health).unwrap())
+    }
+
+    #[tokio::test]
+    async fn health_reports_ok() {
+        let response = client().get("/health").send().await;
+        response.assert_status_is_ok();
+        response.assert_json(json!({ "status": "ok" })).await;
+
+        let response = client().get("/readyz").send().await;
+        response.assert_status_is_ok();
+        response.assert_json(json!({ "status": "ready" })).await;
+    }
+
+    #[tokio::test]
+    async fn builder_endpoint_can_be_nested() {
+        let gateway = ServerBuilder::new()
+            .health_checks(false)
+            .cors(false)
+            .build()
+            .unwrap();
+        let client = TestClient::new(Route::new().nest("/divvun", gateway));
+
+        let response = client
+            .get("/divvun/health")
+            .header("Origin", "https://example.com")
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.assert_header_is_not_exist("Access-Control-Allow-Origin");
+    }
+
+    #[tokio::test]
+    async fn cache_policies_apply_to_successful_responses() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.cache =
+            vec![toml::from_str("path = \"/languages\"\nmax_age = 300\n").unwrap()];
+        languages
+            .config
+            .cache
+            .push(toml::from_str("path = \"/demo\"\nno_store = true\n").unwrap());
+        let services = ServiceRegistry::builtin();
+        let health = HealthMonitor::new(&languages, &services);
+        let client = TestClient::new(app(languages, services, health).unwrap());
+
+        client
+            .get("/languages")
+            .send()
+            .await
+            .assert_header("Cache-Control", "max-age=300");
+        let response = client.get("/demo/xx").send().await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        response.assert_header_is_not_exist("Cache-Control");
+        client
+            .get("/health")
+            .send()
+            .await
+            .assert_header_is_not_exist("Cache-Control");
+    }
+
+    #[tokio::test]
+    async fn preflights_follow_the_route_policy() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.cors = Some(
+            toml::from_str("max_age = 600\n[[routes]]\npath = \"/languages\"\nmax_age = 86400\n")
+                .unwrap(),
+        );
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .languages(languages)
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+        let preflight = |path: &'static str| {
+            client
+                .options(path)
+                .header("Origin", "https://example.com")
+                .header("Access-Control-Request-Method", "GET")
+                .send()
+        };
+
+        preflight("/languages")
+            .await
+            .assert_header("Access-Control-Max-Age", "86400");
+        preflight("/health")
+            .await
+            .assert_header("Access-Control-Max-Age", "600");
+    }
+
+    #[tokio::test]
+    async fn languages_lists_legacy_names() {
+        let response = client().get("/languages").send().await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        json.value()
+            .object()
+            .get("available")
+            .object()
+            .get("speller")
+            .object()
+            .get("se")
+            .assert_string("davvisámegiella");
+    }
+
+    #[tokio::test]
+    async fn languages_v2_groups_languages_by_service() {
+        let response = client().get("/languages?v=2").send().await;
+        response.assert_status_is_ok();
+        let json: serde_json::Value = response.json().await.value().deserialize();
+        assert_eq!(json["version"], 2);
+        assert_eq!(
+            json["services"]["grammar"]["se"],
+            json!({
+                "name": "davvisámegiella",
+                "english_name": "Northern Sami",
+                "aliases": [],
+                "paths": ["/grammar/se"],
+            })
+        );
+        let sma = &json["services"]["tts"]["sma"];
+        assert_eq!(sma["english_name"], "Southern Sami");
+        assert_eq!(sma["paths"], json!(["/tts/sma/aanna"]));
+        assert_eq!(sma["voices"][0]["name"], "Aanna");
+
+        client()
+            .get("/languages?v=3")
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn config_pages_revalidate_with_etags() {
+        let client = client();
+        let response = client.get("/languages").send().await;
+        response.assert_status_is_ok();
+        let etag = response.0.headers()["ETag"].to_str().unwrap().to_string();
+
+        let response = client
+            .get("/languages")
+            .header("If-None-Match", &etag)
+            .send()
+            .await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+        response.assert_header("ETag", &etag);
+
+        // Other versions and translations are other representations
+        client
+            .get("/languages?v=2")
+            .header("If-None-Match", &etag)
+            .send()
+            .await
+            .assert_status_is_ok();
+        let index = client.get("/").header("Accept-Language", "nb").send().await;
+        index.assert_status_is_ok();
+        assert_ne!(index.0.headers()["ETag"], etag.as_str());
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.branding.title = "Other API".to_string();
+        let other = TestClient::new(
+            ServerBuilder::new()
+                .languages(languages)
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+        other
+            .get("/languages")
+            .header("If-None-Match", &etag)
+            .send()
+            .await
+            .assert_status_is_ok();
+    }
+
+    #[tokio::test]
+    async fn openapi_describes_the_configured_routes() {
+        let response = client().get("/openapi.json").send().await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        json.value().object().get("openapi").assert_string("3.1.0");
+        json.value()
+            .object()
+            .get("paths")
+            .object()
+            .get("/speller/se")
+            .object()
+            .get("post")
+            .object()
+            .get("operationId")
+            .assert_string("speller-se");
+    }
+
+    #[tokio::test]
+    async fn index_is_localized() {
+        let response = client()
+            .get("/")
+            .header("Accept-Language", "nb-NO, en;q=0.5")
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.assert_header("Content-Language", "nb");
+        let html = response.0.into_body().into_string().await.unwrap();
+        assert!(html.contains("<h3>Stavekontroll</h3>"));
+        assert!(!html.contains("{{"));
+    }
+
+    #[tokio::test]
+    async fn index_has_a_console_per_service() {
+        let response = client().get("/").send().await;
+        let html = response.0.into_body().into_string().await.unwrap();
+        assert!(html.contains(r#"<div class="try-it" data-service="grammar">"#));
+        assert!(html.contains(r#"<div class="try-it" data-service="tts">"#));
+
+        let start = html
+            .find(r#"<script id="consoles" type="application/json">"#)
+            .unwrap();
+        let json = &html[html[start..].find('>').unwrap() + start + 1..];
+        let json = &json[..json.find("</script>").unwrap()];
+        let consoles: serde_json::Value = serde_json::from_str(json).unwrap();
+        let se = consoles["grammar"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|endpoint| endpoint["path"] == "/grammar/se")
+            .unwrap();
+        assert_eq!(se["label"], "davvisámegiella (se)");
+        assert_eq!(se["example"], "Mun lean sami ja mun hálan sámegiela.");
+        assert_eq!(consoles["tts"][0]["label"], "davvisámegiella (se/biret)");
+    }
+
+    #[tokio::test]
+    async fn index_escapes_branding() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.branding.title = "Giella <API>".to_string();
+        languages.branding.links = vec![crate::config::BrandingLink {
+            label: "Sámi & more".to_string(),
+            url: "https://example.com/?a=1&b=2".to_string(),
+        }];
+        languages.branding.footer = Some("<script>".to_string());
+        languages.grammar.get_mut("se").unwrap().name = "North Sámi <b>x</b>".to_string();
+        languages.tts.get_mut("se").unwrap().name = "North Sámi <b>x</b>".to_string();
+        let services = ServiceRegistry::builtin();
+        let health = HealthMonitor::new(&languages, &services);
+        let client = TestClient::new(app(languages, services, health).unwrap());
+
+        let response = client.get("/").send().await;
+        let html = response.0.into_body().into_string().await.unwrap();
+        assert!(html.contains("<h1>Giella &lt;API&gt;</h1>"));
+        assert!(html.contains(
+            r#"<li><a href="https://example.com/?a=1&amp;b=2">Sámi &amp; more</a></li>"#
+        ));
+        assert!(html.contains("<footer>&lt;script&gt;</footer>"));
+        assert!(html.contains(
+            r#"<a href="/grammar/se"><code>se</code></a> - North Sámi &lt;b&gt;x&lt;/b&gt;</li>"#
+        ));
+        assert!(html.contains("<li><code>se</code> - North Sámi &lt;b&gt;x&lt;/b&gt; ("));
+        assert!(!html.contains("{{") && !html.contains("{%"));
+    }
+
+    struct Shout;
+
+    impl ServiceKind for Shout {
+        fn name(&self) -> &'static str {
+            "shout"
+        }
+
+        fn docs(&self, languages: &LanguagesConfig, l: &Localizer<'_>) -> Option<DocsSection> {
+            let docs = EndpointDocs {
+                title: "Shouting".to_string(),
+                method: "POST",
+                path: "/shout/:tag".to_string(),
+                response_type: "text/plain".to_string(),
+                description: "Shout the text back.".to_string(),
+                languages: languages.custom["shout"]
+                    .iter()
+                    .map(|(tag, service)| (tag.clone(), service.name.clone()))
+                    .collect(),
+                request_example: None,
+                response_example: None,
+            };
+            Some(docs.render(self.name(), l))
+        }
+
+        fn locations(&self, languages: &LanguagesConfig) -> Vec<Location> {
+            service_locations(self.name(), &languages.custom["shout"])
+        }
+
+        fn backends(&self, languages: &LanguagesConfig) -> Vec<Backend> {
+            service_backends(&languages.custom["shout"])
+        }
+
+        fn map_request(&self, tag: &str, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+            Ok(format!("{}:{}", tag, String::from_utf8(body)?).into_bytes())
+        }
+
+        fn map_response(&self, _tag: &str, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+            Ok(String::from_utf8(body)?.to_uppercase().into_bytes())
+        }
+    }
+
+    async fn shout_gateway(configure: impl FnOnce(&mut LanguagesConfig)) -> TestGateway {
+        let source = format!(
+            "{}\n[shout.se]\nname = \"davvisámegiella\"\nport = 19999\n",
+            crate::config::EMBEDDED_CONFIG
+        );
+        let mut languages = LanguagesConfig::from_toml(&source).unwrap();
+        configure(&mut languages);
+        let mut services = ServiceRegistry::builtin();
+        services.register(Shout);
+        TestGateway::start(languages, services).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn text_services_are_proxied_without_nginx() {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let gateway = TestGateway::start(languages, ServiceRegistry::builtin())
+            .await
+            .unwrap();
+
+        let (response, forwarded) = gateway
+            .assert_proxied("grammar", "se", "/grammar/se", r#"{"text":"Bures"}"#)
+            .await;
+        response
+            .assert_json(json!({ "text": "Bures", "errs": [] }))
+            .await;
+        assert_eq!(forwarded.text().as_deref(), Some("Bures"));
+
+        let (response, _) = gateway
+            .assert_proxied("speller", "se", "/speller/se", r#"{"text":"Bures"}"#)
+            .await;
+        response
+            .assert_json(json!({
+                "text": "Bures",
+                "results": [{ "word": "Bures", "is_correct": true, "suggestions": [] }],
+            }))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn tts_requests_select_the_voice_by_query() {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let gateway = TestGateway::start(languages, ServiceRegistry::builtin())
+            .await
+            .unwrap();
+
+        let (response, forwarded) = gateway
+            .assert_proxied("tts", "se", "/tts/se/biret", r#"{"text":"Bures"}"#)
+            .await;
+        response.assert_content_type("audio/wav");
+        assert_eq!(forwarded.query.as_deref(), Some("language=1&speaker=5"));
+        assert_eq!(forwarded.text().as_deref(), Some("Bures"));
+    }
+
+    #[tokio::test]
+    async fn hyphenation_responses_get_positions_and_docs() {
+        let gateway = TestGateway::start(
+            LanguagesConfig::embedded().unwrap(),
+            ServiceRegistry::builtin(),
+        )
+        .await
+        .unwrap();
+
+        let (response, _) = gateway
+            .assert_proxied(
+                "hyphenation",
+                "se",
+                "/hyphenation/se",
+                r#"{"text":"guovlu ja"}"#,
+            )
+            .await;
+        response
+            .assert_json(json!({
+                "text": "guovlu ja",
+                "results": [
+                    {
+                        "word": "guovlu",
+                        "patterns": [{ "value": "guo^vlu", "weight": 0.0 }],
+                        "positions": [3],
+                    },
+                    {
+                        "word": "ja",
+                        "patterns": [{ "value": "ja", "weight": 0.0 }],
+                        "positions": [],
+                    },
+                ],
+            }))
+            .await;
+
+        let response = gateway.client().get("/").send().await;
+        let html = response.0.into_body().into_string().await.unwrap();
+        assert!(html.contains(r#"<div class="endpoint" id="hyphenation">"#));
+    }
+
+    /// A socket on `/tts/se/biret/stream` of a gateway serving `languages`,
+    /// and the gateway's server task.
+    #[cfg(feature = "websocket")]
+    async fn tts_stream(
+        languages: LanguagesConfig,
+    ) -> (
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        tokio::task::JoinHandle<std::io::Result<()>>,
+    ) {
+        use poem::listener::TcpAcceptor;
+
+        let gateway = ServerBuilder::new()
+            .languages(languages)
+            .health_checks(false)
+            .build()
+            .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let acceptor = TcpAcceptor::from_tokio(listener).unwrap();
+        let server = tokio::spawn(Server::new_with_acceptor(acceptor).run(gateway));
+
+        let url = format!("ws://127.0.0.1:{}/tts/se/biret/stream", port);
+        let (socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        (socket, server)
+    }
+
+    /// Synthesize `text` over `socket`, returning the audio and the status.
+    #[cfg(feature = "websocket")]
+    async fn synthesize_over(
+        socket: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        text: &str,
+    ) -> (Vec<u8>, serde_json::Value) {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        socket
+            .send(Message::text(json!({ "text": text }).to_string()))
+            .await
+            .unwrap();
+        let mut audio = Vec::new();
+        loop {
+            match socket.next().await.unwrap().unwrap() {
+                message if message.is_binary() => {
+                    audio.extend_from_slice(message.into_data().as_slice())
+                }
+                message => {
+                    let status = serde_json::from_str(message.to_text().unwrap()).unwrap();
+                    return (audio, status);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "websocket")]
+    #[tokio::test]
+    async fn tts_audio_streams_over_websockets() {
+        let backend = crate::testing::MockBackend::tts().await.unwrap();
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.tts.port = backend.port();
+        let (mut socket, server) = tts_stream(languages).await;
+
+        let (audio, status) = synthesize_over(&mut socket, "Bures").await;
+        assert_eq!(&audio[..4], b"RIFF");
+        assert_eq!(status, json!({ "done": true, "bytes": audio.len() }));
+        let forwarded = backend.requests();
+        assert_eq!(forwarded[0].query.as_deref(), Some("language=1&speaker=5"));
+        assert_eq!(forwarded[0].text().as_deref(), Some("Bures"));
+        server.abort();
+    }
+
+    #[cfg(feature = "websocket")]
+    #[tokio::test]
+    async fn tts_streams_share_the_voice_circuit_breaker() {
+        use crate::testing::{MockBackend, MockResponse};
+
+        let failing = MockBackend::start(|_| MockResponse {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            content_type: "text/plain",
+            body: b"boom".to_vec(),
+        })
+        .await
+        .unwrap();
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.tts.port = failing.port();
+        languages.config.circuit_breaker =
+            Some(toml::from_str("failures = 1\ncooldown = 60").unwrap());
+        let (mut socket, server) = tts_stream(languages).await;
+
+        let (_, status) = synthesize_over(&mut socket, "Bures").await;
+        assert_eq!(status["status"], 502);
+        assert_eq!(status["error"], "tts backend for se is unavailable");
+        let (_, status) = synthesize_over(&mut socket, "Bures").await;
+        assert_eq!(status["status"], 503);
+        assert_eq!(status["reason"], "circuit_open");
+        assert_eq!(failing.requests().len(), 1);
+        server.abort();
+    }
+
+    #[cfg(feature = "websocket")]
+    #[tokio::test]
+    async fn tts_streams_time_out() {
+        // A backend that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let stuck = tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.tts.port = port;
+        languages.config.timeouts.insert("tts".to_string(), 1);
+        let (mut socket, server) = tts_stream(languages).await;
+
+        let (audio, status) = synthesize_over(&mut socket, "Bures").await;
+        assert!(audio.is_empty());
+        assert_eq!(status["status"], 504);
+        assert_eq!(status["timeout"], 1);
+        server.abort();
+        stuck.abort();
+    }
+
+    #[tokio::test]
+    async fn plugins_are_proxied_and_documented() {
+        let gateway = shout_gateway(|_| {}).await;
+
+        let (response, forwarded) = gateway
+            .assert_proxied("shout", "se", "/shout/se", "hello")
+            .await;
+        let timing = response.0.header("Server-Timing").unwrap().to_string();
+        assert!(timing.starts_with("backend;dur="), "{}", timing);
+        assert!(timing.contains(", gateway;dur="), "{}", timing);
+        response.assert_text("SE:HELLO").await;
+        assert_eq!(forwarded.body, b"se:hello");
+
+        let response = gateway.client().get("/").send().await;
+        let html = response.0.into_body().into_string().await.unwrap();
+        assert!(html.contains(r##"<li><a href="#shout">Shouting</a></li>"##));
+        assert!(html.contains(r#"<a href="/shout/se"><code>se</code></a> - davvisámegiella"#));
+    }
+
+    #[tokio::test]
+    async fn requests_fail_over_while_the_backend_is_down() {
+        let fallback = crate::testing::MockBackend::echo().await.unwrap();
+        let source = format!(
+            "{}\n[shout.se]\nname = \"davvisámegiella\"\nport = 1\n\
+             fallback = {{ host = \"127.0.0.1\", port = {} }}\n",
+            crate::config::EMBEDDED_CONFIG,
+            fallback.port()
+        );
+        let mut languages = LanguagesConfig::from_toml(&source).unwrap();
+        languages.grammar.clear();
+        languages.speller.clear();
+        languages.hyphenation.clear();
+        languages.tts.clear();
+        let mut services = ServiceRegistry::builtin();
+        services.register(Shout);
+        let health = HealthMonitor::new(&languages, &services);
+        let client = TestClient::new(app(languages, services, health.clone()).unwrap());
+
+        // Not known to be down yet
+        let response = client.post("/shout/se").body("hello").send().await;
+        response.assert_status(StatusCode::BAD_GATEWAY);
+        assert!(fallback.requests().is_empty());
+
+        health.probe_all().await;
+        let response = client.post("/shout/se").body("hello").send().await;
+        response.assert_text("SE:HELLO").await;
+        assert_eq!(fallback.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_share_of_requests_is_mirrored() {
+        let shadow = crate::testing::MockBackend::echo().await.unwrap();
+        let gateway = shout_gateway(|languages| {
+            languages
+                .custom
+                .get_mut("shout")
+                .unwrap()
+                .get_mut("se")
+                .unwrap()
+                .shadow = Some(crate::config::Shadow {
+                host: "127.0.0.1".to_string(),
+                port: shadow.port(),
+                percent: 50.0,
+            });
+        })
+        .await;
+
+        for text in ["one", "two", "three", "four"] {
+            let response = gateway.client().post("/shout/se").body(text).send().await;
+            response
+                .assert_text(format!("SE:{}", text.to_uppercase()))
+                .await;
+        }
+        for _ in 0..100 {
+            if shadow.requests().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mirrored: Vec<_> = shadow.requests().into_iter().map(|r| r.body).collect();
+        assert_eq!(mirrored, [b"se:two".to_vec(), b"se:four".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn recorded_requests_replay_against_other_backends() {
+        let dir = std::env::temp_dir().join(format!("recordings-{}", std::process::id()));
+        let gateway = shout_gateway(|languages| {
+            languages
+                .custom
+                .get_mut("shout")
+                .unwrap()
+                .get_mut("se")
+                .unwrap()
+                .record = Some(dir.clone());
+        })
+        .await;
+        gateway
+            .client()
+            .post("/shout/se")
+            .body("hello")
+            .send()
+            .await
+            .assert_text("SE:HELLO")
+            .await;
+
+        let recordings = crate::recording::load(&dir.join("shout-se.jsonl")).unwrap();
+        assert_eq!(recordings.len(), 1);
+        assert_eq!(recordings[0].request, "se:hello");
+        assert_eq!(recordings[0].response.as_deref(), Some("se:hello"));
+
+        let client = reqwest::Client::new();
+        let same = format!(
+            "http://127.0.0.1:{}",
+            gateway.backend("shout", "se").unwrap().port()
+        );
+        let differences = crate::recording::replay(&client, &same, &recordings[0]).await;
+        assert!(differences.unwrap().is_empty());
+
+        let other = crate::testing::MockBackend::grammar().await.unwrap();
+        let other = format!("http://127.0.0.1:{}", other.port());
+        let differences = crate::recording::replay(&client, &other, &recordings[0]).await;
+        assert_eq!(differences.unwrap()[0].field, "body");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn latin1_bodies_are_transcoded() {
+        let gateway = shout_gateway(|_| {}).await;
+
+        let (response, forwarded) = gateway
+            .assert_proxied("shout", "se", "/shout/se", b"gi\xe1".to_vec())
+            .await;
+        response.assert_header("Warning", "214 - \"transcoded from windows-1252 to UTF-8\"");
+        response.assert_text("SE:GIÁ").await;
+        assert_eq!(forwarded.body, "se:giá".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn texts_over_the_limit_are_rejected() {
+        let gateway = shout_gateway(|languages| {
+            languages.config.limits.insert("shout".to_string(), 3);
+        })
+        .await;
+
+        let response = gateway
+            .client()
+            .post("/shout/se")
+            .body(r#"{"text":"hello"}"#)
+            .send()
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        response.assert_content_type("application/problem+json");
+        let json = response.json().await;
+        json.value().object().get("limit").assert_i64(3);
+        json.value().object().get("length").assert_i64(5);
+        assert!(gateway
+            .backend("shout", "se")
+            .unwrap()
+            .requests()
+            .is_empty());
+
+        let response = gateway.client().get("/languages").send().await;
+        let json = response.json().await;
+        json.value()
+            .object()
+            .get("limits")
+            .object()
+            .get("shout")
+            .assert_i64(3);
+    }
+
+    #[tokio::test]
+    async fn junk_characters_are_stripped_or_rejected() {
+        let gateway = shout_gateway(|languages| {
+            languages.config.sanitize = SanitizePolicy::Strip;
+        })
+        .await;
+        let (_, forwarded) = gateway
+            .assert_proxied("shout", "se", "/shout/se", "hel\u{200b}lo\u{1}")
+            .await;
+        assert_eq!(forwarded.body, b"se:hello");
+
+        let gateway = shout_gateway(|languages| {
+            languages.config.sanitize = SanitizePolicy::Reject;
+        })
+        .await;
+        let response = gateway
+            .client()
+            .post("/shout/se")
+            .body("hel\u{200b}lo")
+            .send()
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let json = response.json().await;
+        json.value().object().get("offset").assert_i64(3);
+        json.value()
+            .object()
+            .get("character")
+            .assert_string("U+200B");
+    }
+
+    #[tokio::test]
+    async fn line_endings_are_forwarded_as_lf() {
+        let gateway = shout_gateway(|_| {}).await;
+        let (_, forwarded) = gateway
+            .assert_proxied("shout", "se", "/shout/se", "a\r\nb\rc")
+            .await;
+        assert_eq!(forwarded.body, b"se:a\nb\nc");
+    }
+
+    #[tokio::test]
+    async fn apostrophes_are_normalized_before_forwarding() {
+        let gateway = shout_gateway(|languages| {
+            languages
+                .custom
+                .get_mut("shout")
+                .unwrap()
+                .get_mut("se")
+                .unwrap()
+                .apostrophe = Some('ʹ');
+        })
+        .await;
+        let (_, forwarded) = gateway
+            .assert_proxied("shout", "se", "/shout/se", "sää'mǩiõll")
+            .await;
+        assert_eq!(forwarded.body, "se:sääʹmǩiõll".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn mixed_texts_are_routed_by_language() {
+        let gateway = TestGateway::start(
+            LanguagesConfig::embedded().unwrap(),
+            ServiceRegistry::builtin(),
+        )
+        .await
+        .unwrap();
+
+        let text = "Mun lean čállán dán girjji.\nDet er en bok om språk.\n";
+        let response = gateway
+            .client()
+            .post("/grammar/mixed")
+            .body_json(&json!({ "text": text, "languages": ["se", "nb"] }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let segments = json.value().object().get("segments").array();
+        segments.get(0).object().get("language").assert_string("se");
+        segments.get(1).object().get("language").assert_string("nb");
+        segments.get(1).object().get("start").assert_i64(28);
+
+        let se = gateway.backend("grammar", "se").unwrap().requests();
+        let nb = gateway.backend("grammar", "nb").unwrap().requests();
+        assert_eq!(
+            se[0].text().as_deref(),
+            Some("Mun lean čállán dán girjji.\n")
+        );
+        assert_eq!(nb[0].text().as_deref(), Some("Det er en bok om språk.\n"));
+    }
+
+    #[tokio::test]
+    async fn long_grammar_checks_stream_per_paragraph() {
+        use crate::testing::MockResponse;
+
+        let languages = LanguagesConfig::embedded().unwrap();
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", |request| {
+            MockResponse::json(json!({
+                "text": request.text().unwrap_or_default(),
+                "errs": [{ "start_index": 0, "end_index": 3 }],
+            }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+        let backend = gateway.backend("grammar", "se").unwrap();
+
+        let response = client
+            .post("/grammar/se/stream")
+            .body_json(&json!({ "text": "Mun lean.\n\n  Dát lea.\n" }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.assert_content_type("text/event-stream");
+        let body = response.0.into_body().into_string().await.unwrap();
+        let events: Vec<&str> = body.split("\n\n").filter(|e| !e.is_empty()).collect();
+        assert_eq!(
+            events,
+            [
+                "event: result\ndata: {\"end\":9,\"errs\":[{\"end_index\":3,\"start_index\":0}],\"start\":0}",
+                "event: result\ndata: {\"end\":21,\"errs\":[{\"end_index\":16,\"start_index\":13}],\"start\":13}",
+                "event: done\ndata: {}",
+            ]
+        );
+        let texts: Vec<_> = backend.requests().iter().map(|r| r.text()).collect();
+        assert_eq!(
+            texts,
+            [Some("Mun lean.".to_string()), Some("Dát lea.".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn languagetool_clients_get_matches() {
+        use crate::testing::MockResponse;
+
+        let languages = LanguagesConfig::embedded().unwrap();
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", |request| {
+            MockResponse::json(json!({
+                "text": request.text().unwrap_or_default(),
+                "errs": [{
+                    "error_text": "Mun",
+                    "start_index": 2,
+                    "end_index": 5,
+                    "error_code": "msyn-agr",
+                    "description": "Subject and verb don't agree",
+                    "suggestions": ["Mii"],
+                    "title": "Agreement",
+                }],
+            }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+        let backend = gateway.backend("grammar", "se").unwrap();
+
+        let response = client
+            .post("/v2/check")
+            .form(&[("language", "se-NO"), ("text", "😀 Mun lean.")])
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let json = json.value().object();
+        json.get("language")
+            .object()
+            .get("code")
+            .assert_string("se");
+        let found = json.get("matches").array().get(0).object();
+        found.get("offset").assert_i64(3);
+        found.get("length").assert_i64(3);
+        found
+            .get("message")
+            .assert_string("Subject and verb don't agree");
+        found
+            .get("replacements")
+            .array()
+            .get(0)
+            .object()
+            .get("value")
+            .assert_string("Mii");
+        found
+            .get("rule")
+            .object()
+            .get("id")
+            .assert_string("msyn-agr");
+        assert_eq!(
+            backend.requests()[0].text().as_deref(),
+            Some("😀 Mun lean.")
+        );
+
+        let response = client.get("/v2/languages").send().await;
+        let json = response.json().await;
+        let codes: Vec<_> = json
+            .value()
+            .array()
+            .iter()
+            .map(|language| language.object().get("code").string().to_string())
+            .collect();
+        assert!(codes.contains(&"se".to_string()));
+    }
+
+    #[tokio::test]
+    async fn detection_ranks_the_configured_languages() {
+        let response = client()
+            .post("/detect")
+            .body_json(&json!({ "text": "Mun lean čállán dán girjji, ja dat lea buorre." }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let candidates = json.value().object().get("candidates").array();
+        let best = candidates.get(0).object();
+        best.get("tag").assert_string("se");
+        best.get("name").assert_string("davvisámegiella");
+        assert!(best.get("score").f64() > candidates.get(1).object().get("score").f64());
+
+        let response = client()
+            .post("/detect")
+            .body_json(&json!({ "text": "Det er en bok.", "languages": ["nb", "xx"] }))
+            .send()
+            .await;
+        let json = response.json().await;
+        let candidates = json.value().object().get("candidates").array();
+        candidates.assert_len(1);
+        candidates.get(0).object().get("tag").assert_string("nb");
+    }
+
+    #[tokio::test]
+    async fn combined_checks_merge_spelling_and_grammar() {
+        use crate::testing::{MockBackend, MockResponse, TestGateway};
+
+        let speller = MockBackend::start(|request| {
+            let text = request.text().unwrap_or_default();
+            let results: Vec<_> = text
+                .split_whitespace()
+                .map(
+                    |word| json!({ "word": word, "is_correct": word != "lean", "suggestions": [] }),
+                )
+                .collect();
+            MockResponse::json(json!({ "text": text, "results": results }))
+        })
+        .await
+        .unwrap();
+        let grammar = MockBackend::start(|request| {
+            MockResponse::json(json!({
+                "text": request.text().unwrap_or_default(),
+                "errs": [{ "error_code": "msyn-agr", "start_index": 0, "end_index": 3 }],
+            }))
+        })
+        .await
+        .unwrap();
+        let gateway = TestGateway::with_backends(
+            LanguagesConfig::embedded().unwrap(),
+            [("speller", "se", speller), ("grammar", "se", grammar)],
+        )
+        .unwrap();
+        let client = gateway.client();
+        let speller = gateway.backend("speller", "se").unwrap();
+
+        let response = client
+            .post("/check/se")
+            .body_json(&json!({ "text": " Mun lean lean." }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response
+            .assert_json(json!({
+                "text": " Mun lean lean.",
+                "findings": [
+                    { "source": "grammar", "error_code": "msyn-agr", "start_index": 1, "end_index": 4 },
+                    { "source": "speller", "word": "lean", "is_correct": false, "suggestions": [], "start_index": 5, "end_index": 9 },
+                ],
+            }))
+            .await;
+        assert_eq!(
+            speller.requests()[0].text().as_deref(),
+            Some("Mun lean lean.")
+        );
+
+        let response = client
+            .post("/check/xx")
+            .body_json(&json!({ "text": "Bures" }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn aliased_tags_reach_the_same_backend() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().aliases = vec!["sme".to_string()];
+        let gateway = TestGateway::start(languages, ServiceRegistry::builtin())
+            .await
+            .unwrap();
+        let client = gateway.client();
+
+        for tag in ["se", "sme"] {
+            client
+                .post(format!("/grammar/{}", tag))
+                .body_json(&json!({ "text": "Mun leat" }))
+                .send()
+                .await
+                .assert_status_is_ok();
+        }
+        assert_eq!(
+            gateway.backend("grammar", "se").unwrap().requests().len(),
+            2
+        );
+        client
+            .post("/speller/sme")
+            .body_json(&json!({ "text": "Mun leat" }))
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        let response = client.get("/languages").send().await;
+        let json = response.json().await;
+        let aliases: serde_json::Value = json.value().object().get("aliases").deserialize();
+        assert_eq!(aliases, json!({ "grammar": { "sme": "se" } }));
+    }
+
+    #[tokio::test]
+    async fn long_tts_texts_are_synthesized_in_chunks() {
+        use crate::testing::{wav, MockResponse};
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.tts.chunk_length = Some(20);
+        let gateway = TestGateway::with_backend(languages, "tts", "se", |request| MockResponse {
+            status: StatusCode::OK,
+            content_type: "audio/wav",
+            body: wav(&[request.text().unwrap_or_default().len() as u8]),
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+        let backend = gateway.backend("tts", "se").unwrap();
+
+        let response = client
+            .post("/tts/se/biret")
+            .body_json(&json!({ "text": "Bures boahtin! Mun lean Biret. Giitu." }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.assert_content_type("audio/wav");
+        response.assert_bytes(wav(&[14, 15, 6])).await;
+        let texts: Vec<_> = backend
+            .requests()
+            .iter()
+            .map(|request| request.text().unwrap())
+            .collect();
+        assert_eq!(texts, ["Bures boahtin!", "Mun lean Biret.", "Giitu."]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tts_audio_is_transcoded_on_request() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::testing::{wav, MockResponse};
+
+        let dir = std::env::temp_dir().join(format!("dws-ffmpeg-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ffmpeg = dir.join("ffmpeg");
+        std::fs::write(&ffmpeg, "#!/bin/sh\nprintf 'ENC:'\ncat\n").unwrap();
+        std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.tts.ffmpeg = Some(ffmpeg);
+        let gateway = TestGateway::with_backend(languages, "tts", "se", |_| MockResponse {
+            status: StatusCode::OK,
+            content_type: "audio/wav",
+            body: wav(&[1, 2]),
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+        let backend = gateway.backend("tts", "se").unwrap();
+
+        let response = client
+            .post("/tts/se/biret")
+            .query("format", &"mp3")
+            .header("accept", "audio/mpeg")
+            .body_json(&json!({ "text": "Bures" }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.assert_content_type("audio/mpeg");
+        response
+            .assert_bytes([b"ENC:".as_slice(), &wav(&[1, 2])].concat())
+            .await;
+        assert_eq!(backend.requests()[0].headers["accept"], "audio/wav");
+
+        let response = client
+            .post("/tts/se/biret")
+            .body_json(&json!({ "text": "Bures", "format": "aiff" }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn ssml_reaches_only_backends_that_support_it() {
+        use crate::testing::{wav, MockResponse};
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.tts.get_mut("se").unwrap().ssml = true;
+        let gateway = TestGateway::with_backend(languages, "tts", "se", |_| MockResponse {
+            status: StatusCode::OK,
+            content_type: "audio/wav",
+            body: wav(&[1]),
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+        let backend = gateway.backend("tts", "se").unwrap();
+
+        let ssml = r#"<speak>Bures<break time="1s"/><audio src="x">boahtin</audio></speak>"#;
+        for path in ["/tts/se/biret", "/tts/sma/aanna"] {
+            client
+                .post(path)
+                .body_json(&json!({ "ssml": ssml }))
+                .send()
+                .await
+                .assert_status_is_ok();
+        }
+        let bodies: Vec<serde_json::Value> = backend
+            .requests()
+            .iter()
+            .map(|request| serde_json::from_slice(&request.body).unwrap())
+            .collect();
+        assert_eq!(
+            bodies,
+            [
+                json!({ "ssml": "<speak>Bures<break time=\"1s\"/>boahtin</speak>" }),
+                json!({ "text": "Bures boahtin" }),
+            ]
+        );
+
+        let response = client
+            .post("/tts/se/biret")
+            .body_json(&json!({ "ssml": "<speak><prosody>Bures</speak>" }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn voices_are_listed_per_language() {
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .languages(LanguagesConfig::embedded().unwrap())
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+
+        let response = client.get("/tts/smj/voices").send().await;
+        response.assert_status_is_ok();
+        let json: serde_json::Value = response.json().await.value().deserialize();
+        assert_eq!(json["name"], "julevsámegiella");
+        let ids: Vec<_> = json["voices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|voice| voice["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["abmut", "nihkol", "sigga"]);
+        assert_eq!(
+            json["voices"][2],
+            json!({
+                "id": "sigga",
+                "name": "Siggá",
+                "gender": "female",
+                "model": "multi-sami",
+                "speaker": 3,
+                "language": 2,
+                "path": "/tts/smj/sigga",
+                "formats": ["wav", "mp3", "ogg", "opus", "flac"],
+                "ssml": false,
+            })
+        );
+
+        client
+            .get("/tts/fi/voices")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn backend_connections_are_kept_alive() {
+        use crate::testing::MockBackend;
+
+        let backend = MockBackend::grammar().await.unwrap();
+        let connections = |max_idle: usize| {
+            let port = backend.port();
+            async move {
+                let mut languages = LanguagesConfig::embedded().unwrap();
+                languages.grammar.get_mut("se").unwrap().port = port;
+                languages.config.pool.max_idle = max_idle;
+                let client = TestClient::new(
+                    ServerBuilder::new()
+                        .languages(languages)
+                        .health_checks(false)
+                        .build()
+                        .unwrap(),
+                );
+                for _ in 0..3 {
+                    client
+                        .post("/grammar/se")
+                        .body_json(&json!({ "text": "Bures" }))
+                        .send()
+                        .await
+                        .assert_status_is_ok();
+                }
+            }
+        };
+
+        connections(32).await;
+        connections(0).await;
+        let addrs: Vec<_> = backend
+            .requests()
+            .into_iter()
+            .map(|request| request.remote_addr)
+            .collect();
+        assert!(addrs[..3].iter().all(|addr| *addr == addrs[0]));
+        assert_ne!(addrs[3], addrs[4]);
+        assert_ne!(addrs[4], addrs[5]);
+    }
+
+    #[tokio::test]
+    async fn failed_backend_requests_are_retried() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::testing::MockResponse;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.retry = Some(toml::from_str("retries = 2\nbackoff_ms = 1\n").unwrap());
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", move |request| {
+            match counted.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => MockResponse {
+                    status: StatusCode::SERVICE_UNAVAILABLE,
+                    content_type: "text/plain",
+                    body: b"starting".to_vec(),
+                },
+                _ => MockResponse::json(json!({ "text": request.text(), "errs": [] })),
+            }
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+
+        client
+            .post("/grammar/se")
+            .body_json(&json!({ "text": "Bures" }))
+            .send()
+            .await
+            .assert_status_is_ok();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn location_limits_cover_every_route_to_the_backend() {
+        use crate::testing::{MockResponse, TestGateway};
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages
+            .grammar
+            .get_mut("se")
+            .unwrap()
+            .limits
+            .requests_per_minute = Some(2);
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", |request| {
+            MockResponse::json(json!({ "text": request.text(), "errs": [] }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+
+        client
+            .post("/grammar/se")
+            .body_json(&json!({ "text": "Bures" }))
+            .send()
+            .await
+            .assert_status_is_ok();
+        client
+            .post("/grammar/mixed")
+            .body_json(&json!({ "text": "Mun lean čállán dán girjji.", "languages": ["se"] }))
+            .send()
+            .await
+            .assert_status_is_ok();
+        let response = client
+            .post("/v2/check")
+            .content_type("application/x-www-form-urlencoded")
+            .body("language=se&text=Bures")
+            .send()
+            .await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.0.headers().contains_key("Retry-After"));
+        assert_eq!(
+            gateway.backend("grammar", "se").unwrap().requests().len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_go_to_another_instance() {
+        use crate::balance::Balance;
+        use crate::config::Instance;
+        use crate::testing::{MockBackend, MockResponse};
+
+        let failing = MockBackend::start(|_| MockResponse {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            content_type: "text/plain",
+            body: b"starting".to_vec(),
+        })
+        .await
+        .unwrap();
+        let healthy = MockBackend::grammar().await.unwrap();
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        let se = languages.grammar.get_mut("se").unwrap();
+        se.ports = vec![
+            Instance::local(failing.port()),
+            Instance::local(healthy.port()),
+        ];
+        se.balance = Balance::RoundRobin;
+        languages.config.retry = Some(toml::from_str("retries = 1\nbackoff_ms = 1\n").unwrap());
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .languages(languages)
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+
+        for _ in 0..2 {
+            client
+                .post("/grammar/se")
+                .body_json(&json!({ "text": "Bures" }))
+                .send()
+                .await
+                .assert_status_is_ok();
+        }
+        // Each request's turn came to the failing instance first
+        assert_eq!(failing.requests().len(), 2);
+        assert_eq!(healthy.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn requests_are_spread_over_healthy_instances() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::balance::Balance;
+        use crate::config::Instance;
+        use crate::testing::{MockBackend, MockResponse};
+
+        let counter = |status: StatusCode| {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let counted = calls.clone();
+            let handler = move |request: &crate::testing::MockRequest| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                MockResponse {
+                    status,
+                    ..MockResponse::json(json!({ "text": request.text(), "errs": [] }))
+                }
+            };
+            (calls, handler)
+        };
+        let (healthy_calls, healthy) = counter(StatusCode::OK);
+        let (failing_calls, failing) = counter(StatusCode::SERVICE_UNAVAILABLE);
+        let healthy = MockBackend::start(healthy).await.unwrap();
+        let failing = MockBackend::start(failing).await.unwrap();
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        let se = languages.grammar.get_mut("se").unwrap();
+        se.ports = vec![
+            Instance::local(failing.port()),
+            Instance::local(healthy.port()),
+        ];
+        se.balance = Balance::RoundRobin;
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .languages(languages)
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+
+        for _ in 0..8 {
+            client
+                .post("/grammar/se")
+                .body_json(&json!({ "text": "Bures" }))
+                .send()
+                .await;
+        }
+        // Three failures in a row take the failing instance out of rotation
+        assert_eq!(failing_calls.load(Ordering::SeqCst), 3);
+        assert_eq!(healthy_calls.load(Ordering::SeqCst), 5);
+
+        // The gateway's own handlers see the same rotation
+        let text = "Mun lean čállán dán girjji.";
+        for path in ["/grammar/mixed", "/grammar/se/stream"] {
+            let response = client
+                .post(path)
+                .body_json(&json!({ "text": text, "languages": ["se"] }))
+                .send()
+                .await;
+            response.assert_status_is_ok();
+            let body = response.0.into_body().into_string().await.unwrap();
+            assert!(!body.contains("unavailable"), "{}", body);
+        }
+        assert_eq!(failing_calls.load(Ordering::SeqCst), 3);
+        assert_eq!(healthy_calls.load(Ordering::SeqCst), 7);
+    }
+
+    #[tokio::test]
+    async fn grammar_texts_over_the_limit_are_rejected_on_every_route() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.limits.insert("grammar".to_string(), 10);
+        let gateway = TestGateway::start(languages, ServiceRegistry::builtin())
+            .await
+            .unwrap();
+        let client = gateway.client();
+
+        let text = "Mun lean čállán dán girjji.";
+        let responses = [
+            client
+                .post("/grammar/mixed")
+                .body_json(&json!({ "text": text, "languages": ["se"] }))
+                .send()
+                .await,
+            client
+                .post("/v2/check")
+                .form(&[("language", "se"), ("text", text)])
+                .send()
+                .await,
+        ];
+        for response in responses {
+            response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+            let json = response.json().await;
+            json.value().object().get("limit").assert_i64(10);
+            json.value().object().get("length").assert_i64(27);
+        }
+        assert!(gateway
+            .backend("grammar", "se")
+            .unwrap()
+            .requests()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn circuit_breakers_turn_requests_for_dead_backends_away() {
+        // A port nothing listens on
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().port = port;
+        languages.config.circuit_breaker =
+            Some(toml::from_str("failures = 2\ncooldown = 60\n").unwrap());
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .languages(languages)
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+        let check = || {
+            client
+                .post("/grammar/se")
+                .body_json(&json!({ "text": "Bures" }))
+                .send()
+        };
+
+        check().await.assert_status(StatusCode::BAD_GATEWAY);
+        check().await.assert_status(StatusCode::BAD_GATEWAY);
+        let response = check().await;
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        response.assert_header("Retry-After", "60");
+        let json: serde_json::Value = response.json().await.value().deserialize();
+        assert_eq!(json["reason"], "circuit_open");
+        assert_eq!(json["retry_after"], 60);
+
+        let response = client
+            .post("/grammar/mixed")
+            .body_json(&json!({ "text": "Mun lean čállán dán girjji.", "languages": ["se"] }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        response.assert_header("Retry-After", "60");
+    }
+
+    #[tokio::test]
+    async fn stuck_backends_time_out() {
+        // A backend that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let stuck = tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().port = port;
+        languages.config.timeouts.insert("grammar".to_string(), 1);
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .languages(languages)
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+
+        let response = client
+            .post("/grammar/se")
+            .body_json(&json!({ "text": "Bures" }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::GATEWAY_TIMEOUT);
+        response.assert_content_type("application/problem+json");
+        let json: serde_json::Value = response.json().await.value().deserialize();
+        assert_eq!(json["timeout"], 1);
+
+        let text = "Mun lean čállán dán girjji.";
+        let response = client
+            .post("/grammar/mixed")
+            .body_json(&json!({ "text": text, "languages": ["se"] }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::GATEWAY_TIMEOUT);
+        let response = client
+            .post("/grammar/se/stream")
+            .body_json(&json!({ "text": text }))
+            .send()
+            .await;
+        let events = response.0.into_body().into_string().await.unwrap();
+        assert!(events.contains("event: error"), "{}", events);
+        assert!(events.contains("\"status\":504"), "{}", events);
+        stuck.abort();
+    }
+
+    #[tokio::test]
+    async fn speller_batches_answer_in_order() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.max_batch = 20;
+        let gateway = TestGateway::start(languages, ServiceRegistry::builtin())
+            .await
+            .unwrap();
+
+        let texts: Vec<String> = (0..20).map(|i| format!("sátni{}", i)).collect();
+        let response = gateway
+            .client()
+            .post("/speller/se/batch")
+            .body_json(&json!({ "texts": texts }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let results = json.value().array();
+        results.assert_len(20);
+        for (i, text) in texts.iter().enumerate() {
+            results.get(i).object().get("text").assert_string(text);
+        }
+        assert_eq!(
+            gateway.backend("speller", "se").unwrap().requests().len(),
+            20
+        );
+
+        let response = gateway
+            .client()
+            .post("/speller/xx/batch")
+            .body_json(&json!({ "texts": ["a"] }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        let texts = vec!["sátni"; 21];
+        let response = gateway
+            .client()
+            .post("/speller/se/batch")
+            .body_json(&json!({ "texts": texts }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        let json = response.json().await;
+        json.value().object().get("limit").assert_i64(20);
+        json.value().object().get("length").assert_i64(21);
+        assert_eq!(
+            gateway.backend("speller", "se").unwrap().requests().len(),
+            20
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_texts_are_answered_from_the_response_cache() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.response_cache.insert(
+            "speller".to_string(),
+            ResponseCacheConfig {
+                ttl: 60,
+                max_entries: 10,
+            },
+        );
+        let gateway = TestGateway::start(languages, ServiceRegistry::builtin())
+            .await
+            .unwrap();
+
+        let (first, _) = gateway
+            .assert_proxied("speller", "se", "/speller/se", r#"{"text":"Bures"}"#)
+            .await;
+        first.assert_header("X-Cache", "MISS");
+        let answer = first.0.into_body().into_string().await.unwrap();
+
+        let second = gateway
+            .client()
+            .post("/speller/se")
+            .body(r#"{"text":"Bures"}"#)
+            .send()
+            .await;
+        second.assert_status_is_ok();
+        second.assert_header("X-Cache", "HIT");
+        second.assert_text(answer).await;
+        assert_eq!(
+            gateway.backend("speller", "se").unwrap().requests().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn request_ids_reach_the_backend() {
+        let gateway = TestGateway::start(
+            LanguagesConfig::embedded().unwrap(),
+            ServiceRegistry::builtin(),
+        )
+        .await
+        .unwrap();
+
+        let response = gateway
+            .client()
+            .post("/speller/se")
+            .header("X-Request-Id", "editor-42")
+            .body(r#"{"text":"Bures"}"#)
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.assert_header("X-Request-Id", "editor-42");
+        let forwarded = gateway.backend("speller", "se").unwrap().requests();
+        assert_eq!(forwarded[0].headers["X-Request-Id"], "editor-42");
+    }
+
+    #[tokio::test]
+    async fn demo_for_unknown_tag_is_not_found() {
+        let response = client().get("/demo/xx").send().await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn large_text_responses_are_gzipped() {
+        let response = client()
+            .get("/")
+            .header("Accept-Encoding", "br, gzip")
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.assert_header("Content-Encoding", "gzip");
+        let vary: Vec<_> = response.0.headers().get_all("Vary").iter().collect();
+        assert_eq!(vary, ["Accept-Language", "Accept-Encoding"]);
+        assert!(response.0.headers()["ETag"]
+            .to_str()
+            .unwrap()
+            .starts_with("W/\""));
+        let gzipped = response.0.into_body().into_vec().await.unwrap();
+        let html =
+            miniz_oxide::inflate::decompress_to_vec(&gzipped[10..gzipped.len() - 8]).unwrap();
+        assert!(String::from_utf8(html)
+            .unwrap()
+            .starts_with("<!DOCTYPE html>"));
+
+        let response = client()
+            .get("/health")
+            .header("Accept-Encoding", "gzip")
+            .send()
+            .await;
+        response.assert_header_is_not_exist("Content-Encoding");
+        let response = client().get("/").send().await;
+        response.assert_header_is_not_exist("Content-Encoding");
+    }
+
+    #[tokio::test]
+    async fn every_error_is_a_problem() {
+        let response = client().get("/nowhere").send().await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        response.assert_content_type("application/problem+json");
+        response
+            .assert_json(json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "instance": "/nowhere",
+            }))
+            .await;
+
+        let response = client().get("/grammar/se").send().await;
+        response.assert_status(StatusCode::METHOD_NOT_ALLOWED);
+        response.assert_content_type("application/problem+json");
+
+        let response = client()
+            .post("/grammar/se")
+            .content_type("application/json")
+            .body("{")
+            .send()
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let json = response.json().await;
+        json.value()
+            .object()
+            .get("instance")
+            .assert_string("/grammar/se");
+        json.value().object().get("status").assert_i64(400);
+    }
+
+    #[tokio::test]
+    async fn malformed_bodies_are_rejected_per_field() {
+        let location = Location {
+            tag: "se".to_string(),
+            path: "/grammar/se".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 1,
+            instances: Vec::new(),
+            balance: Default::default(),
+            query: Vec::new(),
+            limits: Default::default(),
+            fallback: None,
+            shadow: None,
+        };
+        let endpoint = ProxyEndpoint::new(
+            Arc::new(crate::services::Grammar),
+            location,
+            reqwest::Client::new(),
+        );
+        let client = TestClient::new(endpoint);
+
+        let response = client.post("/").body(r#"{"text":5}"#).send().await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        response.assert_content_type("application/problem+json");
+        let json = response.json().await;
+        json.value()
+            .object()
+            .get("detail")
+            .assert_string("text: expected string, got number");
+        let errors = json.value().object().get("errors").array();
+        errors.get(0).object().get("field").assert_string("text");
+
+        let response = client.post("/").body("Bures").send().await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        response
+            .json()
+            .await
+            .value()
+            .object()
+            .get("errors")
+            .array()
+            .get(0)
+            .object()
+            .get("field")
+            .assert_string("body");
+    }
+
+    #[test]
+    fn dry_run_lists_routes_and_checks_the_address() {
+        let entries = ServerBuilder::new().bind("127.0.0.1", 0).dry_run().unwrap();
+        let entry = |path: &str| entries.iter().find(|e| e.path == path).unwrap();
+        assert_eq!(entry("/health").target, "gateway");
+        assert_eq!(entry("/grammar/mixed").method, "POST");
+        assert_eq!(entry("/grammar/se").target, "http://127.0.0.1:10000/");
+        assert!(route_listing(&entries).starts_with("METHOD  PATH"));
+
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let err = ServerBuilder::new()
+            .bind("127.0.0.1", port)
+            .dry_run()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("can't listen on"));
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages
+            .custom
+            .insert("grammer".to_string(), HashMap::new());
+        let err = ServerBuilder::new()
+            .languages(languages)
+            .health_checks(false)
+            .bind("127.0.0.1", 0)
+            .dry_run()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no service handles [grammer] in languages.toml"
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn dry_run_checks_the_certificate() {
+        let dir = std::env::temp_dir().join(format!("dws-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        std::fs::write(&cert, "not a certificate").unwrap();
+        let err = ServerBuilder::new()
+            .bind("127.0.0.1", 0)
+            .tls(TlsConfig::Files {
+                cert: cert.clone(),
+                key: dir.join("missing.pem"),
+            })
+            .dry_run()
+            .unwrap_err();
+        assert!(err.to_string().ends_with("cert.pem is not a PEM file"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn preflight_refuses_to_serve_without_backends() {
+        let err = ServerBuilder::new()
+            .languages(LanguagesConfig::embedded().unwrap())
+            .health_checks(false)
+            .bind("127.0.0.1", 0)
+            .preflight(true)
+            .serve_until(std::future::pending())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().ends_with("backends are unreachable"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_on_a_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = std::env::temp_dir().join(format!("dws-serve-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gateway.sock");
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(
+            ServerBuilder::new()
+                .health_checks(false)
+                .unix_socket(UnixSocket::new(&path).mode(0o600))
+                .serve_until(async {
+                    let _ = stopped.await;
+                }),
+        );
+
+        let mut stream = None;
+        for _ in 0..50 {
+            if let Ok(connected) = tokio::net::UnixStream::connect(&path).await {
+                stream = Some(connected);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut stream = stream.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serve_until_stops_on_shutdown() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(
+            ServerBuilder::new()
+                .health_checks(false)
+                .bind("127.0.0.1", port)
+                .serve_until(async {
+                    let _ = stopped.await;
+                }),
+        );
+
+        let url = format!("http://127.0.0.1:{}/health", port);
+        let mut up = false;
+        for _ in 0..50 {
+            if reqwest::get(&url).await.is_ok() {
+                up = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(up);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(reqwest::get(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn http2_clients_share_one_connection() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(
+            ServerBuilder::new()
+                .health_checks(false)
+                .bind("127.0.0.1", port)
+                .http2_max_streams(Some(8))
+                .serve_until(async {
+                    let _ = stopped.await;
+                }),
+        );
+
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+        let url = format!("http://127.0.0.1:{}/health", port);
+        let mut up = false;
+        for _ in 0..50 {
+            if client.get(&url).send().await.is_ok() {
+                up = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(up);
+        let responses =
+            futures_util::future::join_all((0..20).map(|_| client.get(&url).send())).await;
+        for response in responses {
+            let response = response.unwrap();
+            assert_eq!(response.version(), reqwest::Version::HTTP_2);
+            assert_eq!(response.status(), 200);
+        }
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn admin_reload_swaps_the_configuration() {
+        let config = |grammar: &str| {
+            format!(
+                "[config.tts]\nport = 40001\n\n[config.admin]\ntoken = \"secret\"\n\n\
+                 {}\n[speller]\n[hyphenation]\n[tts]\n",
+                grammar
+            )
+        };
+        let path = std::env::temp_dir().join(format!("reload-{}.toml", std::process::id()));
+        std::fs::write(&path, config("[grammar.se]\nname = \"se\"\nport = 10000\n")).unwrap();
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .config_file(&path)
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+
+        std::fs::write(
+            &path,
+            config("[grammar.se]\nname = \"se\"\nport = 10010\n[grammar.sma]\nname = \"sma\"\nport = 10006\n"),
+        )
+        .unwrap();
+        let response = client.post("/admin/reload").send().await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .post("/admin/reload")
+            .header("Authorization", "Bearer secret")
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response
+            .assert_json(json!({
+                "added": ["/grammar/sma"],
+                "removed": [],
+                "changed": ["/grammar/se"],
+            }))
+            .await;
+
+        let response = client.get("/languages").send().await;
+        let json = response.json().await;
+        json.value()
+            .object()
+            .get("available")
+            .object()
+            .get("grammar")
+            .object()
+            .get("sma")
+            .assert_string("sma");
+
+        std::fs::write(&path, "not toml").unwrap();
+        let response = client
+            .post("/admin/reload")
+            .header("Authorization", "Bearer secret")
+            .send()
+            .await;
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        client.get("/health").send().await.assert_status_is_ok();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn admins_can_disable_languages() {
+        use crate::testing::{MockResponse, TestGateway};
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().aliases = vec!["sme".to_string()];
+        languages.config.admin = Some(toml::from_str("token = \"secret\"").unwrap());
+        languages.config.circuit_breaker = Some(toml::from_str("failures = 5").unwrap());
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", |request| {
+            MockResponse::json(json!({ "text": request.text(), "errs": [] }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+        let check = || {
+            client
+                .post("/grammar/se")
+                .body_json(&json!({ "text": "Bures" }))
+                .send()
+        };
+        let admin = |path: &str| {
+            client
+                .post(path)
+                .header("Authorization", "Bearer secret")
+                .send()
+        };
+
+        client
+            .get("/admin/services")
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        let response = admin("/admin/services/grammar/sme/disable?seconds=60").await;
+        response.assert_status_is_ok();
+        response
+            .assert_json(json!({ "service": "grammar", "tag": "se", "remaining": 60 }))
+            .await;
+
+        let response = check().await;
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        response.assert_header("Retry-After", "60");
+        let json = response.json().await;
+        json.value()
+            .object()
+            .get("reason")
+            .assert_string("disabled");
+
+        let response = client
+            .get("/admin/services")
+            .header("Authorization", "Bearer secret")
+            .send()
+            .await;
+        let json: serde_json::Value =
+            serde_json::from_str(&response.0.into_body().into_string().await.unwrap()).unwrap();
+        let grammar_se = json["services"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|service| service["path"] == "/grammar/se")
+            .unwrap();
+        assert_eq!(grammar_se["enabled"], false);
+        assert_eq!(grammar_se["disabled_for"], 60);
+
+        let response = client
+            .get("/admin/backends")
+            .header("Authorization", "Bearer secret")
+            .send()
+            .await;
+        let json = response.json().await;
+        json.value().object().get("disabled").array().assert_len(1);
+        json.value().object().get("circuits").array().assert_len(0);
+
+        admin("/admin/services/grammar/se/enable")
+            .await
+            .assert_status_is_ok();
+        check().await.assert_status_is_ok();
+        admin("/admin/services/grammar/xx/disable")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn grammar_findings_are_filtered_by_tag() {
+        use crate::testing::MockResponse;
+
+        let languages = LanguagesConfig::embedded().unwrap();
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", |request| {
+            assert_eq!(request.body, br#"{"text":"sami"}"#);
+            let err = |code: &str| {
+                json!({
+                    "error_text": "sami",
+                    "start_index": 0,
+                    "end_index": 4,
+                    "error_code": code,
+                    "description": "",
+                    "suggestions": ["sámi"],
+                    "title": "",
+                })
+            };
+            MockResponse::json(json!({
+                "text": "sami",
+                "errs": [err("typo"), err("punct-space")],
+            }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+
+        let response = client
+            .post("/grammar/se")
+            .body_json(&json!({ "text": "sami", "ignore_tags": ["punct"] }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let errs = json.value().object().get("errs").array();
+        errs.assert_len(1);
+        errs.get(0).object().get("error_code").assert_string("typo");
+    }
+
+    #[tokio::test]
+    async fn plain_and_form_bodies_are_sent_as_json() {
+        use crate::testing::MockResponse;
+
+        let languages = LanguagesConfig::embedded().unwrap();
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", |request| {
+            assert_eq!(request.headers["content-type"], "application/json");
+            MockResponse::json(json!({ "text": request.text(), "errs": [] }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+
+        for (content_type, body) in [
+            ("text/plain", "sámi \"giella\""),
+            (
+                "application/x-www-form-urlencoded",
+                "text=s%C3%A1mi+%22giella%22",
+            ),
+        ] {
+            let response = client
+                .post("/grammar/se")
+                .content_type(content_type)
+                .body(body)
+                .send()
+                .await;
+            response.assert_status_is_ok();
+            let json = response.json().await;
+            json.value()
+                .object()
+                .get("text")
+                .assert_string("sámi \"giella\"");
+        }
+
+        let response = client
+            .post("/grammar/se")
+            .content_type("application/x-www-form-urlencoded")
+            .body("lang=se")
+            .send()
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn html_errors_point_into_the_document() {
+        use crate::testing::MockResponse;
+
+        let languages = LanguagesConfig::embedded().unwrap();
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", |request| {
+            let text = request.text().unwrap();
+            assert_eq!(text, "Mun lean sami & dat\n");
+            let start = text.find("sami").unwrap();
+            MockResponse::json(json!({
+                "text": text,
+                "errs": [{
+                    "error_text": "sami",
+                    "start_index": start,
+                    "end_index": start + 4,
+                    "error_code": "typo",
+                    "description": "",
+                    "suggestions": ["sámi"],
+                    "title": "",
+                }],
+            }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+
+        let document = "<p>Mun lean <b>sami</b> &amp; dat</p>";
+        let response = client
+            .post("/grammar/se")
+            .body_json(&json!({ "text": document, "format": "html" }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let err = json.value().object().get("errs").array().get(0).object();
+        err.get("start_index").assert_i64(15);
+        err.get("end_index").assert_i64(19);
+        assert_eq!(&document[15..19], "sami");
+    }
+
+    #[tokio::test]
+    async fn docs_assets_are_cached_by_version() {
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .languages(LanguagesConfig::embedded().unwrap())
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+
+        let response = client.get("/").send().await;
+        let html = response.0.into_body().into_string().await.unwrap();
+        let stylesheet = format!("/assets/docs.css?v={}", crate::assets::version());
+        assert!(html.contains(&stylesheet));
+
+        let response = client.get(&stylesheet).send().await;
+        response.assert_status_is_ok();
+        response.assert_content_type("text/css; charset=utf-8");
+        response.assert_header("Cache-Control", "public, max-age=31536000, immutable");
+        let etag = response.0.header("ETag").unwrap().to_string();
+
+        let response = client
+            .get("/assets/docs.css")
+            .header("If-None-Match", etag)
+            .send()
+            .await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+        response.assert_header("Cache-Control", "no-cache");
+        client
+            .get("/assets/../languages.toml")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn speller_suggestions_are_limited() {
+        use crate::testing::MockResponse;
+
+        let languages = LanguagesConfig::embedded().unwrap();
+        let gateway = TestGateway::with_backend(languages, "speller", "se", |request| {
+            assert_eq!(request.body, br#"{"text":"sami"}"#);
+            let suggestion = |value: &str, weight: f64| json!({ "value": value, "weight": weight });
+            MockResponse::json(json!({
+                "text": "sami",
+                "results": [{
+                    "word": "sami",
+                    "is_correct": false,
+                    "suggestions": [
+                        suggestion("sámi", 14.5),
+                        suggestion("sama", 40.3),
+                        suggestion("sam", 45.9),
+                    ],
+                }],
+            }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+
+        let response = client
+            .post("/speller/se")
+            .body_json(&json!({ "text": "sami", "max_suggestions": 1 }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let results = json.value().object().get("results").array();
+        let suggestions = results.get(0).object().get("suggestions").array();
+        suggestions.assert_len(1);
+        suggestions
+            .get(0)
+            .object()
+            .get("value")
+            .assert_string("sámi");
+
+        let response = client
+            .post("/speller/se")
+            .body_json(&json!({ "text": "sami", "max_weight": "low" }))
+            .send()
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn usage_is_counted_per_language() {
+        use crate::testing::{MockResponse, TestGateway};
+
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().aliases = vec!["sme".to_string()];
+        languages.config.stats = Some(toml::from_str("").unwrap());
+        let gateway = TestGateway::with_backend(languages, "grammar", "se", |request| {
+            MockResponse::json(json!({ "text": request.text(), "errs": [] }))
+        })
+        .await
+        .unwrap();
+        let client = gateway.client();
+
+        for path in ["/grammar/se", "/grammar/sme"] {
+            client
+                .post(path)
+                .body_json(&json!({ "text": "Bures" }))
+                .send()
+                .await
+                .assert_status_is_ok();
+        }
+        client
+            .post("/grammar/se")
+            .body_json(&json!({}))
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        client.get("/languages").send().await.assert_status_is_ok();
+
+        let response = client.get("/stats").send().await;
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let services = json.value().object().get("services").object();
+        services.assert_len(1);
+        let se = services.get("grammar").object().get("se").object();
+        se.get("requests").assert_i64(3);
+        se.get("client_errors").assert_i64(1);
+        se.get("errors").assert_i64(0);
+    }
+
+    #[tokio::test]
+    async fn reload_triggers_swap_the_configuration() {
+        let config = |grammar: &str| {
+            format!(
+                "[config.tts]\nport = 40001\n\n[grammar.{}]\nname = \"{}\"\nport = 10000\n\
+                 [speller]\n[hyphenation]\n[tts]\n",
+                grammar, grammar
+            )
+        };
+        let path = std::env::temp_dir().join(format!("sighup-{}.toml", std::process::id()));
+        std::fs::write(&path, config("se")).unwrap();
+        let (reload, triggers) = tokio::sync::mpsc::unbounded_channel();
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .config_file(&path)
+                .health_checks(false)
+                .reload_on(triggers)
+                .build()
+                .unwrap(),
+        );
+
+        std::fs::write(&path, config("sma")).unwrap();
+        reload.send(()).unwrap();
+        let mut languages = serde_json::Value::Null;
+        for _ in 0..100 {
+            let response = client.get("/languages").send().await;
+            languages = response.0.into_body().into_json().await.unwrap();
+            if languages["available"]["grammar"].get("sma").is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(languages["available"]["grammar"], json!({ "sma": "sma" }));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn tenants_are_chosen_by_host() {
+        let dir = std::env::temp_dir().join(format!("tenants-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = |head: &str, grammar: &str| {
+            format!(
+                "{}[config.tts]\nport = 40001\n\n[grammar.{}]\nname = \"{}\"\nport = 10000\n\
+                 [speller]\n[hyphenation]\n[tts]\n",
+                head, grammar, grammar
+            )
+        };
+        let tenant = "[config.tenants.partner]\nhosts = [\"API.Partner.example\"]\n\
+                      config = \"partner.toml\"\n";
+        std::fs::write(dir.join("main.toml"), config(tenant, "se")).unwrap();
+        let branding = "[branding]\ntitle = \"Partner API\"\n";
+        std::fs::write(dir.join("partner.toml"), config(branding, "sma")).unwrap();
+        let client = TestClient::new(
+            ServerBuilder::new()
+                .config_file(dir.join("main.toml"))
+                .health_checks(false)
+                .build()
+                .unwrap(),
+        );
+        let get = |path: &'static str, host: &'static str| {
+            let request = client.get(path).header("Host", host);
+            async move {
+                request
+                    .send()
+                    .await
+                    .0
+                    .into_body()
+                    .into_string()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let languages: serde_json::Value =
+            serde_json::from_str(&get("/languages", "api-giellalt.uit.no").await).unwrap();
+        assert_eq!(languages["available"]["grammar"], json!({ "se": "se" }));
+        let languages: serde_json::Value =
+            serde_json::from_str(&get("/languages", "api.partner.example:443").await).unwrap();
+        assert_eq!(languages["available"]["grammar"], json!({ "sma": "sma" }));
+        assert!(get("/", "api.partner.example")
+            .await
+            .contains("Partner API"));
+        assert!(!get("/", "localhost").await.contains("Partner API"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn routing_snapshots_outlive_a_swap() {
+        let routing = RoutingTable::new(LanguagesConfig::embedded().unwrap(), |languages| {
+            anyhow::ensure!(!languages.grammar.is_empty(), "no grammar checkers");
+            let count = languages.grammar.len().to_string();
+            Ok(RoutingSnapshot {
+                languages,
+                breakers: None,
+                app: poem::endpoint::make_sync(move |_| count.clone())
+                    .map_to_response()
+                    .boxed(),
+            })
+        })
+        .unwrap();
+        let client = TestClient::new(routing.clone());
+        let before = routing.snapshot();
+
+        let mut languages = before.languages.clone();
+        languages.grammar.retain(|tag, _| tag == "se");
+        let previous = routing.apply(languages).unwrap();
+        assert!(Arc::ptr_eq(&previous, &before));
+        assert!(before.languages.grammar.len() > 1);
+        client.get("/").send().await.assert_text("1").await;
+
+        let mut empty = routing.snapshot().languages.clone();
+        empty.grammar.clear();
+        assert!(routing.apply(empty).is_err());
+        client.get("/").send().await.assert_text("1").await;
+    }
+}
@@ -0,0 +1,485 @@
+//! Service categories exposed through the gateway.
+//!
+//! Each category (grammar, speller, …) implements [`ServiceKind`] so that its
+//! documentation, generated proxy locations, health probes and routes are
+//! produced in one place. Downstream crates can add their own categories by
+//! implementing the trait and calling [`ServiceRegistry::register`]; their
+//! languages are read from a top-level table of the same name in
+//! `languages.toml` (see [`LanguagesConfig::custom`]).
+
+mod combined;
+mod grammar;
+mod hyphenation;
+mod languagetool;
+mod speller;
+mod tts;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use poem::Route;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+
+use crate::balance::Balance;
+use crate::config::{is_local, Fallback, Instance, LanguagesConfig, ServiceConfig, Shadow};
+use crate::i18n::Localizer;
+use crate::limits::LocationLimits;
+use crate::schema::{ResponseSchema, SchemaType};
+use crate::template;
+
+pub use grammar::Grammar;
+pub use hyphenation::Hyphenation;
+pub use speller::Speller;
+pub(crate) use tts::voice_details;
+pub use tts::Tts;
+
+/// A backend port probed by the health monitor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backend {
+    pub tag: String,
+    pub host: String,
+    pub port: u16,
+}
+
+/// A backend process to run: one per tag and instance, or one for the whole
+/// category if its backends share a port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Worker {
+    /// The unit or container running it: its `name`, followed by the port
+    /// for a language with several instances, e.g. `speller-se-4002`.
+    pub id: String,
+    /// `grammar-se`, or the category name for shared ports, e.g. `tts`.
+    pub name: String,
+    pub service: String,
+    pub tag: Option<String>,
+    pub port: u16,
+}
+
+impl Worker {
+    /// Fill in `{name}`, `{service}`, `{tag}` (empty for a whole category)
+    /// and `{port}` in `template`.
+    pub fn expand(&self, template: &str) -> String {
+        template
+            .replace("{name}", &self.name)
+            .replace("{service}", &self.service)
+            .replace("{tag}", self.tag.as_deref().unwrap_or_default())
+            .replace("{port}", &self.port.to_string())
+    }
+}
+
+/// Every backend process `languages` needs on this host, in registry and tag
+/// order; backends configured on other hosts are run there.
+pub fn workers(languages: &LanguagesConfig, services: &ServiceRegistry) -> Vec<Worker> {
+    let mut workers = Vec::new();
+    for kind in services.iter() {
+        let backends = kind.backends(languages);
+        if kind.shared_port() {
+            if let Some(backend) = backends.iter().find(|backend| is_local(&backend.host)) {
+                workers.push(Worker {
+                    id: kind.name().to_string(),
+                    name: kind.name().to_string(),
+                    service: kind.name().to_string(),
+                    tag: None,
+                    port: backend.port,
+                });
+            }
+            continue;
+        }
+        let locations = kind.locations(languages);
+        for backend in backends {
+            let name = format!("{}-{}", kind.name(), backend.tag);
+            let servers = locations
+                .iter()
+                .find(|location| {
+                    location.tag == backend.tag
+                        && location.host == backend.host
+                        && location.port == backend.port
+                })
+                .map(Location::servers)
+                .unwrap_or_default();
+            let servers: Vec<_> = servers.into_iter().filter(Instance::is_local).collect();
+            for server in &servers {
+                workers.push(Worker {
+                    id: match servers.len() {
+                        1 => name.clone(),
+                        _ => format!("{}-{}", name, server.port),
+                    },
+                    name: name.clone(),
+                    service: kind.name().to_string(),
+                    tag: Some(backend.tag.clone()),
+                    port: server.port,
+                });
+            }
+        }
+    }
+    workers
+}
+
+/// A public path forwarded to a backend port, either by the generated server
+/// configs or by the gateway itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub tag: String,
+    pub path: String,
+    /// The backend's host, for `port`.
+    pub host: String,
+    pub port: u16,
+    /// The backend instances sharing the requests, if configured with
+    /// `ports`; otherwise there is just the one on `port`.
+    pub instances: Vec<Instance>,
+    /// How requests are spread over the `instances`.
+    pub balance: Balance,
+    /// Query parameters appended to the backend URL, in order.
+    pub query: Vec<(String, String)>,
+    /// Enforced by the gateway for the locations it forwards itself.
+    pub limits: LocationLimits,
+    /// Where the gateway sends requests while the backend is down.
+    pub fallback: Option<Fallback>,
+    /// Where the gateway copies a share of requests to.
+    pub shadow: Option<Shadow>,
+}
+
+impl Location {
+    /// The backend URL requests for this location are sent to.
+    pub fn backend_url(&self) -> String {
+        match self.instances.first() {
+            Some(instance) => self.instance_url(instance),
+            None => self.url(&self.host, self.port),
+        }
+    }
+
+    /// Every backend instance: the configured `instances`, or the one on
+    /// `port`.
+    pub fn servers(&self) -> Vec<Instance> {
+        if self.instances.is_empty() {
+            vec![Instance {
+                host: self.host.clone(),
+                port: self.port,
+                weight: 1,
+            }]
+        } else {
+            self.instances.clone()
+        }
+    }
+
+    /// The URL requests are sent to on one of the backend `instances`.
+    pub fn instance_url(&self, instance: &Instance) -> String {
+        self.url(&instance.host, instance.port)
+    }
+
+    /// The URL of the fallback backend, if there is one.
+    pub fn fallback_url(&self) -> Option<String> {
+        let fallback = self.fallback.as_ref()?;
+        Some(self.url(&fallback.host, fallback.port))
+    }
+
+    /// The URL of the shadow backend, if there is one.
+    pub fn shadow_url(&self) -> Option<String> {
+        let shadow = self.shadow.as_ref()?;
+        Some(self.url(&shadow.host, shadow.port))
+    }
+
+    /// The query parameters as they appear in backend URLs.
+    pub fn query_string(&self) -> String {
+        self.query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    fn url(&self, host: &str, port: u16) -> String {
+        let query = self.query_string();
+        if query.is_empty() {
+            format!("http://{}:{}/", host, port)
+        } else {
+            format!("http://{}:{}/?{}", host, port, query)
+        }
+    }
+}
+
+/// A rendered section of the documentation page.
+#[derive(Debug, Clone)]
+pub struct DocsSection {
+    pub title: String,
+    pub html: String,
+}
+
+/// Documentation metadata for a single endpoint, rendered in the same style as
+/// the built-in sections.
+#[derive(Debug, Clone)]
+pub struct EndpointDocs {
+    pub title: String,
+    pub method: &'static str,
+    /// Path pattern shown to readers, e.g. `/translate/:tag`.
+    pub path: String,
+    pub response_type: String,
+    pub description: String,
+    /// `(tag, name)` pairs listed under the description.
+    pub languages: Vec<(String, String)>,
+    pub request_example: Option<String>,
+    pub response_example: Option<String>,
+}
+
+impl EndpointDocs {
+    pub fn render(&self, id: &str, l: &Localizer<'_>) -> DocsSection {
+        let languages: Vec<_> = self
+            .languages
+            .iter()
+            .map(|(tag, name)| json!({ "tag": tag, "name": name }))
+            .collect();
+        let html = template::render(
+            "endpoint.html",
+            json!({
+                "t": l.messages(),
+                "id": id,
+                "title": self.title,
+                "method": self.method,
+                "path": self.path,
+                "prefix": self.path.split("/:").next().unwrap_or_default(),
+                "response_type": self.response_type,
+                "description": self.description,
+                "languages": languages,
+                "request_example": self.request_example,
+                "response_example": self.response_example,
+            }),
+        );
+
+        DocsSection {
+            title: self.title.clone(),
+            html,
+        }
+    }
+}
+
+pub type ProbeFuture<'a> = Pin<Box<dyn Future<Output = Result<Duration, String>> + Send + 'a>>;
+
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub trait ServiceKind: Send + Sync {
+    /// Config table, URL prefix and docs anchor, e.g. `grammar`.
+    fn name(&self) -> &'static str;
+
+    /// Documentation for the index page, or `None` if nothing is configured.
+    fn docs(&self, languages: &LanguagesConfig, l: &Localizer<'_>) -> Option<DocsSection>;
+
+    /// Proxy locations emitted into generated nginx configs.
+    fn locations(&self, languages: &LanguagesConfig) -> Vec<Location>;
+
+    /// Backends probed by the health monitor.
+    fn backends(&self, languages: &LanguagesConfig) -> Vec<Backend>;
+
+    /// Register routes the gateway serves itself for this category.
+    fn routes(&self, route: Route, _languages: &LanguagesConfig) -> Route {
+        route
+    }
+
+    /// Method and path of each route added by [`routes`], for listings.
+    ///
+    /// [`routes`]: ServiceKind::routes
+    fn route_paths(&self, _languages: &LanguagesConfig) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    /// Whether the gateway forwards this category's [`locations`] itself
+    /// instead of leaving them to a fronting nginx.
+    ///
+    /// [`locations`]: ServiceKind::locations
+    fn proxied(&self) -> bool {
+        true
+    }
+
+    /// Whether a request may be sent to the backend again after it failed,
+    /// as it changes nothing there.
+    fn idempotent(&self) -> bool {
+        true
+    }
+
+    /// Whether a backend always answers the same request the same way, so
+    /// the gateway may reuse responses from `[config.response_cache]`.
+    fn deterministic(&self) -> bool {
+        false
+    }
+
+    /// Rewrite a request body before it is forwarded to `tag`'s backend.
+    fn map_request(&self, _tag: &str, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(body)
+    }
+
+    /// Rewrite a successful backend response before it is returned.
+    fn map_response(&self, _tag: &str, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(body)
+    }
+
+    /// Integer response members holding character offsets into the request
+    /// text, mapped back to the original text after sanitation.
+    fn offset_fields(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Whether the backends trim leading whitespace before checking, which
+    /// shifts their [`offset_fields`]. The gateway then trims it itself and
+    /// maps the offsets back to the text as sent.
+    ///
+    /// [`offset_fields`]: ServiceKind::offset_fields
+    fn trims_leading_whitespace(&self) -> bool {
+        false
+    }
+
+    /// Body accepted by this category's endpoints, checked by the gateway
+    /// before forwarding. `None` forwards any body.
+    fn request_schema(&self) -> Option<SchemaType> {
+        None
+    }
+
+    /// Body returned by this category's endpoints, used by generated clients.
+    fn response_schema(&self) -> ResponseSchema {
+        ResponseSchema::Json(SchemaType::Any)
+    }
+
+    /// Whether all of this category's backends are one process listening on
+    /// a single port, rather than one process per tag.
+    fn shared_port(&self) -> bool {
+        false
+    }
+
+    /// Check that `backend` is reachable, returning the time it took.
+    fn probe<'a>(&'a self, backend: &'a Backend) -> ProbeFuture<'a> {
+        Box::pin(tcp_probe(&backend.host, backend.port))
+    }
+}
+
+/// The service categories known to the gateway, in display order.
+#[derive(Clone)]
+pub struct ServiceRegistry {
+    kinds: Vec<Arc<dyn ServiceKind>>,
+}
+
+impl ServiceRegistry {
+    pub fn builtin() -> Self {
+        Self {
+            kinds: vec![
+                Arc::new(Grammar),
+                Arc::new(Speller),
+                Arc::new(Hyphenation),
+                Arc::new(Tts),
+            ],
+        }
+    }
+
+    /// Add a service category, replacing any existing one with the same name.
+    pub fn register(&mut self, kind: impl ServiceKind + 'static) {
+        let kind: Arc<dyn ServiceKind> = Arc::new(kind);
+        match self.kinds.iter_mut().find(|k| k.name() == kind.name()) {
+            Some(existing) => *existing = kind,
+            None => self.kinds.push(kind),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn ServiceKind>> {
+        self.kinds.iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn ServiceKind>> {
+        self.kinds.iter()
+    }
+
+    /// The tables in `languages` no registered category serves, such as a
+    /// misspelt `[grammer.se]`, sorted.
+    pub fn unclaimed<'a>(&self, languages: &'a LanguagesConfig) -> Vec<&'a str> {
+        let mut names: Vec<&str> = languages
+            .custom
+            .keys()
+            .map(String::as_str)
+            .filter(|name| self.get(name).is_none())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Every category's locations, each followed by a copy under every
+    /// alias of its tag, for the generated proxy configs.
+    pub fn proxy_locations(&self, languages: &LanguagesConfig) -> Vec<Location> {
+        let mut locations = Vec::new();
+        for kind in self.iter() {
+            let aliases = languages.aliases(kind.name());
+            for location in kind.locations(languages) {
+                let prefix = format!("/{}/{}", kind.name(), location.tag);
+                let aliased: Vec<Location> = aliases
+                    .iter()
+                    .filter(|(_, tag)| **tag == location.tag)
+                    .filter_map(|(alias, _)| {
+                        let rest = location.path.strip_prefix(&prefix)?;
+                        Some(Location {
+                            path: format!("/{}/{}{}", kind.name(), alias, rest),
+                            ..location.clone()
+                        })
+                    })
+                    .collect();
+                locations.push(location);
+                locations.extend(aliased);
+            }
+        }
+        locations
+    }
+}
+
+/// Connect to `port` on `host`, timing how long it takes.
+pub async fn tcp_probe(host: &str, port: u16) -> Result<Duration, String> {
+    let start = Instant::now();
+    match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => Ok(start.elapsed()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(format!("timed out after {}s", PROBE_TIMEOUT.as_secs())),
+    }
+}
+
+/// The tag and name of each of `services`, sorted by tag, for the language
+/// lists of the docs sections.
+pub(crate) fn docs_languages(services: &HashMap<String, ServiceConfig>) -> Vec<Value> {
+    let mut sorted: Vec<_> = services.iter().collect();
+    sorted.sort_by_key(|(tag, _)| *tag);
+    sorted
+        .into_iter()
+        .map(|(tag, service)| json!({ "tag": tag, "name": service.name }))
+        .collect()
+}
+
+/// One location per tag at `/{name}/{tag}`.
+pub fn service_locations(name: &str, services: &HashMap<String, ServiceConfig>) -> Vec<Location> {
+    let mut sorted: Vec<_> = services.iter().collect();
+    sorted.sort_by_key(|(tag, _)| *tag);
+    sorted
+        .into_iter()
+        .map(|(tag, service)| Location {
+            tag: tag.clone(),
+            path: format!("/{}/{}", name, tag),
+            host: service.host.clone(),
+            port: service.port,
+            instances: service.ports.clone(),
+            balance: service.balance,
+            query: Vec::new(),
+            limits: service.limits,
+            fallback: service.fallback.clone(),
+            shadow: service.shadow.clone(),
+        })
+        .collect()
+}
+
+/// One backend per tag.
+pub fn service_backends(services: &HashMap<String, ServiceConfig>) -> Vec<Backend> {
+    let mut sorted: Vec<_> = services.iter().collect();
+    sorted.sort_by_key(|(tag, _)| *tag);
+    sorted
+        .into_iter()
+        .map(|(tag, service)| Backend {
+            tag: tag.clone(),
+            host: service.host.clone(),
+            port: service.port,
+        })
+        .collect()
+}
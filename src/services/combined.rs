@@ -0,0 +1,123 @@
+//! `POST /check/:tag`: spelling and grammar in one round trip. Both backends
+//! get the text at the same time and their findings are merged, in text
+//! order, each with the `source` it came from.
+
+use poem::{
+    handler,
+    http::StatusCode,
+    web::{Data, Json, Path},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::LanguagesConfig;
+use crate::problem::Problem;
+use crate::sanitize;
+use crate::upstream::Upstreams;
+
+use super::{grammar, speller};
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CheckRequest {
+    text: String,
+}
+
+#[handler]
+pub(super) async fn check_post(
+    Path(tag): Path<String>,
+    Data(languages): Data<&LanguagesConfig>,
+    Data(client): Data<&reqwest::Client>,
+    Data(upstreams): Data<&Upstreams>,
+    Json(request): Json<CheckRequest>,
+) -> Result<Json<Value>, Problem> {
+    let speller = upstreams.get("speller", &tag);
+    let grammar = upstreams.get("grammar", &tag);
+    if speller.is_none() && grammar.is_none() {
+        return Err(Problem::new(
+            StatusCode::NOT_FOUND,
+            format!("No speller or grammar checker for {}", tag),
+        ));
+    }
+    for (service, upstream) in [("speller", speller), ("grammar", grammar)] {
+        let limit = upstream.and_then(|_| languages.max_length(service));
+        let length = request.text.chars().count();
+        if let Some(limit) = limit.filter(|limit| length > *limit) {
+            return Err(Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "Text too long")
+                .detail(format!(
+                    "The text is {} characters long, but {} accepts at most {}",
+                    length, service, limit
+                ))
+                .extension("limit", limit)
+                .extension("length", length));
+        }
+    }
+
+    // Grammar checkers trim leading whitespace, so both get the text without
+    let (prepared, prepared_map) = sanitize::prepare(&request.text);
+    let (text, leading) = sanitize::strip_leading(&prepared);
+    let offsets = prepared_map.then(&leading);
+
+    let (spelling, grammar) = tokio::join!(
+        async {
+            match speller {
+                Some(upstream) => speller::check(client, upstream, &text).await.map(Some),
+                None => Ok(None),
+            }
+        },
+        async {
+            match grammar {
+                Some(upstream) => grammar::check(client, upstream, &text).await,
+                None => Ok(Vec::new()),
+            }
+        },
+    );
+    let (spelling, grammar) = (spelling?, grammar?);
+
+    let mut findings = misspellings(&text, spelling.as_ref());
+    for mut err in grammar {
+        err["source"] = "grammar".into();
+        findings.push(err);
+    }
+    for finding in &mut findings {
+        offsets.remap(finding, &["start_index", "end_index"]);
+    }
+    findings.sort_by_key(|finding| finding.get("start_index").and_then(Value::as_u64));
+
+    Ok(Json(json!({
+        "text": request.text,
+        "findings": findings,
+    })))
+}
+
+/// The speller's incorrect words, with their character offsets in `text`.
+fn misspellings(text: &str, response: Option<&Value>) -> Vec<Value> {
+    let results = response
+        .and_then(|response| response.get("results")?.as_array())
+        .into_iter()
+        .flatten();
+
+    let mut findings = Vec::new();
+    // The speller answers word by word in text order
+    let mut cursor = 0;
+    for result in results {
+        let Some(word) = result.get("word").and_then(Value::as_str) else {
+            continue;
+        };
+        let Some(found) = text[cursor..].find(word) else {
+            continue;
+        };
+        let start = cursor + found;
+        cursor = start + word.len();
+        if result.get("is_correct").and_then(Value::as_bool) != Some(false) {
+            continue;
+        }
+
+        let start_index = text[..start].chars().count();
+        let mut finding = result.clone();
+        finding["source"] = "speller".into();
+        finding["start_index"] = start_index.into();
+        finding["end_index"] = (start_index + word.chars().count()).into();
+        findings.push(finding);
+    }
+    findings
+}
@@ -0,0 +1,341 @@
+use std::sync::Arc;
+
+use futures_util::stream;
+use poem::{
+    get, handler,
+    http::StatusCode,
+    post,
+    web::sse::{Event, SSE},
+    web::{Data, Json, Path},
+    Route,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::LanguagesConfig;
+use crate::i18n::Localizer;
+use crate::langid::{self, LanguageIdentifier};
+use crate::problem::Problem;
+use crate::sanitize;
+use crate::schema::{ResponseSchema, SchemaType};
+use crate::template;
+use crate::upstream::{Upstream, Upstreams};
+
+use super::languagetool;
+use super::{
+    docs_languages, service_backends, service_locations, Backend, DocsSection, Location,
+    ServiceKind,
+};
+
+pub struct Grammar;
+
+impl ServiceKind for Grammar {
+    fn name(&self) -> &'static str {
+        "grammar"
+    }
+
+    fn docs(&self, languages: &LanguagesConfig, l: &Localizer<'_>) -> Option<DocsSection> {
+        if languages.grammar.is_empty() {
+            return None;
+        }
+
+        Some(DocsSection {
+            title: l.t("grammar_title").to_string(),
+            html: template::render(
+                "grammar.html",
+                json!({
+                    "t": l.messages(),
+                    "languages": docs_languages(&languages.grammar),
+                }),
+            ),
+        })
+    }
+
+    fn locations(&self, languages: &LanguagesConfig) -> Vec<Location> {
+        service_locations(self.name(), &languages.grammar)
+    }
+
+    fn routes(&self, route: Route, languages: &LanguagesConfig) -> Route {
+        if languages.grammar.is_empty() {
+            return route;
+        }
+        route
+            .at("/grammar/mixed", post(mixed_post))
+            .at("/grammar/:tag/stream", post(stream_post))
+            .at("/v2/check", post(languagetool::check_post))
+            .at("/v2/languages", get(languagetool::languages_get))
+    }
+
+    fn route_paths(&self, languages: &LanguagesConfig) -> Vec<(&'static str, String)> {
+        if languages.grammar.is_empty() {
+            return Vec::new();
+        }
+        let mut tags: Vec<_> = languages.grammar.keys().collect();
+        tags.sort();
+        let mut paths = vec![
+            ("POST", "/grammar/mixed".to_string()),
+            ("POST", "/v2/check".to_string()),
+            ("GET", "/v2/languages".to_string()),
+        ];
+        paths.extend(
+            tags.into_iter()
+                .map(|tag| ("POST", format!("/grammar/{}/stream", tag))),
+        );
+        paths
+    }
+
+    fn deterministic(&self) -> bool {
+        true
+    }
+
+    fn offset_fields(&self) -> &'static [&'static str] {
+        &["start_index", "end_index"]
+    }
+
+    fn trims_leading_whitespace(&self) -> bool {
+        true
+    }
+
+    fn request_schema(&self) -> Option<SchemaType> {
+        Some(SchemaType::Named("TextRequest"))
+    }
+
+    fn response_schema(&self) -> ResponseSchema {
+        ResponseSchema::Json(SchemaType::Named("GrammarResponse"))
+    }
+
+    fn backends(&self, languages: &LanguagesConfig) -> Vec<Backend> {
+        service_backends(&languages.grammar)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MixedRequest {
+    text: String,
+    /// Languages to choose between; all configured ones if omitted.
+    #[serde(default)]
+    languages: Option<Vec<String>>,
+}
+
+/// Check a text mixing several languages: each run of lines goes to the
+/// checker for its identified language, and the findings are merged with
+/// offsets into the whole text.
+#[handler]
+async fn mixed_post(
+    Data(languages): Data<&LanguagesConfig>,
+    Data(client): Data<&reqwest::Client>,
+    Data(upstreams): Data<&Upstreams>,
+    Data(identifier): Data<&Arc<dyn LanguageIdentifier>>,
+    Json(request): Json<MixedRequest>,
+) -> Result<Json<Value>, Problem> {
+    let mut candidates: Vec<&str> = match &request.languages {
+        Some(tags) => tags
+            .iter()
+            .map(String::as_str)
+            .filter(|tag| languages.grammar.contains_key(*tag))
+            .collect(),
+        None => languages.grammar.keys().map(String::as_str).collect(),
+    };
+    candidates.sort_unstable();
+    if candidates.is_empty() {
+        return Err(Problem::new(
+            StatusCode::BAD_REQUEST,
+            "No grammar checker for the requested languages",
+        ));
+    }
+    check_length(languages, &request.text)?;
+
+    let (normalized, prepared) = sanitize::prepare(&request.text);
+    let mut segments = langid::segment(identifier.as_ref(), &normalized, &candidates);
+    let chars: Vec<char> = normalized.chars().collect();
+
+    let mut errs = Vec::new();
+    for segment in &segments {
+        let upstream = upstreams.get("grammar", &segment.language).ok_or_else(|| {
+            Problem::new(
+                StatusCode::NOT_FOUND,
+                format!("No grammar checker for {}", segment.language),
+            )
+        })?;
+        let text: String = chars[segment.start..segment.end].iter().collect();
+
+        let found = check(client, upstream, &text).await?;
+
+        for mut err in found {
+            for field in ["start_index", "end_index"] {
+                if let Some(offset) = err.get(field).and_then(Value::as_u64) {
+                    err[field] = prepared.original(offset as usize + segment.start).into();
+                }
+            }
+            err["language"] = segment.language.clone().into();
+            errs.push(err);
+        }
+    }
+
+    for segment in &mut segments {
+        segment.start = prepared.original(segment.start);
+        segment.end = prepared.original(segment.end);
+    }
+
+    Ok(Json(json!({
+        "text": request.text,
+        "errs": errs,
+        "segments": segments,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct TextRequest {
+    text: String,
+}
+
+/// Check a long text paragraph by paragraph, sending each paragraph's
+/// findings as a `result` event as soon as the backend answers, then a
+/// `done` event. Offsets in the events are into the whole text.
+#[handler]
+async fn stream_post(
+    Path(tag): Path<String>,
+    Data(languages): Data<&LanguagesConfig>,
+    Data(client): Data<&reqwest::Client>,
+    Data(upstreams): Data<&Upstreams>,
+    Json(request): Json<TextRequest>,
+) -> Result<SSE, Problem> {
+    let Some(upstream) = upstreams.get("grammar", &tag) else {
+        return Err(Problem::new(
+            StatusCode::NOT_FOUND,
+            format!("No grammar checker for {}", tag),
+        ));
+    };
+
+    let (normalized, prepared) = sanitize::prepare(&request.text);
+    let chars: Vec<char> = normalized.chars().collect();
+    let paragraphs = paragraphs(&chars);
+    if let Some(limit) = languages.max_length("grammar") {
+        if let Some((start, end)) = paragraphs.iter().find(|(start, end)| end - start > limit) {
+            return Err(
+                Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "Paragraph too long")
+                    .detail(format!(
+                        "The paragraph at {} is {} characters long, but grammar accepts at most {}",
+                        prepared.original(*start),
+                        end - start,
+                        limit
+                    ))
+                    .extension("limit", limit)
+                    .extension("length", end - start),
+            );
+        }
+    }
+
+    let state = StreamState {
+        client: client.clone(),
+        upstream: upstream.clone(),
+        chars,
+        prepared,
+        paragraphs: paragraphs.into_iter(),
+        finished: false,
+    };
+    let events = stream::unfold(state, |mut state| async move {
+        let event = state.next_event().await?;
+        Some((event, state))
+    });
+    Ok(SSE::new(events))
+}
+
+struct StreamState {
+    client: reqwest::Client,
+    upstream: Upstream,
+    chars: Vec<char>,
+    prepared: sanitize::OffsetMap,
+    paragraphs: std::vec::IntoIter<(usize, usize)>,
+    finished: bool,
+}
+
+impl StreamState {
+    async fn next_event(&mut self) -> Option<Event> {
+        if self.finished {
+            return None;
+        }
+        let Some((start, end)) = self.paragraphs.next() else {
+            self.finished = true;
+            return Some(Event::message("{}").event_type("done"));
+        };
+
+        let text: String = self.chars[start..end].iter().collect();
+        match check(&self.client, &self.upstream, &text).await {
+            Ok(mut errs) => {
+                for err in &mut errs {
+                    for field in ["start_index", "end_index"] {
+                        if let Some(offset) = err.get(field).and_then(Value::as_u64) {
+                            err[field] = self.prepared.original(offset as usize + start).into();
+                        }
+                    }
+                }
+                let result = json!({
+                    "start": self.prepared.original(start),
+                    "end": self.prepared.original(end),
+                    "errs": errs,
+                });
+                Some(Event::message(result.to_string()).event_type("result"))
+            }
+            Err(problem) => {
+                self.finished = true;
+                Some(Event::message(problem.to_json().to_string()).event_type("error"))
+            }
+        }
+    }
+}
+
+/// Character ranges of the paragraphs of `chars`: runs of lines separated by
+/// blank lines, from their first non-whitespace character, since the
+/// backends trim leading whitespace, to the end of their last line.
+fn paragraphs(chars: &[char]) -> Vec<(usize, usize)> {
+    let mut paragraphs = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    let mut line_start = 0;
+    for end in (0..=chars.len()).filter(|&i| i == chars.len() || chars[i] == '\n') {
+        let line = &chars[line_start..end];
+        match line.iter().position(|c| !c.is_whitespace()) {
+            Some(first) => {
+                let (start, _) = current.get_or_insert((line_start + first, end));
+                current = Some((*start, end));
+            }
+            None => paragraphs.extend(current.take()),
+        }
+        line_start = end + 1;
+    }
+    paragraphs.extend(current);
+    paragraphs
+}
+
+/// Turn away a text longer than grammar checkers accept, before it is
+/// split up or sent on.
+pub(super) fn check_length(languages: &LanguagesConfig, text: &str) -> Result<(), Problem> {
+    let Some(limit) = languages.max_length("grammar") else {
+        return Ok(());
+    };
+    let length = text.chars().count();
+    if length <= limit {
+        return Ok(());
+    }
+    Err(Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "Text too long")
+        .detail(format!(
+            "The text is {} characters long, but grammar accepts at most {}",
+            length, limit
+        ))
+        .extension("limit", limit)
+        .extension("length", length))
+}
+
+pub(super) async fn check(
+    client: &reqwest::Client,
+    upstream: &Upstream,
+    text: &str,
+) -> Result<Vec<Value>, Problem> {
+    let body = upstream.post_json(client, &json!({ "text": text })).await?;
+    let mut response: Value =
+        serde_json::from_slice(&body).map_err(|err| upstream.unavailable(err))?;
+    match response.get_mut("errs").map(Value::take) {
+        Some(Value::Array(errs)) => Ok(errs),
+        _ => Err(upstream.unavailable("response has no errs array")),
+    }
+}
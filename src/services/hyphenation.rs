@@ -0,0 +1,89 @@
+use serde_json::{json, Value};
+
+use crate::config::LanguagesConfig;
+use crate::i18n::Localizer;
+use crate::schema::{ResponseSchema, SchemaType};
+use crate::template;
+
+use super::{
+    docs_languages, service_backends, service_locations, Backend, DocsSection, Location,
+    ServiceKind,
+};
+
+pub struct Hyphenation;
+
+impl ServiceKind for Hyphenation {
+    fn name(&self) -> &'static str {
+        "hyphenation"
+    }
+
+    fn docs(&self, languages: &LanguagesConfig, l: &Localizer<'_>) -> Option<DocsSection> {
+        if languages.hyphenation.is_empty() {
+            return None;
+        }
+
+        Some(DocsSection {
+            title: l.t("hyphenation_title").to_string(),
+            html: template::render(
+                "hyphenation.html",
+                json!({
+                    "t": l.messages(),
+                    "languages": docs_languages(&languages.hyphenation),
+                }),
+            ),
+        })
+    }
+
+    fn locations(&self, languages: &LanguagesConfig) -> Vec<Location> {
+        service_locations(self.name(), &languages.hyphenation)
+    }
+
+    /// Add each word's hyphen `positions`, read from its first pattern.
+    fn map_response(&self, _tag: &str, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let mut response: Value = serde_json::from_slice(&body)?;
+        let results = response
+            .get_mut("results")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten();
+        for result in results {
+            let pattern = result
+                .pointer("/patterns/0/value")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            result["positions"] = positions(pattern).into();
+        }
+        Ok(serde_json::to_vec(&response)?)
+    }
+
+    fn request_schema(&self) -> Option<SchemaType> {
+        Some(SchemaType::Named("TextRequest"))
+    }
+
+    fn response_schema(&self) -> ResponseSchema {
+        ResponseSchema::Json(SchemaType::Named("HyphenationResponse"))
+    }
+
+    fn deterministic(&self) -> bool {
+        true
+    }
+
+    fn backends(&self, languages: &LanguagesConfig) -> Vec<Backend> {
+        service_backends(&languages.hyphenation)
+    }
+}
+
+/// Character offsets into the word where `pattern` marks a hyphenation
+/// point with `^`.
+fn positions(pattern: &str) -> Vec<usize> {
+    let mut positions = Vec::new();
+    let mut offset = 0;
+    for c in pattern.chars() {
+        if c == '^' {
+            positions.push(offset);
+        } else {
+            offset += 1;
+        }
+    }
+    positions
+}
@@ -0,0 +1,173 @@
+//! The LanguageTool HTTP API on top of the grammar checkers, so LanguageTool
+//! clients such as the LibreOffice and browser plugins can use this server
+//! as their "remote server" unchanged.
+//!
+//! `POST /v2/check` takes `language` and `text` form parameters and answers
+//! with LanguageTool's `matches`; `GET /v2/languages` lists the checkers.
+//! Offsets are in UTF-16 code units, as LanguageTool's are.
+
+use std::sync::Arc;
+
+use poem::{
+    handler,
+    http::StatusCode,
+    web::{Data, Form, Json},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::LanguagesConfig;
+use crate::langid::LanguageIdentifier;
+use crate::problem::Problem;
+use crate::sanitize;
+use crate::upstream::Upstreams;
+
+use super::grammar::{check, check_length};
+
+/// Characters of text shown on either side of a match in its context.
+const CONTEXT: usize = 40;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CheckRequest {
+    language: String,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[handler]
+pub(super) async fn languages_get(Data(languages): Data<&LanguagesConfig>) -> Json<Value> {
+    let mut tags: Vec<_> = languages.grammar.iter().collect();
+    tags.sort_by_key(|(tag, _)| *tag);
+    Json(
+        tags.into_iter()
+            .map(|(tag, service)| json!({ "name": service.name, "code": tag, "longCode": tag }))
+            .collect(),
+    )
+}
+
+#[handler]
+pub(super) async fn check_post(
+    Data(languages): Data<&LanguagesConfig>,
+    Data(client): Data<&reqwest::Client>,
+    Data(upstreams): Data<&Upstreams>,
+    Data(identifier): Data<&Arc<dyn LanguageIdentifier>>,
+    Form(request): Form<CheckRequest>,
+) -> Result<Json<Value>, Problem> {
+    let Some(text) = request.text else {
+        return Err(Problem::new(StatusCode::BAD_REQUEST, "Missing text")
+            .detail("Send the text to check as the `text` parameter"));
+    };
+    check_length(languages, &text)?;
+    let tag = resolve(languages, identifier.as_ref(), &request.language, &text)?;
+    let service = &languages.grammar[&tag];
+    let upstream = upstreams.get("grammar", &tag).ok_or_else(|| {
+        Problem::new(StatusCode::BAD_REQUEST, "Unsupported language")
+            .detail(format!("There is no grammar checker for {}", tag))
+    })?;
+
+    let (prepared, prepared_map) = sanitize::prepare(&text);
+    let (trimmed, leading) = sanitize::strip_leading(&prepared);
+    let offsets = prepared_map.then(&leading);
+    let errs = check(client, upstream, &trimmed).await?;
+
+    let chars: Vec<char> = text.chars().collect();
+    let matches: Vec<Value> = errs
+        .iter()
+        .filter_map(|err| {
+            let start = offsets.original(err.get("start_index")?.as_u64()? as usize);
+            let end = offsets.original(err.get("end_index")?.as_u64()? as usize);
+            Some(to_match(
+                err,
+                &chars,
+                start.min(chars.len()),
+                end.min(chars.len()),
+            ))
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "software": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "apiVersion": 1,
+            "premium": false,
+            "status": "",
+        },
+        "warnings": { "incompleteResults": false },
+        "language": {
+            "name": service.name,
+            "code": tag,
+            "detectedLanguage": { "name": service.name, "code": tag, "confidence": 1.0 },
+        },
+        "matches": matches,
+    })))
+}
+
+/// The grammar checker for a LanguageTool `language`: a configured tag, one
+/// with a region such as `se-NO`, or `auto`.
+fn resolve(
+    languages: &LanguagesConfig,
+    identifier: &dyn LanguageIdentifier,
+    language: &str,
+    text: &str,
+) -> Result<String, Problem> {
+    if language == "auto" {
+        let mut candidates: Vec<&str> = languages.grammar.keys().map(String::as_str).collect();
+        candidates.sort_unstable();
+        return identifier.identify(text, &candidates).ok_or_else(|| {
+            Problem::new(StatusCode::BAD_REQUEST, "Language not detected")
+                .detail("Name the text's language instead of `auto`")
+        });
+    }
+    let primary = language.split(['-', '_']).next().unwrap_or(language);
+    [language, primary]
+        .into_iter()
+        .map(|tag| languages.canonical_tag("grammar", tag))
+        .find(|tag| languages.grammar.contains_key(tag))
+        .ok_or_else(|| {
+            Problem::new(StatusCode::BAD_REQUEST, "Unsupported language")
+                .detail(format!("There is no grammar checker for {}", language))
+        })
+}
+
+/// A divvun grammar error as a LanguageTool match, for the characters
+/// `start..end` of `chars`.
+fn to_match(err: &Value, chars: &[char], start: usize, end: usize) -> Value {
+    let field = |name: &str| err.get(name).and_then(Value::as_str).unwrap_or_default();
+    let replacements: Vec<Value> = err
+        .get("suggestions")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(|value| json!({ "value": value }))
+        .collect();
+
+    let context_start = start.saturating_sub(CONTEXT);
+    let context_end = (end + CONTEXT).min(chars.len());
+    let context: String = chars[context_start..context_end].iter().collect();
+
+    json!({
+        "message": field("description"),
+        "shortMessage": field("title"),
+        "replacements": replacements,
+        "offset": utf16_len(&chars[..start]),
+        "length": utf16_len(&chars[start..end]),
+        "context": {
+            "text": context,
+            "offset": utf16_len(&chars[context_start..start]),
+            "length": utf16_len(&chars[start..end]),
+        },
+        "type": { "typeName": "Other" },
+        "rule": {
+            "id": field("error_code"),
+            "description": field("title"),
+            "issueType": "grammar",
+            "category": { "id": "GRAMMAR", "name": "Grammar" },
+        },
+    })
+}
+
+fn utf16_len(chars: &[char]) -> usize {
+    chars.iter().map(|c| c.len_utf16()).sum()
+}
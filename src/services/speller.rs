@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use poem::{
+    handler,
+    http::StatusCode,
+    post,
+    web::{Data, Json, Path},
+    Route,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::config::LanguagesConfig;
+use crate::i18n::Localizer;
+use crate::problem::Problem;
+use crate::schema::{ResponseSchema, SchemaType};
+use crate::template;
+use crate::upstream::{Upstream, Upstreams};
+
+use super::combined;
+use super::{
+    docs_languages, service_backends, service_locations, Backend, DocsSection, Location,
+    ServiceKind,
+};
+
+pub struct Speller;
+
+impl ServiceKind for Speller {
+    fn name(&self) -> &'static str {
+        "speller"
+    }
+
+    fn routes(&self, route: Route, languages: &LanguagesConfig) -> Route {
+        if languages.speller.is_empty() {
+            return route;
+        }
+        route
+            .at("/speller/:tag/batch", post(batch_post))
+            .at("/check/:tag", post(combined::check_post))
+    }
+
+    fn route_paths(&self, languages: &LanguagesConfig) -> Vec<(&'static str, String)> {
+        if languages.speller.is_empty() {
+            return Vec::new();
+        }
+        let mut tags: Vec<_> = languages.speller.keys().collect();
+        tags.sort();
+        let batches = tags
+            .iter()
+            .map(|tag| ("POST", format!("/speller/{}/batch", tag)));
+        // Languages with only a grammar checker are checked too
+        let mut checked: Vec<_> = languages
+            .speller
+            .keys()
+            .chain(languages.grammar.keys())
+            .collect();
+        checked.sort();
+        checked.dedup();
+        batches
+            .chain(
+                checked
+                    .into_iter()
+                    .map(|tag| ("POST", format!("/check/{}", tag))),
+            )
+            .collect()
+    }
+
+    fn docs(&self, languages: &LanguagesConfig, l: &Localizer<'_>) -> Option<DocsSection> {
+        if languages.speller.is_empty() {
+            return None;
+        }
+
+        Some(DocsSection {
+            title: l.t("speller_title").to_string(),
+            html: template::render(
+                "speller.html",
+                json!({
+                    "t": l.messages(),
+                    "languages": docs_languages(&languages.speller),
+                }),
+            ),
+        })
+    }
+
+    fn locations(&self, languages: &LanguagesConfig) -> Vec<Location> {
+        service_locations(self.name(), &languages.speller)
+    }
+
+    fn request_schema(&self) -> Option<SchemaType> {
+        Some(SchemaType::Named("TextRequest"))
+    }
+
+    fn response_schema(&self) -> ResponseSchema {
+        ResponseSchema::Json(SchemaType::Named("SpellerResponse"))
+    }
+
+    fn deterministic(&self) -> bool {
+        true
+    }
+
+    fn backends(&self, languages: &LanguagesConfig) -> Vec<Backend> {
+        service_backends(&languages.speller)
+    }
+}
+
+/// How many texts of one batch are checked at the same time.
+const BATCH_PARALLELISM: usize = 8;
+
+#[derive(Debug, Deserialize)]
+struct BatchRequest {
+    texts: Vec<String>,
+}
+
+/// Check many texts with one request: each text goes to the speller
+/// separately, a few at a time, and the results come back in the order of
+/// the texts.
+#[handler]
+async fn batch_post(
+    Path(tag): Path<String>,
+    Data(languages): Data<&LanguagesConfig>,
+    Data(client): Data<&reqwest::Client>,
+    Data(upstreams): Data<&Upstreams>,
+    Json(request): Json<BatchRequest>,
+) -> Result<Json<Value>, Problem> {
+    let Some(upstream) = upstreams.get("speller", &tag) else {
+        return Err(Problem::new(
+            StatusCode::NOT_FOUND,
+            format!("No speller for {}", tag),
+        ));
+    };
+
+    let limit = languages.config.max_batch;
+    let length = request.texts.len();
+    if length > limit {
+        return Err(
+            Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "Batch too large")
+                .detail(format!(
+                    "The batch holds {} texts, but speller accepts at most {}",
+                    length, limit
+                ))
+                .extension("limit", limit)
+                .extension("length", length),
+        );
+    }
+
+    if let Some(limit) = languages.max_length("speller") {
+        for (index, text) in request.texts.iter().enumerate() {
+            let length = text.chars().count();
+            if length > limit {
+                return Err(Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "Text too long")
+                    .detail(format!(
+                        "Text {} is {} characters long, but speller accepts at most {}",
+                        index, length, limit
+                    ))
+                    .extension("index", index)
+                    .extension("limit", limit)
+                    .extension("length", length));
+            }
+        }
+    }
+
+    let permits = Arc::new(Semaphore::new(BATCH_PARALLELISM));
+    let mut tasks = JoinSet::new();
+    for (index, text) in request.texts.into_iter().enumerate() {
+        let client = client.clone();
+        let permits = permits.clone();
+        let upstream = upstream.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            (index, check(&client, &upstream, &text).await)
+        });
+    }
+
+    let mut results = vec![Value::Null; tasks.len()];
+    while let Some(joined) = tasks.join_next().await {
+        let (index, result) = joined.map_err(|err| {
+            tracing::warn!("speller {} batch task failed: {}", tag, err);
+            Problem::new(StatusCode::INTERNAL_SERVER_ERROR, "Batch check failed")
+        })?;
+        results[index] = result?;
+    }
+
+    Ok(Json(Value::Array(results)))
+}
+
+pub(super) async fn check(
+    client: &reqwest::Client,
+    upstream: &Upstream,
+    text: &str,
+) -> Result<Value, Problem> {
+    let body = upstream.post_json(client, &json!({ "text": text })).await?;
+    serde_json::from_slice(&body).map_err(|err| upstream.unavailable(err))
+}
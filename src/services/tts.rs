@@ -0,0 +1,340 @@
+#[cfg(feature = "websocket")]
+use futures_util::{SinkExt, StreamExt};
+use poem::{
+    get, handler,
+    http::StatusCode,
+    web::{Data, Json, Path},
+    IntoResponse, Route,
+};
+#[cfg(feature = "websocket")]
+use poem::{
+    http::header,
+    web::websocket::{Message, WebSocket, WebSocketStream},
+    Request,
+};
+use serde_json::{json, Value};
+
+use crate::config::{LanguagesConfig, TtsConfig};
+use crate::i18n::Localizer;
+use crate::problem::Problem;
+use crate::schema::{ResponseSchema, SchemaType};
+use crate::template;
+use crate::transcode::AudioFormat;
+#[cfg(feature = "websocket")]
+use crate::upstream::{Upstream, Upstreams};
+
+use super::{Backend, DocsSection, Location, ServiceKind};
+
+pub struct Tts;
+
+impl ServiceKind for Tts {
+    fn name(&self) -> &'static str {
+        "tts"
+    }
+
+    fn docs(&self, languages: &LanguagesConfig, l: &Localizer<'_>) -> Option<DocsSection> {
+        if languages.tts.is_empty() {
+            return None;
+        }
+
+        let mut sorted_langs: Vec<_> = languages.tts.iter().collect();
+        sorted_langs.sort_by_key(|(tag, _)| *tag);
+        let languages: Vec<_> = sorted_langs
+            .into_iter()
+            .map(|(tag, config)| {
+                let mut voices: Vec<_> = config.voices.iter().collect();
+                voices.sort_by_key(|(voice_id, _)| *voice_id);
+                json!({
+                    "tag": tag,
+                    "name": config.name,
+                    "voices": voices
+                        .into_iter()
+                        .map(|(voice_id, voice)| json!({
+                            "id": voice_id,
+                            "name": voice.name,
+                            "gender": voice.gender,
+                        }))
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+
+        Some(DocsSection {
+            title: l.t("tts_title").to_string(),
+            html: template::render(
+                "tts.html",
+                json!({ "t": l.messages(), "languages": languages }),
+            ),
+        })
+    }
+
+    fn locations(&self, languages: &LanguagesConfig) -> Vec<Location> {
+        let mut locations = Vec::new();
+
+        let mut tts_services: Vec<_> = languages.tts.iter().collect();
+        tts_services.sort_by_key(|(tag, _)| *tag);
+        for (tag, tts_config) in tts_services {
+            let mut voices: Vec<_> = tts_config.voices.iter().collect();
+            voices.sort_by_key(|(voice_id, _)| *voice_id);
+            for (voice_id, voice) in voices {
+                let mut query = Vec::new();
+                if let Some(language) = voice.language {
+                    query.push(("language".to_string(), language.to_string()));
+                }
+                if let Some(speaker) = voice.speaker {
+                    query.push(("speaker".to_string(), speaker.to_string()));
+                }
+                locations.push(Location {
+                    tag: tag.clone(),
+                    path: format!("/tts/{}/{}", tag, voice_id),
+                    host: voice
+                        .host
+                        .clone()
+                        .unwrap_or_else(|| languages.config.tts.host.clone()),
+                    port: languages.config.tts.port,
+                    instances: Vec::new(),
+                    balance: Default::default(),
+                    query,
+                    limits: voice.limits,
+                    fallback: languages.config.tts.fallback.clone(),
+                    shadow: languages.config.tts.shadow.clone(),
+                });
+            }
+        }
+
+        locations
+    }
+
+    fn routes(&self, route: Route, languages: &LanguagesConfig) -> Route {
+        if languages.tts.is_empty() {
+            return route;
+        }
+        let route = route.at("/tts/:tag/voices", get(voices_get));
+        #[cfg(feature = "websocket")]
+        let route = route.at("/tts/:tag/:voice/stream", get(stream_get));
+        route
+    }
+
+    fn route_paths(&self, languages: &LanguagesConfig) -> Vec<(&'static str, String)> {
+        let mut tags: Vec<_> = languages.tts.keys().collect();
+        tags.sort();
+        #[allow(unused_mut)]
+        let mut paths: Vec<_> = tags
+            .into_iter()
+            .map(|tag| ("GET", format!("/tts/{}/voices", tag)))
+            .collect();
+        #[cfg(feature = "websocket")]
+        paths.extend(
+            self.locations(languages)
+                .into_iter()
+                .map(|location| ("GET", format!("{}/stream", location.path))),
+        );
+        paths
+    }
+
+    fn request_schema(&self) -> Option<SchemaType> {
+        Some(SchemaType::Named("TextRequest"))
+    }
+
+    fn response_schema(&self) -> ResponseSchema {
+        ResponseSchema::Audio
+    }
+
+    fn shared_port(&self) -> bool {
+        true
+    }
+
+    fn backends(&self, languages: &LanguagesConfig) -> Vec<Backend> {
+        let mut tags: Vec<_> = languages.tts.iter().collect();
+        tags.sort_by_key(|(tag, _)| *tag);
+        tags.into_iter()
+            .map(|(tag, tts)| {
+                // Probed where its first voice is served
+                let mut voices: Vec<_> = tts.voices.iter().collect();
+                voices.sort_by_key(|(voice_id, _)| *voice_id);
+                let host = voices
+                    .first()
+                    .and_then(|(_, voice)| voice.host.clone())
+                    .unwrap_or_else(|| languages.config.tts.host.clone());
+                Backend {
+                    tag: tag.clone(),
+                    host,
+                    port: languages.config.tts.port,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The voices configured for a language, with what a client needs to offer
+/// them in a voice picker.
+#[handler]
+async fn voices_get(
+    Path(tag): Path<String>,
+    Data(languages): Data<&LanguagesConfig>,
+) -> Result<impl IntoResponse, Problem> {
+    let Some(tts) = languages.tts.get(&tag) else {
+        return Err(Problem::new(
+            StatusCode::NOT_FOUND,
+            format!("No voices for {}", tag),
+        ));
+    };
+
+    let voices = voice_details(&tag, tts);
+    Ok(Json(
+        json!({ "tag": tag, "name": tts.name, "voices": voices }),
+    ))
+}
+
+/// The voices of `tag`, sorted by ID, with their path and what they accept.
+pub(crate) fn voice_details(tag: &str, tts: &TtsConfig) -> Vec<Value> {
+    let mut voices: Vec<_> = tts.voices.iter().collect();
+    voices.sort_by_key(|(voice_id, _)| *voice_id);
+    voices
+        .into_iter()
+        .map(|(voice_id, voice)| {
+            json!({
+                "id": voice_id,
+                "name": voice.name,
+                "gender": voice.gender,
+                "model": voice.model,
+                "speaker": voice.speaker,
+                "language": voice.language,
+                "path": format!("/tts/{}/{}", tag, voice_id),
+                "formats": AudioFormat::NAMES,
+                "ssml": tts.ssml,
+            })
+        })
+        .collect()
+}
+
+/// Synthesize texts sent over a WebSocket, answering each with the audio as
+/// binary messages while the backend produces it, then a JSON text message
+/// with `done` and the byte count, or `error`.
+///
+/// The upgrade request's `Accept` header picks the audio format, as for
+/// `POST /tts/:tag/:voice`.
+#[cfg(feature = "websocket")]
+#[handler]
+async fn stream_get(
+    Path((tag, voice)): Path<(String, String)>,
+    Data(languages): Data<&LanguagesConfig>,
+    Data(client): Data<&reqwest::Client>,
+    Data(upstreams): Data<&Upstreams>,
+    req: &Request,
+    ws: WebSocket,
+) -> Result<impl IntoResponse, Problem> {
+    let Some(upstream) = upstreams.at(&format!("/tts/{}/{}", tag, voice)) else {
+        return Err(Problem::new(
+            StatusCode::NOT_FOUND,
+            format!("No voice {} for {}", voice, tag),
+        ));
+    };
+
+    let stream = AudioStream {
+        client: client.clone(),
+        upstream: upstream.clone(),
+        accept: req.header(header::ACCEPT).map(str::to_string),
+        max_length: languages.max_length("tts"),
+    };
+    Ok(ws.on_upgrade(move |socket| stream.run(socket)))
+}
+
+#[cfg(feature = "websocket")]
+struct AudioStream {
+    client: reqwest::Client,
+    upstream: Upstream,
+    accept: Option<String>,
+    max_length: Option<usize>,
+}
+
+#[cfg(feature = "websocket")]
+impl AudioStream {
+    async fn run(self, mut socket: WebSocketStream) {
+        while let Some(Ok(message)) = socket.next().await {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            // Either a TextRequest or the bare text
+            let text = serde_json::from_str::<Value>(&text)
+                .ok()
+                .and_then(|json| json.get("text")?.as_str().map(str::to_string))
+                .unwrap_or(text);
+
+            let length = text.chars().count();
+            let result = match self.max_length {
+                Some(limit) if length > limit => Err(Problem::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "The text is too long; tts accepts at most {} characters",
+                        limit
+                    ),
+                )
+                .extension("limit", limit)
+                .extension("length", length)),
+                _ => match self.upstream.timeout() {
+                    Some(timeout) => {
+                        tokio::time::timeout(timeout, self.synthesize(&mut socket, &text))
+                            .await
+                            .unwrap_or_else(|_| Err(self.upstream.timed_out(timeout)))
+                    }
+                    None => self.synthesize(&mut socket, &text).await,
+                },
+            };
+            let status = match result {
+                Ok(bytes) => json!({ "done": true, "bytes": bytes }),
+                // The problem's fields, with its title as the `error`
+                Err(problem) => {
+                    let mut status = problem.to_json();
+                    status["error"] = status["title"].clone();
+                    status
+                }
+            };
+            if socket
+                .send(Message::Text(status.to_string()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Forward the backend's audio as it arrives, returning its length.
+    async fn synthesize(&self, socket: &mut WebSocketStream, text: &str) -> Result<usize, Problem> {
+        let _permit = self.upstream.admit()?;
+        let mut response = self
+            .upstream
+            .send(|url| {
+                let request = self
+                    .client
+                    .post(url)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(json!({ "text": text }).to_string());
+                match &self.accept {
+                    Some(accept) => request.header(header::ACCEPT, accept),
+                    None => request,
+                }
+            })
+            .await?;
+
+        let mut sent = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| self.upstream.unavailable(err))?
+        {
+            sent += chunk.len();
+            socket
+                .send(Message::Binary(chunk.to_vec()))
+                .await
+                .map_err(|err| {
+                    Problem::new(StatusCode::BAD_REQUEST, "Client went away")
+                        .detail(err.to_string())
+                })?;
+        }
+        Ok(sent)
+    }
+}
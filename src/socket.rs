@@ -0,0 +1,140 @@
+//! Serving on a unix domain socket instead of a TCP port, for a gateway
+//! behind a local nginx. The socket gets the configured file mode, a stale
+//! one left by a crashed server is replaced, and the socket is removed again
+//! when the server stops.
+
+use std::fs::{self, Permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use poem::listener::UnixListener;
+
+/// Owner and group may connect, as the proxy usually runs in the server's
+/// group.
+pub const DEFAULT_MODE: u32 = 0o660;
+
+/// A unix socket to serve on.
+#[derive(Debug, Clone)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    /// File mode of the socket, e.g. `0o660`.
+    pub mode: u32,
+}
+
+impl UnixSocket {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: DEFAULT_MODE,
+        }
+    }
+
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Check that the socket can be created: its directory exists and the
+    /// path is free or a stale socket nothing listens on.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let parent = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if !parent.is_dir() {
+            bail!("can't listen on {}: no such directory", self.path.display());
+        }
+        match fs::symlink_metadata(&self.path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => {
+                Err(err).with_context(|| format!("can't listen on {}", self.path.display()))
+            }
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                bail!("can't listen on {}: not a socket", self.path.display())
+            }
+            Ok(_) if UnixStream::connect(&self.path).is_ok() => {
+                bail!("can't listen on {}: already in use", self.path.display())
+            }
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// The listener for [`Server`](poem::Server), after removing a stale
+    /// socket, with a guard removing the socket when dropped.
+    pub fn listener(&self) -> anyhow::Result<(UnixListener<PathBuf>, SocketGuard)> {
+        self.check()?;
+        if let Err(err) = fs::remove_file(&self.path) {
+            if err.kind() != ErrorKind::NotFound {
+                return Err(err).with_context(|| format!("can't remove {}", self.path.display()));
+            }
+        }
+        let listener = UnixListener::bind(self.path.clone())
+            .with_permissions(Permissions::from_mode(self.mode));
+        Ok((listener, SocketGuard(self.path.clone())))
+    }
+}
+
+/// Removes the socket file when dropped.
+pub struct SocketGuard(PathBuf);
+
+impl Drop for SocketGuard {
+    fn drop(&mut self) {
+        let is_socket = fs::symlink_metadata(&self.0)
+            .map(|metadata| metadata.file_type().is_socket())
+            .unwrap_or(false);
+        if is_socket {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+}
+
+/// Parse an octal file mode such as `660` or `0o660`.
+pub fn parse_mode(mode: &str) -> anyhow::Result<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => bail!("{} is not an octal file mode", mode),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener as StdUnixListener;
+
+    use super::*;
+
+    #[test]
+    fn stale_sockets_are_replaced_and_live_ones_kept() {
+        let dir = std::env::temp_dir().join(format!("dws-socket-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = UnixSocket::new(dir.join("gateway.sock"));
+
+        let live = StdUnixListener::bind(&socket.path).unwrap();
+        let err = socket.check().unwrap_err();
+        assert!(err.to_string().ends_with("already in use"));
+        drop(live);
+        socket.check().unwrap();
+        let (_, guard) = socket.listener().unwrap();
+        assert!(!socket.path.exists());
+
+        StdUnixListener::bind(&socket.path).unwrap();
+        drop(guard);
+        assert!(!socket.path.exists());
+
+        fs::write(&socket.path, "").unwrap();
+        let err = socket.check().unwrap_err();
+        assert!(err.to_string().ends_with("not a socket"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn modes_are_octal() {
+        assert_eq!(parse_mode("660").unwrap(), 0o660);
+        assert_eq!(parse_mode("0o600").unwrap(), 0o600);
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("7777").is_err());
+    }
+}
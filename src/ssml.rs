@@ -0,0 +1,258 @@
+//! SSML for TTS requests. Markup sent in an `ssml` field is reduced to the
+//! tags backends are known to handle, and to its plain text for backends
+//! without SSML support.
+
+use anyhow::{bail, Context};
+use serde_json::Value;
+
+/// Tags kept in the markup, with the attributes kept on them. Other tags are
+/// dropped, keeping their content.
+const ALLOWED: &[(&str, &[&str])] = &[
+    ("break", &["time", "strength"]),
+    ("prosody", &["rate", "pitch", "volume"]),
+    ("say-as", &["interpret-as", "format", "detail"]),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ssml {
+    /// The sanitized markup, in a `<speak>` root.
+    pub markup: String,
+    /// What is spoken, without any tags.
+    pub text: String,
+}
+
+/// Check `input` and keep only the [`ALLOWED`] tags and attributes. Fails on
+/// markup that isn't well-formed.
+pub fn sanitize(input: &str) -> anyhow::Result<Ssml> {
+    let mut markup = String::new();
+    let mut text = String::new();
+    // Open elements, and whether their tags were kept
+    let mut open: Vec<(&str, bool)> = Vec::new();
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            push_text(rest, &mut markup, &mut text)?;
+            break;
+        };
+        if start > 0 {
+            push_text(&rest[..start], &mut markup, &mut text)?;
+            rest = &rest[start..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("<!--") {
+            let end = after.find("-->").context("unterminated comment")?;
+            rest = &after[end + 3..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("<?") {
+            let end = after.find("?>").context("unterminated declaration")?;
+            rest = &after[end + 2..];
+            continue;
+        }
+        if rest.starts_with("<!") {
+            bail!("DOCTYPE and CDATA sections aren't allowed");
+        }
+
+        let end = rest.find('>').context("unterminated tag")?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            match open.pop() {
+                Some((opened, kept)) if opened == name => {
+                    if kept {
+                        markup.push_str(&format!("</{}>", name));
+                    }
+                }
+                Some((opened, _)) => bail!("</{}> closes <{}>", name, opened),
+                None => bail!("</{}> has no opening tag", name),
+            }
+            continue;
+        }
+
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'));
+        if !valid_name {
+            bail!("invalid tag <{}>", tag);
+        }
+        let attributes = parse_attributes(name, attributes)?;
+
+        let allowed = ALLOWED
+            .iter()
+            .find(|(allowed, _)| *allowed == name)
+            .map(|(_, attributes)| *attributes);
+        if let Some(allowed) = allowed {
+            markup.push('<');
+            markup.push_str(name);
+            for (attribute, value) in &attributes {
+                if allowed.contains(attribute) {
+                    markup.push_str(&format!(" {}=\"{}\"", attribute, escape(value)));
+                }
+            }
+            markup.push_str(if empty { "/>" } else { ">" });
+        }
+        // Pauses separate the words around them
+        if name == "break" && !text.ends_with(char::is_whitespace) {
+            text.push(' ');
+        }
+        if !empty {
+            open.push((name, allowed.is_some()));
+        }
+    }
+
+    if let Some((name, _)) = open.last() {
+        bail!("<{}> is never closed", name);
+    }
+    Ok(Ssml {
+        markup: format!("<speak>{}</speak>", markup),
+        text: text.trim().to_string(),
+    })
+}
+
+/// The markup in the `ssml` field of a JSON request body, sanitized. The
+/// body is returned with its text in `text` instead.
+pub fn extract(body: Vec<u8>) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+    let Ok(Value::Object(mut json)) = serde_json::from_slice(&body) else {
+        return Ok((body, None));
+    };
+    let Some(input) = json.remove("ssml") else {
+        return Ok((body, None));
+    };
+    let Value::String(input) = input else {
+        bail!("`ssml` must be a string");
+    };
+    if json.contains_key("text") {
+        bail!("send either `text` or `ssml`, not both");
+    }
+    let ssml = sanitize(&input)?;
+    json.insert("text".to_string(), ssml.text.into());
+    Ok((
+        Value::Object(json).to_string().into_bytes(),
+        Some(ssml.markup),
+    ))
+}
+
+/// `body` with `markup` in an `ssml` field in place of its `text`.
+pub fn with_markup(body: Vec<u8>, markup: String) -> Vec<u8> {
+    let Ok(Value::Object(mut json)) = serde_json::from_slice(&body) else {
+        return body;
+    };
+    json.remove("text");
+    json.insert("ssml".to_string(), markup.into());
+    Value::Object(json).to_string().into_bytes()
+}
+
+fn push_text(raw: &str, markup: &mut String, text: &mut String) -> anyhow::Result<()> {
+    let decoded = decode(raw)?;
+    markup.push_str(&escape(&decoded));
+    text.push_str(&decoded);
+    Ok(())
+}
+
+fn parse_attributes<'a>(tag: &str, mut rest: &'a str) -> anyhow::Result<Vec<(&'a str, String)>> {
+    let mut attributes = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok(attributes);
+        }
+        let malformed = || format!("malformed attributes in <{}>", tag);
+        let (name, after) = rest.split_once('=').with_context(malformed)?;
+        let after = after.trim_start();
+        let quote = after
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '"' | '\''))
+            .with_context(malformed)?;
+        let end = after[1..].find(quote).with_context(malformed)?;
+        attributes.push((name.trim(), decode(&after[1..end + 1])?));
+        rest = &after[end + 2..];
+    }
+}
+
+/// Replace the predefined XML entities and character references.
+fn decode(raw: &str) -> anyhow::Result<String> {
+    let mut decoded = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .with_context(|| format!("unterminated entity in {:?}", raw))?;
+        let entity = &rest[start + 1..start + end];
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32)
+                .with_context(|| format!("unknown entity &{};", entity))?,
+        };
+        decoded.push(c);
+        rest = &rest[start + end + 1..];
+    }
+    decoded.push_str(rest);
+    Ok(decoded)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markup_keeps_only_the_allowed_tags() {
+        let ssml = sanitize(
+            r#"<?xml version="1.0"?><speak xml:lang="se">Bures<break time="500ms" onclick="x"/>
+            <prosody rate="slow"><audio src="http://x">Giitu</audio> &amp; <say-as interpret-as="date">1.2.</say-as></prosody></speak>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ssml.markup,
+            "<speak>Bures<break time=\"500ms\"/>\n            <prosody rate=\"slow\">Giitu &amp; \
+             <say-as interpret-as=\"date\">1.2.</say-as></prosody></speak>"
+        );
+        assert_eq!(ssml.text, "Bures \n            Giitu & 1.2.");
+        assert_eq!(sanitize("Bures&#x21;").unwrap().text, "Bures!");
+    }
+
+    #[test]
+    fn malformed_markup_is_rejected() {
+        let err = |input| sanitize(input).unwrap_err().to_string();
+        assert_eq!(
+            err("<speak><prosody>Bures</speak>"),
+            "</speak> closes <prosody>"
+        );
+        assert_eq!(err("<speak>Bures"), "<speak> is never closed");
+        assert_eq!(err("Bures &nbsp;"), "unknown entity &nbsp;");
+        assert_eq!(
+            err("<!DOCTYPE speak><speak/>"),
+            "DOCTYPE and CDATA sections aren't allowed"
+        );
+        assert_eq!(
+            err("<break time=500ms/>"),
+            "malformed attributes in <break>"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Usage per language, enabled by `[config.stats]`: requests, errors and
+//! latency by service and tag, kept in memory for `GET /stats` and, with a
+//! `file`, saved periodically and restored on start so counts survive
+//! restarts.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use poem::{
+    handler, http::Method, web::Data, web::Json, Endpoint, IntoResponse, Middleware, Request,
+    Response, Result,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::recording;
+use crate::server::RoutingTable;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsConfig {
+    /// JSON file the counts are saved to and restored from.
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Seconds between saves to `file`.
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval: u64,
+}
+
+fn default_flush_interval() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Counts {
+    requests: u64,
+    /// Requests answered with a 5xx.
+    errors: u64,
+    /// Requests answered with a 4xx.
+    client_errors: u64,
+    total_ms: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    /// Unix timestamp counting started at.
+    since: u64,
+    /// Counts by service, then tag.
+    services: BTreeMap<String, BTreeMap<String, Counts>>,
+}
+
+/// The counts of every language requests have been made for.
+pub struct UsageStats {
+    saved: Mutex<Saved>,
+    file: Option<PathBuf>,
+}
+
+impl UsageStats {
+    /// Start counting, from the counts in the config's `file` if it exists.
+    pub fn load(config: &StatsConfig) -> anyhow::Result<Self> {
+        let saved = match &config.file {
+            Some(path) if path.exists() => {
+                let json = std::fs::read_to_string(path)
+                    .with_context(|| format!("can't read {}", path.display()))?;
+                serde_json::from_str(&json)
+                    .with_context(|| format!("invalid stats in {}", path.display()))?
+            }
+            _ => Saved {
+                since: recording::now(),
+                ..Saved::default()
+            },
+        };
+        Ok(Self {
+            saved: Mutex::new(saved),
+            file: config.file.clone(),
+        })
+    }
+
+    pub fn record(&self, service: &str, tag: &str, status: u16, elapsed: Duration) {
+        let mut saved = self.saved.lock().unwrap();
+        let counts = saved
+            .services
+            .entry(service.to_string())
+            .or_default()
+            .entry(tag.to_string())
+            .or_default();
+        counts.requests += 1;
+        match status {
+            500.. => counts.errors += 1,
+            400..=499 => counts.client_errors += 1,
+            _ => {}
+        }
+        counts.total_ms += elapsed.as_millis() as u64;
+    }
+
+    /// The counts with each language's error rate and average latency.
+    pub fn report(&self) -> Value {
+        let saved = self.saved.lock().unwrap();
+        let services: BTreeMap<&String, BTreeMap<&String, Value>> = saved
+            .services
+            .iter()
+            .map(|(service, tags)| {
+                let tags = tags
+                    .iter()
+                    .map(|(tag, counts)| {
+                        let requests = counts.requests.max(1) as f64;
+                        let report = json!({
+                            "requests": counts.requests,
+                            "errors": counts.errors,
+                            "client_errors": counts.client_errors,
+                            "error_rate": counts.errors as f64 / requests,
+                            "average_ms": counts.total_ms as f64 / requests,
+                        });
+                        (tag, report)
+                    })
+                    .collect();
+                (service, tags)
+            })
+            .collect();
+        json!({ "since": saved.since, "services": services })
+    }
+
+    /// Write the counts to the `file`, if there is one.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(&*self.saved.lock().unwrap())?;
+        write_atomically(path, &json).with_context(|| format!("can't write {}", path.display()))
+    }
+
+    /// Save every `interval` for the lifetime of the process.
+    pub fn spawn_flush(self: &Arc<Self>, interval: Duration) {
+        let stats = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                if let Err(err) = stats.save() {
+                    tracing::warn!("Can't save the usage stats: {:#}", err);
+                }
+            }
+        });
+    }
+}
+
+/// Write next to `path` first, so readers never see half a file.
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(&partial, path)
+}
+
+#[handler]
+pub async fn stats_get(Data(stats): Data<&Arc<UsageStats>>) -> Json<Value> {
+    Json(stats.report())
+}
+
+/// Middleware counting the requests for configured languages, by their
+/// service and tag, with aliases counted as their tag.
+pub struct UsageTracking {
+    pub stats: Option<Arc<UsageStats>>,
+    pub routing: RoutingTable,
+}
+
+impl<E: Endpoint> Middleware<E> for UsageTracking {
+    type Output = UsageTrackingEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        UsageTrackingEndpoint {
+            inner: ep,
+            stats: self.stats.clone(),
+            routing: self.routing.clone(),
+        }
+    }
+}
+
+pub struct UsageTrackingEndpoint<E> {
+    inner: E,
+    stats: Option<Arc<UsageStats>>,
+    routing: RoutingTable,
+}
+
+impl<E: Endpoint> UsageTrackingEndpoint<E> {
+    /// The service and configured tag `req` is for, if any.
+    fn language(&self, req: &Request) -> Option<(String, String)> {
+        if req.method() == Method::OPTIONS {
+            return None;
+        }
+        let mut segments = req.uri().path().trim_start_matches('/').split('/');
+        let (service, tag) = (segments.next()?, segments.next()?);
+        let languages = &self.routing.snapshot().languages;
+        let tag = languages.canonical_tag(service, tag);
+        languages.language_name(service, &tag)?;
+        Some((service.to_string(), tag))
+    }
+}
+
+impl<E: Endpoint> Endpoint for UsageTrackingEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let language = self.stats.as_ref().and_then(|_| self.language(&req));
+        let (Some(stats), Some((service, tag))) = (&self.stats, language) else {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        };
+
+        let start = Instant::now();
+        let response = match self.inner.call(req).await {
+            Ok(response) => response.into_response(),
+            Err(err) => err.into_response(),
+        };
+        stats.record(&service, &tag, response.status().as_u16(), start.elapsed());
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("stats-{}.json", std::process::id()));
+        let config = StatsConfig {
+            file: Some(path.clone()),
+            flush_interval: 60,
+        };
+        let stats = UsageStats::load(&config).unwrap();
+        stats.record("grammar", "se", 200, Duration::from_millis(30));
+        stats.record("grammar", "se", 502, Duration::from_millis(10));
+        stats.record("speller", "sma", 400, Duration::from_millis(5));
+        stats.save().unwrap();
+
+        let report = UsageStats::load(&config).unwrap().report();
+        assert_eq!(
+            report["services"]["grammar"]["se"],
+            json!({
+                "requests": 2,
+                "errors": 1,
+                "client_errors": 0,
+                "error_rate": 0.5,
+                "average_ms": 20.0,
+            })
+        );
+        assert_eq!(report["services"]["speller"]["sma"]["client_errors"], 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
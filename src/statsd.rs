@@ -0,0 +1,181 @@
+//! Request metrics pushed to a StatsD or DogStatsD agent.
+//!
+//! Every request counts towards `<prefix>.requests` and times into
+//! `<prefix>.request_duration`, split by the first path segment (`route`,
+//! e.g. `grammar`) and response status. DogStatsD receives these as tags;
+//! plain StatsD gets them appended to the metric name.
+
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Context;
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsdConfig {
+    /// Agent address, e.g. `127.0.0.1:8125`.
+    pub address: String,
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// Send tags in the DogStatsD format instead of folding them into names.
+    #[serde(default)]
+    pub dogstatsd: bool,
+}
+
+fn default_prefix() -> String {
+    "divvun_worker_static".to_string()
+}
+
+/// A fire-and-forget StatsD client. Metrics that can't be sent are dropped.
+pub struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+    dogstatsd: bool,
+}
+
+impl Statsd {
+    pub fn new(config: &StatsdConfig) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket
+            .connect(&config.address)
+            .with_context(|| format!("can't reach StatsD at {}", config.address))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix: config.prefix.clone(),
+            dogstatsd: config.dogstatsd,
+        })
+    }
+
+    pub fn count(&self, name: &str, value: i64, tags: &[(&str, &str)]) {
+        self.send(name, &value.to_string(), "c", tags);
+    }
+
+    pub fn timing(&self, name: &str, millis: u64, tags: &[(&str, &str)]) {
+        self.send(name, &millis.to_string(), "ms", tags);
+    }
+
+    fn send(&self, name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) {
+        let line = if self.dogstatsd {
+            let tags = tags
+                .iter()
+                .map(|(key, value)| format!("{}:{}", key, value))
+                .collect::<Vec<_>>()
+                .join(",");
+            if tags.is_empty() {
+                format!("{}.{}:{}|{}", self.prefix, name, value, kind)
+            } else {
+                format!("{}.{}:{}|{}|#{}", self.prefix, name, value, kind, tags)
+            }
+        } else {
+            let mut metric = format!("{}.{}", self.prefix, name);
+            for (_, value) in tags {
+                metric.push('.');
+                metric.push_str(&value.replace(['.', ':', '|', '@'], "_"));
+            }
+            format!("{}:{}|{}", metric, value, kind)
+        };
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+/// Middleware recording every request with a [`Statsd`] client, if one is
+/// configured.
+pub struct StatsdMetrics(pub Option<Arc<Statsd>>);
+
+impl<E: Endpoint> Middleware<E> for StatsdMetrics {
+    type Output = StatsdEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        StatsdEndpoint {
+            inner: ep,
+            statsd: self.0.clone(),
+        }
+    }
+}
+
+pub struct StatsdEndpoint<E> {
+    inner: E,
+    statsd: Option<Arc<Statsd>>,
+}
+
+impl<E: Endpoint> Endpoint for StatsdEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let Some(statsd) = &self.statsd else {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        };
+        let route = req
+            .uri()
+            .path()
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .filter(|segment| !segment.is_empty())
+            .unwrap_or("index")
+            .to_string();
+        let start = Instant::now();
+
+        let response = match self.inner.call(req).await {
+            Ok(response) => response.into_response(),
+            Err(err) => err.into_response(),
+        };
+
+        let status = response.status().as_u16().to_string();
+        let tags = [("route", route.as_str()), ("status", status.as_str())];
+        statsd.count("requests", 1, &tags);
+        statsd.timing(
+            "request_duration",
+            start.elapsed().as_millis() as u64,
+            &tags,
+        );
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listener() -> (UdpSocket, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+            .unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        (socket, address)
+    }
+
+    fn receive(socket: &UdpSocket) -> String {
+        let mut buf = [0; 512];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn tags_follow_the_flavour() {
+        let (socket, address) = listener();
+        let config = |dogstatsd| StatsdConfig {
+            address: address.clone(),
+            prefix: "dws".to_string(),
+            dogstatsd,
+        };
+        let tags = [("route", "grammar"), ("status", "200")];
+
+        Statsd::new(&config(true))
+            .unwrap()
+            .count("requests", 1, &tags);
+        assert_eq!(
+            receive(&socket),
+            "dws.requests:1|c|#route:grammar,status:200"
+        );
+
+        Statsd::new(&config(false))
+            .unwrap()
+            .timing("request_duration", 12, &tags);
+        assert_eq!(receive(&socket), "dws.request_duration.grammar.200:12|ms");
+    }
+}
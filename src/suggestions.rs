@@ -0,0 +1,120 @@
+//! Shorter speller answers. A check request may send `max_suggestions` and
+//! `max_weight`, e.g. from a mobile client that shows only the top few
+//! corrections; the gateway drops the other suggestions from the backend's
+//! answer.
+
+use anyhow::bail;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SuggestionLimits {
+    /// Suggestions kept per word, best first.
+    max_suggestions: Option<usize>,
+    /// Suggestions weighing more, i.e. less likely, are dropped.
+    max_weight: Option<f64>,
+}
+
+impl SuggestionLimits {
+    /// The limits a JSON request body asks for, and the body without their
+    /// fields.
+    pub fn requested(body: Vec<u8>) -> anyhow::Result<(Vec<u8>, Option<Self>)> {
+        let Ok(Value::Object(mut json)) = serde_json::from_slice(&body) else {
+            return Ok((body, None));
+        };
+        let max_suggestions = match json.remove("max_suggestions") {
+            None => None,
+            Some(value) => match value.as_u64() {
+                Some(max) => Some(max as usize),
+                None => bail!("`max_suggestions` must be a non-negative integer"),
+            },
+        };
+        let max_weight = match json.remove("max_weight") {
+            None => None,
+            Some(value) => match value.as_f64() {
+                Some(max) => Some(max),
+                None => bail!("`max_weight` must be a number"),
+            },
+        };
+        if max_suggestions.is_none() && max_weight.is_none() {
+            return Ok((body, None));
+        }
+        let limits = Self {
+            max_suggestions,
+            max_weight,
+        };
+        Ok((Value::Object(json).to_string().into_bytes(), Some(limits)))
+    }
+
+    /// `body` with each result's `suggestions` cut to the limits.
+    pub fn apply(&self, body: Vec<u8>) -> Vec<u8> {
+        let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+            return body;
+        };
+        let Some(results) = json.get_mut("results").and_then(Value::as_array_mut) else {
+            return body;
+        };
+        for result in results {
+            let Some(suggestions) = result.get_mut("suggestions").and_then(Value::as_array_mut)
+            else {
+                continue;
+            };
+            if let Some(max_weight) = self.max_weight {
+                suggestions.retain(|suggestion| {
+                    suggestion
+                        .get("weight")
+                        .and_then(Value::as_f64)
+                        .is_none_or(|weight| weight <= max_weight)
+                });
+            }
+            if let Some(max) = self.max_suggestions {
+                suggestions.truncate(max);
+            }
+        }
+        json.to_string().into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn suggestions_are_cut_to_the_limits() {
+        let body = json!({ "text": "sami", "max_suggestions": 2, "max_weight": 50.0 });
+        let (body, limits) = SuggestionLimits::requested(body.to_string().into_bytes()).unwrap();
+        assert_eq!(body, br#"{"text":"sami"}"#);
+
+        let suggestion = |value: &str, weight: f64| json!({ "value": value, "weight": weight });
+        let response = json!({
+            "text": "sami",
+            "results": [{
+                "word": "sami",
+                "is_correct": false,
+                "suggestions": [
+                    suggestion("sámi", 14.5),
+                    suggestion("sama", 40.3),
+                    suggestion("sam", 45.9),
+                ],
+            }, {
+                "word": "sámit",
+                "is_correct": false,
+                "suggestions": [suggestion("sámi", 55.3)],
+            }],
+        });
+        let cut: Value =
+            serde_json::from_slice(&limits.unwrap().apply(response.to_string().into_bytes()))
+                .unwrap();
+        assert_eq!(
+            cut["results"][0]["suggestions"],
+            json!([suggestion("sámi", 14.5), suggestion("sama", 40.3)])
+        );
+        assert_eq!(cut["results"][1]["suggestions"], json!([]));
+
+        let err = SuggestionLimits::requested(br#"{"max_suggestions":-1}"#.to_vec()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`max_suggestions` must be a non-negative integer"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! systemd units for the backend workers, one per [`Worker`], so they are
+//! managed with `systemctl` from the same config as the gateway.
+//!
+//! Each unit passes the worker's port as `PORT` and its language as `TAG`;
+//! the command is a template, since workers are separate programs.
+
+use std::path::PathBuf;
+
+use crate::config::LanguagesConfig;
+use crate::services::{workers, ServiceRegistry, Worker};
+
+/// Default worker command; see [`Worker::expand`] for the placeholders.
+pub const EXEC_START: &str = "/usr/local/bin/divvun-worker-{service}";
+
+/// The unit files, e.g. `grammar-se.service`, with `exec_start` as each
+/// worker's command.
+pub fn generate_units(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    exec_start: &str,
+) -> Vec<(PathBuf, String)> {
+    workers(languages, services)
+        .iter()
+        .map(|worker| {
+            (
+                PathBuf::from(format!("{}.service", worker.id)),
+                unit(worker, exec_start),
+            )
+        })
+        .collect()
+}
+
+fn unit(worker: &Worker, exec_start: &str) -> String {
+    let tag = match &worker.tag {
+        Some(tag) => format!("\nEnvironment=TAG={}", tag),
+        None => String::new(),
+    };
+    format!(
+        r#"# Generated by divvun-worker-static. Do not edit.
+[Unit]
+Description=Divvun {name} backend
+After=network.target
+
+[Service]
+Environment=PORT={port}{tag}
+ExecStart={exec_start}
+Restart=on-failure
+DynamicUser=true
+
+[Install]
+WantedBy=multi-user.target
+"#,
+        name = worker.name,
+        port = worker.port,
+        tag = tag,
+        exec_start = worker.expand(exec_start),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_carry_the_configured_ports() {
+        let languages = LanguagesConfig::embedded().unwrap();
+        let units = generate_units(
+            &languages,
+            &ServiceRegistry::builtin(),
+            "/opt/divvun/{service} --port {port}",
+        );
+        let unit = |name: &str| {
+            units
+                .iter()
+                .find(|(path, _)| path == &PathBuf::from(name))
+                .map(|(_, unit)| unit.as_str())
+                .unwrap()
+        };
+
+        assert!(unit("grammar-se.service")
+            .contains("Environment=PORT=10000\nEnvironment=TAG=se\nExecStart=/opt/divvun/grammar --port 10000\n"));
+        assert!(unit("tts.service").contains("Environment=PORT=40001\nExecStart="));
+    }
+
+    #[test]
+    fn each_instance_gets_a_unit() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.speller.get_mut("se").unwrap().ports = vec![
+            crate::config::Instance::local(4001),
+            crate::config::Instance::local(4002),
+        ];
+        let units = generate_units(&languages, &ServiceRegistry::builtin(), EXEC_START);
+        let unit = |name: &str| {
+            units
+                .iter()
+                .find(|(path, _)| path == &PathBuf::from(name))
+                .map(|(_, unit)| unit.as_str())
+        };
+
+        assert!(unit("speller-se.service").is_none());
+        assert!(unit("speller-se-4001.service")
+            .unwrap()
+            .contains("Environment=PORT=4001\nEnvironment=TAG=se\n"));
+        assert!(unit("speller-se-4002.service")
+            .unwrap()
+            .contains("Description=Divvun speller-se backend\n"));
+    }
+
+    #[test]
+    fn backends_on_other_hosts_get_no_unit() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.speller.get_mut("se").unwrap().ports = vec![
+            crate::config::Instance::local(4001),
+            crate::config::Instance {
+                host: "10.0.0.9".to_string(),
+                port: 4001,
+                weight: 1,
+            },
+        ];
+        let units = generate_units(&languages, &ServiceRegistry::builtin(), EXEC_START);
+        let names: Vec<_> = units
+            .iter()
+            .map(|(path, _)| path.to_str().unwrap())
+            .filter(|name| name.starts_with("speller-se"))
+            .collect();
+
+        assert_eq!(names, ["speller-se.service"]);
+    }
+}
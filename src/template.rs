@@ -0,0 +1,100 @@
+//! The HTML pages' templates, rendered with minijinja from a serializable
+//! context. Values are HTML-escaped unless marked `| safe`, a missing or
+//! `none` value prints nothing, and a `{% … %}` tag alone on its line takes
+//! no line in the output.
+//!
+//! The index page is `index.html`; the section each service documents on it
+//! is `templates/<service>.html`, with `templates/endpoint.html` for service
+//! kinds registered from outside the crate.
+
+use std::sync::OnceLock;
+
+use minijinja::{escape_formatter, AutoEscape, Environment, UndefinedBehavior, Value};
+use serde::Serialize;
+
+const TEMPLATES: &[(&str, &str)] = &[
+    ("index.html", include_str!("../index.html")),
+    ("endpoint.html", include_str!("../templates/endpoint.html")),
+    ("grammar.html", include_str!("../templates/grammar.html")),
+    (
+        "hyphenation.html",
+        include_str!("../templates/hyphenation.html"),
+    ),
+    ("speller.html", include_str!("../templates/speller.html")),
+    ("tts.html", include_str!("../templates/tts.html")),
+];
+
+fn environment() -> &'static Environment<'static> {
+    static ENVIRONMENT: OnceLock<Environment<'static>> = OnceLock::new();
+    ENVIRONMENT.get_or_init(|| {
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_keep_trailing_newline(true);
+        env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+        env.set_formatter(|out, state, value| {
+            if value.is_none() {
+                return Ok(());
+            }
+            // minijinja also escapes `/`, which would garble every URL
+            if state.auto_escape() == AutoEscape::Html && !value.is_safe() {
+                return Ok(out.write_str(&escape_html(&value.to_string()))?);
+            }
+            escape_formatter(out, state, value)
+        });
+        for (name, source) in TEMPLATES {
+            env.add_template(name, source)
+                .unwrap_or_else(|err| panic!("{} is a valid template: {:#}", name, err));
+        }
+        env
+    })
+}
+
+/// Render the template `name` from `context`.
+pub fn render(name: &str, context: impl Serialize) -> String {
+    environment()
+        .get_template(name)
+        .and_then(|template| template.render(Value::from_serialize(context)))
+        .unwrap_or_else(|err| panic!("{} renders: {:#}", name, err))
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn values_are_escaped_unless_safe() {
+        let env = environment();
+        let html = env
+            .render_named_str(
+                "test.html",
+                "<ul>\n    {% for link in links %}\n    <li>{{ link.label }}{{ extra | safe }}</li>\n    {% endfor %}\n</ul>\n{% if footer %}<footer>{{ footer }}</footer>{% else %}-{% endif %}{{ missing }}",
+                json!({
+                    "links": [{ "label": "A & B" }, { "label": "<C>" }],
+                    "extra": "<b>!</b>",
+                    "footer": "",
+                    "missing": null,
+                }),
+            )
+            .unwrap();
+        assert_eq!(
+            html,
+            "<ul>\n    <li>A &amp; B<b>!</b></li>\n    <li>&lt;C&gt;<b>!</b></li>\n</ul>\n-"
+        );
+    }
+
+    #[test]
+    fn every_template_compiles() {
+        for (name, _) in TEMPLATES {
+            assert!(environment().get_template(name).is_ok(), "{}", name);
+        }
+    }
+}
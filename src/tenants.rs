@@ -0,0 +1,117 @@
+//! Serving several institutions from one deployment. Each tenant has its own
+//! `languages.toml`, with its own languages, branding and limits, and is
+//! chosen by the request's `Host` header:
+//!
+//! ```toml
+//! [config.tenants.partner]
+//! hosts = ["api.partner.example"]
+//! config = "partner.toml"
+//! ```
+//!
+//! Requests for any other host get the main configuration.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use poem::endpoint::BoxEndpoint;
+use poem::{http::header, Endpoint, Request, Response, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::LanguagesConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantConfig {
+    /// Host names served with this tenant's configuration.
+    pub hosts: Vec<String>,
+    /// The tenant's `languages.toml`, relative to the main config file.
+    pub config: PathBuf,
+}
+
+/// A tenant's name, hosts and loaded configuration.
+pub struct Tenant {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub languages: LanguagesConfig,
+}
+
+/// Load the configurations of the tenants `languages` declares, in name
+/// order. Relative paths resolve against `base`.
+pub fn load(languages: &LanguagesConfig, base: Option<&Path>) -> anyhow::Result<Vec<Tenant>> {
+    let mut tenants: Vec<_> = languages.config.tenants.iter().collect();
+    tenants.sort_by_key(|(name, _)| *name);
+    tenants
+        .into_iter()
+        .map(|(name, tenant)| {
+            let path = match base {
+                Some(base) => base.join(&tenant.config),
+                None => tenant.config.clone(),
+            };
+            let languages = LanguagesConfig::from_file(&path)
+                .with_context(|| format!("can't load tenant {}", name))?;
+            if !languages.config.tenants.is_empty() {
+                bail!("tenant {} can't declare tenants of its own", name);
+            }
+            Ok(Tenant {
+                name: name.clone(),
+                hosts: tenant.hosts.iter().map(|host| normalize(host)).collect(),
+                languages,
+            })
+        })
+        .collect()
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// The host a request was sent to, without the port.
+fn request_host(req: &Request) -> Option<String> {
+    let host = req
+        .header(header::HOST)
+        .or_else(|| req.uri().authority().map(|authority| authority.as_str()))?;
+    let host = match host.rsplit_once(':') {
+        // Keep IPv6 literals like [::1] whole
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+    Some(normalize(host))
+}
+
+/// Routes each request to the app of the tenant serving its host.
+pub struct HostRouter {
+    default: BoxEndpoint<'static>,
+    tenants: Vec<BoxEndpoint<'static>>,
+    /// Index into `tenants` by host.
+    hosts: HashMap<String, usize>,
+}
+
+impl HostRouter {
+    pub fn new(default: BoxEndpoint<'static>) -> Self {
+        Self {
+            default,
+            tenants: Vec::new(),
+            hosts: HashMap::new(),
+        }
+    }
+
+    pub fn tenant(mut self, hosts: &[String], app: BoxEndpoint<'static>) -> Self {
+        for host in hosts {
+            self.hosts.insert(host.clone(), self.tenants.len());
+        }
+        self.tenants.push(app);
+        self
+    }
+}
+
+impl Endpoint for HostRouter {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let tenant = request_host(&req).and_then(|host| self.hosts.get(&host).copied());
+        match tenant {
+            Some(index) => self.tenants[index].call(req).await,
+            None => self.default.call(req).await,
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! Mock backends and an in-process gateway for end-to-end tests, shared by
+//! this crate's tests and downstream smoke tests via the `test-utils`
+//! feature.
+//!
+//! [`TestGateway`] starts one [`MockBackend`] per configured backend port,
+//! points the configuration at them and serves the gateway through poem's
+//! [`TestClient`], so a test can send a request and then check what the
+//! backend received.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use poem::{
+    endpoint::{make, BoxEndpoint},
+    http::{HeaderMap, StatusCode},
+    listener::TcpAcceptor,
+    test::{TestClient, TestResponse},
+    EndpointExt, Request, Response, Server,
+};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+
+use crate::config::LanguagesConfig;
+use crate::server::ServerBuilder;
+use crate::services::ServiceRegistry;
+
+/// A request received by a [`MockBackend`].
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: HeaderMap,
+    /// The client's address, which tells connections apart.
+    pub remote_addr: String,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    /// The `text` field of a JSON request body.
+    pub fn text(&self) -> Option<String> {
+        let body: Value = serde_json::from_slice(&self.body).ok()?;
+        body.get("text")?.as_str().map(str::to_string)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: StatusCode,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn json(body: Value) -> Self {
+        Self {
+            status: StatusCode::OK,
+            content_type: "application/json",
+            body: body.to_string().into_bytes(),
+        }
+    }
+}
+
+/// An HTTP server on an ephemeral local port that records every request and
+/// answers with a canned response. Stops when dropped.
+pub struct MockBackend {
+    port: u16,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockBackend {
+    pub async fn start(
+        respond: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    ) -> anyhow::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let acceptor = TcpAcceptor::from_tokio(listener)?;
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond = Arc::new(respond);
+        let recorded = requests.clone();
+        let endpoint = make(move |req: Request| {
+            let recorded = recorded.clone();
+            let respond = respond.clone();
+            async move {
+                let request = MockRequest {
+                    path: req.uri().path().to_string(),
+                    query: req.uri().query().map(str::to_string),
+                    headers: req.headers().clone(),
+                    remote_addr: req.remote_addr().to_string(),
+                    body: req.into_body().into_vec().await.unwrap_or_default(),
+                };
+                let response = respond(&request);
+                recorded.lock().unwrap().push(request);
+                Response::builder()
+                    .status(response.status)
+                    .content_type(response.content_type)
+                    .body(response.body)
+            }
+        });
+
+        let task = tokio::spawn(async move {
+            let _ = Server::new_with_acceptor(acceptor).run(endpoint).await;
+        });
+
+        Ok(Self {
+            port,
+            requests,
+            task,
+        })
+    }
+
+    /// A grammar checker that finds no errors.
+    pub async fn grammar() -> anyhow::Result<Self> {
+        Self::start(|request| {
+            MockResponse::json(json!({ "text": request.text().unwrap_or_default(), "errs": [] }))
+        })
+        .await
+    }
+
+    /// A speller that accepts every word.
+    pub async fn speller() -> anyhow::Result<Self> {
+        Self::start(|request| {
+            let text = request.text().unwrap_or_default();
+            let results = text
+                .split_whitespace()
+                .map(|word| json!({ "word": word, "is_correct": true, "suggestions": [] }))
+                .collect::<Vec<_>>();
+            MockResponse::json(json!({ "text": text, "results": results }))
+        })
+        .await
+    }
+
+    /// A hyphenator that allows a hyphen in the middle of every word longer
+    /// than three letters.
+    pub async fn hyphenation() -> anyhow::Result<Self> {
+        Self::start(|request| {
+            let text = request.text().unwrap_or_default();
+            let results = text
+                .split_whitespace()
+                .map(|word| {
+                    let chars: Vec<char> = word.chars().collect();
+                    let pattern: String = if chars.len() > 3 {
+                        let (head, tail) = chars.split_at(chars.len() / 2);
+                        head.iter().chain(&['^']).chain(tail).collect()
+                    } else {
+                        word.to_string()
+                    };
+                    json!({ "word": word, "patterns": [{ "value": pattern, "weight": 0.0 }] })
+                })
+                .collect::<Vec<_>>();
+            MockResponse::json(json!({ "text": text, "results": results }))
+        })
+        .await
+    }
+
+    /// A speech synthesizer that answers with an empty WAV file.
+    pub async fn tts() -> anyhow::Result<Self> {
+        Self::start(|_| MockResponse {
+            status: StatusCode::OK,
+            content_type: "audio/wav",
+            body: wav(&[]),
+        })
+        .await
+    }
+
+    /// A backend that returns the request body unchanged.
+    pub async fn echo() -> anyhow::Result<Self> {
+        Self::start(|request| MockResponse {
+            status: StatusCode::OK,
+            content_type: "application/octet-stream",
+            body: request.body.clone(),
+        })
+        .await
+    }
+
+    /// The canned backend for a service category, falling back to [`echo`].
+    ///
+    /// [`echo`]: MockBackend::echo
+    pub async fn for_service(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "grammar" => Self::grammar().await,
+            "speller" => Self::speller().await,
+            "hyphenation" => Self::hyphenation().await,
+            "tts" => Self::tts().await,
+            _ => Self::echo().await,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockBackend {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A WAV file of 16 kHz mono audio with `samples` as its data.
+pub fn wav(samples: &[u8]) -> Vec<u8> {
+    let mut wav = Vec::with_capacity(44 + samples.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&16_000u32.to_le_bytes());
+    wav.extend_from_slice(&32_000u32.to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(samples);
+    wav
+}
+
+/// The gateway wired to a [`MockBackend`] for every configured backend port.
+pub struct TestGateway {
+    client: TestClient<BoxEndpoint<'static>>,
+    backends: HashMap<(String, String), Arc<MockBackend>>,
+}
+
+impl TestGateway {
+    pub async fn start(
+        mut languages: LanguagesConfig,
+        services: ServiceRegistry,
+    ) -> anyhow::Result<Self> {
+        // Backends sharing a port (e.g. TTS voices) share a mock
+        let mut by_port: HashMap<u16, Arc<MockBackend>> = HashMap::new();
+        let mut backends = HashMap::new();
+        for kind in services.iter() {
+            for location in kind.locations(&languages) {
+                let backend = match by_port.get(&location.port) {
+                    Some(backend) => backend.clone(),
+                    None => {
+                        let backend = Arc::new(MockBackend::for_service(kind.name()).await?);
+                        by_port.insert(location.port, backend.clone());
+                        backend
+                    }
+                };
+                backends.insert((kind.name().to_string(), location.tag), backend);
+            }
+        }
+
+        let port = |port: &mut u16| {
+            if let Some(backend) = by_port.get(port) {
+                *port = backend.port();
+            }
+        };
+        port(&mut languages.config.tts.port);
+        for services in [
+            &mut languages.grammar,
+            &mut languages.speller,
+            &mut languages.hyphenation,
+        ]
+        .into_iter()
+        .chain(languages.custom.values_mut())
+        {
+            for service in services.values_mut() {
+                port(&mut service.port);
+            }
+        }
+
+        Self::serve(languages, services, backends)
+    }
+
+    /// The gateway for `languages` with a [`MockBackend`] answering with
+    /// `respond` as `service`'s backend for `tag`, and no other backends.
+    pub async fn with_backend(
+        languages: LanguagesConfig,
+        service: &str,
+        tag: &str,
+        respond: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    ) -> anyhow::Result<Self> {
+        let backend = MockBackend::start(respond).await?;
+        Self::with_backends(languages, [(service, tag, backend)])
+    }
+
+    /// The gateway for `languages` with each of `backends` as `service`'s
+    /// backend for `tag`, and no other backends. A TTS backend serves all
+    /// voices.
+    pub fn with_backends<'a>(
+        mut languages: LanguagesConfig,
+        backends: impl IntoIterator<Item = (&'a str, &'a str, MockBackend)>,
+    ) -> anyhow::Result<Self> {
+        let mut mocks = HashMap::new();
+        for (service, tag, backend) in backends {
+            let port = match service {
+                "tts" => Some(&mut languages.config.tts.port),
+                "grammar" => languages.grammar.get_mut(tag).map(|s| &mut s.port),
+                "speller" => languages.speller.get_mut(tag).map(|s| &mut s.port),
+                "hyphenation" => languages.hyphenation.get_mut(tag).map(|s| &mut s.port),
+                other => languages
+                    .custom
+                    .get_mut(other)
+                    .and_then(|services| services.get_mut(tag))
+                    .map(|s| &mut s.port),
+            };
+            let Some(port) = port else {
+                anyhow::bail!("no {} backend configured for {}", service, tag);
+            };
+            *port = backend.port();
+            mocks.insert((service.to_string(), tag.to_string()), Arc::new(backend));
+        }
+        Self::serve(languages, ServiceRegistry::builtin(), mocks)
+    }
+
+    fn serve(
+        languages: LanguagesConfig,
+        services: ServiceRegistry,
+        backends: HashMap<(String, String), Arc<MockBackend>>,
+    ) -> anyhow::Result<Self> {
+        let gateway = ServerBuilder::new()
+            .languages(languages)
+            .services(services)
+            .health_checks(false)
+            .build()?;
+
+        Ok(Self {
+            client: TestClient::new(gateway.map_to_response().boxed()),
+            backends,
+        })
+    }
+
+    pub fn client(&self) -> &TestClient<BoxEndpoint<'static>> {
+        &self.client
+    }
+
+    /// The mock standing in for `service`'s backend for `tag`.
+    pub fn backend(&self, service: &str, tag: &str) -> Option<&MockBackend> {
+        self.backends
+            .get(&(service.to_string(), tag.to_string()))
+            .map(Arc::as_ref)
+    }
+
+    /// Post `body` to `path`, asserting that the gateway answers successfully
+    /// after forwarding exactly one request to `service`'s backend for `tag`.
+    /// Returns the gateway's response and the request the backend received.
+    pub async fn assert_proxied(
+        &self,
+        service: &str,
+        tag: &str,
+        path: &str,
+        body: impl Into<Vec<u8>>,
+    ) -> (TestResponse, MockRequest) {
+        let backend = self
+            .backend(service, tag)
+            .unwrap_or_else(|| panic!("no {} backend configured for {}", service, tag));
+        let received = backend.requests().len();
+
+        let response = self.client.post(path).body(body.into()).send().await;
+        response.assert_status_is_ok();
+
+        let mut requests = backend.requests();
+        assert_eq!(
+            requests.len(),
+            received + 1,
+            "{} was not forwarded to the {} backend for {}",
+            path,
+            service,
+            tag
+        );
+        (response, requests.remove(received))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn grammar_mock_echoes_text_without_errors() {
+        let backend = MockBackend::grammar().await.unwrap();
+
+        let response = reqwest::Client::new()
+            .post(format!("http://127.0.0.1:{}/", backend.port()))
+            .body(r#"{"text":"Bures"}"#)
+            .send()
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+
+        assert_eq!(body, json!({ "text": "Bures", "errs": [] }));
+        assert_eq!(backend.requests()[0].text().as_deref(), Some("Bures"));
+    }
+}
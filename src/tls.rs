@@ -0,0 +1,78 @@
+//! HTTPS for the built-in server, for deployments without a fronting proxy:
+//! either a certificate and key from PEM files, or, with the `acme` feature,
+//! certificates requested from Let's Encrypt and renewed automatically.
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+#[cfg(feature = "acme")]
+use poem::listener::acme::AutoCert;
+use poem::listener::{BoxListener, Listener, RustlsCertificate, RustlsConfig};
+
+/// Where the server's certificates come from.
+#[derive(Debug, Clone)]
+pub enum TlsConfig {
+    /// A PEM certificate chain and private key.
+    Files { cert: PathBuf, key: PathBuf },
+    /// Certificates for `domains` from Let's Encrypt, answering its
+    /// TLS-ALPN-01 challenges on the server's own port, cached in
+    /// `cache_dir` across restarts.
+    #[cfg(feature = "acme")]
+    Acme {
+        domains: Vec<String>,
+        cache_dir: PathBuf,
+    },
+}
+
+impl TlsConfig {
+    /// Check that the certificate and key can be read.
+    pub fn check(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Files { cert, key } => {
+                read_pem(cert)?;
+                read_pem(key)?;
+            }
+            #[cfg(feature = "acme")]
+            Self::Acme { domains, .. } => {
+                if domains.is_empty() {
+                    anyhow::bail!("ACME needs at least one domain");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Serve TLS on `listener`.
+    pub fn listener(&self, listener: BoxListener) -> anyhow::Result<BoxListener> {
+        match self {
+            Self::Files { cert, key } => {
+                let certificate = RustlsCertificate::new()
+                    .cert(read_pem(cert)?)
+                    .key(read_pem(key)?);
+                Ok(listener
+                    .rustls(RustlsConfig::new().fallback(certificate))
+                    .boxed())
+            }
+            #[cfg(feature = "acme")]
+            Self::Acme { domains, cache_dir } => {
+                let auto_cert = domains
+                    .iter()
+                    .fold(AutoCert::builder(), |builder, domain| {
+                        builder.domain(domain)
+                    })
+                    .cache_path(cache_dir)
+                    .build()
+                    .context("can't set up ACME")?;
+                Ok(listener.acme(auto_cert).boxed())
+            }
+        }
+    }
+}
+
+fn read_pem(path: &PathBuf) -> anyhow::Result<Vec<u8>> {
+    let pem = std::fs::read(path).with_context(|| format!("can't read {}", path.display()))?;
+    if !pem.starts_with(b"-----BEGIN ") {
+        anyhow::bail!("{} is not a PEM file", path.display());
+    }
+    Ok(pem)
+}
@@ -0,0 +1,171 @@
+//! Traefik dynamic configuration, equivalent to the nginx locations for
+//! deployments using Traefik, loaded with its file provider.
+//!
+//! Traefik can rewrite paths but not add query parameters, so locations
+//! that need them (TTS voices) are routed to the gateway, which adds them.
+//! Requests for a language with several backend instances are spread over
+//! them round-robin, by weight.
+
+use crate::config::LanguagesConfig;
+use crate::services::{Location, ServiceRegistry};
+
+/// Render a router, service and middlewares per configured service,
+/// language and voice. `gateway` is the gateway's URL, e.g.
+/// `http://127.0.0.1:4000`.
+pub fn generate_traefik_config(
+    languages: &LanguagesConfig,
+    services: &ServiceRegistry,
+    gateway: &str,
+) -> String {
+    let mut routers = Vec::new();
+    let mut middlewares = Vec::new();
+    let mut backends = Vec::new();
+    let mut via_gateway = false;
+    for location in services.proxy_locations(languages) {
+        let name = route_name(&location);
+        let mut chain = Vec::new();
+        let service = if location.query.is_empty() {
+            middlewares.push(format!(
+                "    {}:\n      replacePath:\n        path: \"/\"",
+                name
+            ));
+            chain.push(name.clone());
+            let servers: Vec<(String, u32)> = location
+                .servers()
+                .iter()
+                .map(|server| (format!("http://{}", server), server.weight))
+                .collect();
+            backends.push(service_block(&name, &servers));
+            name.clone()
+        } else {
+            via_gateway = true;
+            "gateway".to_string()
+        };
+
+        let headers = languages.response_headers(&location.path);
+        if !headers.is_empty() {
+            let headers_name = format!("{}-headers", name);
+            let headers: String = headers
+                .iter()
+                .map(|(header, value)| format!("\n          {}: \"{}\"", header, value))
+                .collect();
+            middlewares.push(format!(
+                "    {}:\n      headers:\n        customResponseHeaders:{}",
+                headers_name, headers
+            ));
+            chain.push(headers_name);
+        }
+
+        let chain: String = chain
+            .iter()
+            .map(|middleware| format!("\n        - {}", middleware))
+            .collect();
+        let chain = if chain.is_empty() {
+            String::new()
+        } else {
+            format!("\n      middlewares:{}", chain)
+        };
+        routers.push(format!(
+            "    {}:\n      rule: \"Path(`{}`)\"\n      service: {}{}",
+            name, location.path, service, chain
+        ));
+    }
+    if via_gateway {
+        let gateway = (gateway.trim_end_matches('/').to_string(), 1);
+        backends.push(service_block("gateway", &[gateway]));
+    }
+
+    let mut sections = vec![format!("  routers:\n{}", routers.join("\n"))];
+    if !middlewares.is_empty() {
+        sections.push(format!("  middlewares:\n{}", middlewares.join("\n")));
+    }
+    sections.push(format!("  services:\n{}", backends.join("\n")));
+    format!(
+        "# Generated by divvun-worker-static. Load with Traefik's file provider.\nhttp:\n{}\n",
+        sections.join("\n")
+    )
+}
+
+/// Router, service and middleware name for a location, e.g. `tts-se-biret`.
+fn route_name(location: &Location) -> String {
+    location.path.trim_start_matches('/').replace('/', "-")
+}
+
+/// A service spreading requests over the `servers`' URLs by weight.
+fn service_block(name: &str, servers: &[(String, u32)]) -> String {
+    let servers: String = servers
+        .iter()
+        .map(|(url, weight)| match weight {
+            1 => format!("\n          - url: \"{}\"", url),
+            weight => format!(
+                "\n          - url: \"{}\"\n            weight: {}",
+                url, weight
+            ),
+        })
+        .collect();
+    format!(
+        "    {}:\n      loadBalancer:\n        servers:{}",
+        name, servers
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voices_are_routed_through_the_gateway() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.config.cors =
+            Some(toml::from_str("[[routes]]\npath = \"/speller\"\nmax_age = 86400\n").unwrap());
+        let config = generate_traefik_config(
+            &languages,
+            &ServiceRegistry::builtin(),
+            "http://127.0.0.1:4000",
+        );
+
+        assert!(config.contains(
+            "    grammar-se:\n      rule: \"Path(`/grammar/se`)\"\n      service: grammar-se\n      \
+             middlewares:\n        - grammar-se\n"
+        ));
+        assert!(config.contains(
+            "    grammar-se:\n      loadBalancer:\n        servers:\n          \
+             - url: \"http://127.0.0.1:10000\"\n"
+        ));
+        assert!(config.contains(
+            "    speller-se-headers:\n      headers:\n        customResponseHeaders:\n          \
+             Access-Control-Max-Age: \"86400\"\n"
+        ));
+        assert!(config.contains(
+            "    tts-se-biret:\n      rule: \"Path(`/tts/se/biret`)\"\n      service: gateway\n"
+        ));
+        assert!(config.ends_with(
+            "    gateway:\n      loadBalancer:\n        servers:\n          \
+             - url: \"http://127.0.0.1:4000\"\n"
+        ));
+    }
+
+    #[test]
+    fn instances_are_servers_of_one_service() {
+        let mut languages = LanguagesConfig::embedded().unwrap();
+        languages.grammar.get_mut("se").unwrap().ports = vec![
+            crate::config::Instance::local(10000),
+            crate::config::Instance {
+                host: "10.0.1.4".to_string(),
+                port: 10000,
+                weight: 2,
+            },
+        ];
+        let config = generate_traefik_config(
+            &languages,
+            &ServiceRegistry::builtin(),
+            "http://127.0.0.1:4000",
+        );
+
+        assert!(config.contains(
+            "    grammar-se:\n      loadBalancer:\n        servers:\n          \
+             - url: \"http://127.0.0.1:10000\"\n          \
+             - url: \"http://10.0.1.4:10000\"\n            weight: 2\n"
+        ));
+    }
+}
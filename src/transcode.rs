@@ -0,0 +1,163 @@
+//! Compressed audio for TTS clients. A request's `format` (`wav`, `mp3`,
+//! `ogg`/`opus` or `flac`), as a query parameter or body field, makes the
+//! gateway ask the backend for WAV and pipe it through ffmpeg.
+
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use anyhow::{bail, Context};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Mp3,
+    Opus,
+    Flac,
+}
+
+impl AudioFormat {
+    pub const NAMES: &'static [&'static str] = &["wav", "mp3", "ogg", "opus", "flac"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "wav" => Some(Self::Wav),
+            "mp3" => Some(Self::Mp3),
+            "ogg" | "opus" | "ogg/opus" => Some(Self::Opus),
+            "flac" => Some(Self::Flac),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Wav => "audio/wav",
+            Self::Mp3 => "audio/mpeg",
+            Self::Opus => "audio/ogg; codecs=opus",
+            Self::Flac => "audio/flac",
+        }
+    }
+
+    /// ffmpeg's output options for the format.
+    fn ffmpeg_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Wav => &["-f", "wav"],
+            Self::Mp3 => &["-f", "mp3", "-codec:a", "libmp3lame", "-q:a", "4"],
+            Self::Opus => &["-f", "ogg", "-codec:a", "libopus", "-b:a", "32k"],
+            Self::Flac => &["-f", "flac"],
+        }
+    }
+}
+
+/// The `format` a request asks for, from the query string or else the JSON
+/// body, which is returned without the field.
+pub fn requested(
+    query: Option<&str>,
+    body: Vec<u8>,
+) -> anyhow::Result<(Vec<u8>, Option<AudioFormat>)> {
+    let from_query = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("format="))
+        .map(|name| name.replace("%2F", "/").replace("%2f", "/"));
+
+    let mut json: Option<Value> = serde_json::from_slice(&body).ok();
+    let from_body = json
+        .as_mut()
+        .and_then(Value::as_object_mut)
+        .and_then(|object| object.remove("format"));
+    let body = match (&from_body, json) {
+        (Some(_), Some(json)) => json.to_string().into_bytes(),
+        _ => body,
+    };
+
+    let name = match (from_query, from_body) {
+        (Some(name), _) => name,
+        (None, Some(Value::String(name))) => name,
+        (None, Some(_)) => bail!("`format` must be a string"),
+        (None, None) => return Ok((body, None)),
+    };
+    match AudioFormat::parse(&name) {
+        Some(format) => Ok((body, Some(format))),
+        None => bail!(
+            "Unknown audio format {}; use one of {}",
+            name,
+            AudioFormat::NAMES.join(", ")
+        ),
+    }
+}
+
+/// Runs ffmpeg to convert WAV audio.
+#[derive(Debug, Clone)]
+pub struct Transcoder {
+    ffmpeg: PathBuf,
+}
+
+impl Transcoder {
+    /// Use `ffmpeg`, or the one on the `PATH`.
+    pub fn new(ffmpeg: Option<PathBuf>) -> Self {
+        Self {
+            ffmpeg: ffmpeg.unwrap_or_else(|| PathBuf::from("ffmpeg")),
+        }
+    }
+
+    pub async fn transcode(&self, wav: Vec<u8>, format: AudioFormat) -> anyhow::Result<Vec<u8>> {
+        let mut child = Command::new(&self.ffmpeg)
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-f",
+                "wav",
+                "-i",
+                "pipe:0",
+            ])
+            .args(format.ffmpeg_args())
+            .arg("pipe:1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("can't run {}", self.ffmpeg.display()))?;
+
+        // Write while reading so neither pipe fills up
+        let mut stdin = child.stdin.take().context("no stdin")?;
+        let writer = tokio::spawn(async move { stdin.write_all(&wav).await });
+        let output = child.wait_with_output().await?;
+        writer.await??;
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                self.ffmpeg.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_come_from_the_query_or_body() {
+        let body = br#"{"text":"Bures","format":"flac"}"#.to_vec();
+        let (body, format) = requested(None, body).unwrap();
+        assert_eq!(format, Some(AudioFormat::Flac));
+        assert_eq!(body, br#"{"text":"Bures"}"#);
+
+        let (_, format) = requested(Some("x=1&format=ogg%2Fopus"), body.clone()).unwrap();
+        assert_eq!(format, Some(AudioFormat::Opus));
+        assert_eq!(requested(None, body).unwrap().1, None);
+
+        let err = requested(Some("format=aiff"), Vec::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown audio format aiff; use one of wav, mp3, ogg, opus, flac"
+        );
+    }
+}
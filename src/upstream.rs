@@ -0,0 +1,254 @@
+//! The way to one location's backend: the instance each request goes to,
+//! the fallback while health checks find the backend down, its circuit
+//! breaker, its rate and concurrency limits and how long it may take. A location's [`ProxyEndpoint`] and the gateway's own handlers
+//! calling the same backend, such as mixed-language grammar checks, streams,
+//! speller batches and `/check`, share one, so requests are spread and
+//! failed over alike whichever route they came in on.
+//!
+//! [`ProxyEndpoint`]: crate::proxy::ProxyEndpoint
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+use poem::http::{header, StatusCode};
+use serde_json::Value;
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::balance::{Balancer, Pick};
+use crate::config::Instance;
+use crate::health::HealthMonitor;
+use crate::limits::LocationLimiter;
+use crate::problem::Problem;
+use crate::retry::{self, CircuitBreakers};
+use crate::services::Location;
+
+#[derive(Clone)]
+pub struct Upstream {
+    service: String,
+    location: Location,
+    /// Spreads requests over the location's instances, if it has several.
+    balancer: Option<Arc<Balancer>>,
+    limiter: Arc<LocationLimiter>,
+    failover: Option<HealthMonitor>,
+    breakers: Option<Arc<CircuitBreakers>>,
+    timeout: Option<Duration>,
+}
+
+impl Upstream {
+    pub fn new(service: &str, location: Location) -> Self {
+        Self {
+            service: service.to_string(),
+            balancer: (location.instances.len() > 1)
+                .then(|| Arc::new(Balancer::new(&location.instances, location.balance))),
+            limiter: Arc::new(LocationLimiter::new(location.limits)),
+            location,
+            failover: None,
+            breakers: None,
+            timeout: None,
+        }
+    }
+
+    /// Send requests to the location's fallback while `health` finds its
+    /// backend down.
+    pub fn with_failover(mut self, health: HealthMonitor) -> Self {
+        self.failover = Some(health);
+        self
+    }
+
+    /// Answer 503 right away while `breakers` has the backend's circuit open.
+    pub fn with_circuit_breakers(mut self, breakers: Arc<CircuitBreakers>) -> Self {
+        self.breakers = Some(breakers);
+        self
+    }
+
+    /// Give up on the backend after `timeout` with a 504.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    /// Where to send a request, and the instance picked for it if the
+    /// location has several.
+    pub fn backend(&self) -> (String, Option<Pick<'_>>) {
+        self.backend_except(&[])
+    }
+
+    /// Like [`backend`](Self::backend), but avoiding the `failed` instances
+    /// while the location has others, for retries.
+    pub fn backend_except(&self, failed: &[Instance]) -> (String, Option<Pick<'_>>) {
+        let down = self.failover.as_ref().is_some_and(|health| {
+            health.is_down(&self.service, &self.location.tag, self.location.port)
+        });
+        if let Some(fallback) = down.then(|| self.location.fallback_url()).flatten() {
+            return (fallback, None);
+        }
+        match &self.balancer {
+            Some(balancer) => {
+                let pick = balancer.pick_except(failed);
+                (self.location.instance_url(pick.instance()), Some(pick))
+            }
+            None => (self.location.backend_url(), None),
+        }
+    }
+
+    /// Let a call to the backend through the location's limits, returning
+    /// its place among the concurrent calls until dropped.
+    pub fn admit(&self) -> Result<Option<OwnedSemaphorePermit>, Problem> {
+        self.limiter.admit()
+    }
+
+    /// How much longer the circuit of the backend at `url` stays open.
+    pub fn open_for(&self, url: &str) -> Option<Duration> {
+        self.breakers.as_ref().and_then(|b| b.open_for(url))
+    }
+
+    /// Count a request to the backend at `url` towards its circuit and the
+    /// health of the `pick`ed instance.
+    pub fn record(&self, url: &str, pick: Option<&Pick<'_>>, failed: bool) {
+        if let Some(breakers) = &self.breakers {
+            breakers.record(url, failed);
+        }
+        if let Some(pick) = pick {
+            pick.record(failed);
+        }
+    }
+
+    /// Post `body` as JSON the way the gateway's own handlers call
+    /// backends, returning the body of a successful answer.
+    pub async fn post_json(
+        &self,
+        client: &reqwest::Client,
+        body: &Value,
+    ) -> Result<Vec<u8>, Problem> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.send_json(client, body))
+                .await
+                .map_err(|_| self.timed_out(timeout))?,
+            None => self.send_json(client, body).await,
+        }
+    }
+
+    async fn send_json(&self, client: &reqwest::Client, body: &Value) -> Result<Vec<u8>, Problem> {
+        let _permit = self.admit()?;
+        let response = self
+            .send(|url| {
+                client
+                    .post(url)
+                    .header(header::CONTENT_TYPE.as_str(), "application/json")
+                    .body(body.to_string())
+            })
+            .await?;
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| self.unavailable(err))?;
+        Ok(body.to_vec())
+    }
+
+    /// Send the request `build` makes for the backend's URL, returning the
+    /// backend's successful response for the caller to read while holding
+    /// an [`admit`](Self::admit)ted place.
+    pub async fn send(
+        &self,
+        build: impl FnOnce(&str) -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Problem> {
+        let (url, pick) = self.backend();
+        if let Some(open) = self.open_for(&url) {
+            return Err(self.circuit_open(open));
+        }
+        let result = build(&url).send().await;
+        self.record(&url, pick.as_ref(), failed(&result));
+        result
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| self.unavailable(err))
+    }
+
+    /// The 502 for a backend that failed or answered with something the
+    /// gateway can't use.
+    pub fn unavailable(&self, err: impl Display) -> Problem {
+        tracing::warn!(
+            "{} {} backend request failed: {:#}",
+            self.service,
+            self.location.tag,
+            err
+        );
+        Problem::new(
+            StatusCode::BAD_GATEWAY,
+            format!(
+                "{} backend for {} is unavailable",
+                self.service, self.location.tag
+            ),
+        )
+    }
+
+    pub fn timed_out(&self, timeout: Duration) -> Problem {
+        tracing::warn!(
+            "{} {} backend didn't answer within {:?}",
+            self.service,
+            self.location.tag,
+            timeout
+        );
+        Problem::new(StatusCode::GATEWAY_TIMEOUT, "Backend timeout")
+            .detail(format!(
+                "The {} backend for {} didn't answer within {}s",
+                self.service,
+                self.location.tag,
+                timeout.as_secs()
+            ))
+            .extension("timeout", timeout.as_secs())
+    }
+
+    pub fn circuit_open(&self, open: Duration) -> Problem {
+        let seconds = open.as_secs_f64().ceil() as u64;
+        Problem::new(StatusCode::SERVICE_UNAVAILABLE, "Backend unavailable")
+            .detail(format!(
+                "The {} backend for {} keeps failing; the gateway tries it again in {}s",
+                self.service, self.location.tag, seconds
+            ))
+            .extension("reason", "circuit_open")
+            .extension("retry_after", seconds)
+    }
+}
+
+/// Whether a request failed to reach the backend or the backend is failing.
+pub(crate) fn failed(result: &reqwest::Result<reqwest::Response>) -> bool {
+    match result {
+        Ok(response) => retry::is_failure(response.status()),
+        Err(_) => true,
+    }
+}
+
+/// The [`Upstream`] of every location the gateway forwards itself, by path.
+#[derive(Clone, Default)]
+pub struct Upstreams(Arc<HashMap<String, Upstream>>);
+
+impl Upstreams {
+    pub fn new(upstreams: impl IntoIterator<Item = Upstream>) -> Self {
+        Self(Arc::new(
+            upstreams
+                .into_iter()
+                .map(|upstream| (upstream.location.path.clone(), upstream))
+                .collect(),
+        ))
+    }
+
+    /// The upstream of `/{service}/{tag}`.
+    pub fn get(&self, service: &str, tag: &str) -> Option<&Upstream> {
+        self.at(&format!("/{}/{}", service, tag))
+    }
+
+    /// The upstream of the location at `path`, e.g. `/tts/se/biret`.
+    pub fn at(&self, path: &str) -> Option<&Upstream> {
+        self.0.get(path)
+    }
+}
@@ -0,0 +1,243 @@
+//! Static checks of a `languages.toml`, for `validate`. Unlike loading the
+//! config, which stops at the first problem, every problem is reported, in
+//! the same format as the doctor's findings.
+
+use std::collections::HashMap;
+
+use crate::config::LanguagesConfig;
+use crate::doctor::{Finding, Severity};
+use crate::ports;
+use crate::services::ServiceRegistry;
+
+const GENDERS: &[&str] = &["female", "male"];
+
+/// Check the `languages.toml` document `source`.
+pub fn validate(
+    source: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+    services: &ServiceRegistry,
+) -> Vec<Finding> {
+    let languages = match LanguagesConfig::parse(source, vars) {
+        Ok(languages) => languages,
+        Err(err) => {
+            return vec![error(
+                "syntax",
+                err.to_string().trim_end(),
+                "Correct the TOML syntax or the value's type",
+            )]
+        }
+    };
+
+    let mut findings = Vec::new();
+    findings.extend(check_categories(&languages, services));
+    findings.extend(check_ports(&languages, services));
+    findings.extend(check_tags(&languages));
+    findings.extend(check_voices(&languages));
+    if findings.is_empty() {
+        findings.push(Finding::ok("config", "languages.toml is valid"));
+    }
+    findings
+}
+
+fn error(check: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Finding {
+    Finding::problem(check, Severity::Error, message, fix)
+}
+
+fn check_categories(languages: &LanguagesConfig, services: &ServiceRegistry) -> Vec<Finding> {
+    services
+        .unclaimed(languages)
+        .into_iter()
+        .map(|name| {
+            error(
+                "services",
+                format!("No service handles [{}]", name),
+                "Correct the table's name or register a service for it",
+            )
+        })
+        .collect()
+}
+
+fn check_ports(languages: &LanguagesConfig, services: &ServiceRegistry) -> Vec<Finding> {
+    ports::audit(languages, services, &(0..=u16::MAX))
+        .into_iter()
+        .filter(|report| report.duplicate)
+        .map(|report| {
+            error(
+                "ports",
+                format!(
+                    "Port {} is used by {}",
+                    report.port,
+                    report.users.join(", ")
+                ),
+                "Give each backend its own port",
+            )
+        })
+        .collect()
+}
+
+fn check_tags(languages: &LanguagesConfig) -> Vec<Finding> {
+    let mut custom: Vec<_> = languages.custom.iter().collect();
+    custom.sort_by_key(|(service, _)| *service);
+    let mut tables: Vec<(&str, Vec<&String>)> = [
+        ("grammar", &languages.grammar),
+        ("speller", &languages.speller),
+        ("hyphenation", &languages.hyphenation),
+    ]
+    .into_iter()
+    .chain(
+        custom
+            .into_iter()
+            .map(|(name, services)| (name.as_str(), services)),
+    )
+    .map(|(name, services)| (name, services.keys().collect()))
+    .collect();
+    tables.push(("tts", languages.tts.keys().collect()));
+
+    let mut findings = Vec::new();
+    for (service, mut tags) in tables {
+        tags.sort();
+        for tag in tags.into_iter().filter(|tag| !is_language_tag(tag)) {
+            findings.push(error(
+                "tags",
+                format!("{}.{} is not a BCP 47 language tag", service, tag),
+                "Use a tag like se, smj or sma-Latn-NO",
+            ));
+        }
+    }
+    findings
+}
+
+/// Whether `tag` is well-formed: a 2–3 or 5–8 letter language, then
+/// subtags of 1–8 letters and digits, as in BCP 47.
+pub fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    let language_ok = matches!(language.len(), 2..=3 | 5..=8)
+        && language.bytes().all(|b| b.is_ascii_alphabetic());
+    language_ok
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+        })
+}
+
+fn check_voices(languages: &LanguagesConfig) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut tts: Vec<_> = languages.tts.iter().collect();
+    tts.sort_by_key(|(tag, _)| *tag);
+    for (tag, config) in tts {
+        if config.voices.is_empty() {
+            findings.push(error(
+                "tts",
+                format!("tts.{} has no voices", tag),
+                format!(
+                    "Add a voice under [tts.{}.voices] or remove the language",
+                    tag
+                ),
+            ));
+        }
+        let voices: HashMap<_, _> = config.voices.iter().collect();
+        let mut ids: Vec<_> = voices.keys().collect();
+        ids.sort();
+        for id in ids {
+            let voice = voices[id];
+            let name = format!("tts.{}.voices.{}", tag, id);
+            if voice.model.trim().is_empty() {
+                findings.push(error(
+                    "tts",
+                    format!("{} has no model", name),
+                    "Set the voice's model",
+                ));
+            }
+            if !GENDERS.contains(&voice.gender.as_str()) {
+                findings.push(error(
+                    "tts",
+                    format!("{} has the unknown gender {:?}", name, voice.gender),
+                    format!("Use one of {}", GENDERS.join(", ")),
+                ));
+            }
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_problem_is_reported() {
+        let source = r#"
+[config.tts]
+port = 40001
+
+[grammar.se]
+name = "davvisámegiella"
+port = 10000
+
+[grammar.north_sami]
+name = "davvisámegiella"
+port = 10000
+
+[grammer.sma]
+name = "Åarjelsaemien gïele"
+port = 10001
+
+[speller]
+[hyphenation]
+
+[tts.sma]
+name = "Åarjelsaemien gïele"
+[tts.sma.voices]
+
+[tts.se]
+name = "davvisámegiella"
+[tts.se.voices.biret]
+name = "Biret"
+gender = "f"
+model = ""
+"#;
+        let messages: Vec<_> = validate(source, [], &ServiceRegistry::builtin())
+            .into_iter()
+            .map(|finding| (finding.severity, finding.message))
+            .collect();
+        let error = |message: &str| (Severity::Error, message.to_string());
+        assert_eq!(
+            messages,
+            [
+                error("No service handles [grammer]"),
+                error("Port 10000 is used by grammar north_sami, grammar se"),
+                error("grammar.north_sami is not a BCP 47 language tag"),
+                error("tts.se.voices.biret has no model"),
+                error("tts.se.voices.biret has the unknown gender \"f\""),
+                error("tts.sma has no voices"),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_embedded_config_is_valid() {
+        let findings = validate(
+            crate::config::EMBEDDED_CONFIG,
+            [],
+            &ServiceRegistry::builtin(),
+        );
+        assert_eq!(findings, [Finding::ok("config", "languages.toml is valid")]);
+        assert!(is_language_tag("sma-Latn-NO"));
+        assert!(!is_language_tag("s"));
+    }
+
+    #[test]
+    fn environment_overrides_are_validated() {
+        let vars = [("DIVVUN_SPELLER__SE__PORT".to_string(), "10000".to_string())];
+        let findings = validate(
+            crate::config::EMBEDDED_CONFIG,
+            vars,
+            &ServiceRegistry::builtin(),
+        );
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].message,
+            "Port 10000 is used by grammar se, speller se"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Running the gateway as a Windows service, for hosts where that is the
+//! only supported way to keep a process running.
+//!
+//! `install` registers the current executable with the service control
+//! manager, to be started as `service run`, and registers an event log
+//! source; while running as a service, logs go to the Windows event log.
+
+use std::ffi::OsString;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use crate::config::LanguagesConfig;
+use crate::server::{ServerBuilder, SHUTDOWN_TIMEOUT};
+
+pub const SERVICE_NAME: &str = "divvun-worker-static";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+/// Address passed from [`run`] to the service's main function, which the
+/// dispatcher calls without our arguments.
+static BIND: OnceLock<(String, u16)> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Register the service to start automatically, serving on `host:port`.
+pub fn install(host: &str, port: u16) -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("Divvun language services gateway"),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: [
+            "service",
+            "run",
+            "--host",
+            host,
+            "--port",
+            &port.to_string(),
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect(),
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Documentation and proxy for the Divvun language services")?;
+    eventlog::register(SERVICE_NAME)?;
+    Ok(())
+}
+
+/// Stop the service if it is running and remove it.
+pub fn uninstall() -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+    eventlog::deregister(SERVICE_NAME)?;
+    Ok(())
+}
+
+/// Hand the process over to the service control manager. Returns when the
+/// service has stopped.
+pub fn run(host: String, port: u16) -> anyhow::Result<()> {
+    let _ = BIND.set((host, port));
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        tracing::error!("Service failed: {:#}", err);
+    }
+}
+
+fn run_service() -> anyhow::Result<()> {
+    eventlog::init(SERVICE_NAME, log::Level::Info)?;
+
+    let (stop, stopped) = tokio::sync::oneshot::channel();
+    let stop = Mutex::new(Some(stop));
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                if let Some(stop) = stop.lock().unwrap().take() {
+                    let _ = stop.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+    let status = |current_state, controls_accepted, wait_hint| ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint,
+        process_id: None,
+    };
+
+    status_handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        Duration::default(),
+    ))?;
+
+    let (host, port) = BIND
+        .get()
+        .cloned()
+        .unwrap_or_else(|| ("127.0.0.1".to_string(), 4000));
+    let result = tokio::runtime::Runtime::new()?.block_on(async {
+        let languages = LanguagesConfig::load(None)?;
+        ServerBuilder::new()
+            .languages(languages)
+            .bind(host, port)
+            .serve_until(async {
+                let _ = stopped.await;
+                tracing::info!("Stopping service");
+            })
+            .await
+    });
+
+    status_handle.set_service_status(status(
+        ServiceState::StopPending,
+        ServiceControlAccept::empty(),
+        SHUTDOWN_TIMEOUT,
+    ))?;
+    status_handle.set_service_status(status(
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        Duration::default(),
+    ))?;
+    result
+}